@@ -0,0 +1,51 @@
+//! The smallpt Cornell box of raytracer_pinhole_path put together with
+//! `Scene::builder`. The walls are spheres so large that they look flat.
+//!
+//! Takes the output path and the samples per pixel, 5000 unless given.
+
+#![allow(unstable)]
+
+extern crate raytracer;
+
+use std::path::Path;
+use raytracer::image;
+use raytracer::integrator::{self, Integrator};
+use raytracer::camera::Projection;
+use raytracer::scene::Scene;
+use raytracer::scene::builder::MaterialDef;
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let path = args.next().unwrap_or("cornell_box.ppm".to_string());
+    let samples = args.next().map(|s| s.parse().ok().expect("samples have to be a number")).unwrap_or(5000);
+
+    let scene = Scene::builder()
+        .size(1024, 768)
+        .samples(samples)
+        .integrator(Integrator::Path)
+        .camera((50.0, 52.0, 295.6), (0.0, -0.042612, -1.0), (1.0, 0.0, 0.0), Projection::Perspective(2.0))
+        .material("red", MaterialDef::diffuse((0.75, 0.25, 0.25)))
+        .material("blue", MaterialDef::diffuse((0.25, 0.25, 0.75)))
+        .material("gray", MaterialDef::diffuse((0.75, 0.75, 0.75)))
+        .material("white", MaterialDef::diffuse((1.0, 1.0, 1.0)))
+        .material("ball", MaterialDef::diffuse((0.999, 0.999, 0.999)))
+        .material("lamp", MaterialDef::diffuse((1.0, 1.0, 1.0)).emission((12.0, 12.0, 12.0)))
+        .sphere("left", 1e5, (1e5 + 1.0, 40.8, 81.6), "red")
+        .sphere("right", 1e5, (-1e5 + 99.0, 40.8, 81.6), "blue")
+        .sphere("back", 1e5, (50.0, 40.8, 1e5), "gray")
+        .sphere("front", 1e5, (50.0, 40.8, -1e5 + 600.0), "white")
+        .sphere("bottom", 1e5, (50.0, 1e5, 81.6), "gray")
+        .sphere("top", 1e5, (50.0, -1e5 + 81.6, 81.6), "gray")
+        .sphere("mirror", 16.5, (27.0, 16.5, 47.0), "ball")
+        .sphere("glass", 16.5, (73.0, 16.5, 78.0), "ball")
+        .sphere("light", 600.0, (50.0, 681.6 - 0.27, 81.6), "lamp")
+        .light_direction((0.0, 1.0, 0.0))
+        .build()
+        .unwrap();
+
+    println!("Raytracing {} samples per pixel...", scene.samples);
+    let output = integrator::render(&scene, scene.integrator, scene.samples, 0);
+
+    println!("Writing Image...");
+    image::save(Path::new(&path), &output).unwrap();
+}
@@ -0,0 +1,31 @@
+//! The scene of raytracer_pinhole put together with `Scene::builder`: a
+//! single sphere in front of a pinhole camera, lit head on.
+
+#![allow(unstable)]
+
+extern crate raytracer;
+
+use std::path::Path;
+use raytracer::image;
+use raytracer::integrator::{self, Integrator};
+use raytracer::camera::Projection;
+use raytracer::scene::Scene;
+use raytracer::scene::builder::MaterialDef;
+
+fn main() {
+    let path = std::env::args().nth(1).unwrap_or("pinhole_sphere.ppm".to_string());
+
+    let scene = Scene::builder()
+        .size(500, 500)
+        .integrator(Integrator::Diffuse)
+        .background((0.25, 0.25, 0.25))
+        .camera((0.0, 0.0, 1.0), (0.0, 0.0, -1.0), (0.0, 1.0, 0.0), Projection::Perspective(1.0))
+        .material("gray", MaterialDef::diffuse((0.75, 0.75, 0.75)))
+        .sphere("ball", 1.41, (0.0, 0.0, -1.0), "gray")
+        .light_direction((0.0, 0.0, 1.0))
+        .build()
+        .unwrap();
+
+    let output = integrator::render(&scene, scene.integrator, scene.samples, 0);
+    image::save(Path::new(&path), &output).unwrap();
+}
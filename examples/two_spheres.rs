@@ -0,0 +1,32 @@
+//! The scene of raytracer_2d put together with `Scene::builder`: two
+//! overlapping spheres seen head on by an orthographic camera.
+
+#![allow(unstable)]
+
+extern crate raytracer;
+
+use std::path::Path;
+use raytracer::image;
+use raytracer::integrator::{self, Integrator};
+use raytracer::camera::Projection;
+use raytracer::scene::Scene;
+use raytracer::scene::builder::MaterialDef;
+
+fn main() {
+    let path = std::env::args().nth(1).unwrap_or("two_spheres.ppm".to_string());
+
+    let scene = Scene::builder()
+        .size(500, 500)
+        .integrator(Integrator::Flat)
+        .background((0.5, 0.5, 0.5))
+        .camera((250.0, 250.0, 0.0), (0.0, 0.0, -1.0), (0.0, 1.0, 0.0), Projection::Orthographic(250.0))
+        .material("blue", MaterialDef::diffuse((0.25, 0.25, 0.75)))
+        .material("teal", MaterialDef::diffuse((0.25, 0.5, 0.75)))
+        .sphere("back", 150.0, (212.0, 284.0, -1000.0), "blue")
+        .sphere("front", 150.0, (300.0, 200.0, -1000.0), "teal")
+        .build()
+        .unwrap();
+
+    let output = integrator::render(&scene, scene.integrator, scene.samples, 0);
+    image::save(Path::new(&path), &output).unwrap();
+}
@@ -0,0 +1,170 @@
+//! Arbitrary output variables: auxiliary buffers rendered next to the
+//! beauty pass for denoising and compositing.
+//!
+//! With an `.exr` output all buffers end up as layers of one file, any other
+//! format writes each buffer to its own file named after the output, e.g.
+//! `image.albedo.png`. Depth, position, normal and the ids hold plain data
+//! values and are only meaningful in the floating point formats. The ids are
+//! those of the first sample of a pixel rather than an average, which would
+//! name no object where objects meet.
+
+use std::io;
+use std::io::BufWriter;
+use std::io::prelude::*;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::default::Default;
+
+use image::{self, Format, Image};
+use image::exr;
+use tonemap::ToneMap;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Aov {
+    Albedo, // reflectance of the first hit
+    Normal, // shading normal of the first hit, facing the camera
+    Depth, // distance of the first hit along the viewing direction
+    Position, // world space position of the first hit
+    Id, // index of the first hit primitive plus one, zero for no hit
+    Material, // number of the first hit material plus one, zero for no hit
+    Direct, // emission seen directly or after one bounce
+    Indirect, // light arriving after two or more bounces
+    Variance, // variance of the pixel's luminance estimate
+}
+
+static ALL: [Aov; 9] = [Aov::Albedo, Aov::Normal, Aov::Depth, Aov::Position, Aov::Id, Aov::Material,
+                        Aov::Direct, Aov::Indirect, Aov::Variance];
+
+impl Aov {
+    pub fn all() -> &'static [Aov] {
+        &ALL
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::Id => "id",
+            Aov::Material => "material",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+            Aov::Variance => "variance",
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        match *self {
+            Aov::Depth | Aov::Id | Aov::Material | Aov::Variance => 1,
+            _ => 3,
+        }
+    }
+
+    /// Whether the buffer holds radiance that should be tone mapped.
+    pub fn is_color(&self) -> bool {
+        match *self {
+            Aov::Albedo | Aov::Direct | Aov::Indirect => true,
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Aov {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Aov, String> {
+        match ALL.iter().find(|aov| aov.name() == s) {
+            Some(aov) => Ok(*aov),
+            None => Err(format!("unknown output variable '{}'", s)),
+        }
+    }
+}
+
+/// Parses a comma separated list of AOV names, `all` selects every one.
+pub fn parse_list(s: &str) -> Result<Vec<Aov>, String> {
+    if s == "all" {
+        return Ok(ALL.to_vec());
+    }
+    s.split(',').map(|name| name.trim().parse()).collect()
+}
+
+/// One image per selected AOV.
+pub struct AovBuffers {
+    pub layers: Vec<(Aov, Image)>,
+}
+
+impl AovBuffers {
+    pub fn new(width: usize, height: usize, selected: &[Aov]) -> AovBuffers {
+        let mut layers: Vec<(Aov, Image)> = Vec::new();
+        for aov in selected.iter() {
+            if !layers.iter().any(|&(a, _)| a == *aov) {
+                layers.push((*aov, Image::new(width, height, aov.channels())));
+            }
+        }
+        AovBuffers { layers: layers }
+    }
+
+    pub fn get(&self, aov: Aov) -> Option<&Image> {
+        self.layers.iter().find(|&&(a, _)| a == aov).map(|&(_, ref img)| img)
+    }
+
+    /// Stores `v` at (x, y) if `aov` is selected, single channel buffers keep
+    /// the first component.
+    pub fn set(&mut self, aov: Aov, x: usize, y: usize, v: (f64, f64, f64)) {
+        for &mut (a, ref mut img) in self.layers.iter_mut() {
+            if a == aov {
+                if img.channels == 1 {
+                    img.set(x, y, 0, v.0);
+                } else {
+                    img.set_rgb(x, y, v.0, v.1, v.2);
+                }
+            }
+        }
+    }
+
+    /// Writes `beauty` to `path` together with the AOVs, as layers of a
+    /// single EXR or as separate files next to it. The format is chosen by
+    /// the file extension.
+    pub fn save(&self, path: &Path, beauty: &Image, tonemap: &ToneMap) -> io::Result<()> {
+        match Format::from_path(path) {
+            Some(format) => self.save_as(path, beauty, format, tonemap),
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "unsupported image format",
+                                       Some(format!("{}", path.display())))),
+        }
+    }
+
+    /// Like `save` in the given format.
+    pub fn save_as(&self, path: &Path, beauty: &Image, format: Format, tonemap: &ToneMap) -> io::Result<()> {
+        match format {
+            Format::Exr(options) => {
+                let mut channels = exr::channels(beauty, "");
+                for &(aov, ref img) in self.layers.iter() {
+                    channels.push_all(&exr::channels(img, aov.name())[..]);
+                }
+                let mut w = BufWriter::new(try!(File::create(path)));
+                try!(exr::write_channels(&mut w, beauty.width, beauty.height, &channels[..], options));
+                w.flush()
+            }
+            _ => {
+                try!(image::save_as(path, beauty, format, tonemap));
+                for &(aov, ref img) in self.layers.iter() {
+                    let tm = if aov.is_color() { *tonemap } else { Default::default() };
+                    try!(image::save_as(&layer_path(path, aov), img, format, &tm));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// `dir/image.png` becomes `dir/image.<aov>.png`.
+pub fn layer_path(path: &Path, aov: Aov) -> PathBuf {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("image");
+    let name = match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) => format!("{}.{}.{}", stem, aov.name(), ext),
+        None => format!("{}.{}", stem, aov.name()),
+    };
+    path.with_file_name(&name[..])
+}
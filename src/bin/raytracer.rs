@@ -0,0 +1,796 @@
+#![allow(unstable)]
+
+extern crate raytracer;
+extern crate time;
+
+use std::path::{Path, PathBuf};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::io::prelude::*;
+use std::thread;
+use std::cmp;
+use std::f64::{INFINITY, NEG_INFINITY};
+use std::num::Float;
+use std::str::FromStr;
+use std::default::Default;
+use raytracer::vector::{Vector, VectorOps};
+use raytracer::image::{self, exr, Image, Format, Depth};
+use raytracer::tonemap::ToneMap;
+use raytracer::aov::{self, Aov};
+use raytracer::denoise::{self, Features};
+use raytracer::compare::{self, Options};
+use raytracer::scene::Scene;
+use raytracer::film::Film;
+use raytracer::tile::{self, Settings, Kernel};
+use raytracer::checkpoint;
+use raytracer::interrupt;
+use raytracer::stats::{Stats, Progress};
+use raytracer::debug;
+use raytracer::net;
+use raytracer::integrator;
+
+fn usage() -> ! {
+    println!("usage: raytracer <command> [options]");
+    println!("");
+    println!("    render [scene]              render a .scene, .pbrt, .gltf or .glb file");
+    println!("    compare <reference> <test>  compare two images");
+    println!("    denoise <input> <output>    filter an EXR image guided by its AOV layers");
+    println!("    info <scene>                describe a scene");
+    println!("    convert <input> <output>    write an image in another format");
+    println!("    worker <address>            render for the coordinator at the address");
+    println!("");
+    println!("render options:");
+    println!("    --scene <file>              the scene, scenes/cornell_box.scene by default");
+    println!("    --resolution <w>x<h>        image size, or --width <w> and --height <h>");
+    println!("    --spp <n>                   samples per pixel");
+    println!("    --integrator <name>         flat, diffuse or path");
+    println!("    --max-depth <n>             bounces before paths end");
+    println!("    --seed <n>                  seed of the random numbers");
+    println!("    --threads <n>               worker threads, one per cpu by default");
+    println!("    --tile-size <n>             side of the tiles in pixels, 32 by default");
+    println!("    --order <order>             hilbert, spiral or scanline tile order");
+    println!("    --kernel <kernel>           megakernel, one path at a time, or wavefront, batches of paths");
+    println!("    --crop <x0>,<y0>,<x1>,<y1>  render only columns x0..x1 and rows y0..y1");
+    println!("    --aov <list>                albedo, normal, depth, position, id, material, direct, indirect, variance or all");
+    println!("    --denoise                   filter the image guided by the AOVs");
+    println!("    --pass <n>                  samples per pixel added in each pass, 16 by default");
+    println!("    --checkpoint <file>         save the render after passes to continue it later");
+    println!("    --checkpoint-interval <s>   seconds between checkpoints, 300 by default");
+    println!("    --resume <file>             continue from a checkpoint, up to the --spp given");
+    println!("    --time-limit <time>         stop after the pass that fits in 90s, 10m, 1.5h, ...");
+    println!("    --target-error <e>          stop once the estimated relative error is below e, e.g. 1%");
+    println!("    --stats <file>              write ray counts, path lengths and speed as JSON");
+    println!("    --debug-pixel <x>,<y>       only trace the samples of this pixel with the megakernel, write");
+    println!("                                their paths to <output>.paths.json and <output>.paths.obj");
+    println!("    --debug-sample <n>          only trace sample n of the debug pixel");
+    println!("    --listen <address>          render on the workers that connect to this address");
+    println!("");
+    println!("output options of render and convert:");
+    println!("    -o, --output <path>         image.ppm by default");
+    println!("    --format <ext>              ppm, pgm, png, hdr or exr, replaces the extension of the output");
+    println!("    --depth <bits>              8 or 16 bits per sample for ppm, pgm and png, 8 by default");
+    println!("    --exr-type <type>           half or float, half by default");
+    println!("    --exr-compression <method>  none, zip or piz, zip by default");
+    println!("    --exposure <stops>");
+    println!("    --tonemap <operator>        clamp, reinhard, reinhard:<white>, aces or hable");
+    println!("    --dither <mode>             none, ordered or blue-noise");
+    println!("");
+    println!("worker options:");
+    println!("    --threads <n>               connections to the coordinator, one per cpu by default");
+    println!("");
+    println!("compare options:");
+    println!("    --diff <output>             write a false color map of the FLIP error");
+    println!("    --exposure <stops>, --tonemap <operator>, --ppd <pixels per degree>");
+    println!("");
+    println!("denoise options:");
+    println!("    --iterations <n>, --sigma-color <s>, --sigma-normal <s>, --sigma-depth <s>");
+    println!("                                the input needs R, G, B channels and may hold albedo,");
+    println!("                                normal, depth and variance layers");
+    std::process::exit(1);
+}
+
+fn fail(message: &str) -> ! {
+    println!("{}", message);
+    std::process::exit(1);
+}
+
+/// The value following `flag`, exits unless it parses.
+fn value<T: FromStr>(args: &mut Iterator<Item=String>, flag: &str, expected: &str) -> T {
+    match args.next().and_then(|v| v.parse().ok()) {
+        Some(v) => v,
+        None => fail(&format!("{} takes {}", flag, expected)[..]),
+    }
+}
+
+fn load_scene(path: &str) -> Scene {
+    match Scene::load(Path::new(path)) {
+        Ok(scene) => scene,
+        Err(e) => fail(&format!("{}", e)[..]),
+    }
+}
+
+fn load_image(path: &str) -> Image {
+    match image::load(Path::new(path)) {
+        Ok(img) => img,
+        Err(e) => fail(&format!("{}: {}", path, e)[..]),
+    }
+}
+
+/// Where an image goes and how integer formats are tone mapped.
+struct Output {
+    path: String,
+    format: Option<String>,
+    depth: Option<Depth>,
+    exr_type: Option<exr::PixelType>,
+    exr_compression: Option<exr::Compression>,
+    tonemap: ToneMap,
+}
+
+impl Output {
+    fn new() -> Output {
+        Output { path: "image.ppm".to_string(), format: None, depth: None, exr_type: None, exr_compression: None,
+                 tonemap: Default::default() }
+    }
+
+    /// Handles `arg` if it is an output option.
+    fn parse(&mut self, arg: &str, args: &mut Iterator<Item=String>) -> bool {
+        match arg {
+            "-o" | "--output" => self.path = value(args, arg, "a path"),
+            "--format" => self.format = Some(value(args, arg, "ppm, pgm, png, hdr or exr")),
+            "--depth" => self.depth = Some(value(args, arg, "8 or 16")),
+            "--exr-type" => self.exr_type = Some(value(args, arg, "half or float")),
+            "--exr-compression" => self.exr_compression = Some(value(args, arg, "none, zip or piz")),
+            "--exposure" => self.tonemap.exposure = value(args, arg, "a number of stops"),
+            "--tonemap" => self.tonemap.operator = value(args, arg, "clamp, reinhard, reinhard:<white>, aces or hable"),
+            "--dither" => self.tonemap.dither = value(args, arg, "none, ordered or blue-noise"),
+            _ => return false,
+        }
+        true
+    }
+
+    /// The output path with the extension of `--format`, exits if there is
+    /// no format for it.
+    fn path(&self) -> PathBuf {
+        let path = match self.format {
+            Some(ref format) => Path::new(&self.path).with_extension(&format[..]),
+            None => Path::new(&self.path).to_path_buf(),
+        };
+        if Format::from_path(&path).is_none() {
+            fail(&format!("{}: unknown image format, use .ppm, .pgm, .png, .hdr or .exr", path.display())[..]);
+        }
+        path
+    }
+
+    /// The format to write `path` in, exits if the options asked for do not
+    /// apply to it.
+    fn format(&self, path: &Path) -> Format {
+        let mut format = Format::from_path(path).unwrap();
+        if let Some(depth) = self.depth {
+            format = match format.with_depth(depth) {
+                Some(format) => format,
+                None => fail("--depth is only for ppm, pgm and png"),
+            };
+        }
+        if self.exr_type.is_some() || self.exr_compression.is_some() {
+            let mut options = match format {
+                Format::Exr(options) => options,
+                _ => fail("--exr-type and --exr-compression are only for exr"),
+            };
+            options.pixel_type = self.exr_type.unwrap_or(options.pixel_type);
+            options.compression = self.exr_compression.unwrap_or(options.compression);
+            format = Format::Exr(options);
+        }
+        format
+    }
+}
+
+/// Columns x0..x1 and rows y0..y1.
+#[derive(Debug, Copy, Clone)]
+struct Crop {
+    x0: usize,
+    y0: usize,
+    x1: usize,
+    y1: usize,
+}
+
+impl FromStr for Crop {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Crop, String> {
+        let v: Vec<usize> = match s.split(',').map(|c| c.trim().parse()).collect() {
+            Ok(v) => v,
+            Err(_) => return Err(format!("invalid crop window '{}'", s)),
+        };
+        if v.len() != 4 || v[0] >= v[2] || v[1] >= v[3] {
+            return Err(format!("invalid crop window '{}'", s));
+        }
+        Ok(Crop { x0: v[0], y0: v[1], x1: v[2], y1: v[3] })
+    }
+}
+
+/// A number of seconds, or of minutes or hours with an `m` or `h` suffix.
+#[derive(Debug, Copy, Clone)]
+struct Duration(f64);
+
+impl FromStr for Duration {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Duration, String> {
+        let (number, unit) = match s.chars().last() {
+            Some('s') => (&s[..s.len() - 1], 1.0),
+            Some('m') => (&s[..s.len() - 1], 60.0),
+            Some('h') => (&s[..s.len() - 1], 3600.0),
+            _ => (s, 1.0),
+        };
+        match number.parse::<f64>() {
+            Ok(v) if v > 0.0 => Ok(Duration(v * unit)),
+            _ => Err(format!("invalid duration '{}'", s)),
+        }
+    }
+}
+
+/// A fraction, or a percentage with a `%` suffix.
+#[derive(Debug, Copy, Clone)]
+struct Fraction(f64);
+
+impl FromStr for Fraction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Fraction, String> {
+        let (number, unit) = if s.ends_with("%") { (&s[..s.len() - 1], 0.01) } else { (s, 1.0) };
+        match number.parse::<f64>() {
+            Ok(v) if v > 0.0 => Ok(Fraction(v * unit)),
+            _ => Err(format!("invalid fraction '{}'", s)),
+        }
+    }
+}
+
+fn render(mut args: std::vec::IntoIter<String>) {
+    let mut output = Output::new();
+    let mut scene_path = "scenes/cornell_box.scene".to_string();
+    let mut width = None;
+    let mut height = None;
+    let mut samples = None;
+    let mut integrator = None;
+    let mut max_depth = None;
+    let mut settings: Settings = Default::default();
+    let mut crop: Option<Crop> = None;
+    let mut selected: Vec<Aov> = Vec::new();
+    let mut denoise = false;
+    let mut pass = 16;
+    let mut checkpoint_path: Option<String> = None;
+    let mut interval = 300.0;
+    let mut resume: Option<String> = None;
+    let mut time_limit: Option<Duration> = None;
+    let mut target_error: Option<Fraction> = None;
+    let mut stats_path: Option<String> = None;
+    let mut debug_pixel: Option<(usize, usize)> = None;
+    let mut debug_sample: Option<usize> = None;
+    let mut listen: Option<String> = None;
+    while let Some(arg) = args.next() {
+        if output.parse(&arg[..], &mut args) {
+            continue;
+        }
+        match &arg[..] {
+            "--scene" => scene_path = value(&mut args, &arg[..], "a scene file"),
+            "--resolution" => {
+                let resolution: String = value(&mut args, &arg[..], "<width>x<height>");
+                let v: Vec<Option<usize>> = resolution.split('x').map(|n| n.parse().ok()).collect();
+                if v.len() != 2 || v[0].is_none() || v[1].is_none() {
+                    fail("--resolution takes <width>x<height>");
+                }
+                width = v[0];
+                height = v[1];
+            }
+            "--width" => width = Some(value(&mut args, &arg[..], "a number of pixels")),
+            "--height" => height = Some(value(&mut args, &arg[..], "a number of pixels")),
+            "--spp" => samples = Some(value(&mut args, &arg[..], "a number of samples")),
+            "--integrator" => integrator = Some(value(&mut args, &arg[..], "flat, diffuse or path")),
+            "--max-depth" => max_depth = Some(value(&mut args, &arg[..], "a number of bounces")),
+            "--seed" => settings.seed = value(&mut args, &arg[..], "a number"),
+            "--threads" => settings.threads = value(&mut args, &arg[..], "a number"),
+            "--tile-size" => settings.tile_size = value(&mut args, &arg[..], "a number of pixels"),
+            "--order" => settings.order = value(&mut args, &arg[..], "hilbert, spiral or scanline"),
+            "--kernel" => settings.kernel = value(&mut args, &arg[..], "megakernel or wavefront"),
+            "--crop" => crop = Some(value(&mut args, &arg[..], "<x0>,<y0>,<x1>,<y1>")),
+            "--aov" => selected = match aov::parse_list(&value::<String>(&mut args, &arg[..], "a list")[..]) {
+                Ok(list) => list,
+                Err(e) => fail(&e[..]),
+            },
+            "--denoise" => denoise = true,
+            "--pass" => pass = value(&mut args, &arg[..], "a number of samples"),
+            "--checkpoint" => checkpoint_path = Some(value(&mut args, &arg[..], "a path")),
+            "--checkpoint-interval" => interval = value(&mut args, &arg[..], "a number of seconds"),
+            "--resume" => resume = Some(value(&mut args, &arg[..], "a checkpoint file")),
+            "--time-limit" => time_limit = Some(value(&mut args, &arg[..], "a duration such as 10m")),
+            "--target-error" => target_error = Some(value(&mut args, &arg[..], "a fraction such as 1%")),
+            "--stats" => stats_path = Some(value(&mut args, &arg[..], "a path")),
+            "--debug-pixel" => {
+                let pixel: String = value(&mut args, &arg[..], "<x>,<y>");
+                let v: Vec<Option<usize>> = pixel.split(',').map(|n| n.trim().parse().ok()).collect();
+                if v.len() != 2 || v[0].is_none() || v[1].is_none() {
+                    fail("--debug-pixel takes <x>,<y>");
+                }
+                debug_pixel = Some((v[0].unwrap(), v[1].unwrap()));
+            }
+            "--debug-sample" => debug_sample = Some(value(&mut args, &arg[..], "a sample number")),
+            "--listen" => listen = Some(value(&mut args, &arg[..], "an address such as 0.0.0.0:7878")),
+            _ if arg.starts_with("-") => fail(&format!("unknown option '{}'", arg)[..]),
+            _ => scene_path = arg,
+        }
+    }
+
+    let mut scene = load_scene(&scene_path[..]);
+    if let Some(w) = width { scene.width = w; }
+    if let Some(h) = height { scene.height = h; }
+    if let Some(s) = samples { scene.samples = s; }
+    if let Some(i) = integrator { scene.integrator = i; }
+    if let Some(d) = max_depth { scene.max_depth = d; }
+    if scene.width == 0 || scene.height == 0 || scene.samples == 0 ||
+       settings.threads == 0 || settings.tile_size == 0 || pass == 0 {
+        fail("image size, samples, threads, tile size and pass have to be positive");
+    }
+    let crop = crop.unwrap_or(Crop { x0: 0, y0: 0, x1: scene.width, y1: scene.height });
+    if crop.x1 > scene.width || crop.y1 > scene.height {
+        fail(&format!("the crop window is not within the {}x{} image", scene.width, scene.height)[..]);
+    }
+    let path = output.path();
+    let format = output.format(&path);
+    let materials = scene.material_ids();
+
+    if let Some((x, y)) = debug_pixel {
+        if x >= scene.width || y >= scene.height {
+            fail(&format!("the debug pixel is not within the {}x{} image", scene.width, scene.height)[..]);
+        }
+        // The wavefront kernel draws from a stream per pixel and sample and
+        // shades differently, its paths are not the ones traced here
+        if settings.kernel == Kernel::Wavefront {
+            fail("--debug-pixel replays the megakernel, it does not work with --kernel wavefront");
+        }
+        let (first, count) = match debug_sample {
+            Some(k) => (k, 1),
+            None => (0, scene.samples),
+        };
+        trace_pixel(&scene, x, y, &settings, first, count, &path);
+        return;
+    }
+
+    if denoise {
+        // The denoiser is guided by these, keeping them in the output allows
+        // filtering again with raytracer denoise
+        selected.push_all(&[Aov::Albedo, Aov::Normal, Aov::Depth, Aov::Variance]);
+    }
+
+    let scene_hash = checkpoint::scene_hash(&scene);
+    let mut film = match resume {
+        Some(ref resume) => {
+            let c = match checkpoint::load(Path::new(resume)) {
+                Ok(c) => c,
+                Err(e) => fail(&format!("{}: {}", resume, e)[..]),
+            };
+            if c.scene_hash != scene_hash {
+                fail(&format!("{} is a checkpoint of another scene", resume)[..]);
+            }
+            if c.kernel != settings.kernel {
+                let kernel = match c.kernel {
+                    Kernel::Megakernel => "megakernel",
+                    Kernel::Wavefront => "wavefront",
+                };
+                fail(&format!("{} was rendered with --kernel {}", resume, kernel)[..]);
+            }
+            if (c.film.x0, c.film.y0, c.film.width, c.film.height) !=
+               (crop.x0, crop.y0, crop.x1 - crop.x0, crop.y1 - crop.y0) {
+                fail(&format!("{} is a checkpoint of another crop window", resume)[..]);
+            }
+            println!("Resuming at {} samples per pixel", c.film.samples);
+            settings.seed = c.seed;
+            c.film
+        }
+        None => Film::new(crop.x0, crop.y0, crop.x1 - crop.x0, crop.y1 - crop.y0),
+    };
+    // Resumed renders keep writing to the checkpoint they started from
+    let checkpoint_path = checkpoint_path.or(resume).map(|p| Path::new(&p).to_path_buf());
+
+    // With a time limit or a target error the samples of the scene file are
+    // no limit, only those given with --spp
+    let target = if samples.is_none() && (time_limit.is_some() || target_error.is_some()) {
+        std::usize::MAX
+    } else {
+        scene.samples
+    };
+    let pixels = film.width * film.height;
+    let start = time::precise_time_s();
+    let resumed_at = film.samples;
+    let mut last_checkpoint = start;
+    let mut reason = "the sample count was reached";
+    let coordinator = listen.map(|address| match net::Coordinator::listen(&address[..], &scene) {
+        Ok(c) => {
+            println!("Listening for workers on {}", address);
+            c
+        }
+        Err(e) => fail(&format!("{}: {}", address, e)[..]),
+    });
+    let progress = Progress::new();
+    let mut totals: Stats = Default::default();
+    // Samples per pixel expected to reach the target error
+    let mut needed = None;
+    // Ctrl-C lets the pass in flight finish and saves the render
+    interrupt::catch();
+    loop {
+        if film.samples >= target {
+            break;
+        }
+        if interrupt::interrupted() {
+            reason = "it was interrupted";
+            break;
+        }
+        // The variance is only meaningful after a couple of samples
+        if let Some(Fraction(e)) = target_error {
+            if film.samples >= 2 {
+                // The error falls with the square root of the samples
+                let error = film.relative_error();
+                if error <= e {
+                    reason = "the target error was reached";
+                    break;
+                }
+                needed = Some(film.samples as f64 * (error / e).powi(2));
+            }
+        }
+        let (first, mut count) = (film.samples, cmp::min(pass, target - film.samples));
+        if let Some(Duration(limit)) = time_limit {
+            // Shorten the pass to what the time left allows at the speed so far
+            let elapsed = time::precise_time_s() - start;
+            if film.samples > resumed_at {
+                let per_sample = elapsed / (film.samples - resumed_at) as f64;
+                let fit = ((limit - elapsed) / per_sample).floor();
+                if fit < 1.0 {
+                    reason = "the time limit was reached";
+                    break;
+                }
+                count = cmp::min(count, fit as usize);
+            } else if elapsed >= limit {
+                reason = "the time limit was reached";
+                break;
+            }
+        }
+        let update = |done: usize| {
+            // Of the work of this run, which the time left is estimated from,
+            // not counting the samples of a checkpoint it resumed
+            let spp = first as f64 + (done * count) as f64 / pixels as f64;
+            let (rendered, before) = (spp - resumed_at as f64, resumed_at as f64);
+            let mut fraction = if target == std::usize::MAX { 0.0 } else { rendered / (target as f64 - before) };
+            if let Some(Duration(limit)) = time_limit {
+                fraction = fraction.max((time::precise_time_s() - start) / limit);
+            }
+            if let Some(needed) = needed {
+                fraction = fraction.max(rendered / (needed - before));
+            }
+            progress.update(fraction, &format!("{:.0} spp", spp)[..]);
+        };
+        let pass_stats = match coordinator {
+            Some(ref c) => c.render_pass(&mut film, &settings, first, count, |done, _| update(done)),
+            None => tile::render_pass(&scene, &mut film, &settings, first, count, |done, _| update(done)),
+        };
+        totals.add(&pass_stats);
+        if let Some(ref checkpoint_path) = checkpoint_path {
+            if time::precise_time_s() - last_checkpoint >= interval {
+                if let Err(e) = checkpoint::save(checkpoint_path, &film, scene_hash, &settings) {
+                    fail(&format!("{}: {}", checkpoint_path.display(), e)[..]);
+                }
+                // A look at the progress so far
+                save(&film, &materials[..], &selected[..], denoise, &path, format, &output.tonemap);
+                last_checkpoint = time::precise_time_s();
+            }
+        }
+    }
+    println!("\nStopped after {:.1}s as {}: {} samples per pixel, estimated relative error {:.2}%",
+             time::precise_time_s() - start, reason, film.samples, film.relative_error() * 100.0);
+    report(&totals, &stats_path);
+
+    // An interrupted render is always left to be continued
+    let checkpoint_path = match checkpoint_path {
+        None if interrupt::interrupted() => Some(path.with_extension("ck")),
+        p => p,
+    };
+    if let Some(ref checkpoint_path) = checkpoint_path {
+        if let Err(e) = checkpoint::save(checkpoint_path, &film, scene_hash, &settings) {
+            fail(&format!("{}: {}", checkpoint_path.display(), e)[..]);
+        }
+        if interrupt::interrupted() {
+            println!("Saved {}, continue with --resume {}", checkpoint_path.display(),
+                     checkpoint_path.display());
+        }
+    }
+
+    println!("Writing {}...", path.display());
+    save(&film, &materials[..], &selected[..], denoise, &path, format, &output.tonemap);
+}
+
+/// Writes the paths of samples `first..first + count` of pixel (`x`, `y`)
+/// next to `path` and prints their average and the brightest of them.
+fn trace_pixel(scene: &Scene, x: usize, y: usize, settings: &Settings, first: usize, count: usize, path: &Path) {
+    let paths = debug::trace_pixel(scene, x, y, settings.seed, first, count);
+    let json_path = path.with_extension("paths.json");
+    let obj_path = path.with_extension("paths.obj");
+    if let Err(e) = File::create(&json_path).and_then(|mut f| debug::write_json(&paths[..], &mut f)) {
+        fail(&format!("{}: {}", json_path.display(), e)[..]);
+    }
+    if let Err(e) = File::create(&obj_path).and_then(|mut f| debug::write_obj(&paths[..], &mut f)) {
+        fail(&format!("{}: {}", obj_path.display(), e)[..]);
+    }
+
+    let mut sum = Vector::new(0.0, 0.0, 0.0);
+    let mut brightest = &paths[0];
+    for p in paths.iter() {
+        sum = &sum + &p.radiance;
+        if integrator::luminance(&p.radiance) > integrator::luminance(&brightest.radiance) {
+            brightest = p;
+        }
+    }
+    println!("Traced {} samples of pixel {},{}: mean {}", paths.len(), x, y, show(sum.smul(1.0 / paths.len() as f64)));
+    println!("Brightest is sample {} with {} after {} vertices", brightest.sample, show(brightest.radiance),
+             brightest.vertices.len());
+    println!("Wrote {} and {}", json_path.display(), obj_path.display());
+}
+
+/// Prints the speed of the render and writes `stats` as JSON to `path`.
+fn report(stats: &Stats, path: &Option<String>) {
+    println!("{}", stats);
+    if let Some(ref path) = *path {
+        if let Err(e) = File::create(Path::new(path)).and_then(|mut f| stats.write_json(&mut f)) {
+            fail(&format!("{}: {}", path, e)[..]);
+        }
+    }
+}
+
+/// Writes the image of `film` and the selected AOVs, denoised if asked to.
+fn save(film: &Film, materials: &[usize], selected: &[Aov], denoise: bool, path: &Path, format: Format,
+        tonemap: &ToneMap) {
+    // Unclamped, tone mapping happens when writing the image
+    let mut image = film.image();
+    let aovs = film.aovs(selected, materials);
+
+    if denoise {
+        let features = Features {
+            albedo: aovs.get(Aov::Albedo),
+            normal: aovs.get(Aov::Normal),
+            depth: aovs.get(Aov::Depth),
+            variance: aovs.get(Aov::Variance),
+        };
+        image = denoise::denoise(&image, &features, &Default::default());
+    }
+
+    if let Err(e) = aovs.save_as(path, &image, format, tonemap) {
+        fail(&format!("{}: {}", path.display(), e)[..]);
+    }
+}
+
+fn compare(mut args: std::vec::IntoIter<String>) {
+    let mut options: Options = Default::default();
+    let mut diff = None;
+    let mut files: Vec<String> = Vec::new();
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--diff" => diff = Some(value::<String>(&mut args, &arg[..], "an output path")),
+            "--exposure" => options.tonemap.exposure = value(&mut args, &arg[..], "a number of stops"),
+            "--tonemap" => options.tonemap.operator = value(&mut args, &arg[..],
+                                                            "clamp, reinhard, reinhard:<white>, aces or hable"),
+            "--ppd" => options.pixels_per_degree = value(&mut args, &arg[..], "a number"),
+            _ if arg.starts_with("-") => fail(&format!("unknown option '{}'", arg)[..]),
+            _ => files.push(arg),
+        }
+    }
+    if files.len() != 2 {
+        usage();
+    }
+
+    let reference = load_image(&files[0][..]);
+    let test = load_image(&files[1][..]);
+    let metrics = match compare::compare(&reference, &test, &options) {
+        Ok(metrics) => metrics,
+        Err(e) => fail(&e[..]),
+    };
+    println!("MSE:     {:.6e}", metrics.mse);
+    println!("relMSE:  {:.6e}", metrics.rel_mse);
+    println!("PSNR:    {:.2} dB", metrics.psnr);
+    println!("SSIM:    {:.4}", metrics.ssim);
+    println!("FLIP:    {:.4}", metrics.flip);
+
+    if let Some(path) = diff {
+        let written = compare::difference(&reference, &test, &options).and_then(|map| {
+            image::save(Path::new(&path), &map).map_err(|e| format!("{}: {}", path, e))
+        });
+        if let Err(e) = written {
+            fail(&e[..]);
+        }
+    }
+}
+
+fn denoise(mut args: std::vec::IntoIter<String>) {
+    let mut options: denoise::Options = Default::default();
+    let mut files: Vec<String> = Vec::new();
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--iterations" => options.iterations = value(&mut args, &arg[..], "a number"),
+            "--sigma-color" => options.sigma_color = value(&mut args, &arg[..], "a number"),
+            "--sigma-normal" => options.sigma_normal = value(&mut args, &arg[..], "a number"),
+            "--sigma-depth" => options.sigma_depth = value(&mut args, &arg[..], "a number"),
+            _ if arg.starts_with("-") => fail(&format!("unknown option '{}'", arg)[..]),
+            _ => files.push(arg),
+        }
+    }
+    if files.len() != 2 {
+        usage();
+    }
+
+    let input = &files[0][..];
+    let (width, height, channels) = match File::open(&Path::new(input)).and_then(|f| {
+        exr::read(&mut BufReader::new(f))
+    }) {
+        Ok(read) => read,
+        Err(e) => fail(&format!("{}: {}", input, e)[..]),
+    };
+    let color = match exr::layer(width, height, &channels[..], "") {
+        Some(color) => color,
+        None => fail(&format!("{}: no R, G, B channels", input)[..]),
+    };
+    let albedo = exr::layer(width, height, &channels[..], "albedo");
+    let normal = exr::layer(width, height, &channels[..], "normal");
+    let depth = exr::layer(width, height, &channels[..], "depth");
+    let variance = exr::layer(width, height, &channels[..], "variance");
+
+    println!("Denoising...");
+    let features = Features {
+        albedo: albedo.as_ref(),
+        normal: normal.as_ref(),
+        depth: depth.as_ref(),
+        variance: variance.as_ref(),
+    };
+    let output = denoise::denoise(&color, &features, &options);
+
+    println!("Writing Image...");
+    let path = Path::new(&files[1][..]);
+    let written = match Format::from_path(path) {
+        Some(Format::Exr(exr_options)) => {
+            // Keep the other layers of the input
+            let mut out_channels = exr::channels(&output, "");
+            for c in channels.iter() {
+                if c.name.contains(".") {
+                    out_channels.push(c.clone());
+                }
+            }
+            File::create(path).and_then(|f| {
+                let mut w = BufWriter::new(f);
+                try!(exr::write_channels(&mut w, width, height, &out_channels[..], exr_options));
+                w.flush()
+            })
+        }
+        _ => image::save(path, &output),
+    };
+    if let Err(e) = written {
+        fail(&format!("{}: {}", path.display(), e)[..]);
+    }
+}
+
+fn show(v: Vector) -> String {
+    format!("{} {} {}", v.x, v.y, v.z)
+}
+
+fn info(args: std::vec::IntoIter<String>) {
+    let files: Vec<String> = args.collect();
+    if files.len() != 1 {
+        usage();
+    }
+    let scene = load_scene(&files[0][..]);
+    let camera = &scene.camera;
+    let emitting = |e: &Vector| e.dot(e) > 0.0;
+
+    println!("image:       {}x{}, {} samples per pixel", scene.width, scene.height, scene.samples);
+    println!("integrator:  {}, max depth {}", scene.integrator, scene.max_depth);
+    println!("camera:      eye {}, direction {}, up {}", show(camera.eye.o), show(camera.eye.d), show(camera.up));
+    println!("projection:  {:?}", camera.projection);
+    println!("shutter:     {} to {}", camera.shutter_open, camera.shutter_close);
+    println!("spheres:     {} ({} emitting)", scene.spheres.len(),
+             scene.spheres.iter().filter(|s| emitting(&s.emission)).count());
+    println!("triangles:   {} ({} emitting)", scene.triangles.len(),
+             scene.triangles.iter().filter(|t| emitting(&t.emission)).count());
+    println!("background:  {}", show(scene.background));
+
+    // Centers and radii of everything in the scene
+    let mut extents: Vec<(Vector, f64)> = scene.spheres.iter().map(|s| (s.position, s.radius)).collect();
+    for t in scene.triangles.iter() {
+        extents.extend(t.vertices.iter().map(|v| (*v, 0.0)));
+    }
+    if extents.len() > 0 {
+        let mut lo = Vector::new(INFINITY, INFINITY, INFINITY);
+        let mut hi = Vector::new(NEG_INFINITY, NEG_INFINITY, NEG_INFINITY);
+        for &(p, r) in extents.iter() {
+            lo = Vector::new(lo.x.min(p.x - r), lo.y.min(p.y - r), lo.z.min(p.z - r));
+            hi = Vector::new(hi.x.max(p.x + r), hi.y.max(p.y + r), hi.z.max(p.z + r));
+        }
+        println!("bounds:      {} to {}", show(lo), show(hi));
+    }
+}
+
+fn convert(mut args: std::vec::IntoIter<String>) {
+    let mut output = Output::new();
+    let mut files: Vec<String> = Vec::new();
+    while let Some(arg) = args.next() {
+        if output.parse(&arg[..], &mut args) {
+            continue;
+        }
+        if arg.starts_with("-") {
+            fail(&format!("unknown option '{}'", arg)[..]);
+        }
+        files.push(arg);
+    }
+    match files.len() {
+        1 => {}
+        2 => output.path = files.pop().unwrap(),
+        _ => usage(),
+    }
+
+    let img = load_image(&files[0][..]);
+    let path = output.path();
+    if let Err(e) = image::save_as(&path, &img, output.format(&path), &output.tonemap) {
+        fail(&format!("{}: {}", path.display(), e)[..]);
+    }
+}
+
+fn worker(mut args: std::vec::IntoIter<String>) {
+    let settings: Settings = Default::default();
+    let mut threads = settings.threads;
+    let mut address = None;
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--threads" => threads = value(&mut args, &arg[..], "a number"),
+            _ if arg.starts_with("-") => fail(&format!("unknown option '{}'", arg)[..]),
+            _ => address = Some(arg),
+        }
+    }
+    let address = match address {
+        Some(address) => address,
+        None => usage(),
+    };
+
+    let address = &address[..];
+    let workers: Vec<_> = (0..threads).map(|k| {
+        thread::scoped(move || {
+            let mut connected = None;
+            while connected.is_none() {
+                match net::connect(address) {
+                    Ok(stream) => connected = Some(stream),
+                    Err(e) => {
+                        if k == 0 {
+                            println!("Waiting for the coordinator at {}: {}", address, e);
+                        }
+                        thread::sleep_ms(1000);
+                    }
+                }
+            }
+            match net::work(connected.as_mut().unwrap()) {
+                Ok(tiles) => println!("Connection {} rendered {} tiles", k, tiles),
+                Err(e) => println!("Connection {} failed: {}", k, e),
+            }
+        })
+    }).collect();
+    // Joins the threads
+    drop(workers);
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1).collect::<Vec<String>>().into_iter();
+    let command = match args.next() {
+        Some(command) => command,
+        None => usage(),
+    };
+    match &command[..] {
+        "render" => render(args),
+        "compare" => compare(args),
+        "denoise" => denoise(args),
+        "info" => info(args),
+        "convert" => convert(args),
+        "worker" => worker(args),
+        _ => usage(),
+    }
+}
@@ -1,33 +1,62 @@
+#![allow(unstable)]
+
 extern crate opencl;
+extern crate raytracer;
 
-use std::io::prelude::*;
-use std::fs::File;
+use std::path::Path;
 use opencl::hl::EventList;
-use std::num::Float;
 use opencl::array::*;
+use raytracer::image::{self, Image};
+use raytracer::scene::Scene;
+use raytracer::camera::Projection;
+use raytracer::material::Material;
 
-fn clamp(x: f32) -> f32
+fn main()
 {
-    if x < 0.0 { 
-        return 0.0;
-    }
-    if x > 1.0 {
-        return 1.0;
+    let mut path = "image.ppm".to_string();
+    let mut scene_path = "scenes/cornell_box.scene".to_string();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--scene" => scene_path = args.next().expect("--scene takes a scene file"),
+            _ => path = arg,
+        }
     }
 
-    x
-}
-
-fn to_int(x: f32) -> i64
-{
-    (clamp(x).powf(1.0f32 / 2.2f32) * 255.0 + 0.5) as i64
-}
-
-const HEIGHT: usize = 768;
-const WIDTH: usize = 1024;
+    let scene = match Scene::load(Path::new(&scene_path)) {
+        Ok(scene) => scene,
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let (width, height) = (scene.width, scene.height);
+    // The kernel path traces diffuse spheres seen by a static pinhole camera
+    let dist = match scene.camera.projection {
+        Projection::Perspective(d) => d,
+        _ => {
+            println!("The OpenCL renderer only supports perspective cameras with an image plane distance");
+            std::process::exit(1);
+        }
+    };
+    if scene.spheres.iter().any(|s| s.material != Material::Diffuse || s.texture.is_some()) {
+        println!("Warning: the OpenCL renderer draws all spheres diffuse and untextured");
+    }
+    if scene.triangles.len() > 0 {
+        println!("Warning: the OpenCL renderer leaves out triangles");
+    }
+    let mut spheres: Vec<f32> = Vec::new();
+    for s in scene.spheres.iter() {
+        spheres.push_all(&[s.radius as f32,
+                           s.position.x as f32, s.position.y as f32, s.position.z as f32,
+                           s.emission.x as f32, s.emission.y as f32, s.emission.z as f32,
+                           s.color.x as f32, s.color.y as f32, s.color.z as f32]);
+    }
+    let cam = &scene.camera;
+    let camera: Vec<f32> = vec![cam.eye.o.x as f32, cam.eye.o.y as f32, cam.eye.o.z as f32,
+                                cam.eye.d.x as f32, cam.eye.d.y as f32, cam.eye.d.z as f32,
+                                cam.up.x as f32, cam.up.y as f32, cam.up.z as f32, dist as f32];
 
-fn main()
-{
     let ker = include_str!("raytracer.ocl");
     println!("ker {}", ker);
 
@@ -46,33 +75,42 @@ fn main()
 
     let kernel = program.create_kernel("vector_add");
 
-    let arr_in_x = Array2D::new(768, 1024, |_, _| { 0.0f32 });
+    let arr_in_x = Array2D::new(height, width, |_, _| { 0.0f32 });
     let arr_x = ctx.create_buffer_from(&arr_in_x, opencl::cl::CL_MEM_READ_WRITE);
     kernel.set_arg(0, &arr_x);
 
-    let arr_in_y = Array2D::new(768, 1024, |_, _| { 0.0f32 });
+    let arr_in_y = Array2D::new(height, width, |_, _| { 0.0f32 });
     let arr_y = ctx.create_buffer_from(&arr_in_y, opencl::cl::CL_MEM_READ_WRITE);
     kernel.set_arg(1, &arr_y);
     
-    let arr_in_z = Array2D::new(768, 1024, |_, _| { 0.0f32 });
+    let arr_in_z = Array2D::new(height, width, |_, _| { 0.0f32 });
     let arr_z = ctx.create_buffer_from(&arr_in_z, opencl::cl::CL_MEM_READ_WRITE);
     kernel.set_arg(2, &arr_z);
 
-    queue.enqueue_async_kernel(&kernel, (HEIGHT, WIDTH), None, ()).wait();
+    let spheres_buf = ctx.create_buffer_from(&spheres[..], opencl::cl::CL_MEM_READ_ONLY);
+    kernel.set_arg(3, &spheres_buf);
+    kernel.set_arg(4, &(scene.spheres.len() as u32));
+    let camera_buf = ctx.create_buffer_from(&camera[..], opencl::cl::CL_MEM_READ_ONLY);
+    kernel.set_arg(5, &camera_buf);
+    kernel.set_arg(6, &(width as u32));
+    kernel.set_arg(7, &(height as u32));
+    kernel.set_arg(8, &(scene.samples as u32));
+
+    queue.enqueue_async_kernel(&kernel, (height, width), None, ()).wait();
    
     let vec_x: Array2D<(f32)> = queue.get(&arr_x, ());
     let vec_y: Array2D<(f32)> = queue.get(&arr_y, ());
     let vec_z: Array2D<(f32)> = queue.get(&arr_z, ());
 
     println!("\nWriting Image...");
-    let mut f = File::create("image.ppm").unwrap();
-    f.write_all( format!("P3\n{} {}\n{}\n", WIDTH, HEIGHT, 255).as_bytes() ).ok();
-    for i in 0..HEIGHT {
-        for j in 0..WIDTH {
+    let mut img = Image::new(width, height, 3);
+    for i in 0..height {
+        for j in 0..width {
             let x: f32 = vec_x.get(i, j);
             let y: f32 = vec_y.get(i, j);
             let z: f32 = vec_z.get(i, j);
-            f.write_all( format!("{} {} {} ", to_int(x), to_int(y), to_int(z)).as_bytes() ).ok();
+            img.set_rgb(j, i, x as f64, y as f64, z as f64);
         }
     }
+    image::save(Path::new(&path), &img).unwrap();
 }
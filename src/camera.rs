@@ -0,0 +1,80 @@
+use std::num::Float;
+use std::default::Default;
+
+use vector::{Vector, VectorOps};
+use ray::Ray;
+use motion::Motion;
+use rng::Rng;
+
+#[derive(Debug, Copy, Clone)]
+pub enum Projection {
+    Perspective(f64), // distance of the image plane, which spans [-1, 1]^2
+    Orthographic(f64), // half the extent of the view, rays run along the view direction
+    Fov(f64), // perspective with this field of view in degrees across the shorter image side and square pixels
+}
+
+impl Default for Projection {
+    fn default() -> Projection {
+        Projection::Perspective(1.0)
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Camera {
+    pub eye: Ray, // origin and direction of cam
+    // Field of view:
+    pub right: Vector, // right vector
+    pub up: Vector, // up vector
+    pub projection: Projection,
+    // Shutter interval, rays are spread uniformly over [open, close]:
+    pub shutter_open: f64,
+    pub shutter_close: f64,
+    pub motion: Motion, // moves the eye
+    pub pan: Motion, // offsets the viewing direction
+}
+
+impl Camera {
+    pub fn sample_time(&self, rng: &mut Rng) -> f64 {
+        let r = rng.next_f64();
+        self.shutter_open + r * (self.shutter_close - self.shutter_open)
+    }
+
+    /// Viewing direction at `time`.
+    pub fn forward(&self, time: f64) -> Vector {
+        (&self.eye.d + &self.pan.offset(time)).norm()
+    }
+
+    /// Ray through row `a` and column `b` of a `width` x `height` image.
+    pub fn get_ray(&self, a: usize, b: usize, width: usize, height: usize, time: f64) -> Ray {
+        let eye = &self.eye.o + &self.motion.offset(time);
+        let w = self.forward(time).smul(-1.0);
+        let u = self.up.cross(w).norm();
+        let v = w.cross(u);
+
+        // Half the extent of the image plane along the rows and the columns
+        let (extent_u, extent_v, d) = match self.projection {
+            Projection::Perspective(d) => (1.0, 1.0, d),
+            Projection::Orthographic(extent) => (extent, extent, 0.0),
+            Projection::Fov(fov) => {
+                let e = (fov.to_radians() / 2.0).tan() / (if width < height { width } else { height }) as f64;
+                (e * height as f64, e * width as f64, 1.0)
+            }
+        };
+        let u0 = -extent_u;
+        let v0 = -extent_v;
+        let u1 = extent_u;
+        let v1 = extent_v;
+
+        let across = u.smul(u1-u0);
+        let up = v.smul(v1-v0);
+        let an = (a as f64) / height as f64;
+        let bn = (b as f64) / width as f64;
+
+        let corner = &(&(&eye + &u.smul(u0)) + &v.smul(v0)) - &w.smul(d);
+        let target = &( &corner + &across.smul(an)) + &up.smul(bn);
+        match self.projection {
+            Projection::Orthographic(_) => Ray{o: target, d: w.smul(-1.0), time: time},
+            _ => Ray{o: eye, d: (&target-&eye).norm(), time: time},
+        }
+    }
+}
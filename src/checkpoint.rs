@@ -0,0 +1,329 @@
+//! Saving a render in progress so it can be continued later.
+//!
+//! A checkpoint holds the film, that is the sums of every pixel, the number
+//! of samples per pixel so far and the crop window, together with the seed,
+//! the kernel and a hash of the scene. The random numbers of a sample only
+//! depend on the seed, the pixel and the sample number (see `tile`), so that
+//! is the whole state of the render: continuing from a checkpoint gives the
+//! same image as a render that was never stopped. The hash covers
+//! everything in the scene but the sample count, which may be raised when
+//! resuming.
+//!
+//! The file starts with the magic `RTCHECK3`, then the scene hash, the seed,
+//! the kernel (0 for the megakernel, 1 for the wavefront), the samples per
+//! pixel and the window (x0, y0, width and height) as little endian u64,
+//! followed by the pixels row by row: the 17 values of the summed `Sample`,
+//! the sum of squares and the weight as little endian f64.
+
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
+use std::fs::{self, File};
+use std::path::Path;
+use std::mem;
+use std::sync::Arc;
+use std::num::wrapping::WrappingOps;
+use std::default::Default;
+
+use scene::Scene;
+use camera::Projection;
+use motion::Motion;
+use material::Material;
+use texture::Texture;
+use image::Image;
+use integrator::Integrator;
+use film::{Film, Pixel};
+use tile::{Settings, Kernel};
+use vector::Vector;
+
+static MAGIC: &'static [u8] = b"RTCHECK3";
+
+pub struct Checkpoint {
+    pub scene_hash: u64,
+    pub seed: u64,
+    pub kernel: Kernel,
+    pub film: Film,
+}
+
+/// 64 bit FNV-1a over the fields of a scene.
+struct Hasher {
+    hash: u64,
+    images: Vec<*const Image>, // hashed so far, later uses only hash the index
+}
+
+impl Hasher {
+    fn new() -> Hasher {
+        Hasher { hash: 0xcbf29ce484222325, images: Vec::new() }
+    }
+
+    fn u8(&mut self, v: u8) {
+        self.hash = (self.hash ^ v as u64).wrapping_mul(0x100000001b3);
+    }
+
+    fn u64(&mut self, v: u64) {
+        for i in 0..8 {
+            self.u8((v >> (8 * i)) as u8);
+        }
+    }
+
+    fn usize(&mut self, v: usize) {
+        self.u64(v as u64);
+    }
+
+    fn f64(&mut self, v: f64) {
+        let bits: u64 = unsafe { mem::transmute(v) };
+        self.u64(bits);
+    }
+
+    fn vector(&mut self, v: &Vector) {
+        self.f64(v.x);
+        self.f64(v.y);
+        self.f64(v.z);
+    }
+
+    fn motion(&mut self, m: &Motion) {
+        match *m {
+            Motion::Static => self.u8(0),
+            Motion::Linear(v) => {
+                self.u8(1);
+                self.vector(&v);
+            }
+            Motion::Keyframed(ref keys) => {
+                self.u8(2);
+                self.usize(keys.len());
+                for k in keys.iter() {
+                    self.f64(k.time);
+                    self.vector(&k.offset);
+                }
+            }
+        }
+    }
+
+    fn material(&mut self, m: &Material) {
+        match *m {
+            Material::Diffuse => self.u8(0),
+            Material::Glossy(exponent) => { self.u8(1); self.f64(exponent); }
+            Material::Mirror => self.u8(2),
+            Material::Dielectric(ior) => { self.u8(3); self.f64(ior); }
+        }
+    }
+
+    fn image(&mut self, img: &Arc<Image>) {
+        let ptr: *const Image = &**img;
+        match self.images.iter().position(|p| *p == ptr) {
+            Some(index) => self.usize(index),
+            None => {
+                let index = self.images.len();
+                self.usize(index);
+                self.images.push(ptr);
+                self.usize(img.width);
+                self.usize(img.height);
+                self.usize(img.channels);
+                for v in img.data.iter() {
+                    self.f64(*v);
+                }
+            }
+        }
+    }
+
+    fn texture(&mut self, t: &Option<Texture>) {
+        match *t {
+            None => self.u8(0),
+            Some(Texture::Constant(c)) => { self.u8(1); self.vector(&c); }
+            Some(Texture::Checker(a, b, size)) => {
+                self.u8(2);
+                self.vector(&a);
+                self.vector(&b);
+                self.f64(size);
+            }
+            Some(Texture::Image(ref img)) => { self.u8(3); self.image(img); }
+            Some(Texture::Mapped(ref img)) => { self.u8(4); self.image(img); }
+        }
+    }
+}
+
+/// Hash of everything in `scene` but the samples per pixel.
+pub fn scene_hash(scene: &Scene) -> u64 {
+    let mut h = Hasher::new();
+    let c = &scene.camera;
+    h.vector(&c.eye.o);
+    h.vector(&c.eye.d);
+    h.f64(c.eye.time);
+    h.vector(&c.right);
+    h.vector(&c.up);
+    match c.projection {
+        Projection::Perspective(d) => { h.u8(0); h.f64(d); }
+        Projection::Orthographic(s) => { h.u8(1); h.f64(s); }
+        Projection::Fov(fov) => { h.u8(2); h.f64(fov); }
+    }
+    h.f64(c.shutter_open);
+    h.f64(c.shutter_close);
+    h.motion(&c.motion);
+    h.motion(&c.pan);
+
+    h.usize(scene.spheres.len());
+    for s in scene.spheres.iter() {
+        h.f64(s.radius);
+        h.vector(&s.position);
+        h.vector(&s.emission);
+        h.vector(&s.color);
+        h.texture(&s.texture);
+        h.material(&s.material);
+        h.motion(&s.motion);
+    }
+    h.usize(scene.triangles.len());
+    for t in scene.triangles.iter() {
+        for v in t.vertices.iter() {
+            h.vector(v);
+        }
+        h.vector(&t.emission);
+        h.vector(&t.color);
+        h.material(&t.material);
+        h.texture(&t.texture);
+        for &(u, v) in t.uv.iter() {
+            h.f64(u);
+            h.f64(v);
+        }
+    }
+
+    h.usize(scene.width);
+    h.usize(scene.height);
+    h.u8(match scene.integrator {
+        Integrator::Flat => 0,
+        Integrator::Diffuse => 1,
+        Integrator::Path => 2,
+    });
+    h.usize(scene.max_depth);
+    h.vector(&scene.background);
+    h.vector(&scene.light);
+    h.hash
+}
+
+fn push_u64(out: &mut Vec<u8>, v: u64) {
+    for i in 0..8 {
+        out.push((v >> (8 * i)) as u8);
+    }
+}
+
+fn push_f64(out: &mut Vec<u8>, v: f64) {
+    let bits: u64 = unsafe { mem::transmute(v) };
+    push_u64(out, bits);
+}
+
+fn push_vector(out: &mut Vec<u8>, v: &Vector) {
+    push_f64(out, v.x);
+    push_f64(out, v.y);
+    push_f64(out, v.z);
+}
+
+/// Writes `film`, rendered with `settings`, to `path`. The file is written
+/// next to it first and then renamed, so an earlier checkpoint survives a
+/// crash while writing.
+pub fn save(path: &Path, film: &Film, scene_hash: u64, settings: &Settings) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    {
+        let mut w = BufWriter::new(try!(File::create(&tmp)));
+        let mut header = MAGIC.to_vec();
+        let kernel = match settings.kernel {
+            Kernel::Megakernel => 0,
+            Kernel::Wavefront => 1,
+        };
+        for v in [scene_hash, settings.seed, kernel, film.samples as u64,
+                  film.x0 as u64, film.y0 as u64, film.width as u64, film.height as u64].iter() {
+            push_u64(&mut header, *v);
+        }
+        try!(w.write_all(&header[..]));
+        let mut row = Vec::with_capacity(film.width * 19 * 8);
+        for y in 0..film.height {
+            row.clear();
+            for p in film.pixels[y * film.width..(y + 1) * film.width].iter() {
+                let s = &p.sum;
+                push_vector(&mut row, &s.direct);
+                push_vector(&mut row, &s.indirect);
+                push_vector(&mut row, &s.albedo);
+                push_vector(&mut row, &s.normal);
+                push_vector(&mut row, &s.position);
+                push_f64(&mut row, s.depth);
+                push_f64(&mut row, s.id);
+                push_f64(&mut row, p.sum_sq);
+                push_f64(&mut row, p.weight);
+            }
+            try!(w.write_all(&row[..]));
+        }
+        try!(w.flush());
+    }
+    fs::rename(&tmp, path)
+}
+
+fn invalid(desc: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, desc, None)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn u64(&mut self) -> io::Result<u64> {
+        if self.pos + 8 > self.data.len() {
+            return Err(invalid("truncated checkpoint"));
+        }
+        let mut v = 0;
+        for i in 0..8 {
+            v |= (self.data[self.pos + i] as u64) << (8 * i);
+        }
+        self.pos += 8;
+        Ok(v)
+    }
+
+    fn f64(&mut self) -> io::Result<f64> {
+        let bits = try!(self.u64());
+        Ok(unsafe { mem::transmute(bits) })
+    }
+
+    fn vector(&mut self) -> io::Result<Vector> {
+        Ok(Vector::new(try!(self.f64()), try!(self.f64()), try!(self.f64())))
+    }
+}
+
+/// Reads the checkpoint at `path`.
+pub fn load(path: &Path) -> io::Result<Checkpoint> {
+    let mut data = Vec::new();
+    try!(BufReader::new(try!(File::open(path))).read_to_end(&mut data));
+    if data.len() < MAGIC.len() || &data[..MAGIC.len()] != MAGIC {
+        return Err(invalid("not a checkpoint file"));
+    }
+    let mut r = Reader { data: &data[..], pos: MAGIC.len() };
+    let scene_hash = try!(r.u64());
+    let seed = try!(r.u64());
+    let kernel = match try!(r.u64()) {
+        0 => Kernel::Megakernel,
+        1 => Kernel::Wavefront,
+        _ => return Err(invalid("unknown kernel in checkpoint")),
+    };
+    let samples = try!(r.u64()) as usize;
+    let (x0, y0) = (try!(r.u64()) as usize, try!(r.u64()) as usize);
+    let (width, height) = (try!(r.u64()) as usize, try!(r.u64()) as usize);
+    let size = width.checked_mul(height).and_then(|n| n.checked_mul(19 * 8));
+    if size != Some(data.len() - r.pos) {
+        return Err(invalid("checkpoint size does not match its window"));
+    }
+
+    let mut film = Film::new(x0, y0, width, height);
+    film.samples = samples;
+    for p in film.pixels.iter_mut() {
+        let mut pixel: Pixel = Default::default();
+        pixel.sum.direct = try!(r.vector());
+        pixel.sum.indirect = try!(r.vector());
+        pixel.sum.albedo = try!(r.vector());
+        pixel.sum.normal = try!(r.vector());
+        pixel.sum.position = try!(r.vector());
+        pixel.sum.depth = try!(r.f64());
+        pixel.sum.id = try!(r.f64());
+        pixel.sum_sq = try!(r.f64());
+        pixel.weight = try!(r.f64());
+        *p = pixel;
+    }
+    Ok(Checkpoint { scene_hash: scene_hash, seed: seed, kernel: kernel, film: film })
+}
@@ -0,0 +1,374 @@
+//! Error metrics between a rendered image and a reference.
+//!
+//! MSE and relative MSE are computed on the linear values. PSNR, SSIM and
+//! FLIP look at what ends up on screen, so both images first go through the
+//! tone map and the sRGB transfer function. The FLIP metric follows
+//! Andersson et al. 2020 ("FLIP: A Difference Evaluator for Alternating
+//! Images"): contrast sensitivity filtering in YCxCz, a Hunt adjusted HyAB
+//! color distance and a penalty for differences in edges and points.
+
+use std::num::Float;
+use std::f64;
+use std::f64::consts::PI;
+use std::default::Default;
+
+use image::Image;
+use tonemap::{ToneMap, srgb_encode, srgb_decode};
+
+#[derive(Debug, Copy, Clone)]
+pub struct Options {
+    pub tonemap: ToneMap, // display mapping for PSNR, SSIM and FLIP
+    pub pixels_per_degree: f64, // viewing condition for FLIP
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        // A 0.7 m wide 4K monitor seen from 0.7 m
+        Options { tonemap: Default::default(), pixels_per_degree: 67.0 }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Metrics {
+    pub mse: f64,
+    pub rel_mse: f64, // squared error divided by the squared reference value
+    pub psnr: f64, // in dB, infinite for identical images
+    pub ssim: f64, // mean structural similarity, 1 for identical images
+    pub flip: f64, // mean FLIP error in [0, 1], 0 for identical images
+}
+
+/// The metrics are only defined for images of the same size.
+fn check_size(reference: &Image, test: &Image) -> Result<(), String> {
+    if reference.width != test.width || reference.height != test.height {
+        return Err(format!("images differ in size: {}x{} and {}x{}", reference.width, reference.height,
+                           test.width, test.height));
+    }
+    Ok(())
+}
+
+/// Mean squared error over all pixels and color channels.
+pub fn mse(reference: &Image, test: &Image) -> Result<f64, String> {
+    try!(check_size(reference, test));
+    let mut sum = 0.0;
+    for y in 0..reference.height {
+        for x in 0..reference.width {
+            let (r0, g0, b0) = reference.rgb(x, y);
+            let (r1, g1, b1) = test.rgb(x, y);
+            sum += (r1 - r0).powi(2) + (g1 - g0).powi(2) + (b1 - b0).powi(2);
+        }
+    }
+    Ok(sum / (3 * reference.width * reference.height) as f64)
+}
+
+/// Relative mean squared error, `(test - ref)^2 / (ref^2 + 0.01)` averaged
+/// over all pixels and color channels. The offset keeps dark pixels from
+/// dominating.
+pub fn rel_mse(reference: &Image, test: &Image) -> Result<f64, String> {
+    try!(check_size(reference, test));
+    let mut sum = 0.0;
+    for y in 0..reference.height {
+        for x in 0..reference.width {
+            let (r0, g0, b0) = reference.rgb(x, y);
+            let (r1, g1, b1) = test.rgb(x, y);
+            sum += (r1 - r0).powi(2) / (r0 * r0 + 0.01) + (g1 - g0).powi(2) / (g0 * g0 + 0.01) +
+                   (b1 - b0).powi(2) / (b0 * b0 + 0.01);
+        }
+    }
+    Ok(sum / (3 * reference.width * reference.height) as f64)
+}
+
+/// Tone maps and sRGB encodes `img` into an RGB image of values in [0, 1].
+fn display(img: &Image, tonemap: &ToneMap) -> Image {
+    let mut out = Image::new(img.width, img.height, 3);
+    for y in 0..img.height {
+        for x in 0..img.width {
+            let (r, g, b) = img.rgb(x, y);
+            out.set_rgb(x, y, srgb_encode(tonemap.map(r)), srgb_encode(tonemap.map(g)),
+                        srgb_encode(tonemap.map(b)));
+        }
+    }
+    out
+}
+
+/// Peak signal to noise ratio of the displayed images, with a peak of 1.
+pub fn psnr(reference: &Image, test: &Image, tonemap: &ToneMap) -> Result<f64, String> {
+    let error = try!(mse(&display(reference, tonemap), &display(test, tonemap)));
+    Ok(if error == 0.0 { f64::INFINITY } else { -10.0 * error.log10() })
+}
+
+/// Convolves the planar `v` with the separable kernel `k` (odd length),
+/// clamping at the borders.
+fn convolve(v: &[f64], width: usize, height: usize, kx: &[f64], ky: &[f64]) -> Vec<f64> {
+    let clamp = |i: isize, n: usize| if i < 0 { 0 } else if i >= n as isize { n - 1 } else { i as usize };
+    let (rx, ry) = ((kx.len() / 2) as isize, (ky.len() / 2) as isize);
+    let mut tmp = vec![0.0; v.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = 0.0;
+            for (i, w) in kx.iter().enumerate() {
+                sum += *w * v[y * width + clamp(x as isize + i as isize - rx, width)];
+            }
+            tmp[y * width + x] = sum;
+        }
+    }
+    let mut out = vec![0.0; v.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = 0.0;
+            for (i, w) in ky.iter().enumerate() {
+                sum += *w * tmp[clamp(y as isize + i as isize - ry, height) * width + x];
+            }
+            out[y * width + x] = sum;
+        }
+    }
+    out
+}
+
+fn gaussian(sigma: f64, radius: usize) -> Vec<f64> {
+    let mut k: Vec<f64> = (0..2 * radius + 1).map(|i| {
+        let x = i as f64 - radius as f64;
+        (-x * x / (2.0 * sigma * sigma)).exp()
+    }).collect();
+    let sum = k.iter().fold(0.0, |s, w| s + *w);
+    for w in k.iter_mut() {
+        *w /= sum;
+    }
+    k
+}
+
+fn luminance_plane(img: &Image) -> Vec<f64> {
+    let mut v = Vec::with_capacity(img.width * img.height);
+    for y in 0..img.height {
+        for x in 0..img.width {
+            v.push(img.luminance(x, y));
+        }
+    }
+    v
+}
+
+/// Mean structural similarity (Wang et al. 2004) of the displayed luminance,
+/// with the usual 11x11 Gaussian window of standard deviation 1.5.
+pub fn ssim(reference: &Image, test: &Image, tonemap: &ToneMap) -> Result<f64, String> {
+    try!(check_size(reference, test));
+    let (width, height) = (reference.width, reference.height);
+    let a = luminance_plane(&display(reference, tonemap));
+    let b = luminance_plane(&display(test, tonemap));
+    let k = gaussian(1.5, 5);
+    let blur = |v: &[f64]| convolve(v, width, height, &k[..], &k[..]);
+    let product = |p: &[f64], q: &[f64]| -> Vec<f64> { p.iter().zip(q.iter()).map(|(x, y)| *x * *y).collect() };
+
+    let mu_a = blur(&a[..]);
+    let mu_b = blur(&b[..]);
+    let aa = blur(&product(&a[..], &a[..])[..]);
+    let bb = blur(&product(&b[..], &b[..])[..]);
+    let ab = blur(&product(&a[..], &b[..])[..]);
+
+    let (c1, c2) = (0.01f64.powi(2), 0.03f64.powi(2));
+    let mut sum = 0.0;
+    for i in 0..a.len() {
+        let var_a = aa[i] - mu_a[i] * mu_a[i];
+        let var_b = bb[i] - mu_b[i] * mu_b[i];
+        let cov = ab[i] - mu_a[i] * mu_b[i];
+        sum += (2.0 * mu_a[i] * mu_b[i] + c1) * (2.0 * cov + c2) /
+               ((mu_a[i] * mu_a[i] + mu_b[i] * mu_b[i] + c1) * (var_a + var_b + c2));
+    }
+    Ok(sum / a.len() as f64)
+}
+
+// D65 white point
+static WHITE: [f64; 3] = [0.950428545, 1.0, 1.088900371];
+
+fn rgb_to_xyz(c: [f64; 3]) -> [f64; 3] {
+    [0.4124564 * c[0] + 0.3575761 * c[1] + 0.1804375 * c[2],
+     0.2126729 * c[0] + 0.7151522 * c[1] + 0.0721750 * c[2],
+     0.0193339 * c[0] + 0.1191920 * c[1] + 0.9503041 * c[2]]
+}
+
+fn xyz_to_rgb(c: [f64; 3]) -> [f64; 3] {
+    [3.2404542 * c[0] - 1.5371385 * c[1] - 0.4985314 * c[2],
+     -0.9692660 * c[0] + 1.8760108 * c[1] + 0.0415560 * c[2],
+     0.0556434 * c[0] - 0.2040259 * c[1] + 1.0572252 * c[2]]
+}
+
+fn xyz_to_ycxcz(c: [f64; 3]) -> [f64; 3] {
+    let (x, y, z) = (c[0] / WHITE[0], c[1] / WHITE[1], c[2] / WHITE[2]);
+    [116.0 * y - 16.0, 500.0 * (x - y), 200.0 * (y - z)]
+}
+
+fn ycxcz_to_xyz(c: [f64; 3]) -> [f64; 3] {
+    let y = (c[0] + 16.0) / 116.0;
+    [(c[1] / 500.0 + y) * WHITE[0], y * WHITE[1], (y - c[2] / 200.0) * WHITE[2]]
+}
+
+/// CIELAB with Hunt's adjustment of the chroma by the lightness.
+fn hunt_lab(c: [f64; 3]) -> [f64; 3] {
+    let f = |t: f64| {
+        let delta: f64 = 6.0 / 29.0;
+        if t > delta.powi(3) { t.cbrt() } else { t / (3.0 * delta * delta) + 4.0 / 29.0 }
+    };
+    let (x, y, z) = (f(c[0] / WHITE[0]), f(c[1] / WHITE[1]), f(c[2] / WHITE[2]));
+    let l = 116.0 * y - 16.0;
+    [l, 0.01 * l * 500.0 * (x - y), 0.01 * l * 200.0 * (y - z)]
+}
+
+fn hyab(a: [f64; 3], b: [f64; 3]) -> f64 {
+    (a[0] - b[0]).abs() + ((a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+/// Spatial filter of the contrast sensitivity function for one opponent
+/// channel, a sum of two Gaussians with amplitudes `a` and widths `b` given
+/// in degrees.
+fn csf_kernel(a: [f64; 2], b: [f64; 2], ppd: f64) -> Vec<f64> {
+    let widest = b[0].max(b[1]);
+    let radius = (3.0 * (widest / (2.0 * PI * PI)).sqrt() * ppd).ceil() as usize;
+    let mut k: Vec<f64> = (0..2 * radius + 1).map(|i| {
+        let x = (i as f64 - radius as f64) / ppd;
+        let g = |a: f64, b: f64| a * (PI / b).sqrt() * (-PI * PI * x * x / b).exp();
+        g(a[0], b[0]) + g(a[1], b[1])
+    }).collect();
+    let sum = k.iter().fold(0.0, |s, w| s + *w);
+    for w in k.iter_mut() {
+        *w /= sum;
+    }
+    k
+}
+
+/// First (`order` 1) or second (`order` 2) derivative of a Gaussian, scaled
+/// so that the positive weights sum to one and the negative ones to minus one.
+fn feature_kernel(order: usize, ppd: f64) -> Vec<f64> {
+    let sigma = 0.5 * 0.082 * ppd;
+    let radius = (3.0 * sigma).ceil() as usize;
+    let mut k: Vec<f64> = (0..2 * radius + 1).map(|i| {
+        let x = i as f64 - radius as f64;
+        let g = (-x * x / (2.0 * sigma * sigma)).exp();
+        if order == 1 { -x * g } else { (x * x / (sigma * sigma) - 1.0) * g }
+    }).collect();
+    if order == 2 {
+        let mean = k.iter().fold(0.0, |s, w| s + *w) / k.len() as f64;
+        for w in k.iter_mut() {
+            *w -= mean;
+        }
+    }
+    let positive = k.iter().fold(0.0, |s, w| if *w > 0.0 { s + *w } else { s });
+    let negative = k.iter().fold(0.0, |s, w| if *w < 0.0 { s - *w } else { s });
+    for w in k.iter_mut() {
+        *w /= if *w > 0.0 { positive } else { negative };
+    }
+    k
+}
+
+/// Per pixel FLIP error in [0, 1], stored row by row.
+pub fn flip_map(reference: &Image, test: &Image, options: &Options) -> Result<Vec<f64>, String> {
+    try!(check_size(reference, test));
+    let (width, height) = (reference.width, reference.height);
+    let ppd = options.pixels_per_degree;
+
+    // Opponent channels of both images, planar
+    let opponent = |img: &Image| -> Vec<Vec<f64>> {
+        let shown = display(img, &options.tonemap);
+        let mut planes = vec![Vec::with_capacity(width * height); 3];
+        for y in 0..height {
+            for x in 0..width {
+                let (r, g, b) = shown.rgb(x, y);
+                let c = xyz_to_ycxcz(rgb_to_xyz([srgb_decode(r), srgb_decode(g), srgb_decode(b)]));
+                for i in 0..3 {
+                    planes[i].push(c[i]);
+                }
+            }
+        }
+        planes
+    };
+    let planes = [opponent(reference), opponent(test)];
+
+    // Color pipeline: filter each channel with its CSF, back to linear RGB,
+    // clamp to the gamut and compare in Hunt adjusted L*a*b*
+    let kernels = [csf_kernel([1.0, 0.0], [0.0047, 1e-5], ppd),
+                   csf_kernel([1.0, 0.0], [0.0053, 1e-5], ppd),
+                   csf_kernel([34.1, 13.5], [0.04, 0.025], ppd)];
+    let mut filtered = Vec::new();
+    for p in planes.iter() {
+        let f: Vec<Vec<f64>> = (0..3).map(|i| {
+            convolve(&p[i][..], width, height, &kernels[i][..], &kernels[i][..])
+        }).collect();
+        filtered.push(f);
+    }
+    let lab = |f: &Vec<Vec<f64>>, i: usize| {
+        let rgb = xyz_to_rgb(ycxcz_to_xyz([f[0][i], f[1][i], f[2][i]]));
+        let clamped = [rgb[0].max(0.0).min(1.0), rgb[1].max(0.0).min(1.0), rgb[2].max(0.0).min(1.0)];
+        hunt_lab(rgb_to_xyz(clamped))
+    };
+
+    let (qc, pc, pt) = (0.7, 0.4, 0.95);
+    let cmax = hyab(hunt_lab(rgb_to_xyz([0.0, 1.0, 0.0])), hunt_lab(rgb_to_xyz([0.0, 0.0, 1.0]))).powf(qc);
+
+    // Feature pipeline on the normalized achromatic channel
+    let edge = feature_kernel(1, ppd);
+    let point = feature_kernel(2, ppd);
+    let smooth = gaussian(0.5 * 0.082 * ppd, edge.len() / 2);
+    let features = |p: &Vec<Vec<f64>>| -> (Vec<f64>, Vec<f64>) {
+        let y: Vec<f64> = p[0].iter().map(|l| (*l + 16.0) / 116.0).collect();
+        let magnitude = |k: &[f64]| -> Vec<f64> {
+            let gx = convolve(&y[..], width, height, k, &smooth[..]);
+            let gy = convolve(&y[..], width, height, &smooth[..], k);
+            gx.iter().zip(gy.iter()).map(|(a, b)| (a * a + b * b).sqrt()).collect()
+        };
+        (magnitude(&edge[..]), magnitude(&point[..]))
+    };
+    let (edges0, points0) = features(&planes[0]);
+    let (edges1, points1) = features(&planes[1]);
+
+    let mut out = Vec::with_capacity(width * height);
+    for i in 0..width * height {
+        let distance = hyab(lab(&filtered[0], i), lab(&filtered[1], i)).powf(qc);
+        let color = if distance < pc * cmax {
+            pt / (pc * cmax) * distance
+        } else {
+            pt + (distance - pc * cmax) / (cmax - pc * cmax) * (1.0 - pt)
+        };
+        let feature = ((edges1[i] - edges0[i]).abs().max((points1[i] - points0[i]).abs()) /
+                       2.0f64.sqrt()).sqrt();
+        out.push(color.min(1.0).powf(1.0 - feature));
+    }
+    Ok(out)
+}
+
+/// Mean FLIP error.
+pub fn flip(reference: &Image, test: &Image, options: &Options) -> Result<f64, String> {
+    let map = try!(flip_map(reference, test, options));
+    Ok(map.iter().fold(0.0, |s, e| s + *e) / map.len() as f64)
+}
+
+/// Computes all metrics at once.
+pub fn compare(reference: &Image, test: &Image, options: &Options) -> Result<Metrics, String> {
+    Ok(Metrics {
+        mse: try!(mse(reference, test)),
+        rel_mse: try!(rel_mse(reference, test)),
+        psnr: try!(psnr(reference, test, &options.tonemap)),
+        ssim: try!(ssim(reference, test, &options.tonemap)),
+        flip: try!(flip(reference, test, options)),
+    })
+}
+
+// Samples of the magma color map, in sRGB
+static MAGMA: [[f64; 3]; 9] = [
+    [0.001, 0.000, 0.014], [0.113, 0.065, 0.277], [0.316, 0.071, 0.485],
+    [0.512, 0.128, 0.504], [0.716, 0.215, 0.475], [0.904, 0.332, 0.395],
+    [0.986, 0.535, 0.382], [0.996, 0.757, 0.525], [0.987, 0.991, 0.750]];
+
+/// False color image of the per pixel FLIP error: black where the images
+/// agree, through purple and orange to pale yellow for the largest errors.
+/// The result holds linear values, ready for `image::save`.
+pub fn difference(reference: &Image, test: &Image, options: &Options) -> Result<Image, String> {
+    let map = try!(flip_map(reference, test, options));
+    let mut out = Image::new(reference.width, reference.height, 3);
+    for y in 0..reference.height {
+        for x in 0..reference.width {
+            let e = map[y * reference.width + x].max(0.0).min(1.0) * (MAGMA.len() - 1) as f64;
+            let i = (e.floor() as usize).min(MAGMA.len() - 2);
+            let t = e - i as f64;
+            let c = |k: usize| srgb_decode(MAGMA[i][k] * (1.0 - t) + MAGMA[i+1][k] * t);
+            out.set_rgb(x, y, c(0), c(1), c(2));
+        }
+    }
+    Ok(out)
+}
@@ -0,0 +1,179 @@
+//! Following the paths of a single pixel to see where its light comes from.
+//!
+//! `trace_pixel` replays the samples of one pixel exactly as the tiled
+//! megakernel draws them for the given seed, so a firefly in a render shows
+//! up again in the same sample, and records every vertex of each path. The records are written as JSON, and as an
+//! OBJ file with a polyline per path to look at the paths in a 3D viewer
+//! next to the scene.
+//!
+//! The vertices follow the path integrator: each adds the emission of its
+//! surface times the throughput up to it, then samples the BSDF for the next
+//! ray unless the path has reached the maximum depth. The flat and diffuse
+//! integrators record only the first hit.
+
+use std::io;
+use std::num::Float;
+use std::io::prelude::*;
+
+use vector::{Vector, VectorOps};
+use ray::Ray;
+use scene::Scene;
+use material::{Material, BsdfSample};
+use integrator::{self, Integrator};
+use rng::Rng;
+
+#[derive(Debug, Clone)]
+pub struct Vertex {
+    pub position: Vector,
+    pub normal: Vector, // facing the ray that arrived
+    pub object: usize,
+    pub material: Material,
+    pub emission: Vector,
+    pub bsdf: Option<BsdfSample>, // None where the path ends
+    pub throughput: Vector, // of the path up to this vertex
+    pub contribution: Vector, // throughput times emission
+}
+
+#[derive(Debug, Clone)]
+pub struct PathRecord {
+    pub sample: usize,
+    pub camera: Ray,
+    pub vertices: Vec<Vertex>,
+    pub escaped: Option<Ray>, // the last ray, if it left the scene
+    pub background: Vector, // contribution of the background it reached
+    pub radiance: Vector, // the estimate of the sample, the sum of all contributions
+}
+
+/// Follows one camera ray, drawing from `rng` like `integrator::get_sample`.
+fn trace(scene: &Scene, sample: usize, camera: Ray, rng: &mut Rng) -> PathRecord {
+    let zero = Vector::new(0.0, 0.0, 0.0);
+    let mut record = PathRecord {
+        sample: sample,
+        camera: camera,
+        vertices: Vec::new(),
+        escaped: None,
+        background: zero,
+        radiance: zero,
+    };
+    let mut ray = camera;
+    let mut throughput = Vector::new(1.0, 1.0, 1.0);
+    let mut t: f64 = 0.0;
+    let mut id: usize = 0;
+    loop {
+        if !scene.intersect(&ray, &mut t, &mut id) {
+            record.escaped = Some(ray);
+            record.background = &throughput * &scene.background;
+            record.radiance = &record.radiance + &record.background;
+            return record;
+        }
+        let depth = record.vertices.len();
+        let (x, nl, error) = integrator::hit_frame(scene, &ray, t, id);
+        let object = scene.object(id);
+        let mut vertex = Vertex {
+            position: x,
+            normal: nl,
+            object: id,
+            material: object.material(),
+            emission: object.emission(),
+            bsdf: None,
+            throughput: throughput,
+            contribution: &throughput * &object.emission(),
+        };
+        match scene.integrator {
+            Integrator::Flat => vertex.contribution = object.albedo(x, ray.time),
+            Integrator::Diffuse => vertex.contribution = object.albedo(x, ray.time).smul(nl.dot(&scene.light)),
+            Integrator::Path => {
+                if depth <= scene.max_depth {
+                    vertex.bsdf = integrator::sample_bsdf(scene, &ray, id, x, nl, rng);
+                }
+            }
+        }
+        record.radiance = &record.radiance + &vertex.contribution;
+        let bsdf = vertex.bsdf;
+        record.vertices.push(vertex);
+        match bsdf {
+            Some(s) => {
+                throughput = &throughput * &s.weight;
+                ray = Ray::spawn(x, error, nl, s.wi, ray.time);
+            }
+            None => return record,
+        }
+    }
+}
+
+/// The paths of samples `first..first + count` of column `x`, row `y` in a
+/// render with the given seed.
+pub fn trace_pixel(scene: &Scene, x: usize, y: usize, seed: u64, first: usize, count: usize) -> Vec<PathRecord> {
+    (first..first + count).map(|k| {
+        let mut rng = Rng::with_sample(seed, y, x, k);
+        let time = scene.camera.sample_time(&mut rng);
+        let ray = scene.camera.get_ray(y, x, scene.width, scene.height, time);
+        trace(scene, k, ray, &mut rng)
+    }).collect()
+}
+
+/// `v` as a JSON number, or as the string "NaN", "inf" or "-inf", which are
+/// just what one looks for in a firefly.
+fn json_number(v: f64) -> String {
+    if v.is_finite() { v.to_string() } else { format!("\"{}\"", v) }
+}
+
+fn json_vector(v: &Vector) -> String {
+    format!("[{}, {}, {}]", json_number(v.x), json_number(v.y), json_number(v.z))
+}
+
+/// Writes `paths` as a JSON array with an object per path.
+pub fn write_json(paths: &[PathRecord], w: &mut Write) -> io::Result<()> {
+    try!(write!(w, "["));
+    for (n, path) in paths.iter().enumerate() {
+        try!(write!(w, "{}\n  {{\n    \"sample\": {},\n    \"origin\": {},\n    \"direction\": {},\n    \
+                        \"radiance\": {},\n    \"vertices\": [",
+                    if n > 0 { "," } else { "" }, path.sample, json_vector(&path.camera.o),
+                    json_vector(&path.camera.d), json_vector(&path.radiance)));
+        for (m, v) in path.vertices.iter().enumerate() {
+            let bsdf = match v.bsdf {
+                Some(s) => format!("{{\"wi\": {}, \"weight\": {}, \"pdf\": {}, \"specular\": {}}}",
+                                   json_vector(&s.wi), json_vector(&s.weight), json_number(s.pdf), s.specular),
+                None => "null".to_string(),
+            };
+            try!(write!(w, "{}\n      {{\"position\": {}, \"normal\": {}, \"object\": {}, \"material\": \"{:?}\", \
+                            \"emission\": {}, \"bsdf\": {}, \"throughput\": {}, \"contribution\": {}}}",
+                        if m > 0 { "," } else { "" }, json_vector(&v.position), json_vector(&v.normal),
+                        v.object, v.material, json_vector(&v.emission), bsdf, json_vector(&v.throughput),
+                        json_vector(&v.contribution)));
+        }
+        try!(write!(w, "\n    ],\n    \"escaped\": {},\n    \"background\": {}\n  }}",
+                    path.escaped.is_some(), json_vector(&path.background)));
+    }
+    write!(w, "\n]\n")
+}
+
+/// Writes `paths` as OBJ polylines from the camera through every vertex.
+/// Paths that leave the scene end as far along their last ray as their
+/// longest segment.
+pub fn write_obj(paths: &[PathRecord], w: &mut Write) -> io::Result<()> {
+    let mut index = 1;
+    for path in paths.iter() {
+        let mut points = vec![path.camera.o];
+        points.extend(path.vertices.iter().map(|v| v.position));
+        if let Some(ray) = path.escaped {
+            let mut longest: f64 = 1.0;
+            for n in 1..points.len() {
+                let d = &points[n] - &points[n - 1];
+                longest = longest.max(d.dot(&d).sqrt());
+            }
+            points.push(&ray.o + &ray.d.smul(longest));
+        }
+        try!(write!(w, "o sample_{}\n", path.sample));
+        for p in points.iter() {
+            try!(write!(w, "v {} {} {}\n", p.x, p.y, p.z));
+        }
+        try!(write!(w, "l"));
+        for n in 0..points.len() {
+            try!(write!(w, " {}", index + n));
+        }
+        try!(write!(w, "\n"));
+        index += points.len();
+    }
+    Ok(())
+}
@@ -0,0 +1,199 @@
+//! Edge avoiding à-trous wavelet denoiser (Dammertz et al. 2010), with the
+//! variance guided color weights of SVGF (Schied et al. 2017).
+//!
+//! The filter runs a 5x5 B3 spline kernel several times with doubling step
+//! sizes. Each tap is weighted down where the auxiliary buffers (normal and
+//! depth) show an edge or where the color differs by more than the noise
+//! level estimated from the per-pixel variance. Colors are divided by the
+//! albedo before filtering so textures and material edges stay sharp.
+
+use std::num::Float;
+use std::default::Default;
+
+use image::Image;
+
+/// Optional guide buffers, all of the same size as the color image.
+pub struct Features<'a> {
+    pub albedo: Option<&'a Image>, // RGB
+    pub normal: Option<&'a Image>, // RGB, need not be normalized
+    pub depth: Option<&'a Image>, // gray
+    pub variance: Option<&'a Image>, // gray, variance of the pixel luminance estimate
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Options {
+    pub iterations: usize,
+    pub sigma_color: f64, // color difference tolerated, in standard deviations of the noise
+    pub sigma_normal: f64, // exponent of the normal similarity
+    pub sigma_depth: f64, // depth difference tolerated, relative to the local depth gradient
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options { iterations: 5, sigma_color: 4.0, sigma_normal: 128.0, sigma_depth: 1.0 }
+    }
+}
+
+static KERNEL: [f64; 3] = [3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+fn luminance(c: &[f64]) -> f64 {
+    0.2126 * c[0] + 0.7152 * c[1] + 0.0722 * c[2]
+}
+
+/// Blurs `v` with a 3x3 Gaussian, used to stabilize the variance estimate.
+fn blur3(v: &[f64], width: usize, height: usize) -> Vec<f64> {
+    let k = [0.25, 0.5, 0.25];
+    let mut out = vec![0.0; v.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let (mut sum, mut wsum) = (0.0, 0.0);
+            for dy in 0..3 {
+                for dx in 0..3 {
+                    let (qx, qy) = (x + dx, y + dy);
+                    if qx >= 1 && qy >= 1 && qx <= width && qy <= height {
+                        let w = k[dx] * k[dy];
+                        sum += w * v[(qy - 1) * width + qx - 1];
+                        wsum += w;
+                    }
+                }
+            }
+            out[y * width + x] = sum / wsum;
+        }
+    }
+    out
+}
+
+/// Estimates the luminance variance from the 3x3 neighbourhood, for inputs
+/// that come without a variance buffer.
+fn spatial_variance(lum: &[f64], width: usize, height: usize) -> Vec<f64> {
+    let mut out = vec![0.0; lum.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let (mut sum, mut sum_sq, mut n) = (0.0, 0.0, 0.0);
+            for qy in (if y > 0 { y - 1 } else { 0 })..(if y + 2 < height { y + 2 } else { height }) {
+                for qx in (if x > 0 { x - 1 } else { 0 })..(if x + 2 < width { x + 2 } else { width }) {
+                    let l = lum[qy * width + qx];
+                    sum += l;
+                    sum_sq += l * l;
+                    n += 1.0;
+                }
+            }
+            out[y * width + x] = (sum_sq / n - (sum / n) * (sum / n)).max(0.0);
+        }
+    }
+    out
+}
+
+pub fn denoise(color: &Image, features: &Features, options: &Options) -> Image {
+    let (width, height) = (color.width, color.height);
+    let n = width * height;
+
+    // Demodulate the albedo, pixels without albedo are filtered as they are
+    let mut albedo = vec![1.0; 3 * n];
+    if let Some(img) = features.albedo {
+        for i in 0..n {
+            let (r, g, b) = img.rgb(i % width, i / width);
+            for (c, &a) in [r, g, b].iter().enumerate() {
+                if a > 1e-3 {
+                    albedo[3 * i + c] = a;
+                }
+            }
+        }
+    }
+    let mut c: Vec<f64> = (0..3 * n).map(|i| {
+        let (r, g, b) = color.rgb((i / 3) % width, (i / 3) / width);
+        [r, g, b][i % 3] / albedo[i]
+    }).collect();
+
+    let lum: Vec<f64> = (0..n).map(|i| luminance(&c[3 * i..3 * i + 3])).collect();
+    let mut variance: Vec<f64> = match features.variance {
+        Some(img) => (0..n).map(|i| {
+            let a = luminance(&albedo[3 * i..3 * i + 3]);
+            img.luminance(i % width, i / width) / (a * a)
+        }).collect(),
+        None => spatial_variance(&lum[..], width, height),
+    };
+
+    let normal: Option<Vec<(f64, f64, f64)>> = features.normal.map(|img| {
+        (0..n).map(|i| {
+            let (x, y, z) = img.rgb(i % width, i / width);
+            let len = (x * x + y * y + z * z).sqrt();
+            if len > 0.0 { (x / len, y / len, z / len) } else { (0.0, 0.0, 0.0) }
+        }).collect()
+    });
+    let depth: Option<Vec<f64>> = features.depth.map(|img| {
+        (0..n).map(|i| img.luminance(i % width, i / width)).collect()
+    });
+    // Largest depth difference to a direct neighbour
+    let depth_gradient: Option<Vec<f64>> = depth.as_ref().map(|z| {
+        (0..n).map(|i| {
+            let (x, y) = (i % width, i / width);
+            let mut g: f64 = 0.0;
+            if x + 1 < width { g = g.max((z[i + 1] - z[i]).abs()); }
+            if x > 0 { g = g.max((z[i - 1] - z[i]).abs()); }
+            if y + 1 < height { g = g.max((z[i + width] - z[i]).abs()); }
+            if y > 0 { g = g.max((z[i - width] - z[i]).abs()); }
+            g
+        }).collect()
+    });
+
+    for iteration in 0..options.iterations {
+        let step = 1isize << iteration;
+        let blurred = blur3(&variance[..], width, height);
+        let mut next_c = vec![0.0; 3 * n];
+        let mut next_variance = vec![0.0; n];
+
+        for y in 0..height {
+            for x in 0..width {
+                let p = y * width + x;
+                let lp = luminance(&c[3 * p..3 * p + 3]);
+                let color_scale = options.sigma_color * blurred[p].sqrt() + 1e-10;
+                let (mut sum, mut wsum, mut vsum) = ([0.0; 3], 0.0, 0.0);
+
+                for dy in -2..3isize {
+                    for dx in -2..3isize {
+                        let qx = x as isize + dx * step;
+                        let qy = y as isize + dy * step;
+                        if qx < 0 || qy < 0 || qx >= width as isize || qy >= height as isize {
+                            continue;
+                        }
+                        let q = qy as usize * width + qx as usize;
+                        let mut w = KERNEL[dx.abs() as usize] * KERNEL[dy.abs() as usize];
+
+                        if q != p {
+                            let lq = luminance(&c[3 * q..3 * q + 3]);
+                            w *= (-(lp - lq).abs() / color_scale).exp();
+                            if let Some(ref nrm) = normal {
+                                let (a, b) = (nrm[p], nrm[q]);
+                                w *= (a.0 * b.0 + a.1 * b.1 + a.2 * b.2).max(0.0).powf(options.sigma_normal);
+                            }
+                            if let (Some(z), Some(g)) = (depth.as_ref(), depth_gradient.as_ref()) {
+                                let distance = (((dx * dx + dy * dy) * step * step) as f64).sqrt();
+                                w *= (-(z[p] - z[q]).abs() / (options.sigma_depth * g[p] * distance + 1e-10)).exp();
+                            }
+                        }
+
+                        for k in 0..3 {
+                            sum[k] += w * c[3 * q + k];
+                        }
+                        wsum += w;
+                        vsum += w * w * variance[q];
+                    }
+                }
+
+                for k in 0..3 {
+                    next_c[3 * p + k] = sum[k] / wsum;
+                }
+                next_variance[p] = vsum / (wsum * wsum);
+            }
+        }
+        c = next_c;
+        variance = next_variance;
+    }
+
+    let mut out = Image::new(width, height, 3);
+    for i in 0..3 * n {
+        out.data[i] = c[i] * albedo[i];
+    }
+    out
+}
@@ -0,0 +1,120 @@
+//! The pixels a render accumulates samples in.
+//!
+//! A film covers a rectangle of the image, all of it unless the render is
+//! cropped. Each pixel sums its samples and their squared luminance, so a
+//! render can go on adding samples in later passes; the image and the AOVs
+//! are the averages and the variance of the luminance estimate is derived
+//! from the squares.
+
+use std::num::Float;
+use std::default::Default;
+
+use integrator::{self, Sample};
+use image::Image;
+use aov::{Aov, AovBuffers};
+
+/// Sums over the samples of one pixel.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Pixel {
+    pub sum: Sample, // weighted samples, with the id of the first one
+    pub sum_sq: f64, // weighted squared luminance of the samples
+    pub weight: f64, // sample weights
+}
+
+impl Pixel {
+    pub fn add(&mut self, s: &Sample, weight: f64) {
+        let l = integrator::luminance(&s.radiance());
+        if self.weight == 0.0 {
+            // Averaged ids would name no object at the edges
+            self.sum.id = s.id;
+        }
+        self.sum.add_scaled(s, weight);
+        self.sum_sq += weight * l * l;
+        self.weight += weight;
+    }
+
+    /// The average sample and the variance of its luminance.
+    pub fn mean(&self) -> (Sample, f64) {
+        let mut mean: Sample = Default::default();
+        if self.weight == 0.0 {
+            return (mean, 0.0);
+        }
+        mean.add_scaled(&self.sum, 1.0 / self.weight);
+        mean.id = self.sum.id;
+        let l = integrator::luminance(&mean.radiance());
+        let variance = (self.sum_sq / self.weight - l * l).max(0.0) / self.weight;
+        (mean, variance)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Film {
+    // Columns x0..x0+width and rows y0..y0+height of the image
+    pub x0: usize,
+    pub y0: usize,
+    pub width: usize,
+    pub height: usize,
+    pub samples: usize, // per pixel so far
+    pub pixels: Vec<Pixel>,
+}
+
+impl Film {
+    pub fn new(x0: usize, y0: usize, width: usize, height: usize) -> Film {
+        let empty: Pixel = Default::default();
+        Film { x0: x0, y0: y0, width: width, height: height, samples: 0, pixels: vec![empty; width * height] }
+    }
+
+    /// The sums of image column `x` and row `y`.
+    pub fn pixel(&self, x: usize, y: usize) -> &Pixel {
+        &self.pixels[(y - self.y0) * self.width + (x - self.x0)]
+    }
+
+    pub fn pixel_mut(&mut self, x: usize, y: usize) -> &mut Pixel {
+        &mut self.pixels[(y - self.y0) * self.width + (x - self.x0)]
+    }
+
+    /// The average radiance, unclamped.
+    pub fn image(&self) -> Image {
+        let mut img = Image::new(self.width, self.height, 3);
+        for (index, p) in self.pixels.iter().enumerate() {
+            let color = p.mean().0.radiance();
+            img.set_rgb(index % self.width, index / self.width, color.x, color.y, color.z);
+        }
+        img
+    }
+
+    /// Estimate of the relative error of the image from the variance of the
+    /// pixels: the root of the relative MSE of their luminance, with 0.01
+    /// added to the squared luminance as in `compare::rel_mse`. Needs two
+    /// samples per pixel to mean anything.
+    pub fn relative_error(&self) -> f64 {
+        let mut sum = 0.0;
+        for p in self.pixels.iter() {
+            let (s, variance) = p.mean();
+            let l = integrator::luminance(&s.radiance());
+            sum += variance / (l * l + 0.01);
+        }
+        (sum / self.pixels.len() as f64).sqrt()
+    }
+
+    /// The selected AOVs, `materials` numbers the material of each object
+    /// as `Scene::material_ids` does.
+    pub fn aovs(&self, selected: &[Aov], materials: &[usize]) -> AovBuffers {
+        let mut aovs = AovBuffers::new(self.width, self.height, selected);
+        for (index, p) in self.pixels.iter().enumerate() {
+            let (x, y) = (index % self.width, index / self.width);
+            let (s, variance) = p.mean();
+            aovs.set(Aov::Albedo, x, y, (s.albedo.x, s.albedo.y, s.albedo.z));
+            aovs.set(Aov::Normal, x, y, (s.normal.x, s.normal.y, s.normal.z));
+            aovs.set(Aov::Depth, x, y, (s.depth, 0.0, 0.0));
+            aovs.set(Aov::Position, x, y, (s.position.x, s.position.y, s.position.z));
+            aovs.set(Aov::Id, x, y, (s.id, 0.0, 0.0));
+            let material = if s.id > 0.0 { materials[s.id as usize - 1] + 1 } else { 0 };
+            aovs.set(Aov::Material, x, y, (material as f64, 0.0, 0.0));
+            aovs.set(Aov::Direct, x, y, (s.direct.x, s.direct.y, s.direct.z));
+            aovs.set(Aov::Indirect, x, y, (s.indirect.x, s.indirect.y, s.indirect.z));
+            aovs.set(Aov::Variance, x, y, (variance, 0.0, 0.0));
+        }
+        aovs
+    }
+}
@@ -0,0 +1,44 @@
+//! Floating point error analysis helpers (see Pharr, Jakob and Humphreys,
+//! "Physically Based Rendering", 3rd edition, section 3.9).
+
+use std::f64;
+use std::num::Float;
+use std::mem;
+
+/// Unit roundoff of f64 arithmetic, half the machine epsilon.
+pub const MACHINE_EPSILON: f64 = f64::EPSILON * 0.5;
+
+/// Bound on the relative error accumulated by `n` floating point operations.
+pub fn gamma(n: usize) -> f64 {
+    let n = n as f64;
+    (n * MACHINE_EPSILON) / (1.0 - n * MACHINE_EPSILON)
+}
+
+fn to_bits(v: f64) -> u64 {
+    unsafe { mem::transmute(v) }
+}
+
+fn from_bits(b: u64) -> f64 {
+    unsafe { mem::transmute(b) }
+}
+
+/// Smallest representable value greater than `v`.
+pub fn next_float_up(v: f64) -> f64 {
+    if v.is_infinite() && v > 0.0 {
+        return v;
+    }
+    // Skip -0 so that stepping up from zero gives the smallest positive value
+    let v = if v == -0.0 { 0.0 } else { v };
+    let bits = to_bits(v);
+    from_bits(if v >= 0.0 { bits + 1 } else { bits - 1 })
+}
+
+/// Largest representable value less than `v`.
+pub fn next_float_down(v: f64) -> f64 {
+    if v.is_infinite() && v < 0.0 {
+        return v;
+    }
+    let v = if v == 0.0 { -0.0 } else { v };
+    let bits = to_bits(v);
+    from_bits(if v > 0.0 { bits - 1 } else { bits + 1 })
+}
@@ -0,0 +1,370 @@
+//! zlib (RFC 1950/1951) compression and decompression and the CRC-32
+//! checksum, as used by PNG and OpenEXR.
+//!
+//! The compressor emits a single block with the fixed Huffman code and finds
+//! matches with hash chains over the 32k window. That is a lot simpler than a
+//! dynamic Huffman encoder and still gets most of the gain on rendered images.
+
+use std::io;
+use std::cmp::min;
+
+use super::invalid;
+
+const WINDOW: usize = 32768;
+const HASH_SIZE: usize = 1 << 15;
+const MAX_CHAIN: usize = 64;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+
+static LENGTH_BASE: [u32; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+                                 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+static LENGTH_EXTRA: [u32; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+                                  3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+static DIST_BASE: [u32; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+                               257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+                               8193, 12289, 16385, 24577];
+static DIST_EXTRA: [u32; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+                                7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+/// Collects bits least significant first, as deflate expects.
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u32,
+    nbits: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, bits: u32, n: u32) {
+        self.acc |= bits << self.nbits;
+        self.nbits += n;
+        while self.nbits >= 8 {
+            self.out.push((self.acc & 0xff) as u8);
+            self.acc >>= 8;
+            self.nbits -= 8;
+        }
+    }
+
+    /// Huffman codes are packed starting with their most significant bit.
+    fn write_code(&mut self, code: u32, len: u32) {
+        let mut reversed = 0;
+        for i in 0..len {
+            reversed |= ((code >> i) & 1) << (len - 1 - i);
+        }
+        self.write(reversed, len);
+    }
+
+    fn flush(&mut self) {
+        if self.nbits > 0 {
+            self.out.push((self.acc & 0xff) as u8);
+        }
+        self.acc = 0;
+        self.nbits = 0;
+    }
+
+    fn write_literal(&mut self, lit: u32) {
+        match lit {
+            0...143 => self.write_code(0x30 + lit, 8),
+            144...255 => self.write_code(0x190 + lit - 144, 9),
+            256...279 => self.write_code(lit - 256, 7),
+            _ => self.write_code(0xc0 + lit - 280, 8),
+        }
+    }
+
+    fn write_match(&mut self, len: usize, dist: usize) {
+        let (len, dist) = (len as u32, dist as u32);
+        let l = LENGTH_BASE.iter().rposition(|&b| b <= len).unwrap();
+        self.write_literal(257 + l as u32);
+        self.write(len - LENGTH_BASE[l], LENGTH_EXTRA[l]);
+        let d = DIST_BASE.iter().rposition(|&b| b <= dist).unwrap();
+        self.write_code(d as u32, 5);
+        self.write(dist - DIST_BASE[d], DIST_EXTRA[d]);
+    }
+}
+
+fn hash(data: &[u8], i: usize) -> usize {
+    (((data[i] as usize) << 10) ^ ((data[i+1] as usize) << 5) ^ (data[i+2] as usize)) & (HASH_SIZE - 1)
+}
+
+/// Compresses `data` into a zlib stream.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut w = BitWriter { out: Vec::with_capacity(data.len() / 2 + 64), acc: 0, nbits: 0 };
+    // CMF: deflate with a 32k window, FLG: no dictionary, fastest level
+    w.out.push(0x78);
+    w.out.push(0x01);
+    // One final block using the fixed code
+    w.write(1, 1);
+    w.write(1, 2);
+
+    // head[h] and prev[i % WINDOW] hold position + 1 so that 0 ends a chain
+    let mut head = vec![0usize; HASH_SIZE];
+    let mut prev = vec![0usize; WINDOW];
+    let n = data.len();
+    let mut i = 0;
+    while i < n {
+        let mut best_len = 0;
+        let mut best_dist = 0;
+        if i + MIN_MATCH <= n {
+            let max = min(MAX_MATCH, n - i);
+            let mut candidate = head[hash(data, i)];
+            let mut chain = 0;
+            while candidate > 0 && chain < MAX_CHAIN {
+                let j = candidate - 1;
+                if j >= i || i - j > WINDOW {
+                    break;
+                }
+                let mut l = 0;
+                while l < max && data[j + l] == data[i + l] {
+                    l += 1;
+                }
+                if l > best_len {
+                    best_len = l;
+                    best_dist = i - j;
+                    if l == max {
+                        break;
+                    }
+                }
+                candidate = prev[j % WINDOW];
+                chain += 1;
+            }
+        }
+
+        let step = if best_len >= MIN_MATCH {
+            w.write_match(best_len, best_dist);
+            best_len
+        } else {
+            w.write_literal(data[i] as u32);
+            1
+        };
+        for p in i..i + step {
+            if p + MIN_MATCH <= n {
+                let h = hash(data, p);
+                prev[p % WINDOW] = head[h];
+                head[h] = p + 1;
+            }
+        }
+        i += step;
+    }
+
+    w.write_literal(256);
+    w.flush();
+
+    let adler = adler32(data);
+    for shift in [24, 16, 8, 0].iter() {
+        w.out.push((adler >> *shift) as u8);
+    }
+    w.out
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 is the largest block for which b cannot overflow before the modulo
+    for chunk in data.chunks(5552) {
+        for &byte in chunk.iter() {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+/// Running CRC-32 (ISO 3309, as used by PNG).
+pub struct Crc32 {
+    table: [u32; 256],
+    crc: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Crc32 {
+        let mut table = [0u32; 256];
+        for n in 0..256 {
+            let mut c = n as u32;
+            for _ in 0..8 {
+                c = if c & 1 == 1 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+            }
+            table[n] = c;
+        }
+        Crc32 { table: table, crc: 0xffffffff }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data.iter() {
+            self.crc = self.table[((self.crc ^ byte as u32) & 0xff) as usize] ^ (self.crc >> 8);
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        self.crc ^ 0xffffffff
+    }
+}
+
+/// Reads bits least significant first.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize, // in bits
+}
+
+impl<'a> BitReader<'a> {
+    fn bits(&mut self, n: u32) -> io::Result<u32> {
+        let mut v = 0;
+        for i in 0..n {
+            let byte = self.pos >> 3;
+            if byte >= self.data.len() {
+                return Err(invalid("truncated deflate stream"));
+            }
+            v |= (((self.data[byte] >> (self.pos & 7)) & 1) as u32) << i;
+            self.pos += 1;
+        }
+        Ok(v)
+    }
+}
+
+/// Canonical Huffman decoding table: the number of codes of each length and
+/// the symbols ordered by code.
+struct Huffman {
+    count: [u16; 16],
+    symbol: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut count = [0u16; 16];
+        for &l in lengths.iter() {
+            count[l as usize] += 1;
+        }
+        count[0] = 0;
+        let mut offset = [0u16; 16];
+        for len in 1..15 {
+            offset[len + 1] = offset[len] + count[len];
+        }
+        let mut symbol = vec![0u16; lengths.len()];
+        for (s, &l) in lengths.iter().enumerate() {
+            if l != 0 {
+                symbol[offset[l as usize] as usize] = s as u16;
+                offset[l as usize] += 1;
+            }
+        }
+        Huffman { count: count, symbol: symbol }
+    }
+
+    fn decode(&self, r: &mut BitReader) -> io::Result<u32> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= try!(r.bits(1)) as i32;
+            let count = self.count[len] as i32;
+            if code - first < count {
+                return Ok(self.symbol[(index + code - first) as usize] as u32);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("invalid Huffman code in deflate stream"))
+    }
+}
+
+static CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+fn inflate_block(r: &mut BitReader, out: &mut Vec<u8>, lit: &Huffman, dist: &Huffman) -> io::Result<()> {
+    loop {
+        let sym = try!(lit.decode(r));
+        if sym < 256 {
+            out.push(sym as u8);
+        } else if sym == 256 {
+            return Ok(());
+        } else {
+            let l = sym as usize - 257;
+            if l >= 29 {
+                return Err(invalid("invalid length symbol in deflate stream"));
+            }
+            let len = (LENGTH_BASE[l] + try!(r.bits(LENGTH_EXTRA[l]))) as usize;
+            let d = try!(dist.decode(r)) as usize;
+            if d >= 30 {
+                return Err(invalid("invalid distance symbol in deflate stream"));
+            }
+            let distance = (DIST_BASE[d] + try!(r.bits(DIST_EXTRA[d]))) as usize;
+            if distance > out.len() {
+                return Err(invalid("distance too far back in deflate stream"));
+            }
+            let start = out.len() - distance;
+            for i in 0..len {
+                let b = out[start + i];
+                out.push(b);
+            }
+        }
+    }
+}
+
+/// Decompresses a zlib stream.
+pub fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    if data.len() < 2 || data[0] & 0x0f != 8 || ((data[0] as u32) << 8 | data[1] as u32) % 31 != 0 ||
+       data[1] & 0x20 != 0 {
+        return Err(invalid("invalid zlib header"));
+    }
+    let mut r = BitReader { data: &data[2..], pos: 0 };
+    let mut out = Vec::new();
+    loop {
+        let last = try!(r.bits(1));
+        match try!(r.bits(2)) {
+            0 => {
+                // Stored block, aligned to the next byte
+                let start = (r.pos + 7) >> 3;
+                if start + 4 > r.data.len() {
+                    return Err(invalid("truncated deflate stream"));
+                }
+                let len = r.data[start] as usize | (r.data[start + 1] as usize) << 8;
+                if start + 4 + len > r.data.len() {
+                    return Err(invalid("truncated deflate stream"));
+                }
+                out.push_all(&r.data[start + 4..start + 4 + len]);
+                r.pos = (start + 4 + len) << 3;
+            }
+            1 => {
+                let mut lengths = [0u8; 288];
+                for i in 0..288 {
+                    lengths[i] = match i { 0...143 => 8, 144...255 => 9, 256...279 => 7, _ => 8 };
+                }
+                let lit = Huffman::new(&lengths);
+                let dist = Huffman::new(&[5u8; 30]);
+                try!(inflate_block(&mut r, &mut out, &lit, &dist));
+            }
+            2 => {
+                let nlen = try!(r.bits(5)) as usize + 257;
+                let ndist = try!(r.bits(5)) as usize + 1;
+                let ncode = try!(r.bits(4)) as usize + 4;
+                let mut code_lengths = [0u8; 19];
+                for i in 0..ncode {
+                    code_lengths[CODE_LENGTH_ORDER[i]] = try!(r.bits(3)) as u8;
+                }
+                let code = Huffman::new(&code_lengths);
+                let mut lengths = Vec::with_capacity(nlen + ndist);
+                while lengths.len() < nlen + ndist {
+                    let sym = try!(code.decode(&mut r));
+                    let (value, repeat) = match sym {
+                        0...15 => (sym as u8, 1),
+                        16 => match lengths.last() {
+                            Some(&prev) => (prev, 3 + try!(r.bits(2))),
+                            None => return Err(invalid("invalid code lengths in deflate stream")),
+                        },
+                        17 => (0, 3 + try!(r.bits(3))),
+                        _ => (0, 11 + try!(r.bits(7))),
+                    };
+                    for _ in 0..repeat {
+                        lengths.push(value);
+                    }
+                }
+                if lengths.len() > nlen + ndist {
+                    return Err(invalid("invalid code lengths in deflate stream"));
+                }
+                let lit = Huffman::new(&lengths[..nlen]);
+                let dist = Huffman::new(&lengths[nlen..]);
+                try!(inflate_block(&mut r, &mut out, &lit, &dist));
+            }
+            _ => return Err(invalid("invalid deflate block type")),
+        }
+        if last == 1 {
+            return Ok(out);
+        }
+    }
+}
@@ -0,0 +1,576 @@
+//! OpenEXR input and output: single part scanline images with half or float
+//! channels, stored uncompressed or with ZIP or PIZ compression.
+
+use std::io;
+use std::io::prelude::*;
+use std::mem;
+use std::str::FromStr;
+
+use super::{Image, invalid};
+use super::deflate;
+use super::piz::{self, ChannelLayout};
+
+/// Most channel values `read` accepts, 1 GiB of floats, so that a corrupt
+/// data window cannot make it allocate any amount of memory.
+const MAX_VALUES: usize = 1 << 28;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PixelType {
+    Half,
+    Float,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Compression {
+    None,
+    Zip, // zlib, blocks of 16 scanlines
+    Piz, // wavelet + Huffman, blocks of 32 scanlines
+}
+
+impl FromStr for PixelType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<PixelType, String> {
+        match s {
+            "half" => Ok(PixelType::Half),
+            "float" => Ok(PixelType::Float),
+            _ => Err(format!("unknown EXR pixel type '{}'", s)),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Compression, String> {
+        match s {
+            "none" => Ok(Compression::None),
+            "zip" => Ok(Compression::Zip),
+            "piz" => Ok(Compression::Piz),
+            _ => Err(format!("unknown EXR compression '{}'", s)),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Options {
+    pub pixel_type: PixelType,
+    pub compression: Compression,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options { pixel_type: PixelType::Half, compression: Compression::Zip }
+    }
+}
+
+/// One named channel of `width * height` values, stored row by row.
+#[derive(Debug, Clone)]
+pub struct Channel {
+    pub name: String,
+    pub data: Vec<f32>,
+}
+
+/// Splits `img` into channels R, G, B (or Y for gray images), each name
+/// prefixed with `layer` and a dot unless `layer` is empty.
+pub fn channels(img: &Image, layer: &str) -> Vec<Channel> {
+    let names: &[&str] = if img.channels == 1 { &["Y"] } else { &["R", "G", "B"] };
+    names.iter().enumerate().map(|(c, name)| {
+        Channel {
+            name: if layer.len() == 0 { name.to_string() } else { format!("{}.{}", layer, name) },
+            data: (0..img.width * img.height).map(|i| img.data[i * img.channels + c] as f32).collect(),
+        }
+    }).collect()
+}
+
+pub fn write<W: Write>(w: &mut W, img: &Image, options: Options) -> io::Result<()> {
+    write_channels(w, img.width, img.height, &channels(img, "")[..], options)
+}
+
+/// Converts to half precision, rounding to nearest even.
+pub fn f32_to_half(f: f32) -> u16 {
+    let x: u32 = unsafe { mem::transmute(f) };
+    let sign = ((x >> 16) & 0x8000) as u16;
+    let exp = ((x >> 23) & 0xff) as i32;
+    let mant = x & 0x7fffff;
+
+    if exp == 255 {
+        // Infinity stays infinity, NaN stays NaN
+        return sign | 0x7c00 | if mant != 0 { 0x200 } else { 0 };
+    }
+    let e = exp - 127 + 15;
+    if e >= 31 {
+        return sign | 0x7c00;
+    }
+    if e <= 0 {
+        // Denormalized half, or zero if too small
+        if e < -10 {
+            return sign;
+        }
+        let m = mant | 0x800000;
+        let shift = (14 - e) as u32;
+        let mut h = m >> shift;
+        let rest = m & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        if rest > halfway || (rest == halfway && h & 1 == 1) {
+            h += 1;
+        }
+        return sign | h as u16;
+    }
+    let mut h = ((e as u32) << 10) | (mant >> 13);
+    let rest = mant & 0x1fff;
+    // A carry out of the mantissa correctly bumps the exponent
+    if rest > 0x1000 || (rest == 0x1000 && h & 1 == 1) {
+        h += 1;
+    }
+    sign | h as u16
+}
+
+fn push_i32(out: &mut Vec<u8>, v: i32) {
+    for shift in [0, 8, 16, 24].iter() {
+        out.push((v >> *shift) as u8);
+    }
+}
+
+fn push_u64(out: &mut Vec<u8>, v: u64) {
+    for i in 0..8 {
+        out.push((v >> (8 * i)) as u8);
+    }
+}
+
+fn push_f32(out: &mut Vec<u8>, v: f32) {
+    let bits: u32 = unsafe { mem::transmute(v) };
+    push_i32(out, bits as i32);
+}
+
+fn attribute(out: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    out.push_all(name.as_bytes());
+    out.push(0);
+    out.push_all(kind.as_bytes());
+    out.push(0);
+    push_i32(out, value.len() as i32);
+    out.push_all(value);
+}
+
+/// Writes the given channels as one EXR image. The channels may come in any
+/// order and must all hold `width * height` values.
+pub fn write_channels<W: Write>(w: &mut W, width: usize, height: usize, channels: &[Channel],
+                                options: Options) -> io::Result<()> {
+    // The file stores channels sorted by name
+    let mut sorted: Vec<&Channel> = channels.iter().collect();
+    sorted.sort_by(|a, b| a.name.cmp(&b.name));
+    for c in sorted.iter() {
+        assert!(c.data.len() == width * height);
+    }
+
+    let (type_id, sample_bytes) = match options.pixel_type {
+        PixelType::Half => (1, 2),
+        PixelType::Float => (2, 4),
+    };
+    let (compression_id, block_lines) = match options.compression {
+        Compression::None => (0, 1),
+        Compression::Zip => (3, 16),
+        Compression::Piz => (4, 32),
+    };
+
+    let mut header = vec![0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0];
+    let mut chlist = Vec::new();
+    for c in sorted.iter() {
+        chlist.push_all(c.name.as_bytes());
+        chlist.push(0);
+        push_i32(&mut chlist, type_id);
+        chlist.push_all(&[0, 0, 0, 0]); // pLinear and reserved
+        push_i32(&mut chlist, 1); // x sampling
+        push_i32(&mut chlist, 1); // y sampling
+    }
+    chlist.push(0);
+    attribute(&mut header, "channels", "chlist", &chlist[..]);
+    attribute(&mut header, "compression", "compression", &[compression_id]);
+    let mut window = Vec::new();
+    for &v in [0, 0, width as i32 - 1, height as i32 - 1].iter() {
+        push_i32(&mut window, v);
+    }
+    attribute(&mut header, "dataWindow", "box2i", &window[..]);
+    attribute(&mut header, "displayWindow", "box2i", &window[..]);
+    attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    let mut value = Vec::new();
+    push_f32(&mut value, 1.0);
+    attribute(&mut header, "pixelAspectRatio", "float", &value[..]);
+    value.clear();
+    push_f32(&mut value, 0.0);
+    push_f32(&mut value, 0.0);
+    attribute(&mut header, "screenWindowCenter", "v2f", &value[..]);
+    value.clear();
+    push_f32(&mut value, 1.0);
+    attribute(&mut header, "screenWindowWidth", "float", &value[..]);
+    header.push(0);
+
+    let mut chunks = Vec::new();
+    let mut y0 = 0;
+    while y0 < height {
+        let y1 = if y0 + block_lines < height { y0 + block_lines } else { height };
+
+        // Scanline by scanline, each holding all values of one channel
+        // after the other
+        let mut raw = Vec::with_capacity((y1 - y0) * width * sorted.len() * sample_bytes);
+        for y in y0..y1 {
+            for c in sorted.iter() {
+                for &v in c.data[y * width..(y + 1) * width].iter() {
+                    match options.pixel_type {
+                        PixelType::Half => {
+                            let h = f32_to_half(v);
+                            raw.push(h as u8);
+                            raw.push((h >> 8) as u8);
+                        }
+                        PixelType::Float => push_f32(&mut raw, v),
+                    }
+                }
+            }
+        }
+
+        let packed = match options.compression {
+            Compression::None => raw.clone(),
+            Compression::Zip => zip_compress(&raw[..]),
+            Compression::Piz => {
+                let layout: Vec<ChannelLayout> = sorted.iter().map(|_| {
+                    ChannelLayout { nx: width, ny: y1 - y0, size: sample_bytes / 2 }
+                }).collect();
+                let mut words = planar_words(&raw[..], width, y1 - y0, sorted.len(), sample_bytes / 2);
+                piz::compress(&mut words[..], &layout[..])
+            }
+        };
+        // Blocks that do not shrink are stored as they are
+        chunks.push((y0, if packed.len() < raw.len() { packed } else { raw }));
+        y0 = y1;
+    }
+
+    let mut offset = (header.len() + 8 * chunks.len()) as u64;
+    for &(_, ref data) in chunks.iter() {
+        push_u64(&mut header, offset);
+        offset += 8 + data.len() as u64;
+    }
+    try!(w.write_all(&header[..]));
+
+    for &(y, ref data) in chunks.iter() {
+        let mut prefix = Vec::with_capacity(8);
+        push_i32(&mut prefix, y as i32);
+        push_i32(&mut prefix, data.len() as i32);
+        try!(w.write_all(&prefix[..]));
+        try!(w.write_all(&data[..]));
+    }
+    Ok(())
+}
+
+/// Reorders a block from line interleaved bytes into 16 bit words grouped by
+/// channel, the layout PIZ works on.
+fn planar_words(raw: &[u8], width: usize, lines: usize, channels: usize, size: usize) -> Vec<u16> {
+    let line_words = width * size;
+    let mut words = Vec::with_capacity(raw.len() / 2);
+    for c in 0..channels {
+        for y in 0..lines {
+            let start = (y * channels + c) * line_words * 2;
+            for i in 0..line_words {
+                let k = start + 2 * i;
+                words.push(raw[k] as u16 | ((raw[k + 1] as u16) << 8));
+            }
+        }
+    }
+    words
+}
+
+/// ZIP compression: bytes split into even and odd positions, delta encoded
+/// and deflated.
+fn zip_compress(raw: &[u8]) -> Vec<u8> {
+    let n = raw.len();
+    let mut tmp = vec![0u8; n];
+    let half = (n + 1) / 2;
+    for i in 0..n {
+        if i % 2 == 0 {
+            tmp[i / 2] = raw[i];
+        } else {
+            tmp[half + i / 2] = raw[i];
+        }
+    }
+    let mut p = if n > 0 { tmp[0] as i32 } else { 0 };
+    for i in 1..n {
+        let d = tmp[i] as i32 - p + (128 + 256);
+        p = tmp[i] as i32;
+        tmp[i] = d as u8;
+    }
+    deflate::compress(&tmp[..])
+}
+
+/// Converts from half precision.
+pub fn half_to_f32(h: u16) -> f32 {
+    let sign = ((h & 0x8000) as u32) << 16;
+    let exp = ((h >> 10) & 0x1f) as u32;
+    let mant = (h & 0x3ff) as u32;
+    let bits = if exp == 0 {
+        if mant == 0 {
+            sign
+        } else {
+            // Denormalized, renormalize the mantissa
+            let mut e = 127 - 15 + 1;
+            let mut m = mant;
+            while m & 0x400 == 0 {
+                m <<= 1;
+                e -= 1;
+            }
+            sign | (e << 23) | ((m & 0x3ff) << 13)
+        }
+    } else if exp == 31 {
+        sign | 0x7f800000 | (mant << 13)
+    } else {
+        sign | ((exp + 127 - 15) << 23) | (mant << 13)
+    };
+    unsafe { mem::transmute(bits) }
+}
+
+struct Header {
+    channels: Vec<(String, i32)>, // name and pixel type
+    compression: u8,
+    data_window: [i32; 4],
+}
+
+fn read_i32(data: &[u8], pos: usize) -> io::Result<i32> {
+    if data.len() < 4 || pos > data.len() - 4 {
+        return Err(invalid("truncated EXR file"));
+    }
+    Ok((data[pos] as u32 | (data[pos + 1] as u32) << 8 | (data[pos + 2] as u32) << 16 |
+        (data[pos + 3] as u32) << 24) as i32)
+}
+
+/// Reads a null terminated string starting at `*pos`.
+fn read_name(data: &[u8], pos: &mut usize) -> io::Result<String> {
+    let start = *pos;
+    while *pos < data.len() && data[*pos] != 0 {
+        *pos += 1;
+    }
+    if *pos >= data.len() {
+        return Err(invalid("truncated EXR header"));
+    }
+    *pos += 1;
+    String::from_utf8(data[start..*pos - 1].to_vec()).map_err(|_| invalid("invalid EXR attribute name"))
+}
+
+fn read_header(data: &[u8], pos: &mut usize) -> io::Result<Header> {
+    if data.len() < 8 || &data[..4] != &[0x76u8, 0x2f, 0x31, 0x01][..] {
+        return Err(invalid("not an OpenEXR file"));
+    }
+    // Tiled, deep and multi-part files are not supported
+    if data[5] & (0x02 | 0x08 | 0x10) != 0 {
+        return Err(invalid("only single part scanline EXR files are supported"));
+    }
+    *pos = 8;
+    let mut header = Header { channels: Vec::new(), compression: 255, data_window: [0; 4] };
+    loop {
+        let name = try!(read_name(data, pos));
+        if name.len() == 0 {
+            break;
+        }
+        let kind = try!(read_name(data, pos));
+        let size = try!(read_i32(data, *pos));
+        *pos += 4;
+        if size < 0 || size as usize > data.len() - *pos {
+            return Err(invalid("truncated EXR header"));
+        }
+        let size = size as usize;
+        let value = &data[*pos..*pos + size];
+        *pos += size;
+
+        match (&name[..], &kind[..]) {
+            ("channels", "chlist") => {
+                let mut p = 0;
+                while p < value.len() && value[p] != 0 {
+                    let channel = try!(read_name(value, &mut p));
+                    let pixel_type = try!(read_i32(value, p));
+                    if try!(read_i32(value, p + 8)) != 1 || try!(read_i32(value, p + 12)) != 1 {
+                        return Err(invalid("subsampled EXR channels are not supported"));
+                    }
+                    header.channels.push((channel, pixel_type));
+                    p += 16;
+                }
+            }
+            ("compression", "compression") if size == 1 => header.compression = value[0],
+            ("dataWindow", "box2i") => {
+                for i in 0..4 {
+                    header.data_window[i] = try!(read_i32(value, 4 * i));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(header)
+}
+
+/// Reads a single part scanline EXR file with uncompressed, RLE, ZIP or PIZ
+/// data. Channels of any pixel type come back as floats, in file order.
+pub fn read<R: Read>(r: &mut R) -> io::Result<(usize, usize, Vec<Channel>)> {
+    let mut data = Vec::new();
+    try!(r.read_to_end(&mut data));
+    let mut pos = 0;
+    let header = try!(read_header(&data[..], &mut pos));
+
+    let window = header.data_window;
+    let (x0, y0, x1, y1) = (window[0], window[1], window[2], window[3]);
+    if x1 < x0 || y1 < y0 {
+        return Err(invalid("empty EXR data window"));
+    }
+    let (width, height) = ((x1 as i64 - x0 as i64 + 1) as usize, (y1 as i64 - y0 as i64 + 1) as usize);
+    let block_lines = match header.compression {
+        0 | 1 | 2 => 1,
+        3 => 16,
+        4 => 32,
+        _ => return Err(invalid("unsupported EXR compression")),
+    };
+    let mut sizes = Vec::new();
+    for &(_, pixel_type) in header.channels.iter() {
+        sizes.push(match pixel_type {
+            1 => 2,
+            0 | 2 => 4,
+            _ => return Err(invalid("invalid EXR pixel type")),
+        });
+    }
+    let values = width.checked_mul(height).and_then(|n| n.checked_mul(sizes.len()));
+    if values.map_or(true, |n| n > MAX_VALUES) {
+        return Err(invalid("EXR image too large"));
+    }
+    let line_bytes = sizes.iter().fold(0, |sum, s| sum + s * width);
+    let blocks = (height + block_lines - 1) / block_lines;
+    if blocks > (data.len() - pos) / 8 {
+        return Err(invalid("truncated EXR offset table"));
+    }
+
+    let mut channels: Vec<Channel> = header.channels.iter().map(|&(ref name, _)| {
+        Channel { name: name.clone(), data: vec![0.0; width * height] }
+    }).collect();
+
+    for b in 0..blocks {
+        let offset_pos = pos + 8 * b;
+        let offset = ((try!(read_i32(&data[..], offset_pos)) as u32 as u64) |
+                      ((try!(read_i32(&data[..], offset_pos + 4)) as u32 as u64) << 32)) as usize;
+        let y = try!(read_i32(&data[..], offset)) as i64 - y0 as i64;
+        let size = try!(read_i32(&data[..], offset + 4));
+        if y < 0 || y as usize >= height || size < 0 || size as usize > data.len() - (offset + 8) {
+            return Err(invalid("invalid EXR block"));
+        }
+        let (y, size) = (y as usize, size as usize);
+        let lines = if y + block_lines < height { block_lines } else { height - y };
+        let expected = lines * line_bytes;
+        let packed = &data[offset + 8..offset + 8 + size];
+
+        let raw = if size == expected {
+            packed.to_vec()
+        } else {
+            match header.compression {
+                1 => unpredict(try!(rle_decompress(packed))),
+                2 | 3 => unpredict(try!(deflate::decompress(packed))),
+                4 => {
+                    let layout: Vec<ChannelLayout> = sizes.iter().map(|&s| {
+                        ChannelLayout { nx: width, ny: lines, size: s / 2 }
+                    }).collect();
+                    let words = try!(piz::decompress(packed, &layout[..], expected / 2));
+                    interleaved_bytes(&words[..], width, lines, &sizes[..])
+                }
+                _ => return Err(invalid("invalid EXR block")),
+            }
+        };
+        if raw.len() != expected {
+            return Err(invalid("EXR block does not match its size"));
+        }
+
+        let mut p = 0;
+        for line in y..y + lines {
+            for (c, &(_, pixel_type)) in header.channels.iter().enumerate() {
+                for x in 0..width {
+                    let v = match pixel_type {
+                        1 => half_to_f32(raw[p] as u16 | (raw[p + 1] as u16) << 8),
+                        2 => unsafe { mem::transmute(try!(read_i32(&raw[..], p))) },
+                        _ => try!(read_i32(&raw[..], p)) as u32 as f32,
+                    };
+                    channels[c].data[line * width + x] = v;
+                    p += sizes[c];
+                }
+            }
+        }
+    }
+    Ok((width, height, channels))
+}
+
+/// Inverse of the planar word layout used by PIZ.
+fn interleaved_bytes(words: &[u16], width: usize, lines: usize, sizes: &[usize]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(words.len() * 2);
+    for y in 0..lines {
+        let mut start = 0;
+        for &s in sizes.iter() {
+            let line_words = width * s / 2;
+            for &w in words[start + y * line_words..start + (y + 1) * line_words].iter() {
+                out.push(w as u8);
+                out.push((w >> 8) as u8);
+            }
+            start += lines * line_words;
+        }
+    }
+    out
+}
+
+fn rle_decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let count = data[i] as i8 as i32;
+        i += 1;
+        if count < 0 {
+            let n = (-count) as usize;
+            if i + n > data.len() {
+                return Err(invalid("truncated EXR RLE data"));
+            }
+            out.push_all(&data[i..i + n]);
+            i += n;
+        } else {
+            if i >= data.len() {
+                return Err(invalid("truncated EXR RLE data"));
+            }
+            for _ in 0..count + 1 {
+                out.push(data[i]);
+            }
+            i += 1;
+        }
+    }
+    Ok(out)
+}
+
+/// Undoes the delta encoding and byte split of RLE and ZIP compression.
+fn unpredict(mut tmp: Vec<u8>) -> Vec<u8> {
+    let n = tmp.len();
+    for i in 1..n {
+        tmp[i] = ((tmp[i - 1] as i32 + tmp[i] as i32 - 128) & 0xff) as u8;
+    }
+    let half = (n + 1) / 2;
+    let mut raw = vec![0u8; n];
+    for i in 0..n {
+        raw[i] = if i % 2 == 0 { tmp[i / 2] } else { tmp[half + i / 2] };
+    }
+    raw
+}
+
+/// Finds the channels `<layer>.R`, `.G`, `.B` (or `.Y`) and turns them into an
+/// image, the empty layer name selects the plain R, G, B (or Y) channels.
+pub fn layer(width: usize, height: usize, channels: &[Channel], layer: &str) -> Option<Image> {
+    let find = |suffix: &str| {
+        let name = if layer.len() == 0 { suffix.to_string() } else { format!("{}.{}", layer, suffix) };
+        channels.iter().find(|c| c.name == name)
+    };
+    let planes: Vec<&Channel> = match (find("R"), find("G"), find("B"), find("Y")) {
+        (Some(r), Some(g), Some(b), _) => vec![r, g, b],
+        (_, _, _, Some(y)) => vec![y],
+        _ => return None,
+    };
+    let mut img = Image::new(width, height, planes.len());
+    for i in 0..width * height {
+        for (c, plane) in planes.iter().enumerate() {
+            img.data[i * planes.len() + c] = plane.data[i] as f64;
+        }
+    }
+    Some(img)
+}
@@ -0,0 +1,103 @@
+//! Radiance RGBE (.hdr) output with the run length encoded scanlines of
+//! newer Radiance versions.
+
+use std::io;
+use std::io::prelude::*;
+use std::num::Float;
+
+use super::Image;
+
+const MIN_RUN: usize = 4;
+
+/// Shared exponent encoding of a linear RGB triple.
+pub fn to_rgbe(r: f64, g: f64, b: f64) -> [u8; 4] {
+    let (r, g, b) = (r.max(0.0), g.max(0.0), b.max(0.0));
+    let v = r.max(g).max(b);
+    if v < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+    // v = m * 2^e with m in [0.5, 1)
+    let mut e = v.log2().floor() as i32 + 1;
+    let mut m = v / 2.0f64.powi(e);
+    if m >= 1.0 {
+        m /= 2.0;
+        e += 1;
+    }
+    if m < 0.5 {
+        m *= 2.0;
+        e -= 1;
+    }
+    let scale = m * 256.0 / v;
+    [(r * scale) as u8, (g * scale) as u8, (b * scale) as u8, (e + 128) as u8]
+}
+
+/// Run length encodes one component of a scanline.
+fn write_rle<W: Write>(w: &mut W, data: &[u8]) -> io::Result<()> {
+    let n = data.len();
+    let mut cur = 0;
+    while cur < n {
+        // Find the next run of at least MIN_RUN bytes
+        let mut begin = cur;
+        let mut run = 0;
+        let mut old_run = 0;
+        while run < MIN_RUN && begin < n {
+            begin += run;
+            old_run = run;
+            run = 1;
+            while begin + run < n && run < 127 && data[begin] == data[begin + run] {
+                run += 1;
+            }
+        }
+
+        // A short run right before the long one is still worth a run code
+        if old_run > 1 && old_run == begin - cur {
+            try!(w.write_all(&[(128 + old_run) as u8, data[cur]]));
+            cur = begin;
+        }
+
+        while cur < begin {
+            let count = if begin - cur > 128 { 128 } else { begin - cur };
+            try!(w.write_all(&[count as u8]));
+            try!(w.write_all(&data[cur..cur + count]));
+            cur += count;
+        }
+
+        if run >= MIN_RUN {
+            try!(w.write_all(&[(128 + run) as u8, data[begin]]));
+            cur += run;
+        }
+    }
+    Ok(())
+}
+
+pub fn write<W: Write>(w: &mut W, img: &Image) -> io::Result<()> {
+    try!(w.write_all(b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n"));
+    try!(w.write_all(format!("-Y {} +X {}\n", img.height, img.width).as_bytes()));
+
+    // Widths outside this range can not be run length encoded
+    let rle = img.width >= 8 && img.width < 0x8000;
+    let mut components: Vec<Vec<u8>> = (0..4).map(|_| Vec::with_capacity(img.width)).collect();
+    for y in 0..img.height {
+        for c in components.iter_mut() {
+            c.clear();
+        }
+        for x in 0..img.width {
+            let (r, g, b) = img.rgb(x, y);
+            let rgbe = to_rgbe(r, g, b);
+            if rle {
+                for c in 0..4 {
+                    components[c].push(rgbe[c]);
+                }
+            } else {
+                try!(w.write_all(&rgbe));
+            }
+        }
+        if rle {
+            try!(w.write_all(&[2, 2, (img.width >> 8) as u8, (img.width & 0xff) as u8]));
+            for c in components.iter() {
+                try!(write_rle(w, &c[..]));
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,195 @@
+//! Writing rendered images to disk and reading them back.
+//!
+//! Renderers produce linear floating point radiance. An `Image` holds those
+//! values and `save` encodes them in the format implied by the file
+//! extension, writing through a buffered file. The integer formats go through
+//! a `ToneMap`, Radiance HDR and OpenEXR keep the linear values. `load` reads
+//! PPM, PGM, PNG and OpenEXR files back into linear values.
+
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
+use std::fs::File;
+use std::path::Path;
+use std::ascii::AsciiExt;
+use std::default::Default;
+use std::str::FromStr;
+
+use tonemap::ToneMap;
+
+mod deflate;
+mod piz;
+pub mod ppm;
+pub mod png;
+pub mod hdr;
+pub mod exr;
+
+/// A width x height image of linear values with one (gray) or three (RGB)
+/// interleaved channels per pixel, stored row by row.
+#[derive(Debug, Clone)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub channels: usize,
+    pub data: Vec<f64>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize, channels: usize) -> Image {
+        assert!(channels == 1 || channels == 3);
+        Image { width: width, height: height, channels: channels, data: vec![0.0; width * height * channels] }
+    }
+
+    pub fn get(&self, x: usize, y: usize, c: usize) -> f64 {
+        self.data[(y * self.width + x) * self.channels + c]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, c: usize, v: f64) {
+        self.data[(y * self.width + x) * self.channels + c] = v;
+    }
+
+    pub fn set_rgb(&mut self, x: usize, y: usize, r: f64, g: f64, b: f64) {
+        assert!(self.channels == 3);
+        let i = (y * self.width + x) * 3;
+        self.data[i] = r;
+        self.data[i+1] = g;
+        self.data[i+2] = b;
+    }
+
+    /// Returns the pixel as RGB, gray images are replicated.
+    pub fn rgb(&self, x: usize, y: usize) -> (f64, f64, f64) {
+        let i = (y * self.width + x) * self.channels;
+        if self.channels == 1 {
+            (self.data[i], self.data[i], self.data[i])
+        } else {
+            (self.data[i], self.data[i+1], self.data[i+2])
+        }
+    }
+
+    /// Returns the pixel as a single gray value (Rec. 709 luminance for RGB).
+    pub fn luminance(&self, x: usize, y: usize) -> f64 {
+        let (r, g, b) = self.rgb(x, y);
+        if self.channels == 1 { r } else { 0.2126 * r + 0.7152 * g + 0.0722 * b }
+    }
+}
+
+/// Bits per sample of integer formats.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Depth {
+    Eight,
+    Sixteen,
+}
+
+impl FromStr for Depth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Depth, String> {
+        match s {
+            "8" => Ok(Depth::Eight),
+            "16" => Ok(Depth::Sixteen),
+            _ => Err(format!("unsupported bit depth '{}'", s)),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Format {
+    Ppm,   // binary P6, 8 bit
+    Ppm16, // binary P6, 16 bit
+    Pgm,   // binary P5, 8 bit gray
+    Pgm16, // binary P5, 16 bit gray
+    Png,   // 8 bit
+    Png16, // 16 bit
+    Hdr,   // Radiance RGBE
+    Exr(exr::Options),
+}
+
+impl Format {
+    /// Picks the format from the extension of `path`.
+    pub fn from_path(path: &Path) -> Option<Format> {
+        let ext = match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) => ext.to_ascii_lowercase(),
+            None => return None,
+        };
+        match &ext[..] {
+            "ppm" => Some(Format::Ppm),
+            "pgm" => Some(Format::Pgm),
+            "png" => Some(Format::Png),
+            "hdr" => Some(Format::Hdr),
+            "exr" => Some(Format::Exr(Default::default())),
+            _ => None
+        }
+    }
+
+    /// The same integer format with `depth` bits per sample, None for the
+    /// floating point formats.
+    pub fn with_depth(self, depth: Depth) -> Option<Format> {
+        match (self, depth) {
+            (Format::Ppm, Depth::Sixteen) | (Format::Ppm16, Depth::Sixteen) => Some(Format::Ppm16),
+            (Format::Ppm, Depth::Eight) | (Format::Ppm16, Depth::Eight) => Some(Format::Ppm),
+            (Format::Pgm, Depth::Sixteen) | (Format::Pgm16, Depth::Sixteen) => Some(Format::Pgm16),
+            (Format::Pgm, Depth::Eight) | (Format::Pgm16, Depth::Eight) => Some(Format::Pgm),
+            (Format::Png, Depth::Sixteen) | (Format::Png16, Depth::Sixteen) => Some(Format::Png16),
+            (Format::Png, Depth::Eight) | (Format::Png16, Depth::Eight) => Some(Format::Png),
+            _ => None,
+        }
+    }
+}
+
+/// Error for malformed input files.
+fn invalid(desc: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, desc, None)
+}
+
+/// Writes `img` to `path` with the default tone map, the format is chosen by
+/// the file extension.
+pub fn save(path: &Path, img: &Image) -> io::Result<()> {
+    save_tonemapped(path, img, &Default::default())
+}
+
+/// Like `save` but with the given tone map for integer formats.
+pub fn save_tonemapped(path: &Path, img: &Image, tonemap: &ToneMap) -> io::Result<()> {
+    match Format::from_path(path) {
+        Some(format) => save_as(path, img, format, tonemap),
+        None => Err(io::Error::new(io::ErrorKind::InvalidInput, "unsupported image format",
+                                   Some(format!("{}", path.display()))))
+    }
+}
+
+/// Writes `img` to `path` in the given format.
+pub fn save_as(path: &Path, img: &Image, format: Format, tonemap: &ToneMap) -> io::Result<()> {
+    let mut w = BufWriter::new(try!(File::create(path)));
+    try!(write(&mut w, img, format, tonemap));
+    w.flush()
+}
+
+/// Encodes `img` into `w`, `tonemap` is ignored by the floating point formats.
+pub fn write<W: Write>(w: &mut W, img: &Image, format: Format, tonemap: &ToneMap) -> io::Result<()> {
+    match format {
+        Format::Ppm => ppm::write(w, img, Depth::Eight, false, tonemap),
+        Format::Ppm16 => ppm::write(w, img, Depth::Sixteen, false, tonemap),
+        Format::Pgm => ppm::write(w, img, Depth::Eight, true, tonemap),
+        Format::Pgm16 => ppm::write(w, img, Depth::Sixteen, true, tonemap),
+        Format::Png => png::write(w, img, Depth::Eight, tonemap),
+        Format::Png16 => png::write(w, img, Depth::Sixteen, tonemap),
+        Format::Hdr => hdr::write(w, img),
+        Format::Exr(options) => exr::write(w, img, options),
+    }
+}
+
+/// Reads the image at `path`, the format is chosen by the file extension.
+/// Integer formats are sRGB decoded, EXR files yield their R, G, B (or Y)
+/// channels.
+pub fn load(path: &Path) -> io::Result<Image> {
+    let mut r = BufReader::new(try!(File::open(path)));
+    match Format::from_path(path) {
+        Some(Format::Ppm) | Some(Format::Pgm) => ppm::read(&mut r),
+        Some(Format::Png) => png::read(&mut r),
+        Some(Format::Exr(_)) => {
+            let (width, height, channels) = try!(exr::read(&mut r));
+            exr::layer(width, height, &channels[..], "").ok_or(invalid("EXR file has no R, G, B or Y channels"))
+        }
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "unsupported image format",
+                                Some(format!("{}", path.display()))))
+    }
+}
@@ -0,0 +1,600 @@
+//! OpenEXR PIZ compression: a lossless Haar wavelet transform followed by
+//! Huffman coding of the 16 bit words of a block of scanlines.
+//!
+//! The bitstream follows ImfPizCompressor.cpp, ImfWav.cpp and ImfHuf.cpp of
+//! the OpenEXR library.
+
+use std::io;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use super::invalid;
+
+const USHORT_RANGE: usize = 1 << 16;
+const BITMAP_SIZE: usize = USHORT_RANGE >> 3;
+
+const HUF_ENCSIZE: usize = (1 << 16) + 1;
+const SHORT_ZEROCODE_RUN: u64 = 59;
+const LONG_ZEROCODE_RUN: u64 = 63;
+const SHORTEST_LONG_RUN: usize = 2 + LONG_ZEROCODE_RUN as usize - SHORT_ZEROCODE_RUN as usize;
+const LONGEST_LONG_RUN: usize = 255 + SHORTEST_LONG_RUN;
+
+/// Location of one channel inside the word buffer of a block. `size` is the
+/// number of 16 bit words per sample (1 for half, 2 for float).
+pub struct ChannelLayout {
+    pub nx: usize,
+    pub ny: usize,
+    pub size: usize,
+}
+
+/// Compresses `words`, which holds the channels one after another, each
+/// channel line by line, as described by `layout`.
+pub fn compress(words: &mut [u16], layout: &[ChannelLayout]) -> Vec<u8> {
+    let mut bitmap = vec![0u8; BITMAP_SIZE];
+    for &w in words.iter() {
+        bitmap[(w >> 3) as usize] |= 1 << (w & 7);
+    }
+    // Zero is always representable and never stored
+    bitmap[0] &= !1;
+    let (min_non_zero, max_non_zero) = match bitmap.iter().position(|&b| b != 0) {
+        Some(first) => (first, bitmap.iter().rposition(|&b| b != 0).unwrap()),
+        None => (BITMAP_SIZE - 1, 0),
+    };
+
+    // Map the used values onto 0..max_value
+    let mut lut = vec![0u16; USHORT_RANGE];
+    let mut k = 0;
+    for i in 0..USHORT_RANGE {
+        if i == 0 || bitmap[i >> 3] & (1 << (i & 7)) != 0 {
+            lut[i] = k as u16;
+            k += 1;
+        }
+    }
+    let max_value = (k - 1) as u16;
+    for w in words.iter_mut() {
+        *w = lut[*w as usize];
+    }
+
+    let mut out = Vec::new();
+    push_u16(&mut out, min_non_zero as u16);
+    push_u16(&mut out, max_non_zero as u16);
+    if min_non_zero <= max_non_zero {
+        out.push_all(&bitmap[min_non_zero..max_non_zero + 1]);
+    }
+
+    let mut start = 0;
+    for c in layout.iter() {
+        for j in 0..c.size {
+            wav2_encode(words, start + j, c.nx, c.size, c.ny, c.nx * c.size, max_value);
+        }
+        start += c.nx * c.ny * c.size;
+    }
+
+    let huf = huf_compress(words);
+    push_u32(&mut out, huf.len() as u32);
+    out.push_all(&huf[..]);
+    out
+}
+
+fn push_u16(out: &mut Vec<u8>, v: u16) {
+    out.push(v as u8);
+    out.push((v >> 8) as u8);
+}
+
+fn push_u32(out: &mut Vec<u8>, v: u32) {
+    for shift in [0, 8, 16, 24].iter() {
+        out.push((v >> *shift) as u8);
+    }
+}
+
+// Wavelet basis functions. The 14 bit variant is exact as long as all values
+// are below 1 << 14, the 16 bit variant works modulo 1 << 16.
+
+fn wenc14(a: u16, b: u16) -> (u16, u16) {
+    let (a, b) = (a as i16 as i32, b as i16 as i32);
+    (((a + b) >> 1) as u16, (a - b) as u16)
+}
+
+fn wenc16(a: u16, b: u16) -> (u16, u16) {
+    let ao = (a as i32 + 0x8000) & 0xffff;
+    let mut m = (ao + b as i32) >> 1;
+    let d = ao - b as i32;
+    if d < 0 {
+        m = (m + 0x8000) & 0xffff;
+    }
+    (m as u16, (d & 0xffff) as u16)
+}
+
+/// In place 2D wavelet encoding of the nx * ny samples starting at `base`,
+/// `ox` and `oy` being the offsets between horizontal and vertical neighbours.
+fn wav2_encode(buf: &mut [u16], base: usize, nx: usize, ox: usize, ny: usize, oy: usize, mx: u16) {
+    let enc: fn(u16, u16) -> (u16, u16) = if mx < (1 << 14) { wenc14 } else { wenc16 };
+    let n = if nx > ny { ny } else { nx };
+    let mut p = 1;
+    let mut p2 = 2;
+    while p2 <= n {
+        let (oy1, oy2, ox1, ox2) = (oy * p, oy * p2, ox * p, ox * p2);
+        let ey = base + oy * (ny - p2);
+        let mut py = base;
+        while py <= ey {
+            let ex = py + ox * (nx - p2);
+            let mut px = py;
+            while px <= ex {
+                let (p01, p10) = (px + ox1, px + oy1);
+                let p11 = p10 + ox1;
+                let (i00, i01) = enc(buf[px], buf[p01]);
+                let (i10, i11) = enc(buf[p10], buf[p11]);
+                let (a, b) = enc(i00, i10);
+                buf[px] = a;
+                buf[p10] = b;
+                let (a, b) = enc(i01, i11);
+                buf[p01] = a;
+                buf[p11] = b;
+                px += ox2;
+            }
+            // Odd column
+            if nx & p != 0 {
+                let p10 = px + oy1;
+                let (i00, h) = enc(buf[px], buf[p10]);
+                buf[p10] = h;
+                buf[px] = i00;
+            }
+            py += oy2;
+        }
+        // Odd line
+        if ny & p != 0 {
+            let ex = py + ox * (nx - p2);
+            let mut px = py;
+            while px <= ex {
+                let p01 = px + ox1;
+                let (i00, h) = enc(buf[px], buf[p01]);
+                buf[p01] = h;
+                buf[px] = i00;
+                px += ox2;
+            }
+        }
+        p = p2;
+        p2 <<= 1;
+    }
+}
+
+/// Heap entry ordered so that the least frequent node is on top.
+#[derive(PartialEq, Eq)]
+struct HeapNode {
+    freq: u64,
+    id: usize,
+}
+
+impl Ord for HeapNode {
+    fn cmp(&self, other: &HeapNode) -> Ordering {
+        match other.freq.cmp(&self.freq) {
+            Ordering::Equal => other.id.cmp(&self.id),
+            order => order,
+        }
+    }
+}
+
+impl PartialOrd for HeapNode {
+    fn partial_cmp(&self, other: &HeapNode) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Computes Huffman code lengths for `freq`; symbols with zero frequency
+/// get length zero.
+fn code_lengths(freq: &[u64]) -> Vec<u64> {
+    let n = freq.len();
+    let mut heap = BinaryHeap::new();
+    for (i, &f) in freq.iter().enumerate() {
+        if f > 0 {
+            heap.push(HeapNode { freq: f, id: i });
+        }
+    }
+    // Leaves are 0..n, inner nodes are appended after them
+    let mut parent: Vec<usize> = vec![0; n];
+    while heap.len() > 1 {
+        let a = heap.pop().unwrap();
+        let b = heap.pop().unwrap();
+        let id = parent.len();
+        parent.push(id);
+        parent[a.id] = id;
+        parent[b.id] = id;
+        heap.push(HeapNode { freq: a.freq + b.freq, id: id });
+    }
+    // Parents always come after their children, so walking backwards from
+    // the root visits a parent before its children
+    let mut depth = vec![0u64; parent.len()];
+    for i in (n..parent.len()).rev() {
+        if parent[i] != i {
+            depth[i] = depth[parent[i]] + 1;
+        }
+    }
+    (0..n).map(|i| if freq[i] > 0 { depth[parent[i]] + 1 } else { 0 }).collect()
+}
+
+/// Turns code lengths into a canonical code, stored as `code << 6 | length`.
+fn canonical_codes(lengths: &mut [u64]) {
+    let mut n = [0u64; 59];
+    for &l in lengths.iter() {
+        n[l as usize] += 1;
+    }
+    let mut c = 0;
+    for i in (1..59).rev() {
+        let nc = (c + n[i]) >> 1;
+        n[i] = c;
+        c = nc;
+    }
+    for l in lengths.iter_mut() {
+        let len = *l;
+        if len > 0 {
+            *l = len | (n[len as usize] << 6);
+            n[len as usize] += 1;
+        }
+    }
+}
+
+/// Packs bits most significant first.
+struct BitWriter {
+    out: Vec<u8>,
+    c: u64,
+    lc: u32,
+}
+
+impl BitWriter {
+    fn bits(&mut self, n: u32, bits: u64) {
+        self.c = (self.c << n) | bits;
+        self.lc += n;
+        while self.lc >= 8 {
+            self.lc -= 8;
+            self.out.push((self.c >> self.lc) as u8);
+        }
+    }
+
+    fn code(&mut self, code: u64) {
+        self.bits((code & 63) as u32, code >> 6);
+    }
+
+    fn flush(&mut self) {
+        if self.lc > 0 {
+            self.out.push((self.c << (8 - self.lc)) as u8);
+        }
+    }
+}
+
+/// Writes the code lengths of symbols im..=i_max, collapsing runs of unused
+/// symbols.
+fn pack_table(codes: &[u64], im: usize, i_max: usize, w: &mut BitWriter) {
+    let mut i = im;
+    while i <= i_max {
+        let l = codes[i] & 63;
+        if l == 0 {
+            let mut zerun = 1;
+            while i < i_max && zerun < LONGEST_LONG_RUN {
+                if codes[i + 1] & 63 > 0 {
+                    break;
+                }
+                i += 1;
+                zerun += 1;
+            }
+            if zerun >= 2 {
+                if zerun >= SHORTEST_LONG_RUN {
+                    w.bits(6, LONG_ZEROCODE_RUN);
+                    w.bits(8, (zerun - SHORTEST_LONG_RUN) as u64);
+                } else {
+                    w.bits(6, SHORT_ZEROCODE_RUN + zerun as u64 - 2);
+                }
+                i += 1;
+                continue;
+            }
+        }
+        w.bits(6, l);
+        i += 1;
+    }
+}
+
+/// Emits `run + 1` copies of the symbol with code `s`, using the run length
+/// symbol when that is shorter.
+fn send_code(s: u64, run: usize, rlc: u64, w: &mut BitWriter) {
+    if (s & 63) + (rlc & 63) + 8 < (s & 63) * run as u64 {
+        w.code(s);
+        w.code(rlc);
+        w.bits(8, run as u64);
+    } else {
+        for _ in 0..run + 1 {
+            w.code(s);
+        }
+    }
+}
+
+fn huf_compress(raw: &[u16]) -> Vec<u8> {
+    if raw.len() == 0 {
+        return Vec::new();
+    }
+    let mut freq = vec![0u64; HUF_ENCSIZE];
+    for &r in raw.iter() {
+        freq[r as usize] += 1;
+    }
+    let im = freq.iter().position(|&f| f > 0).unwrap();
+    // The symbol after the largest one used marks runs
+    let rlc = freq.iter().rposition(|&f| f > 0).unwrap() + 1;
+    freq[rlc] = 1;
+
+    let mut codes = code_lengths(&freq[..]);
+    canonical_codes(&mut codes[..]);
+
+    let mut table = BitWriter { out: Vec::new(), c: 0, lc: 0 };
+    pack_table(&codes[..], im, rlc, &mut table);
+    table.flush();
+
+    let mut data = BitWriter { out: Vec::new(), c: 0, lc: 0 };
+    let mut s = raw[0];
+    let mut run = 0;
+    for &r in raw[1..].iter() {
+        if s == r && run < 255 {
+            run += 1;
+        } else {
+            send_code(codes[s as usize], run, codes[rlc], &mut data);
+            run = 0;
+        }
+        s = r;
+    }
+    send_code(codes[s as usize], run, codes[rlc], &mut data);
+    let n_bits = data.out.len() * 8 + data.lc as usize;
+    data.flush();
+
+    let mut out = Vec::with_capacity(20 + table.out.len() + data.out.len());
+    push_u32(&mut out, im as u32);
+    push_u32(&mut out, rlc as u32);
+    push_u32(&mut out, table.out.len() as u32);
+    push_u32(&mut out, n_bits as u32);
+    push_u32(&mut out, 0);
+    out.push_all(&table.out[..]);
+    out.push_all(&data.out[..]);
+    out
+}
+
+/// Inverse of `compress`, `words` is the number of 16 bit words in the
+/// uncompressed block.
+pub fn decompress(data: &[u8], layout: &[ChannelLayout], words: usize) -> io::Result<Vec<u16>> {
+    let mut r = ByteReader { data: data, pos: 0 };
+    let min_non_zero = try!(r.u16()) as usize;
+    let max_non_zero = try!(r.u16()) as usize;
+    if max_non_zero >= BITMAP_SIZE {
+        return Err(invalid("invalid PIZ bitmap"));
+    }
+    let mut bitmap = vec![0u8; BITMAP_SIZE];
+    if min_non_zero <= max_non_zero {
+        let bytes = try!(r.take(max_non_zero - min_non_zero + 1));
+        for (i, &b) in bytes.iter().enumerate() {
+            bitmap[min_non_zero + i] = b;
+        }
+    }
+
+    // Maps 0..max_value back to the values that were used
+    let mut lut: Vec<u16> = Vec::new();
+    for i in 0..USHORT_RANGE {
+        if i == 0 || bitmap[i >> 3] & (1 << (i & 7)) != 0 {
+            lut.push(i as u16);
+        }
+    }
+    let max_value = (lut.len() - 1) as u16;
+
+    let length = try!(r.u32()) as usize;
+    let mut out = try!(huf_decompress(try!(r.take(length)), words));
+
+    let mut start = 0;
+    for c in layout.iter() {
+        for j in 0..c.size {
+            wav2_decode(&mut out[..], start + j, c.nx, c.size, c.ny, c.nx * c.size, max_value);
+        }
+        start += c.nx * c.ny * c.size;
+    }
+    for w in out.iter_mut() {
+        if *w > max_value {
+            return Err(invalid("invalid PIZ data"));
+        }
+        *w = lut[*w as usize];
+    }
+    Ok(out)
+}
+
+struct ByteReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.pos + n > self.data.len() {
+            return Err(invalid("truncated PIZ data"));
+        }
+        self.pos += n;
+        Ok(&self.data[self.pos - n..self.pos])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let b = try!(self.take(2));
+        Ok(b[0] as u16 | (b[1] as u16) << 8)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let b = try!(self.take(4));
+        Ok(b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24)
+    }
+}
+
+fn wdec14(l: u16, h: u16) -> (u16, u16) {
+    let (ls, hs) = (l as i16 as i32, h as i16 as i32);
+    let ai = ls + (hs & 1) + (hs >> 1);
+    (ai as u16, (ai - hs) as u16)
+}
+
+fn wdec16(l: u16, h: u16) -> (u16, u16) {
+    let (m, d) = (l as i32, h as i32);
+    let bb = (m - (d >> 1)) & 0xffff;
+    let aa = (d + bb - 0x8000) & 0xffff;
+    (aa as u16, bb as u16)
+}
+
+/// Inverse of `wav2_encode`.
+fn wav2_decode(buf: &mut [u16], base: usize, nx: usize, ox: usize, ny: usize, oy: usize, mx: u16) {
+    let dec: fn(u16, u16) -> (u16, u16) = if mx < (1 << 14) { wdec14 } else { wdec16 };
+    let n = if nx > ny { ny } else { nx };
+    // Start at the coarsest level
+    let mut p = 1;
+    while p <= n {
+        p <<= 1;
+    }
+    p >>= 1;
+    let mut p2 = p;
+    p >>= 1;
+    while p >= 1 {
+        let (oy1, oy2, ox1, ox2) = (oy * p, oy * p2, ox * p, ox * p2);
+        let ey = base + oy * (ny - p2);
+        let mut py = base;
+        while py <= ey {
+            let ex = py + ox * (nx - p2);
+            let mut px = py;
+            while px <= ex {
+                let (p01, p10) = (px + ox1, px + oy1);
+                let p11 = p10 + ox1;
+                let (i00, i10) = dec(buf[px], buf[p10]);
+                let (i01, i11) = dec(buf[p01], buf[p11]);
+                let (a, b) = dec(i00, i01);
+                buf[px] = a;
+                buf[p01] = b;
+                let (a, b) = dec(i10, i11);
+                buf[p10] = a;
+                buf[p11] = b;
+                px += ox2;
+            }
+            // Odd column
+            if nx & p != 0 {
+                let p10 = px + oy1;
+                let (i00, h) = dec(buf[px], buf[p10]);
+                buf[p10] = h;
+                buf[px] = i00;
+            }
+            py += oy2;
+        }
+        // Odd line
+        if ny & p != 0 {
+            let ex = py + ox * (nx - p2);
+            let mut px = py;
+            while px <= ex {
+                let p01 = px + ox1;
+                let (i00, h) = dec(buf[px], buf[p01]);
+                buf[p01] = h;
+                buf[px] = i00;
+                px += ox2;
+            }
+        }
+        p2 = p;
+        p >>= 1;
+    }
+}
+
+/// Reads bits most significant first.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize, // in bits
+}
+
+impl<'a> BitReader<'a> {
+    fn bits(&mut self, n: u32) -> io::Result<u64> {
+        let mut v = 0;
+        for _ in 0..n {
+            let byte = self.pos >> 3;
+            if byte >= self.data.len() {
+                return Err(invalid("truncated PIZ Huffman data"));
+            }
+            v = (v << 1) | ((self.data[byte] >> (7 - (self.pos & 7))) & 1) as u64;
+            self.pos += 1;
+        }
+        Ok(v)
+    }
+}
+
+fn huf_decompress(data: &[u8], n_raw: usize) -> io::Result<Vec<u16>> {
+    if n_raw == 0 {
+        return Ok(Vec::new());
+    }
+    let mut r = ByteReader { data: data, pos: 0 };
+    let im = try!(r.u32()) as usize;
+    let i_max = try!(r.u32()) as usize;
+    let table_length = try!(r.u32()) as usize;
+    let n_bits = try!(r.u32()) as usize;
+    try!(r.u32());
+    if im >= HUF_ENCSIZE || i_max >= HUF_ENCSIZE {
+        return Err(invalid("invalid PIZ Huffman table"));
+    }
+
+    // Unpack the code lengths
+    let mut codes = vec![0u64; HUF_ENCSIZE];
+    let mut table = BitReader { data: try!(r.take(table_length)), pos: 0 };
+    let mut i = im;
+    while i <= i_max {
+        let l = try!(table.bits(6));
+        let zerun = if l == LONG_ZEROCODE_RUN {
+            try!(table.bits(8)) as usize + SHORTEST_LONG_RUN
+        } else if l >= SHORT_ZEROCODE_RUN {
+            (l - SHORT_ZEROCODE_RUN) as usize + 2
+        } else {
+            codes[i] = l;
+            1
+        };
+        i += zerun;
+    }
+    if i > i_max + 1 {
+        return Err(invalid("invalid PIZ Huffman table"));
+    }
+    canonical_codes(&mut codes[..]);
+
+    // Symbols of each length ordered by code, codes of one length are
+    // consecutive starting at first[length]
+    let mut first = [0u64; 59];
+    let mut symbols: Vec<Vec<u32>> = (0..59).map(|_| Vec::new()).collect();
+    for (s, &c) in codes.iter().enumerate() {
+        let len = (c & 63) as usize;
+        if len > 0 {
+            if symbols[len].len() == 0 {
+                first[len] = c >> 6;
+            }
+            symbols[len].push(s as u32);
+        }
+    }
+
+    let mut bits = BitReader { data: &data[r.pos..], pos: 0 };
+    let mut out: Vec<u16> = Vec::with_capacity(n_raw);
+    let (mut code, mut len) = (0u64, 0usize);
+    while bits.pos < n_bits {
+        code = (code << 1) | try!(bits.bits(1));
+        len += 1;
+        if len > 58 {
+            return Err(invalid("invalid PIZ Huffman code"));
+        }
+        let n = symbols[len].len() as u64;
+        if n > 0 && code >= first[len] && code < first[len] + n {
+            let s = symbols[len][(code - first[len]) as usize] as usize;
+            if s == i_max {
+                // Run of the previous value
+                let run = try!(bits.bits(8)) as usize;
+                let prev = match out.last() {
+                    Some(&prev) => prev,
+                    None => return Err(invalid("invalid PIZ run length")),
+                };
+                for _ in 0..run {
+                    out.push(prev);
+                }
+            } else {
+                out.push(s as u16);
+            }
+            code = 0;
+            len = 0;
+        }
+    }
+    if out.len() != n_raw {
+        return Err(invalid("PIZ data does not match the block size"));
+    }
+    Ok(out)
+}
@@ -0,0 +1,241 @@
+//! PNG input and output (gray or RGB, 8 or 16 bits per sample, no
+//! interlacing).
+
+use std::io;
+use std::io::prelude::*;
+
+use super::{Image, Depth, invalid};
+use super::deflate::{self, Crc32};
+use tonemap::{ToneMap, srgb_decode};
+
+fn push_u32(out: &mut Vec<u8>, v: u32) {
+    out.push((v >> 24) as u8);
+    out.push((v >> 16) as u8);
+    out.push((v >> 8) as u8);
+    out.push(v as u8);
+}
+
+fn write_chunk<W: Write>(w: &mut W, kind: &[u8], data: &[u8]) -> io::Result<()> {
+    let mut header = Vec::with_capacity(8);
+    push_u32(&mut header, data.len() as u32);
+    header.push_all(kind);
+    try!(w.write_all(&header[..]));
+    try!(w.write_all(data));
+
+    let mut crc = Crc32::new();
+    crc.update(kind);
+    crc.update(data);
+    let mut footer = Vec::with_capacity(4);
+    push_u32(&mut footer, crc.finish());
+    w.write_all(&footer[..])
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i32 + b as i32 - c as i32;
+    let pa = (p - a as i32).abs();
+    let pb = (p - b as i32).abs();
+    let pc = (p - c as i32).abs();
+    if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c }
+}
+
+/// Applies filter `kind` to scanline `cur` given the previous one.
+fn filter(kind: u8, cur: &[u8], prev: &[u8], bpp: usize, out: &mut Vec<u8>) {
+    out.clear();
+    for i in 0..cur.len() {
+        let left = if i >= bpp { cur[i - bpp] } else { 0 };
+        let up = prev[i];
+        let up_left = if i >= bpp { prev[i - bpp] } else { 0 };
+        let predicted = match kind {
+            0 => 0,
+            1 => left,
+            2 => up,
+            3 => ((left as u32 + up as u32) / 2) as u8,
+            _ => paeth(left, up, up_left),
+        };
+        out.push(((cur[i] as i32 - predicted as i32) & 0xff) as u8);
+    }
+}
+
+/// Sum of the filtered bytes taken as signed values, the usual heuristic for
+/// picking the filter that compresses best.
+fn cost(line: &[u8]) -> u32 {
+    line.iter().fold(0, |sum, &b| sum + if b < 128 { b as u32 } else { 256 - b as u32 })
+}
+
+/// Writes `img` as PNG, gray images use color type 0, RGB images type 2.
+pub fn write<W: Write>(w: &mut W, img: &Image, depth: Depth, tonemap: &ToneMap) -> io::Result<()> {
+    let bytes = if depth == Depth::Eight { 1 } else { 2 };
+    let quantizer = tonemap.quantizer();
+    let max = if depth == Depth::Eight { 255 } else { 65535 };
+    let bpp = img.channels * bytes;
+    let stride = img.width * bpp;
+
+    try!(w.write_all(&[137, 80, 78, 71, 13, 10, 26, 10]));
+
+    let mut ihdr = Vec::with_capacity(13);
+    push_u32(&mut ihdr, img.width as u32);
+    push_u32(&mut ihdr, img.height as u32);
+    ihdr.push((bytes * 8) as u8);
+    ihdr.push(if img.channels == 1 { 0 } else { 2 });
+    ihdr.push(0); // deflate
+    ihdr.push(0); // adaptive filtering
+    ihdr.push(0); // no interlace
+    try!(write_chunk(w, b"IHDR", &ihdr[..]));
+
+    let mut raw = Vec::with_capacity((stride + 1) * img.height);
+    let mut prev = vec![0u8; stride];
+    let mut cur = Vec::with_capacity(stride);
+    let mut best = Vec::with_capacity(stride);
+    let mut candidate = Vec::with_capacity(stride);
+    for y in 0..img.height {
+        cur.clear();
+        for x in 0..img.width {
+            for c in 0..img.channels {
+                let v = quantizer.encode(img.get(x, y, c), x, y, max);
+                if depth == Depth::Sixteen {
+                    cur.push((v >> 8) as u8);
+                }
+                cur.push((v & 0xff) as u8);
+            }
+        }
+
+        let mut best_kind = 0;
+        let mut best_cost = 0;
+        for kind in 0..5 {
+            filter(kind, &cur[..], &prev[..], bpp, &mut candidate);
+            let c = cost(&candidate[..]);
+            if kind == 0 || c < best_cost {
+                best_kind = kind;
+                best_cost = c;
+                best.clear();
+                best.push_all(&candidate[..]);
+            }
+        }
+        raw.push(best_kind);
+        raw.push_all(&best[..]);
+        prev.clear();
+        prev.push_all(&cur[..]);
+    }
+
+    try!(write_chunk(w, b"IDAT", &deflate::compress(&raw[..])[..]));
+    write_chunk(w, b"IEND", &[])
+}
+
+fn read_u32(data: &[u8], pos: usize) -> u32 {
+    (data[pos] as u32) << 24 | (data[pos+1] as u32) << 16 | (data[pos+2] as u32) << 8 | data[pos+3] as u32
+}
+
+/// Reverses filter `kind` on scanline `line` in place.
+fn unfilter(kind: u8, line: &mut [u8], prev: &[u8], bpp: usize) -> io::Result<()> {
+    for i in 0..line.len() {
+        let left = if i >= bpp { line[i - bpp] } else { 0 };
+        let up = prev[i];
+        let up_left = if i >= bpp { prev[i - bpp] } else { 0 };
+        let predicted = match kind {
+            0 => 0,
+            1 => left,
+            2 => up,
+            3 => ((left as u32 + up as u32) / 2) as u8,
+            4 => paeth(left, up, up_left),
+            _ => return Err(invalid("invalid PNG filter type")),
+        };
+        line[i] = ((line[i] as u32 + predicted as u32) & 0xff) as u8;
+    }
+    Ok(())
+}
+
+/// Reads a non interlaced PNG with 8 or 16 bits per sample. Samples are sRGB
+/// decoded to linear values, alpha is dropped and palettes are expanded.
+pub fn read<R: Read>(r: &mut R) -> io::Result<Image> {
+    let mut data = Vec::new();
+    try!(r.read_to_end(&mut data));
+    if data.len() < 8 || &data[..8] != &[137u8, 80, 78, 71, 13, 10, 26, 10][..] {
+        return Err(invalid("not a PNG file"));
+    }
+
+    let mut ihdr = None;
+    let mut palette = Vec::new();
+    let mut idat = Vec::new();
+    let mut pos = 8;
+    while pos + 8 <= data.len() {
+        let len = read_u32(&data[..], pos) as usize;
+        let kind = &data[pos+4..pos+8];
+        let start = pos + 8;
+        if start + len + 4 > data.len() {
+            return Err(invalid("truncated PNG chunk"));
+        }
+        let chunk = &data[start..start+len];
+        if kind == b"IHDR" && len == 13 {
+            ihdr = Some((read_u32(chunk, 0) as usize, read_u32(chunk, 4) as usize,
+                         chunk[8] as usize, chunk[9], chunk[12]));
+        } else if kind == b"PLTE" {
+            palette.push_all(chunk);
+        } else if kind == b"IDAT" {
+            idat.push_all(chunk);
+        } else if kind == b"IEND" {
+            break;
+        }
+        pos = start + len + 4;
+    }
+
+    let (width, height, bits, color_type, interlace) = match ihdr {
+        Some(header) => header,
+        None => return Err(invalid("missing PNG header")),
+    };
+    if interlace != 0 {
+        return Err(invalid("interlaced PNG files are not supported"));
+    }
+    let samples = match color_type {
+        0 | 3 => 1,
+        2 => 3,
+        4 => 2,
+        6 => 4,
+        _ => return Err(invalid("invalid PNG color type")),
+    };
+    if bits != 8 && !(bits == 16 && color_type != 3) {
+        return Err(invalid("unsupported PNG bit depth"));
+    }
+
+    let raw = try!(deflate::decompress(&idat[..]));
+    let bytes = bits / 8;
+    let bpp = samples * bytes;
+    let stride = width * bpp;
+    if raw.len() < (stride + 1) * height {
+        return Err(invalid("truncated PNG image data"));
+    }
+
+    let channels = if color_type == 0 || color_type == 4 { 1 } else { 3 };
+    let max = if bytes == 1 { 255.0 } else { 65535.0 };
+    let mut img = Image::new(width, height, channels);
+    let mut prev = vec![0u8; stride];
+    let mut line = vec![0u8; stride];
+    for y in 0..height {
+        let offset = y * (stride + 1);
+        for i in 0..stride {
+            line[i] = raw[offset + 1 + i];
+        }
+        try!(unfilter(raw[offset], &mut line[..], &prev[..], bpp));
+        for x in 0..width {
+            let sample = |s: usize| {
+                let i = x * bpp + s * bytes;
+                if bytes == 1 { line[i] as u32 } else { (line[i] as u32) << 8 | line[i+1] as u32 }
+            };
+            for c in 0..channels {
+                let v = if color_type == 3 {
+                    let entry = sample(0) as usize * 3 + c;
+                    if entry >= palette.len() {
+                        return Err(invalid("PNG palette index out of range"));
+                    }
+                    palette[entry] as f64 / 255.0
+                } else {
+                    sample(c) as f64 / max
+                };
+                img.set(x, y, c, srgb_decode(v));
+            }
+        }
+        for i in 0..stride {
+            prev[i] = line[i];
+        }
+    }
+    Ok(img)
+}
@@ -0,0 +1,102 @@
+//! Binary Netpbm input and output: P6 (RGB) and P5 (gray), 8 or 16 bits per
+//! sample.
+
+use std::io;
+use std::io::prelude::*;
+
+use super::{Image, Depth, invalid};
+use tonemap::{ToneMap, srgb_decode};
+
+/// Writes `img` as binary PPM, or as PGM if `gray` is set.
+pub fn write<W: Write>(w: &mut W, img: &Image, depth: Depth, gray: bool, tonemap: &ToneMap) -> io::Result<()> {
+    let (magic, channels) = if gray { ("P5", 1) } else { ("P6", 3) };
+    let max = match depth { Depth::Eight => 255, Depth::Sixteen => 65535 };
+    try!(w.write_all(format!("{}\n{} {}\n{}\n", magic, img.width, img.height, max).as_bytes()));
+
+    let bytes = if depth == Depth::Eight { 1 } else { 2 };
+    let quantizer = tonemap.quantizer();
+    let mut row: Vec<u8> = Vec::with_capacity(img.width * channels * bytes);
+    for y in 0..img.height {
+        row.clear();
+        for x in 0..img.width {
+            let (r, g, b) = img.rgb(x, y);
+            let samples = if gray { [img.luminance(x, y), 0.0, 0.0] } else { [r, g, b] };
+            for c in 0..channels {
+                let v = quantizer.encode(samples[c], x, y, max);
+                // 16 bit samples are stored most significant byte first
+                if depth == Depth::Sixteen {
+                    row.push((v >> 8) as u8);
+                }
+                row.push((v & 0xff) as u8);
+            }
+        }
+        try!(w.write_all(&row[..]));
+    }
+
+    Ok(())
+}
+
+/// Reads the next whitespace separated header field, skipping comments.
+fn header_field(data: &[u8], pos: &mut usize) -> io::Result<usize> {
+    loop {
+        while *pos < data.len() && (data[*pos] as char).is_whitespace() {
+            *pos += 1;
+        }
+        if *pos < data.len() && data[*pos] == b'#' {
+            while *pos < data.len() && data[*pos] != b'\n' {
+                *pos += 1;
+            }
+        } else {
+            break;
+        }
+    }
+    let start = *pos;
+    while *pos < data.len() && (data[*pos] as char).is_digit(10) {
+        *pos += 1;
+    }
+    if start == *pos {
+        return Err(invalid("invalid PNM header"));
+    }
+    match ::std::str::from_utf8(&data[start..*pos]).unwrap().parse() {
+        Ok(v) => Ok(v),
+        Err(_) => Err(invalid("PNM header number out of range")),
+    }
+}
+
+/// Reads a binary PPM or PGM file, samples are sRGB decoded to linear values.
+pub fn read<R: Read>(r: &mut R) -> io::Result<Image> {
+    let mut data = Vec::new();
+    try!(r.read_to_end(&mut data));
+    if data.len() < 2 || data[0] != b'P' || (data[1] != b'5' && data[1] != b'6') {
+        return Err(invalid("not a binary PPM or PGM file"));
+    }
+    let channels = if data[1] == b'5' { 1 } else { 3 };
+    let mut pos = 2;
+    let width = try!(header_field(&data[..], &mut pos));
+    let height = try!(header_field(&data[..], &mut pos));
+    let max = try!(header_field(&data[..], &mut pos));
+    if max == 0 || max > 65535 {
+        return Err(invalid("invalid PNM maximum value"));
+    }
+    // A single whitespace character separates the header from the samples
+    pos += 1;
+
+    let bytes = if max < 256 { 1 } else { 2 };
+    let count = match width.checked_mul(height).and_then(|n| n.checked_mul(channels * bytes)) {
+        Some(n) => n / bytes,
+        None => return Err(invalid("PNM image too large")),
+    };
+    if data.len() < pos + count * bytes {
+        return Err(invalid("truncated PNM file"));
+    }
+    let mut img = Image::new(width, height, channels);
+    for i in 0..count {
+        let v = if bytes == 1 {
+            data[pos + i] as usize
+        } else {
+            (data[pos + 2 * i] as usize) << 8 | data[pos + 2 * i + 1] as usize
+        };
+        img.data[i] = srgb_decode(v as f64 / max as f64);
+    }
+    Ok(img)
+}
@@ -0,0 +1,224 @@
+//! Estimating the light arriving along camera rays.
+
+use std::fmt;
+use std::num::Float;
+use std::default::Default;
+use std::str::FromStr;
+
+use vector::{Vector, VectorOps};
+use ray::Ray;
+use material::BsdfSample;
+use scene::Scene;
+use image::Image;
+use rng::Rng;
+use stats;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Integrator {
+    Flat, // the color of the first hit
+    Diffuse, // first hit color shaded by the angle to the scene light
+    Path, // path tracing, emission is the only light
+}
+
+impl FromStr for Integrator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Integrator, String> {
+        match s {
+            "flat" => Ok(Integrator::Flat),
+            "diffuse" => Ok(Integrator::Diffuse),
+            "path" => Ok(Integrator::Path),
+            _ => Err(format!("unknown integrator '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for Integrator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Integrator::Flat => "flat",
+            Integrator::Diffuse => "diffuse",
+            Integrator::Path => "path",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Paths end at the surface reached after `max_depth + 1` bounces, which only
+/// adds its emission. This is the `max_depth` of loaded scenes.
+pub const MAX_DEPTH: usize = 5;
+
+/// Hit point of `ray` on object `id`, the surface normal facing the ray and
+/// the error bound of the hit point.
+pub fn hit_frame(scene: &Scene, ray: &Ray, t: f64, id: usize) -> (Vector, Vector, Vector) {
+    let object = scene.object(id);
+    let (x, error) = object.surface_point(&ray.o + &ray.d.smul(t), ray.time);
+    let n: Vector = object.normal(x, ray.time);
+    let nl = if n.dot(&ray.d) < 0.0 { n } else { n.smul(-1.0) };
+    (x, nl, error)
+}
+
+/// Samples the material of object `id` at the hit point `x` of `ray` for
+/// the direction the path continues in, None if the path ends.
+pub fn sample_bsdf(scene: &Scene, ray: &Ray, id: usize, x: Vector, nl: Vector, rng: &mut Rng) -> Option<BsdfSample> {
+    let object = scene.object(id);
+    let outside = object.normal(x, ray.time).dot(&nl) > 0.0;
+    let u1 = rng.next_f64();
+    let u2 = rng.next_f64();
+    object.material().seen_from(outside).sample(object.albedo(x, ray.time), nl, ray.d.smul(-1.0), u1, u2)
+}
+
+/// Samples the material of object `id` at the hit point `x` for the next
+/// ray of the path and returns it with its weight, None if the path ends.
+/// The ray starts just far enough from `x` to not hit the same surface again.
+pub fn bounce(scene: &Scene, ray: &Ray, id: usize, x: Vector, nl: Vector, error: Vector, rng: &mut Rng) -> Option<(Ray, Vector)> {
+    match sample_bsdf(scene, ray, id, x, nl, rng) {
+        Some(s) => Some((Ray::spawn(x, error, nl, s.wi, ray.time), s.weight)),
+        None => None,
+    }
+}
+
+pub fn get_light(scene: &Scene, ray: Ray, depth: usize, rng: &mut Rng) -> Vector {
+    let mut t: f64 = 0.0;
+    let mut id: usize = 0;
+    if scene.intersect(&ray, &mut t, &mut id) {
+        let emission = scene.object(id).emission();
+        if depth > scene.max_depth {
+            return emission;
+        }
+
+        let (x, nl, error) = hit_frame(scene, &ray, t, id);
+        return match bounce(scene, &ray, id, x, nl, error, rng) {
+            Some((next, weight)) => &emission + &(&weight * &get_light(scene, next, depth+1, rng)),
+            None => emission,
+        };
+    }
+
+    return scene.background;
+}
+
+pub fn luminance(c: &Vector) -> f64 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+/// Radiance of a camera ray split into direct and indirect light, plus the
+/// first hit information for the output variables.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Sample {
+    pub direct: Vector,
+    pub indirect: Vector,
+    pub albedo: Vector,
+    pub normal: Vector,
+    pub position: Vector,
+    pub depth: f64,
+    pub id: f64,
+}
+
+impl Sample {
+    pub fn radiance(&self) -> Vector {
+        &self.direct + &self.indirect
+    }
+
+    /// Adds `other` scaled by `s`. The id is a label, which is kept and not
+    /// summed.
+    pub fn add_scaled(&mut self, other: &Sample, s: f64) {
+        self.direct = &self.direct + &other.direct.smul(s);
+        self.indirect = &self.indirect + &other.indirect.smul(s);
+        self.albedo = &self.albedo + &other.albedo.smul(s);
+        self.normal = &self.normal + &other.normal.smul(s);
+        self.position = &self.position + &other.position.smul(s);
+        self.depth += other.depth * s;
+    }
+}
+
+/// Estimates the light along a camera ray. For path tracing this is the same
+/// estimate as `get_light(scene, ray, 0, rng)`, with the first two bounces
+/// unrolled to separate direct from indirect light.
+pub fn get_sample(scene: &Scene, integrator: Integrator, ray: Ray, rng: &mut Rng) -> Sample {
+    let rays = stats::rays();
+    let sample = trace_sample(scene, integrator, ray, rng);
+    stats::path(stats::rays() - rays);
+    sample
+}
+
+fn trace_sample(scene: &Scene, integrator: Integrator, ray: Ray, rng: &mut Rng) -> Sample {
+    let mut sample: Sample = Default::default();
+    let mut t: f64 = 0.0;
+    let mut id: usize = 0;
+    if !scene.intersect(&ray, &mut t, &mut id) {
+        sample.direct = scene.background;
+        return sample;
+    }
+
+    let (x, nl, error) = hit_frame(scene, &ray, t, id);
+    let object = scene.object(id);
+    sample.albedo = object.albedo(x, ray.time);
+    sample.normal = nl;
+    sample.position = x;
+    sample.depth = t * ray.d.dot(&scene.camera.forward(ray.time));
+    sample.id = (id + 1) as f64;
+
+    match integrator {
+        Integrator::Flat => sample.direct = sample.albedo,
+        Integrator::Diffuse => sample.direct = sample.albedo.smul(nl.dot(&scene.light)),
+        Integrator::Path => {
+            sample.direct = object.emission();
+            let (second, weight) = match bounce(scene, &ray, id, x, nl, error, rng) {
+                Some(b) => b,
+                None => return sample,
+            };
+            if scene.intersect(&second, &mut t, &mut id) {
+                sample.direct = &sample.direct + &(&weight * &scene.object(id).emission());
+                if scene.max_depth == 0 {
+                    return sample;
+                }
+                let (x, nl, error) = hit_frame(scene, &second, t, id);
+                if let Some((third, weight2)) = bounce(scene, &second, id, x, nl, error, rng) {
+                    let throughput = &weight * &weight2;
+                    sample.indirect = &throughput * &get_light(scene, third, 2, rng);
+                }
+            } else {
+                sample.direct = &sample.direct + &(&weight * &scene.background);
+            }
+        }
+    }
+    sample
+}
+
+/// Averages `samples` estimates for row `i` and column `j`. Also returns the
+/// variance of the mean luminance.
+pub fn render_pixel(scene: &Scene, integrator: Integrator, i: usize, j: usize, samples: usize,
+                    rng: &mut Rng) -> (Sample, f64) {
+    let mut r: Sample = Default::default();
+    let mut sum_sq = 0.0;
+    for n in 0..samples {
+        let time = scene.camera.sample_time(rng);
+        let ray = scene.camera.get_ray(i, j, scene.width, scene.height, time);
+        let s = get_sample(scene, integrator, ray, rng);
+        if n == 0 {
+            r.id = s.id;
+        }
+        let l = luminance(&s.radiance());
+        sum_sq += l * l;
+        r.add_scaled(&s, 1.0/samples as f64);
+    }
+    let mean = luminance(&r.radiance());
+    let variance = (sum_sq / samples as f64 - mean * mean).max(0.0) / samples as f64;
+    (r, variance)
+}
+
+/// Renders the whole image on the calling thread. Each pixel draws from its
+/// own generator, so the result only depends on `seed`.
+pub fn render(scene: &Scene, integrator: Integrator, samples: usize, seed: u64) -> Image {
+    let mut output = Image::new(scene.width, scene.height, 3);
+    for i in 0..scene.height {
+        for j in 0..scene.width {
+            let mut rng = Rng::with_stream(seed, i, j);
+            let (s, _) = render_pixel(scene, integrator, i, j, samples, &mut rng);
+            let color = s.radiance();
+            // Unclamped, tone mapping happens when writing the image
+            output.set_rgb(j, i, color.x, color.y, color.z);
+        }
+    }
+    output
+}
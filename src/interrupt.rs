@@ -0,0 +1,36 @@
+//! Stopping a render cleanly on Ctrl-C or a termination request.
+//!
+//! Once `catch` is called the first SIGINT or SIGTERM only raises a flag,
+//! which the render loops check between passes to write out what they have.
+//! A second one exits right away.
+
+use std::sync::atomic::{AtomicBool, ATOMIC_BOOL_INIT, Ordering};
+
+static INTERRUPTED: AtomicBool = ATOMIC_BOOL_INIT;
+
+const SIGINT: i32 = 2;
+const SIGTERM: i32 = 15;
+
+extern {
+    fn signal(signum: i32, handler: extern fn(i32)) -> usize;
+    fn _exit(status: i32) -> !;
+}
+
+extern fn handler(signum: i32) {
+    if INTERRUPTED.swap(true, Ordering::SeqCst) {
+        unsafe { _exit(128 + signum) };
+    }
+}
+
+/// Handles SIGINT and SIGTERM from now on.
+pub fn catch() {
+    unsafe {
+        signal(SIGINT, handler);
+        signal(SIGTERM, handler);
+    }
+}
+
+/// Whether a signal came in since `catch`.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
@@ -0,0 +1,30 @@
+#![allow(unstable)]
+
+extern crate time;
+
+pub mod float;
+pub mod vector;
+pub mod ray;
+pub mod motion;
+pub mod shape;
+pub mod material;
+pub mod texture;
+pub mod light;
+pub mod transform;
+pub mod camera;
+pub mod scene;
+pub mod rng;
+pub mod integrator;
+pub mod film;
+pub mod tile;
+pub mod wavefront;
+pub mod checkpoint;
+pub mod interrupt;
+pub mod stats;
+pub mod debug;
+pub mod net;
+pub mod image;
+pub mod tonemap;
+pub mod aov;
+pub mod denoise;
+pub mod compare;
@@ -0,0 +1,76 @@
+//! Sampling directions towards emitters.
+//!
+//! Pdfs are with respect to solid angle at the receiving point, like the
+//! ones of `Material`, so both strategies can be combined.
+
+use std::num::Float;
+use std::f64::consts::PI;
+
+use vector::{Vector, VectorOps};
+use shape::Sphere;
+use material::coordinate_system;
+
+#[derive(Debug, Copy, Clone)]
+pub struct LightSample {
+    pub wi: Vector, // unit direction from the receiving point to the light
+    pub distance: f64, // along wi to the sampled point on the light
+    pub radiance: Vector, // emitted towards the receiving point
+    pub pdf: f64,
+}
+
+pub trait Light {
+    /// Draws a direction from `x` towards the light at `time`, from the
+    /// uniform numbers `u1` and `u2`. Returns None if `x` cannot see the
+    /// light, e.g. because it lies inside it.
+    fn sample(&self, x: Vector, time: f64, u1: f64, u2: f64) -> Option<LightSample>;
+
+    /// Density of `sample` drawing `wi` from `x`.
+    fn pdf(&self, x: Vector, time: f64, wi: Vector) -> f64;
+}
+
+impl Sphere {
+    /// Cosine of the half angle of the cone the sphere covers seen from
+    /// `x`, None if `x` lies inside.
+    fn cone(&self, x: Vector, time: f64) -> Option<(Vector, f64, f64)> {
+        let to_center = &self.center(time) - &x;
+        let d2 = to_center.dot(&to_center);
+        let r2 = self.radius * self.radius;
+        if d2 <= r2 {
+            return None;
+        }
+        let d = d2.sqrt();
+        Some((to_center.smul(1.0 / d), d, (1.0 - r2 / d2).max(0.0).sqrt()))
+    }
+}
+
+impl Light for Sphere {
+    fn sample(&self, x: Vector, time: f64, u1: f64, u2: f64) -> Option<LightSample> {
+        let (axis, d, cos_max) = match self.cone(x, time) {
+            Some(cone) => cone,
+            None => return None,
+        };
+        // Uniform over the cone of directions that hit the sphere
+        let cos_theta = 1.0 - u2 * (1.0 - cos_max);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * u1;
+        let (u, v) = coordinate_system(axis);
+        let wi = (&(&u.smul(phi.cos() * sin_theta) + &v.smul(phi.sin() * sin_theta)) + &axis.smul(cos_theta)).norm();
+
+        // Distance to the near intersection with the sphere
+        let b = d * cos_theta;
+        let det = (self.radius * self.radius - d * d * sin_theta * sin_theta).max(0.0);
+        Some(LightSample {
+            wi: wi,
+            distance: b - det.sqrt(),
+            radiance: self.emission,
+            pdf: 1.0 / (2.0 * PI * (1.0 - cos_max)),
+        })
+    }
+
+    fn pdf(&self, x: Vector, time: f64, wi: Vector) -> f64 {
+        match self.cone(x, time) {
+            Some((axis, _, cos_max)) if wi.dot(&axis) >= cos_max => 1.0 / (2.0 * PI * (1.0 - cos_max)),
+            _ => 0.0,
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! How surfaces scatter light.
+//!
+//! Directions point away from the surface: `wo` towards the viewer, `wi`
+//! towards where the light comes from, `n` is the normal on the side of `wo`.
+//! `eval` returns the BSDF times the cosine of `wi`, so a sample's weight is
+//! `eval / pdf`. Pdfs are with respect to solid angle. The surface color
+//! scales the reflected light and is passed in by the caller.
+
+use std::num::Float;
+use std::default::Default;
+use std::f64::consts::PI;
+
+use vector::{Vector, VectorOps};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Material {
+    Diffuse, // Lambertian
+    Glossy(f64), // normalized Phong lobe with the given exponent around the mirror direction
+    Mirror, // perfect specular reflection
+    Dielectric(f64), // smooth glass, the index of refraction of the side `n` points away from relative to the side of `n`
+}
+
+impl Default for Material {
+    fn default() -> Material {
+        Material::Diffuse
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct BsdfSample {
+    pub wi: Vector,
+    pub weight: Vector, // eval / pdf
+    pub pdf: f64,
+    pub specular: bool, // drawn from a delta distribution, eval and pdf are 0 for it
+}
+
+/// Two unit vectors that form an orthonormal basis with `w`.
+pub fn coordinate_system(w: Vector) -> (Vector, Vector) {
+    let u = if w.x.abs() > 0.1 { Vector{x: 0.0, y: 1.0, z: 0.0} } else { Vector{x: 1.0, y: 0.0, z: 0.0 } }.cross(w).norm();
+    let v = w.cross(u);
+    (u, v)
+}
+
+/// Direction at polar angle acos(`cos_theta`) and azimuth `phi` around `w`.
+fn spherical(w: Vector, cos_theta: f64, phi: f64) -> Vector {
+    let (u, v) = coordinate_system(w);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    (&(&u.smul(phi.cos() * sin_theta) + &v.smul(phi.sin() * sin_theta)) + &w.smul(cos_theta)).norm()
+}
+
+fn reflect(wo: Vector, n: Vector) -> Vector {
+    &n.smul(2.0 * wo.dot(&n)) - &wo
+}
+
+impl Material {
+    pub fn is_specular(&self) -> bool {
+        match *self {
+            Material::Mirror | Material::Dielectric(_) => true,
+            _ => false,
+        }
+    }
+
+    /// The material as seen from outside the shape or from inside, where
+    /// a dielectric has the inverse relative index of refraction.
+    pub fn seen_from(&self, outside: bool) -> Material {
+        match *self {
+            Material::Dielectric(ior) if !outside => Material::Dielectric(1.0 / ior),
+            m => m,
+        }
+    }
+
+    /// BSDF times the cosine between `wi` and `n`, zero for specular
+    /// materials.
+    pub fn eval(&self, color: Vector, n: Vector, wo: Vector, wi: Vector) -> Vector {
+        let cos_i = wi.dot(&n);
+        if cos_i <= 0.0 {
+            return Default::default();
+        }
+        match *self {
+            Material::Diffuse => color.smul(cos_i / PI),
+            Material::Glossy(exponent) => {
+                let cos_r = reflect(wo, n).dot(&wi);
+                if cos_r <= 0.0 {
+                    return Default::default();
+                }
+                color.smul((exponent + 2.0) / (2.0 * PI) * cos_r.powf(exponent) * cos_i)
+            }
+            Material::Mirror | Material::Dielectric(_) => Default::default(),
+        }
+    }
+
+    /// Density of `sample` drawing `wi`, zero for specular materials.
+    pub fn pdf(&self, n: Vector, wo: Vector, wi: Vector) -> f64 {
+        let cos_i = wi.dot(&n);
+        if cos_i <= 0.0 {
+            return 0.0;
+        }
+        match *self {
+            Material::Diffuse => cos_i / PI,
+            Material::Glossy(exponent) => {
+                let cos_r = reflect(wo, n).dot(&wi);
+                if cos_r <= 0.0 { 0.0 } else { (exponent + 1.0) / (2.0 * PI) * cos_r.powf(exponent) }
+            }
+            Material::Mirror | Material::Dielectric(_) => 0.0,
+        }
+    }
+
+    /// Draws an incoming direction from the uniform numbers `u1` and `u2`.
+    /// Returns None if the drawn direction lies below the surface.
+    pub fn sample(&self, color: Vector, n: Vector, wo: Vector, u1: f64, u2: f64) -> Option<BsdfSample> {
+        let phi = 2.0 * PI * u1;
+        match *self {
+            Material::Diffuse => {
+                // Cosine weighted
+                let wi = spherical(n, (1.0 - u2).sqrt(), phi);
+                let pdf = self.pdf(n, wo, wi);
+                if pdf <= 0.0 {
+                    return None;
+                }
+                Some(BsdfSample { wi: wi, weight: color, pdf: pdf, specular: false })
+            }
+            Material::Glossy(exponent) => {
+                let wi = spherical(reflect(wo, n), u2.powf(1.0 / (exponent + 1.0)), phi);
+                let pdf = self.pdf(n, wo, wi);
+                if pdf <= 0.0 {
+                    return None;
+                }
+                let weight = self.eval(color, n, wo, wi).smul(1.0 / pdf);
+                Some(BsdfSample { wi: wi, weight: weight, pdf: pdf, specular: false })
+            }
+            Material::Mirror => {
+                let wi = reflect(wo, n);
+                if wi.dot(&n) <= 0.0 {
+                    return None;
+                }
+                Some(BsdfSample { wi: wi, weight: color, pdf: 1.0, specular: true })
+            }
+            Material::Dielectric(eta) => {
+                // Reflect with the probability given by the Fresnel equations,
+                // so the weight of either direction is just the color
+                let cos_o = wo.dot(&n);
+                let sin2_t = (1.0 - cos_o * cos_o).max(0.0) / (eta * eta);
+                if sin2_t >= 1.0 {
+                    // Total internal reflection
+                    return Some(BsdfSample { wi: reflect(wo, n), weight: color, pdf: 1.0, specular: true });
+                }
+                let cos_t = (1.0 - sin2_t).sqrt();
+                let r_parallel = (eta * cos_o - cos_t) / (eta * cos_o + cos_t);
+                let r_perpendicular = (cos_o - eta * cos_t) / (cos_o + eta * cos_t);
+                let fresnel = 0.5 * (r_parallel * r_parallel + r_perpendicular * r_perpendicular);
+                if u1 < fresnel {
+                    Some(BsdfSample { wi: reflect(wo, n), weight: color, pdf: fresnel, specular: true })
+                } else {
+                    let wi = (&wo.smul(-1.0 / eta) + &n.smul(cos_o / eta - cos_t)).norm();
+                    Some(BsdfSample { wi: wi, weight: color, pdf: 1.0 - fresnel, specular: true })
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,55 @@
+//! Time-varying translations of spheres and the camera for motion blur.
+//!
+//! Objects only move, they do not turn: a sphere looks the same from every
+//! side unless it is textured, and triangles, which the importers flatten
+//! instanced meshes into, stay where they are.
+
+use std::sync::Arc;
+use std::default::Default;
+
+use vector::{Vector, VectorOps};
+
+#[derive(Debug, Copy, Clone)]
+pub struct Keyframe {
+    pub time: f64,
+    pub offset: Vector,
+}
+
+/// Time-varying translation of an object or the camera.
+#[derive(Debug, Clone)]
+pub enum Motion {
+    Static,
+    Linear(Vector), // velocity, the offset at time t is velocity * t
+    Keyframed(Arc<Vec<Keyframe>>), // offsets sorted by time, linearly interpolated
+}
+
+impl Default for Motion {
+    fn default() -> Motion {
+        Motion::Static
+    }
+}
+
+impl Motion {
+    pub fn offset(&self, time: f64) -> Vector {
+        match *self {
+            Motion::Static => Default::default(),
+            Motion::Linear(velocity) => velocity.smul(time),
+            Motion::Keyframed(ref keys) => {
+                if keys.len() == 0 {
+                    return Default::default();
+                }
+                if time <= keys[0].time {
+                    return keys[0].offset;
+                }
+                for i in 1..keys.len() {
+                    if time < keys[i].time {
+                        let (a, b) = (&keys[i-1], &keys[i]);
+                        let s = (time - a.time) / (b.time - a.time);
+                        return &a.offset.smul(1.0 - s) + &b.offset.smul(s);
+                    }
+                }
+                keys[keys.len()-1].offset
+            }
+        }
+    }
+}
@@ -0,0 +1,486 @@
+//! Rendering on several machines.
+//!
+//! A coordinator listens for workers and hands them the tiles of each pass.
+//! Workers connect over TCP and first get the scene, encoded by `wire` so it
+//! needs no files on their side, then jobs: a tile, the samples to add and
+//! the sums its pixels have so far. They render the tile as `tile` would and
+//! send the pixels back, which the coordinator puts into the film, so a
+//! render comes out the same no matter which machines took part. A worker
+//! may start one connection per thread and join or leave at any time. The
+//! tile of a worker that disconnects, or that stays silent for `TIMEOUT`
+//! seconds, is handed out again. If the render is interrupted while no
+//! worker is connected, the pass is given up rather than waited for.
+//!
+//! Both sides begin with the magic `RTNET003`. After that every message is
+//! a tag byte, the length of the rest as a little endian u64, at most
+//! `MAX_MESSAGE` bytes, and the rest:
+//!
+//! * scene: the scene, sent before the first job,
+//! * job: the seed, the kernel, the tile, the first sample and the number
+//!   of samples and the pixels of the tile,
+//! * result: the pixels of the tile and the statistics of rendering it,
+//! * alive: nothing, sent by a worker every `HEARTBEAT` milliseconds while
+//!   it renders a tile, so a long tile does not look like a lost worker. One
+//!   may also arrive late, after the result, and is skipped.
+
+use std::io;
+use std::io::prelude::*;
+use std::mem;
+use std::collections::VecDeque;
+use std::net::{TcpListener, TcpStream, Shutdown};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::default::Default;
+
+use scene::Scene;
+use film::Film;
+use tile::{self, Tile, Kernel, Settings};
+use stats::{self, Stats};
+use interrupt;
+use self::wire::{Writer, Reader, invalid};
+
+use time;
+
+pub mod wire;
+
+static MAGIC: &'static [u8] = b"RTNET003";
+
+const SCENE: u8 = 1;
+const JOB: u8 = 2;
+const RESULT: u8 = 3;
+const ALIVE: u8 = 4;
+
+/// Milliseconds between the alive messages of a worker.
+const HEARTBEAT: u32 = 10000;
+
+/// Seconds the coordinator waits on a worker before it counts as lost.
+const TIMEOUT: f64 = 60.0;
+
+/// Longest message accepted. The length is read before the message, so
+/// without a bound a corrupt or hostile header could make us allocate any
+/// amount of memory.
+pub const MAX_MESSAGE: u64 = 1 << 30;
+
+/// Reads until `buf` is full. Returns false if the stream ended before the
+/// first byte.
+fn read_full(stream: &mut TcpStream, buf: &mut [u8]) -> io::Result<bool> {
+    let mut pos = 0;
+    while pos < buf.len() {
+        match try!(stream.read(&mut buf[pos..])) {
+            0 if pos == 0 => return Ok(false),
+            0 => return Err(invalid("connection closed in the middle of a message")),
+            n => pos += n,
+        }
+    }
+    Ok(true)
+}
+
+fn send(stream: &mut TcpStream, tag: u8, payload: &[u8]) -> io::Result<()> {
+    if payload.len() as u64 > MAX_MESSAGE {
+        return Err(invalid("message too long"));
+    }
+    // In one write, a small header on its own would wait for the
+    // acknowledgement of the previous message
+    let mut message = Writer::new();
+    message.u8(tag);
+    message.usize(payload.len());
+    message.data.push_all(payload);
+    try!(stream.write_all(&message.data[..]));
+    stream.flush()
+}
+
+/// The next message, None once the other side closed the connection.
+fn receive(stream: &mut TcpStream) -> io::Result<Option<(u8, Vec<u8>)>> {
+    let mut header = [0u8; 9];
+    if !try!(read_full(stream, &mut header)) {
+        return Ok(None);
+    }
+    let mut r = Reader::new(&header);
+    let tag = try!(r.u8());
+    let length = try!(r.u64());
+    if length > MAX_MESSAGE {
+        return Err(invalid("message too long"));
+    }
+    let length = length as usize;
+    let mut payload = vec![0u8; length];
+    if !try!(read_full(stream, &mut payload[..])) && length > 0 {
+        return Err(invalid("connection closed in the middle of a message"));
+    }
+    Ok(Some((tag, payload)))
+}
+
+fn handshake(stream: &mut TcpStream) -> io::Result<()> {
+    try!(stream.write_all(MAGIC));
+    let mut magic = [0u8; 8];
+    if !try!(read_full(stream, &mut magic)) || &magic[..] != MAGIC {
+        return Err(invalid("not a raytracer on the other side"));
+    }
+    Ok(())
+}
+
+/// Shared between a connection and its watchdog.
+struct Watch {
+    deadline: Mutex<Option<f64>>, // in `time::precise_time_s`, None while idle
+    expired: AtomicBool,
+    closed: AtomicBool,
+}
+
+/// A worker as the coordinator sees it. A watchdog thread shuts the stream
+/// down once a deadline passes, which makes a read or write blocked on a
+/// silent worker fail, so its tile is handed out again.
+struct Connection {
+    stream: TcpStream,
+    peer: String,
+    has_scene: bool,
+    watch: Arc<Watch>,
+}
+
+impl Connection {
+    fn new(stream: TcpStream, peer: String) -> io::Result<Connection> {
+        let watched = try!(stream.try_clone());
+        let watch = Arc::new(Watch {
+            deadline: Mutex::new(None),
+            expired: AtomicBool::new(false),
+            closed: AtomicBool::new(false),
+        });
+        let dog = watch.clone();
+        thread::spawn(move || {
+            while !dog.closed.load(Ordering::SeqCst) {
+                thread::sleep_ms(1000);
+                let expired = match *dog.deadline.lock().unwrap() {
+                    Some(deadline) => time::precise_time_s() > deadline,
+                    None => false,
+                };
+                if expired {
+                    dog.expired.store(true, Ordering::SeqCst);
+                    let _ = watched.shutdown(Shutdown::Both);
+                    return;
+                }
+            }
+        });
+        Ok(Connection { stream: stream, peer: peer, has_scene: false, watch: watch })
+    }
+
+    /// Gives the worker `TIMEOUT` seconds from now to answer.
+    fn arm(&self) {
+        *self.watch.deadline.lock().unwrap() = Some(time::precise_time_s() + TIMEOUT);
+    }
+
+    fn disarm(&self) {
+        *self.watch.deadline.lock().unwrap() = None;
+    }
+
+    /// `result`, or an error if the watchdog gave up on the worker.
+    fn watched<T>(&self, result: io::Result<T>) -> io::Result<T> {
+        if self.watch.expired.load(Ordering::SeqCst) {
+            return Err(invalid("no answer in time"));
+        }
+        result
+    }
+
+    fn handshake(&mut self) -> io::Result<()> {
+        self.arm();
+        let result = handshake(&mut self.stream);
+        try!(self.watched(result));
+        self.disarm();
+        Ok(())
+    }
+
+    /// The next message.
+    fn receive(&mut self) -> io::Result<Option<(u8, Vec<u8>)>> {
+        let message = receive(&mut self.stream);
+        self.watched(message)
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.watch.closed.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Renders passes on the workers connected to it.
+pub struct Coordinator {
+    scene: Vec<u8>, // encoded
+    idle: Arc<Mutex<Vec<Connection>>>, // connected workers not busy with a pass
+}
+
+impl Coordinator {
+    /// Listens for workers on `address`, such as 0.0.0.0:7878, to render
+    /// `scene`. Workers are accepted in the background from now on.
+    pub fn listen(address: &str, scene: &Scene) -> io::Result<Coordinator> {
+        let listener = try!(TcpListener::bind(address));
+        let idle = Arc::new(Mutex::new(Vec::new()));
+        let accepted = idle.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                // On a thread of its own, so a client that says nothing
+                // does not hold up the others
+                let accepted = accepted.clone();
+                thread::spawn(move || {
+                    let peer = match stream.peer_addr() {
+                        Ok(a) => a.to_string(),
+                        Err(_) => "unknown".to_string(),
+                    };
+                    let connection = Connection::new(stream, peer.clone()).and_then(|mut connection| {
+                        try!(connection.handshake());
+                        Ok(connection)
+                    });
+                    match connection {
+                        Ok(connection) => {
+                            println!("\nWorker {} connected", peer);
+                            accepted.lock().unwrap().push(connection);
+                        }
+                        Err(e) => println!("\nRejected {}: {}", peer, e),
+                    }
+                });
+            }
+        });
+        let mut w = Writer::new();
+        w.scene(scene);
+        Ok(Coordinator { scene: w.data, idle: idle })
+    }
+
+    /// Adds samples `first..first + count` to every pixel of `film` on the
+    /// workers, like `tile::render_pass`, and returns what it took them.
+    /// Waits for workers while there are none, unless the render is
+    /// interrupted: then the pass is given up and `film` left as it was.
+    pub fn render_pass<F>(&self, film: &mut Film, settings: &Settings, first: usize, count: usize,
+                          progress: F) -> Stats
+        where F: Fn(usize, usize) + Sync
+    {
+        let start = time::precise_time_s();
+        assert!(film.samples == first);
+        let tiles = tile::tiles(film.x0, film.y0, film.x0 + film.width, film.y0 + film.height,
+                                settings.tile_size, settings.order);
+        let total = film.width * film.height;
+        let remaining = AtomicUsize::new(tiles.len()); // not yet in the film
+        let queue: Mutex<VecDeque<Tile>> = Mutex::new(tiles.into_iter().collect());
+        let active = AtomicUsize::new(0);
+        let done = AtomicUsize::new(0);
+        let totals: Mutex<Stats> = Mutex::new(Default::default());
+        let finished: Mutex<Vec<Connection>> = Mutex::new(Vec::new());
+        // To undo the tiles already done if the pass is given up
+        let before = film.pixels.clone();
+        {
+            let shared = Mutex::new(&mut *film);
+            let job = Job { seed: settings.seed, kernel: settings.kernel, first: first, count: count };
+            let mut workers = Vec::new();
+            let mut waiting = false;
+            while remaining.load(Ordering::SeqCst) > 0 {
+                let connections = mem::replace(&mut *self.idle.lock().unwrap(), Vec::new());
+                for connection in connections.into_iter() {
+                    active.fetch_add(1, Ordering::SeqCst);
+                    let (queue, remaining, active, done, film, totals, finished, progress) =
+                        (&queue, &remaining, &active, &done, &shared, &totals, &finished, &progress);
+                    let scene = &self.scene[..];
+                    workers.push(thread::scoped(move || {
+                        let mut connection = connection;
+                        loop {
+                            let tile = queue.lock().unwrap().pop_front();
+                            let tile = match tile {
+                                Some(tile) => tile,
+                                None if remaining.load(Ordering::SeqCst) == 0 => break,
+                                None => {
+                                    // Another worker may still drop out and leave its tile
+                                    thread::sleep_ms(50);
+                                    continue;
+                                }
+                            };
+                            match job.run(&mut connection, scene, &tile, film) {
+                                Ok(stats) => {
+                                    totals.lock().unwrap().add(&stats);
+                                    remaining.fetch_sub(1, Ordering::SeqCst);
+                                    let finished = done.fetch_add(tile.pixels(), Ordering::SeqCst) + tile.pixels();
+                                    progress(finished, total);
+                                }
+                                Err(e) => {
+                                    queue.lock().unwrap().push_back(tile);
+                                    println!("\nLost worker {}: {}", connection.peer, e);
+                                    active.fetch_sub(1, Ordering::SeqCst);
+                                    return;
+                                }
+                            }
+                        }
+                        finished.lock().unwrap().push(connection);
+                        active.fetch_sub(1, Ordering::SeqCst);
+                    }));
+                }
+                if active.load(Ordering::SeqCst) == 0 && remaining.load(Ordering::SeqCst) > 0 {
+                    // Nobody is left to finish the pass
+                    if interrupt::interrupted() {
+                        break;
+                    }
+                    if !waiting {
+                        println!("\nWaiting for workers...");
+                    }
+                    waiting = true;
+                } else {
+                    waiting = false;
+                }
+                thread::sleep_ms(100);
+            }
+            // Joins the threads
+            drop(workers);
+        }
+        if remaining.load(Ordering::SeqCst) == 0 {
+            film.samples += count;
+        } else {
+            film.pixels = before;
+        }
+        let finished = mem::replace(&mut *finished.lock().unwrap(), Vec::new());
+        self.idle.lock().unwrap().extend(finished.into_iter());
+        let mut stats = totals.lock().unwrap().clone();
+        stats.seconds = time::precise_time_s() - start;
+        stats
+    }
+}
+
+/// What the tiles of a pass have in common.
+#[derive(Copy, Clone)]
+struct Job {
+    seed: u64,
+    kernel: Kernel,
+    first: usize,
+    count: usize,
+}
+
+impl Job {
+    /// Renders `tile` on the worker at the other end of `connection` and puts
+    /// the result into `film`.
+    fn run(&self, connection: &mut Connection, scene: &[u8], tile: &Tile, film: &Mutex<&mut Film>)
+           -> io::Result<Stats> {
+        // Also covers the sends, which block while the worker reads nothing
+        connection.arm();
+        if !connection.has_scene {
+            try!(send(&mut connection.stream, SCENE, scene));
+            connection.has_scene = true;
+        }
+        let mut w = Writer::new();
+        w.u64(self.seed);
+        w.kernel(self.kernel);
+        w.tile(tile);
+        w.usize(self.first);
+        w.usize(self.count);
+        {
+            let film = film.lock().unwrap();
+            let mut pixels = Vec::with_capacity(tile.pixels());
+            for i in tile.y0..tile.y1 {
+                for j in tile.x0..tile.x1 {
+                    pixels.push(*film.pixel(j, i));
+                }
+            }
+            w.pixels(&pixels[..]);
+        }
+        try!(send(&mut connection.stream, JOB, &w.data[..]));
+
+        let mut message = try!(connection.receive());
+        while let Some((ALIVE, _)) = message {
+            connection.arm();
+            message = try!(connection.receive());
+        }
+        connection.disarm();
+        let data = match message {
+            Some((RESULT, data)) => data,
+            Some(_) => return Err(invalid("unexpected message")),
+            None => return Err(invalid("connection closed")),
+        };
+        let mut r = Reader::new(&data[..]);
+        let pixels = try!(r.pixels());
+        let stats = try!(r.stats());
+        if pixels.len() != tile.pixels() {
+            return Err(invalid("result does not match the tile"));
+        }
+        let mut film = film.lock().unwrap();
+        let width = tile.x1 - tile.x0;
+        for (index, p) in pixels.iter().enumerate() {
+            *film.pixel_mut(tile.x0 + index % width, tile.y0 + index / width) = *p;
+        }
+        Ok(stats)
+    }
+}
+
+/// Connects to the coordinator at `address`.
+pub fn connect(address: &str) -> io::Result<TcpStream> {
+    let mut stream = try!(TcpStream::connect(address));
+    try!(handshake(&mut stream));
+    Ok(stream)
+}
+
+/// What the worker's heartbeat thread goes by.
+struct Heartbeat {
+    rendering: AtomicBool,
+    stopped: AtomicBool,
+}
+
+/// Renders the jobs the coordinator sends over `stream` until it closes the
+/// connection. Returns the number of tiles rendered.
+pub fn work(stream: &mut TcpStream) -> io::Result<usize> {
+    // Messages go out through `writer` only, so the alive messages of the
+    // heartbeat thread never land in the middle of a result
+    let writer = Arc::new(Mutex::new(try!(stream.try_clone())));
+    let heartbeat = Arc::new(Heartbeat { rendering: AtomicBool::new(false), stopped: AtomicBool::new(false) });
+    {
+        let (writer, heartbeat) = (writer.clone(), heartbeat.clone());
+        thread::spawn(move || {
+            loop {
+                thread::sleep_ms(HEARTBEAT);
+                if heartbeat.stopped.load(Ordering::SeqCst) {
+                    return;
+                }
+                // A broken connection shows when sending the result
+                if heartbeat.rendering.load(Ordering::SeqCst) &&
+                   send(&mut *writer.lock().unwrap(), ALIVE, &[]).is_err() {
+                    return;
+                }
+            }
+        });
+    }
+    let result = serve(stream, &writer, &heartbeat);
+    heartbeat.stopped.store(true, Ordering::SeqCst);
+    result
+}
+
+fn serve(stream: &mut TcpStream, writer: &Mutex<TcpStream>, heartbeat: &Heartbeat) -> io::Result<usize> {
+    let mut scene: Option<Scene> = None;
+    let mut tiles = 0;
+    loop {
+        let (tag, data) = match try!(receive(stream)) {
+            Some(message) => message,
+            None => return Ok(tiles),
+        };
+        let mut r = Reader::new(&data[..]);
+        match tag {
+            SCENE => scene = Some(try!(r.scene())),
+            JOB => {
+                let scene = match scene {
+                    Some(ref scene) => scene,
+                    None => return Err(invalid("job before the scene")),
+                };
+                let seed = try!(r.u64());
+                let kernel = try!(r.kernel());
+                let tile = try!(r.tile());
+                let (first, count) = (try!(r.usize()), try!(r.usize()));
+                let mut pixels = try!(r.pixels());
+                if tile.x1 > scene.width || tile.y1 > scene.height || pixels.len() != tile.pixels() {
+                    return Err(invalid("job does not match the scene"));
+                }
+                stats::take();
+                heartbeat.rendering.store(true, Ordering::SeqCst);
+                kernel.render_tile(scene, &tile, seed, first, count, &mut pixels[..]);
+                heartbeat.rendering.store(false, Ordering::SeqCst);
+                let mut w = Writer::new();
+                w.pixels(&pixels[..]);
+                w.stats(&stats::take());
+                try!(send(&mut *writer.lock().unwrap(), RESULT, &w.data[..]));
+                tiles += 1;
+            }
+            _ => return Err(invalid("unexpected message")),
+        }
+    }
+}
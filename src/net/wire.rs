@@ -0,0 +1,473 @@
+//! Encoding scenes, pixels and statistics for the network.
+//!
+//! Everything is written as little endian u64 and f64 values, with a byte
+//! for the variant of enums. Images are sent once and referenced by number
+//! afterwards, textured meshes share them between thousands of triangles.
+
+use std::io;
+use std::mem;
+use std::sync::Arc;
+use std::default::Default;
+
+use vector::Vector;
+use ray::Ray;
+use camera::{Camera, Projection};
+use motion::{Motion, Keyframe};
+use material::Material;
+use texture::Texture;
+use image::Image;
+use shape::{Sphere, Triangle};
+use integrator::{Integrator, Sample};
+use scene::Scene;
+use film::Pixel;
+use tile::{Tile, Kernel};
+use stats::Stats;
+
+pub struct Writer {
+    pub data: Vec<u8>,
+    images: Vec<*const Image>,
+}
+
+impl Writer {
+    pub fn new() -> Writer {
+        Writer { data: Vec::new(), images: Vec::new() }
+    }
+
+    pub fn u8(&mut self, v: u8) {
+        self.data.push(v);
+    }
+
+    pub fn u64(&mut self, v: u64) {
+        for i in 0..8 {
+            self.data.push((v >> (8 * i)) as u8);
+        }
+    }
+
+    pub fn usize(&mut self, v: usize) {
+        self.u64(v as u64);
+    }
+
+    pub fn f64(&mut self, v: f64) {
+        let bits: u64 = unsafe { mem::transmute(v) };
+        self.u64(bits);
+    }
+
+    pub fn vector(&mut self, v: &Vector) {
+        self.f64(v.x);
+        self.f64(v.y);
+        self.f64(v.z);
+    }
+
+    fn ray(&mut self, r: &Ray) {
+        self.vector(&r.o);
+        self.vector(&r.d);
+        self.f64(r.time);
+    }
+
+    fn motion(&mut self, m: &Motion) {
+        match *m {
+            Motion::Static => self.u8(0),
+            Motion::Linear(v) => {
+                self.u8(1);
+                self.vector(&v);
+            }
+            Motion::Keyframed(ref keys) => {
+                self.u8(2);
+                self.usize(keys.len());
+                for k in keys.iter() {
+                    self.f64(k.time);
+                    self.vector(&k.offset);
+                }
+            }
+        }
+    }
+
+    fn material(&mut self, m: &Material) {
+        match *m {
+            Material::Diffuse => self.u8(0),
+            Material::Glossy(exponent) => {
+                self.u8(1);
+                self.f64(exponent);
+            }
+            Material::Mirror => self.u8(2),
+            Material::Dielectric(ior) => {
+                self.u8(3);
+                self.f64(ior);
+            }
+        }
+    }
+
+    fn image(&mut self, img: &Arc<Image>) {
+        let ptr: *const Image = &**img;
+        match self.images.iter().position(|p| *p == ptr) {
+            Some(index) => self.usize(index),
+            None => {
+                let index = self.images.len();
+                self.usize(index);
+                self.images.push(ptr);
+                self.usize(img.width);
+                self.usize(img.height);
+                self.usize(img.channels);
+                for v in img.data.iter() {
+                    self.f64(*v);
+                }
+            }
+        }
+    }
+
+    fn texture(&mut self, t: &Option<Texture>) {
+        match *t {
+            None => self.u8(0),
+            Some(Texture::Constant(c)) => {
+                self.u8(1);
+                self.vector(&c);
+            }
+            Some(Texture::Checker(a, b, size)) => {
+                self.u8(2);
+                self.vector(&a);
+                self.vector(&b);
+                self.f64(size);
+            }
+            Some(Texture::Image(ref img)) => {
+                self.u8(3);
+                self.image(img);
+            }
+            Some(Texture::Mapped(ref img)) => {
+                self.u8(4);
+                self.image(img);
+            }
+        }
+    }
+
+    pub fn scene(&mut self, scene: &Scene) {
+        let c = &scene.camera;
+        self.ray(&c.eye);
+        self.vector(&c.right);
+        self.vector(&c.up);
+        match c.projection {
+            Projection::Perspective(d) => { self.u8(0); self.f64(d); }
+            Projection::Orthographic(s) => { self.u8(1); self.f64(s); }
+            Projection::Fov(fov) => { self.u8(2); self.f64(fov); }
+        }
+        self.f64(c.shutter_open);
+        self.f64(c.shutter_close);
+        self.motion(&c.motion);
+        self.motion(&c.pan);
+
+        self.usize(scene.spheres.len());
+        for s in scene.spheres.iter() {
+            self.f64(s.radius);
+            self.vector(&s.position);
+            self.vector(&s.emission);
+            self.vector(&s.color);
+            self.texture(&s.texture);
+            self.material(&s.material);
+            self.motion(&s.motion);
+        }
+        self.usize(scene.triangles.len());
+        for t in scene.triangles.iter() {
+            for v in t.vertices.iter() {
+                self.vector(v);
+            }
+            self.vector(&t.emission);
+            self.vector(&t.color);
+            self.material(&t.material);
+            self.texture(&t.texture);
+            for &(u, v) in t.uv.iter() {
+                self.f64(u);
+                self.f64(v);
+            }
+        }
+
+        self.usize(scene.width);
+        self.usize(scene.height);
+        self.usize(scene.samples);
+        self.u8(match scene.integrator {
+            Integrator::Flat => 0,
+            Integrator::Diffuse => 1,
+            Integrator::Path => 2,
+        });
+        self.usize(scene.max_depth);
+        self.vector(&scene.background);
+        self.vector(&scene.light);
+    }
+
+    pub fn tile(&mut self, t: &Tile) {
+        self.usize(t.x0);
+        self.usize(t.y0);
+        self.usize(t.x1);
+        self.usize(t.y1);
+    }
+
+    pub fn kernel(&mut self, k: Kernel) {
+        match k {
+            Kernel::Megakernel => self.u8(0),
+            Kernel::Wavefront => self.u8(1),
+        }
+    }
+
+    pub fn pixels(&mut self, pixels: &[Pixel]) {
+        self.usize(pixels.len());
+        for p in pixels.iter() {
+            let s = &p.sum;
+            self.vector(&s.direct);
+            self.vector(&s.indirect);
+            self.vector(&s.albedo);
+            self.vector(&s.normal);
+            self.vector(&s.position);
+            self.f64(s.depth);
+            self.f64(s.id);
+            self.f64(p.sum_sq);
+            self.f64(p.weight);
+        }
+    }
+
+    pub fn stats(&mut self, s: &Stats) {
+        self.u64(s.camera_rays);
+        self.u64(s.secondary_rays);
+        self.u64(s.shadow_rays);
+        self.u64(s.intersection_tests);
+        self.u64(s.bvh_nodes);
+        self.usize(s.path_lengths.len());
+        for n in s.path_lengths.iter() {
+            self.u64(*n);
+        }
+    }
+}
+
+pub fn invalid(desc: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, desc, None)
+}
+
+pub struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    images: Vec<Arc<Image>>,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data: data, pos: 0, images: Vec::new() }
+    }
+
+    pub fn u8(&mut self) -> io::Result<u8> {
+        if self.pos >= self.data.len() {
+            return Err(invalid("truncated message"));
+        }
+        self.pos += 1;
+        Ok(self.data[self.pos - 1])
+    }
+
+    pub fn u64(&mut self) -> io::Result<u64> {
+        if self.pos + 8 > self.data.len() {
+            return Err(invalid("truncated message"));
+        }
+        let mut v = 0;
+        for i in 0..8 {
+            v |= (self.data[self.pos + i] as u64) << (8 * i);
+        }
+        self.pos += 8;
+        Ok(v)
+    }
+
+    pub fn usize(&mut self) -> io::Result<usize> {
+        Ok(try!(self.u64()) as usize)
+    }
+
+    /// A count of items of at least `size` bytes each, checked against the
+    /// rest of the message before anything is allocated for them.
+    fn count(&mut self, size: usize) -> io::Result<usize> {
+        let n = try!(self.usize());
+        if n > (self.data.len() - self.pos) / size {
+            return Err(invalid("truncated message"));
+        }
+        Ok(n)
+    }
+
+    pub fn f64(&mut self) -> io::Result<f64> {
+        let bits = try!(self.u64());
+        Ok(unsafe { mem::transmute(bits) })
+    }
+
+    pub fn vector(&mut self) -> io::Result<Vector> {
+        Ok(Vector::new(try!(self.f64()), try!(self.f64()), try!(self.f64())))
+    }
+
+    fn ray(&mut self) -> io::Result<Ray> {
+        Ok(Ray { o: try!(self.vector()), d: try!(self.vector()), time: try!(self.f64()) })
+    }
+
+    fn motion(&mut self) -> io::Result<Motion> {
+        Ok(match try!(self.u8()) {
+            0 => Motion::Static,
+            1 => Motion::Linear(try!(self.vector())),
+            2 => {
+                let n = try!(self.count(32));
+                let mut keys = Vec::with_capacity(n);
+                for _ in 0..n {
+                    keys.push(Keyframe { time: try!(self.f64()), offset: try!(self.vector()) });
+                }
+                Motion::Keyframed(Arc::new(keys))
+            }
+            _ => return Err(invalid("unknown motion")),
+        })
+    }
+
+    fn material(&mut self) -> io::Result<Material> {
+        Ok(match try!(self.u8()) {
+            0 => Material::Diffuse,
+            1 => Material::Glossy(try!(self.f64())),
+            2 => Material::Mirror,
+            3 => Material::Dielectric(try!(self.f64())),
+            _ => return Err(invalid("unknown material")),
+        })
+    }
+
+    fn image(&mut self) -> io::Result<Arc<Image>> {
+        let index = try!(self.usize());
+        if index < self.images.len() {
+            return Ok(self.images[index].clone());
+        }
+        if index > self.images.len() {
+            return Err(invalid("image sent out of order"));
+        }
+        let (width, height) = (try!(self.usize()), try!(self.usize()));
+        let channels = try!(self.usize());
+        if channels != 1 && channels != 3 {
+            return Err(invalid("image with an unsupported number of channels"));
+        }
+        let values = match width.checked_mul(height).and_then(|n| n.checked_mul(channels)) {
+            Some(values) => values,
+            None => return Err(invalid("image too large")),
+        };
+        if values > (self.data.len() - self.pos) / 8 {
+            return Err(invalid("truncated message"));
+        }
+        let mut img = Image::new(width, height, channels);
+        for v in img.data.iter_mut() {
+            *v = try!(self.f64());
+        }
+        let img = Arc::new(img);
+        self.images.push(img.clone());
+        Ok(img)
+    }
+
+    fn texture(&mut self) -> io::Result<Option<Texture>> {
+        Ok(match try!(self.u8()) {
+            0 => None,
+            1 => Some(Texture::Constant(try!(self.vector()))),
+            2 => Some(Texture::Checker(try!(self.vector()), try!(self.vector()), try!(self.f64()))),
+            3 => Some(Texture::Image(try!(self.image()))),
+            4 => Some(Texture::Mapped(try!(self.image()))),
+            _ => return Err(invalid("unknown texture")),
+        })
+    }
+
+    pub fn scene(&mut self) -> io::Result<Scene> {
+        let mut camera: Camera = Default::default();
+        camera.eye = try!(self.ray());
+        camera.right = try!(self.vector());
+        camera.up = try!(self.vector());
+        camera.projection = match try!(self.u8()) {
+            0 => Projection::Perspective(try!(self.f64())),
+            1 => Projection::Orthographic(try!(self.f64())),
+            2 => Projection::Fov(try!(self.f64())),
+            _ => return Err(invalid("unknown projection")),
+        };
+        camera.shutter_open = try!(self.f64());
+        camera.shutter_close = try!(self.f64());
+        camera.motion = try!(self.motion());
+        camera.pan = try!(self.motion());
+
+        let n = try!(self.count(8));
+        let mut spheres = Vec::with_capacity(n);
+        for _ in 0..n {
+            let mut s = Sphere::new(try!(self.f64()), try!(self.vector()), try!(self.vector()), try!(self.vector()));
+            s.texture = try!(self.texture());
+            s.material = try!(self.material());
+            s.motion = try!(self.motion());
+            spheres.push(s);
+        }
+        let n = try!(self.count(8));
+        let mut triangles = Vec::with_capacity(n);
+        for _ in 0..n {
+            let vertices = [try!(self.vector()), try!(self.vector()), try!(self.vector())];
+            let mut t = Triangle::new(vertices, try!(self.vector()), try!(self.vector()));
+            t.material = try!(self.material());
+            t.texture = try!(self.texture());
+            for uv in t.uv.iter_mut() {
+                *uv = (try!(self.f64()), try!(self.f64()));
+            }
+            triangles.push(t);
+        }
+
+        let (width, height) = (try!(self.usize()), try!(self.usize()));
+        let samples = try!(self.usize());
+        let integrator = match try!(self.u8()) {
+            0 => Integrator::Flat,
+            1 => Integrator::Diffuse,
+            2 => Integrator::Path,
+            _ => return Err(invalid("unknown integrator")),
+        };
+        Ok(Scene {
+            camera: camera,
+            spheres: spheres,
+            triangles: triangles,
+            width: width,
+            height: height,
+            samples: samples,
+            integrator: integrator,
+            max_depth: try!(self.usize()),
+            background: try!(self.vector()),
+            light: try!(self.vector()),
+        })
+    }
+
+    pub fn tile(&mut self) -> io::Result<Tile> {
+        let t = Tile { x0: try!(self.usize()), y0: try!(self.usize()), x1: try!(self.usize()), y1: try!(self.usize()) };
+        if t.x0 >= t.x1 || t.y0 >= t.y1 {
+            return Err(invalid("empty tile"));
+        }
+        Ok(t)
+    }
+
+    pub fn kernel(&mut self) -> io::Result<Kernel> {
+        Ok(match try!(self.u8()) {
+            0 => Kernel::Megakernel,
+            1 => Kernel::Wavefront,
+            _ => return Err(invalid("unknown kernel")),
+        })
+    }
+
+    pub fn pixels(&mut self) -> io::Result<Vec<Pixel>> {
+        let n = try!(self.count(19 * 8));
+        let mut pixels = Vec::with_capacity(n);
+        for _ in 0..n {
+            let sum = Sample {
+                direct: try!(self.vector()),
+                indirect: try!(self.vector()),
+                albedo: try!(self.vector()),
+                normal: try!(self.vector()),
+                position: try!(self.vector()),
+                depth: try!(self.f64()),
+                id: try!(self.f64()),
+            };
+            pixels.push(Pixel { sum: sum, sum_sq: try!(self.f64()), weight: try!(self.f64()) });
+        }
+        Ok(pixels)
+    }
+
+    pub fn stats(&mut self) -> io::Result<Stats> {
+        let mut s: Stats = Default::default();
+        s.camera_rays = try!(self.u64());
+        s.secondary_rays = try!(self.u64());
+        s.shadow_rays = try!(self.u64());
+        s.intersection_tests = try!(self.u64());
+        s.bvh_nodes = try!(self.u64());
+        let n = try!(self.count(8));
+        for _ in 0..n {
+            s.path_lengths.push(try!(self.u64()));
+        }
+        Ok(s)
+    }
+}
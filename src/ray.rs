@@ -0,0 +1,33 @@
+use std::num::Float;
+
+use vector::{Vector, VectorOps};
+use float::{next_float_up, next_float_down};
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Ray {
+    pub o: Vector,
+    pub d: Vector,
+    pub time: f64 // instant within the shutter interval the ray samples
+}
+
+impl Ray {
+    /// A ray leaving the surface point `p` in direction `d`. `error` bounds
+    /// the absolute error of each coordinate of `p` and `n` is the surface
+    /// normal. The origin is pushed along the normal, to the side `d` points
+    /// to, just past the region `p` may lie in, so the ray cannot hit the
+    /// surface it starts on again.
+    pub fn spawn(p: Vector, error: Vector, n: Vector, d: Vector, time: f64) -> Ray {
+        let dist = n.x.abs() * error.x + n.y.abs() * error.y + n.z.abs() * error.z;
+        let mut offset = n.smul(dist);
+        if d.dot(&n) < 0.0 {
+            offset = offset.smul(-1.0);
+        }
+        let mut o = &p + &offset;
+        // Round away from p
+        let away = |v: f64, off: f64| if off > 0.0 { next_float_up(v) } else if off < 0.0 { next_float_down(v) } else { v };
+        o.x = away(o.x, offset.x);
+        o.y = away(o.y, offset.y);
+        o.z = away(o.z, offset.z);
+        Ray { o: o, d: d, time: time }
+    }
+}
@@ -0,0 +1,75 @@
+//! A small seedable random number generator.
+//!
+//! Renders have to be reproducible for a given seed no matter how pixels are
+//! distributed over threads, so every pixel, or every tile for the tiled
+//! renderer, draws from its own generator seeded from the scene seed and its
+//! position. The tiled renderer starts a new generator for every sample
+//! number, so an image rendered in several passes comes out the same as one
+//! rendered at once. The generator is Marsaglia's xorshift128, its state is
+//! seeded by hashing the seed, position and sample number with splitmix64.
+
+use std::num::wrapping::WrappingOps;
+
+/// xorshift128 state.
+#[derive(Debug, Copy, Clone)]
+pub struct Rng {
+    x: u32,
+    y: u32,
+    z: u32,
+    w: u32,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng::with_stream(seed, 0, 0)
+    }
+
+    /// Generator for the pixel or tile at row `i` and column `j` of a render
+    /// with the given seed.
+    pub fn with_stream(seed: u64, i: usize, j: usize) -> Rng {
+        Rng::with_sample(seed, i, j, 0)
+    }
+
+    /// Generator for sample number `sample` of the pixel or tile at row `i`
+    /// and column `j`, so progressive renders can draw any sample without
+    /// the ones before it.
+    pub fn with_sample(seed: u64, i: usize, j: usize, sample: usize) -> Rng {
+        // Hashing the inputs one after the other keeps streams that differ
+        // in a single input, or swap two, from sharing a state
+        let mut h = splitmix64(seed);
+        for &v in [i as u64, j as u64, sample as u64].iter() {
+            h = splitmix64(h ^ v);
+        }
+        let (a, b) = (splitmix64(h), splitmix64(h ^ 0x9e3779b97f4a7c15));
+        let mut rng = Rng { x: a as u32, y: (a >> 32) as u32, z: b as u32, w: (b >> 32) as u32 };
+        // xorshift never leaves the all zero state
+        if rng.x == 0 && rng.y == 0 && rng.z == 0 && rng.w == 0 {
+            rng.w = 0x7f4a7c15;
+        }
+        rng
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let t = self.x ^ (self.x << 11);
+        self.x = self.y;
+        self.y = self.z;
+        self.z = self.w;
+        self.w = self.w ^ (self.w >> 19) ^ (t ^ (t >> 8));
+        self.w
+    }
+
+    /// Uniform in [0, 1) with 53 random bits.
+    pub fn next_f64(&mut self) -> f64 {
+        let a = (self.next_u32() >> 5) as f64;
+        let b = (self.next_u32() >> 6) as f64;
+        (a * 67108864.0 + b) / 9007199254740992.0
+    }
+}
+
+/// The splitmix64 finalizer, a bijection on 64 bits with good avalanche.
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
@@ -0,0 +1,377 @@
+//! Building scenes in code.
+//!
+//! ```ignore
+//! let scene = Scene::builder()
+//!     .size(1024, 768)
+//!     .samples(5000)
+//!     .camera((50.0, 52.0, 295.6), (0.0, -0.042612, -1.0), (1.0, 0.0, 0.0), Projection::Perspective(2.0))
+//!     .material("red", MaterialDef::diffuse((0.75, 0.25, 0.25)))
+//!     .sphere("left", 1e5, (1e5 + 1.0, 40.8, 81.6), "red")
+//!     .light("lamp", 600.0, (50.0, 681.33, 81.6), (12.0, 12.0, 12.0))
+//!     .build()
+//!     .unwrap();
+//! ```
+//!
+//! Materials and objects are named. Objects refer to materials by name and
+//! may do so before the material is added; names show up in the errors of
+//! `build`, which checks the whole scene. The defaults are those of scene
+//! files: a 512x512 image with one sample per pixel, path tracing, a black
+//! background and a camera at the origin looking along -z.
+
+use std::fmt;
+use std::num::Float;
+use std::collections::HashMap;
+use std::default::Default;
+
+use vector::{Vector, VectorOps, IntoVector};
+use shape::{Sphere, Triangle};
+use material::Material;
+use texture::Texture;
+use motion::Motion;
+use camera::{Camera, Projection};
+use integrator::{Integrator, MAX_DEPTH};
+use super::Scene;
+
+/// What a material name stands for: how light scatters, the surface color
+/// (or a texture replacing it) and the emitted radiance.
+#[derive(Debug, Clone)]
+pub struct MaterialDef {
+    pub material: Material,
+    pub color: Vector,
+    pub texture: Option<Texture>,
+    pub emission: Vector,
+}
+
+impl MaterialDef {
+    pub fn new<C: IntoVector>(material: Material, color: C) -> MaterialDef {
+        MaterialDef { material: material, color: color.into_vector(), texture: None, emission: Vector::new(0.0, 0.0, 0.0) }
+    }
+
+    pub fn diffuse<C: IntoVector>(color: C) -> MaterialDef {
+        MaterialDef::new(Material::Diffuse, color)
+    }
+
+    pub fn mirror<C: IntoVector>(color: C) -> MaterialDef {
+        MaterialDef::new(Material::Mirror, color)
+    }
+
+    /// A Phong lobe with the given exponent.
+    pub fn glossy<C: IntoVector>(exponent: f64, color: C) -> MaterialDef {
+        MaterialDef::new(Material::Glossy(exponent), color)
+    }
+
+    /// Smooth glass with the given index of refraction.
+    pub fn dielectric<C: IntoVector>(ior: f64, color: C) -> MaterialDef {
+        MaterialDef::new(Material::Dielectric(ior), color)
+    }
+
+    pub fn texture(mut self, texture: Texture) -> MaterialDef {
+        self.texture = Some(texture);
+        self
+    }
+
+    pub fn emission<C: IntoVector>(mut self, emission: C) -> MaterialDef {
+        self.emission = emission.into_vector();
+        self
+    }
+}
+
+/// Why `SceneBuilder::build` rejected a scene. Objects and materials are
+/// given by name.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BuildError {
+    /// Zero width, height or samples.
+    EmptyImage,
+    /// A camera that does not define a view, with the reason.
+    InvalidCamera(String),
+    /// Two materials or two objects with the same name.
+    DuplicateName(String),
+    /// A NaN or infinite number, with what it belongs to.
+    NotFinite(String),
+    /// A parameter of a material outside its range, with the reason.
+    InvalidMaterial(String, String),
+    /// A sphere or light with a radius that is not positive.
+    DegenerateRadius(String, f64),
+    /// A triangle without area.
+    DegenerateTriangle(String),
+    /// An object and the material it refers to, which was never added.
+    MissingMaterial(String, String),
+    /// An object name that was set up with `motion` but never added.
+    UnknownObject(String),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BuildError::EmptyImage => write!(f, "image width, height and samples have to be positive"),
+            BuildError::InvalidCamera(ref why) => write!(f, "invalid camera: {}", why),
+            BuildError::DuplicateName(ref name) => write!(f, "\"{}\" is defined twice", name),
+            BuildError::NotFinite(ref what) => write!(f, "{} is not finite", what),
+            BuildError::InvalidMaterial(ref name, ref why) => write!(f, "material \"{}\": {}", name, why),
+            BuildError::DegenerateRadius(ref name, r) => write!(f, "\"{}\" has radius {}", name, r),
+            BuildError::DegenerateTriangle(ref name) => write!(f, "triangle \"{}\" has no area", name),
+            BuildError::MissingMaterial(ref name, ref material) =>
+                write!(f, "\"{}\" uses the unknown material \"{}\"", name, material),
+            BuildError::UnknownObject(ref name) => write!(f, "there is no object \"{}\"", name),
+        }
+    }
+}
+
+enum Kind {
+    Sphere(f64, Vector, String), // radius, center, material
+    Light(f64, Vector, Vector), // radius, center, emission
+    Triangle([Vector; 3], String),
+}
+
+struct Object {
+    name: String,
+    kind: Kind,
+    motion: Motion,
+}
+
+pub struct SceneBuilder {
+    scene: Scene,
+    materials: Vec<(String, MaterialDef)>,
+    objects: Vec<Object>,
+    motions: Vec<(String, Motion)>,
+}
+
+impl SceneBuilder {
+    pub fn new() -> SceneBuilder {
+        let mut camera: Camera = Default::default();
+        camera.eye.d = Vector::new(0.0, 0.0, -1.0);
+        camera.up = Vector::new(0.0, 1.0, 0.0);
+        SceneBuilder {
+            scene: Scene {
+                camera: camera,
+                spheres: Vec::new(),
+                triangles: Vec::new(),
+                width: 512,
+                height: 512,
+                samples: 1,
+                integrator: Integrator::Path,
+                max_depth: MAX_DEPTH,
+                background: Vector::new(0.0, 0.0, 0.0),
+                light: Vector::new(0.0, 0.0, 1.0),
+            },
+            materials: Vec::new(),
+            objects: Vec::new(),
+            motions: Vec::new(),
+        }
+    }
+
+    pub fn size(mut self, width: usize, height: usize) -> SceneBuilder {
+        self.scene.width = width;
+        self.scene.height = height;
+        self
+    }
+
+    /// Samples per pixel.
+    pub fn samples(mut self, samples: usize) -> SceneBuilder {
+        self.scene.samples = samples;
+        self
+    }
+
+    pub fn integrator(mut self, integrator: Integrator) -> SceneBuilder {
+        self.scene.integrator = integrator;
+        self
+    }
+
+    /// Bounces after which paths only add the emission they reach.
+    pub fn max_depth(mut self, max_depth: usize) -> SceneBuilder {
+        self.scene.max_depth = max_depth;
+        self
+    }
+
+    /// Radiance of rays that leave the scene.
+    pub fn background<C: IntoVector>(mut self, color: C) -> SceneBuilder {
+        self.scene.background = color.into_vector();
+        self
+    }
+
+    /// Places the camera at `eye`, looking along `direction`. Image rows
+    /// advance along `up` crossed with the backwards direction, as for
+    /// `Camera`.
+    pub fn camera<E: IntoVector, D: IntoVector, U: IntoVector>(mut self, eye: E, direction: D, up: U,
+                                                              projection: Projection) -> SceneBuilder {
+        self.scene.camera.eye.o = eye.into_vector();
+        self.scene.camera.eye.d = direction.into_vector();
+        self.scene.camera.up = up.into_vector();
+        self.scene.camera.projection = projection;
+        self
+    }
+
+    /// The time interval the camera sees, objects with `motion` blur over it.
+    pub fn shutter(mut self, open: f64, close: f64) -> SceneBuilder {
+        self.scene.camera.shutter_open = open;
+        self.scene.camera.shutter_close = close;
+        self
+    }
+
+    pub fn material(mut self, name: &str, def: MaterialDef) -> SceneBuilder {
+        self.materials.push((name.to_string(), def));
+        self
+    }
+
+    pub fn sphere<C: IntoVector>(mut self, name: &str, radius: f64, center: C, material: &str) -> SceneBuilder {
+        self.add(name, Kind::Sphere(radius, center.into_vector(), material.to_string()))
+    }
+
+    /// A single sided triangle, facing the side the vertices run
+    /// counterclockwise on.
+    pub fn triangle<A: IntoVector, B: IntoVector, C: IntoVector>(self, name: &str, a: A, b: B, c: C,
+                                                                 material: &str) -> SceneBuilder {
+        let vertices = [a.into_vector(), b.into_vector(), c.into_vector()];
+        self.add(name, Kind::Triangle(vertices, material.to_string()))
+    }
+
+    /// A black sphere emitting `emission`.
+    pub fn light<C: IntoVector, E: IntoVector>(self, name: &str, radius: f64, center: C, emission: E) -> SceneBuilder {
+        self.add(name, Kind::Light(radius, center.into_vector(), emission.into_vector()))
+    }
+
+    /// The direction towards the light of the diffuse integrator.
+    pub fn light_direction<D: IntoVector>(mut self, direction: D) -> SceneBuilder {
+        self.scene.light = direction.into_vector();
+        self
+    }
+
+    /// Moves the object `name` over the shutter interval.
+    pub fn motion(mut self, name: &str, motion: Motion) -> SceneBuilder {
+        self.motions.push((name.to_string(), motion));
+        self
+    }
+
+    fn add(mut self, name: &str, kind: Kind) -> SceneBuilder {
+        self.objects.push(Object { name: name.to_string(), kind: kind, motion: Motion::Static });
+        self
+    }
+
+    /// Checks the scene and puts it together.
+    pub fn build(self) -> Result<Scene, BuildError> {
+        let mut scene = self.scene;
+        if scene.width == 0 || scene.height == 0 || scene.samples == 0 {
+            return Err(BuildError::EmptyImage);
+        }
+        try!(check_camera(&scene.camera));
+        try!(finite(scene.background, "background"));
+        try!(finite(scene.light, "light direction"));
+        if scene.light.dot(&scene.light) > 0.0 {
+            scene.light = scene.light.norm();
+        }
+
+        let mut materials = HashMap::new();
+        for &(ref name, ref def) in self.materials.iter() {
+            try!(check_material(name, def));
+            if materials.insert(name.clone(), def.clone()).is_some() {
+                return Err(BuildError::DuplicateName(name.clone()));
+            }
+        }
+
+        let mut objects = self.objects;
+        for &(ref name, ref motion) in self.motions.iter() {
+            match objects.iter_mut().find(|o| o.name == *name) {
+                Some(object) => object.motion = motion.clone(),
+                None => return Err(BuildError::UnknownObject(name.clone())),
+            }
+        }
+
+        let mut names = HashMap::new();
+        for object in objects.into_iter() {
+            let name = object.name;
+            if names.insert(name.clone(), ()).is_some() {
+                return Err(BuildError::DuplicateName(name));
+            }
+            let material = |m: &String| match materials.get(m) {
+                Some(def) => Ok(def.clone()),
+                None => Err(BuildError::MissingMaterial(name.clone(), m.clone())),
+            };
+            match object.kind {
+                Kind::Sphere(radius, center, ref m) => {
+                    try!(check_radius(&name, radius));
+                    try!(finite(center, &format!("center of \"{}\"", name)[..]));
+                    let def = try!(material(m));
+                    scene.spheres.push(Sphere {
+                        radius: radius,
+                        position: center,
+                        emission: def.emission,
+                        color: def.color,
+                        texture: def.texture,
+                        material: def.material,
+                        motion: object.motion,
+                    });
+                }
+                Kind::Light(radius, center, emission) => {
+                    try!(check_radius(&name, radius));
+                    try!(finite(center, &format!("center of \"{}\"", name)[..]));
+                    try!(finite(emission, &format!("emission of \"{}\"", name)[..]));
+                    let mut sphere = Sphere::new(radius, center, emission, Vector::new(0.0, 0.0, 0.0));
+                    sphere.motion = object.motion;
+                    scene.spheres.push(sphere);
+                }
+                Kind::Triangle(vertices, ref m) => {
+                    for v in vertices.iter() {
+                        try!(finite(*v, &format!("vertex of \"{}\"", name)[..]));
+                    }
+                    let n = (&vertices[1] - &vertices[0]).cross(&vertices[2] - &vertices[0]);
+                    if !(n.dot(&n) > 0.0) {
+                        return Err(BuildError::DegenerateTriangle(name.clone()));
+                    }
+                    let def = try!(material(m));
+                    let mut triangle = Triangle::new(vertices, def.emission, def.color);
+                    triangle.material = def.material;
+                    triangle.texture = def.texture;
+                    scene.triangles.push(triangle);
+                }
+            }
+        }
+        Ok(scene)
+    }
+}
+
+fn finite(v: Vector, what: &str) -> Result<(), BuildError> {
+    if v.x.is_finite() && v.y.is_finite() && v.z.is_finite() {
+        Ok(())
+    } else {
+        Err(BuildError::NotFinite(what.to_string()))
+    }
+}
+
+fn check_radius(name: &String, radius: f64) -> Result<(), BuildError> {
+    if radius > 0.0 && radius.is_finite() { Ok(()) } else { Err(BuildError::DegenerateRadius(name.clone(), radius)) }
+}
+
+fn invalid(why: &str) -> Result<(), BuildError> {
+    Err(BuildError::InvalidCamera(why.to_string()))
+}
+
+fn check_camera(camera: &Camera) -> Result<(), BuildError> {
+    try!(finite(camera.eye.o, "camera eye"));
+    try!(finite(camera.eye.d, "camera direction"));
+    try!(finite(camera.up, "camera up"));
+    if camera.eye.d.dot(&camera.eye.d) == 0.0 {
+        return invalid("the direction is zero");
+    }
+    let side = camera.up.cross(camera.eye.d);
+    if !(side.dot(&side) > 0.0) {
+        return invalid("up is zero or parallel to the direction");
+    }
+    match camera.projection {
+        Projection::Perspective(d) if !(d > 0.0 && d.is_finite()) => invalid("the image plane distance is not positive"),
+        Projection::Orthographic(e) if !(e > 0.0 && e.is_finite()) => invalid("the extent is not positive"),
+        Projection::Fov(fov) if !(fov > 0.0 && fov < 180.0) => invalid("the field of view is not between 0 and 180 degrees"),
+        _ => Ok(()),
+    }
+}
+
+fn check_material(name: &String, def: &MaterialDef) -> Result<(), BuildError> {
+    try!(finite(def.color, &format!("color of \"{}\"", name)[..]));
+    try!(finite(def.emission, &format!("emission of \"{}\"", name)[..]));
+    match def.material {
+        Material::Glossy(e) if !(e > 0.0 && e.is_finite()) =>
+            Err(BuildError::InvalidMaterial(name.clone(), format!("Phong exponent {} is not positive", e))),
+        Material::Dielectric(ior) if !(ior > 0.0 && ior.is_finite()) =>
+            Err(BuildError::InvalidMaterial(name.clone(), format!("index of refraction {} is not positive", ior))),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,592 @@
+//! The native scene description format.
+//!
+//! A scene file is a sequence of statements. `#` starts a comment that runs
+//! to the end of the line. Numbers are decimal with an optional exponent,
+//! strings are in double quotes and file names in strings are relative to
+//! the file they appear in. The properties within a block may come in any
+//! order, textures and materials have to be defined before they are used.
+//!
+//! ```text
+//! include "common.scene"          # the statements of another file, in place
+//!
+//! film { width 1024 height 768 samples 5000 }
+//! integrator path                 # flat, diffuse or path
+//! background 0 0 0                # radiance of rays that leave the scene
+//!
+//! camera {
+//!     eye 50 52 295.6
+//!     direction 0 -0.042612 -1    # or: look_at <x y z>
+//!     up 1 0 0
+//!     perspective 2               # image plane distance, or: orthographic <half extent>
+//!                                 # or: fov <degrees across the shorter image side>
+//!     shutter 0 0                 # open and close time
+//!     velocity 0 0 0              # movement of the eye per unit of time
+//!     pan 0 0 0                   # change of the viewing direction per unit of time
+//! }
+//!
+//! texture "gray" { constant 0.5 0.5 0.5 }
+//! texture "checks" { checker 0.9 0.9 0.9 0.1 0.1 0.1 size 10 }
+//! texture "earth" { image "earth.png" }   # latitude-longitude map
+//!
+//! material "red" { diffuse color 0.75 0.25 0.25 }
+//! material "floor" { diffuse texture "checks" }
+//! material "metal" { glossy 50 color 0.9 0.9 0.9 }    # Phong exponent
+//! material "chrome" { mirror color 0.999 0.999 0.999 }
+//! material "glass" { dielectric 1.5 color 0.999 0.999 0.999 }     # index of refraction
+//! material "lamp" { diffuse color 0 0 0 emission 12 12 12 }
+//!
+//! sphere { radius 16.5 center 27 16.5 47 material "chrome" velocity 0 0 0 }
+//! sphere { radius 10 center 73 10 78 material "glass" keyframe 0 0 0 0 keyframe 1 0 5 0 }
+//!
+//! light { direction 0 1 0 }       # towards the light of the diffuse integrator
+//! light { radius 5 center 50 70 80 emission 12 12 12 }    # spherical area light
+//! ```
+//!
+//! Materials are diffuse with color 0.75 unless stated otherwise. Spheres
+//! need a radius, a center and a material. Spherical lights are black unless
+//! they are given a `color`.
+//!
+//! Instead of a `velocity`, spheres and the camera eye can follow
+//! `keyframe <time> <x y z>` offsets, and the camera direction
+//! `pan_keyframe <time> <x y z>` offsets. Keyframes come in order of time,
+//! the offset is interpolated linearly between them and held before the
+//! first and after the last.
+
+use std::num::Float;
+use std::io::prelude::*;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::default::Default;
+
+use vector::{Vector, VectorOps};
+use shape::Sphere;
+use material::Material;
+use texture::Texture;
+use motion::{Motion, Keyframe};
+use camera::{Camera, Projection};
+use integrator::{Integrator, MAX_DEPTH};
+use image;
+use super::{Scene, Error};
+use super::builder::MaterialDef;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Number(f64),
+    Str(String),
+    Open,
+    Close,
+    End,
+}
+
+#[derive(Debug, Clone)]
+struct Spanned {
+    token: Token,
+    line: usize,
+    column: usize,
+}
+
+fn error(path: &Path, line: usize, column: usize, message: String) -> Error {
+    Error { path: path.to_path_buf(), line: line, column: column, message: message }
+}
+
+fn tokenize(path: &Path, src: &str) -> Result<Vec<Spanned>, Error> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut tokens = Vec::new();
+    let (mut i, mut line, mut column) = (0, 1, 1);
+
+    while i < chars.len() {
+        let c = chars[i];
+        let (start_line, start_column) = (line, column);
+        // Length of the token in chars, none of them a newline
+        let len;
+        let token;
+        if c == '\n' {
+            i += 1;
+            line += 1;
+            column = 1;
+            continue;
+        } else if c.is_whitespace() {
+            len = 1;
+            token = None;
+        } else if c == '#' {
+            let mut n = 0;
+            while i + n < chars.len() && chars[i + n] != '\n' {
+                n += 1;
+            }
+            len = n;
+            token = None;
+        } else if c == '{' {
+            len = 1;
+            token = Some(Token::Open);
+        } else if c == '}' {
+            len = 1;
+            token = Some(Token::Close);
+        } else if c == '"' {
+            let mut n = 1;
+            while i + n < chars.len() && chars[i + n] != '"' && chars[i + n] != '\n' {
+                n += 1;
+            }
+            if i + n == chars.len() || chars[i + n] == '\n' {
+                return Err(error(path, line, column, "unterminated string".to_string()));
+            }
+            len = n + 1;
+            token = Some(Token::Str(chars[i + 1..i + n].iter().map(|c| *c).collect()));
+        } else if c.is_numeric() || c == '-' || c == '+' || c == '.' {
+            let mut n = 1;
+            while i + n < chars.len() && (chars[i + n].is_alphanumeric() || chars[i + n] == '.' ||
+                                          ((chars[i + n] == '-' || chars[i + n] == '+') &&
+                                           (chars[i + n - 1] == 'e' || chars[i + n - 1] == 'E'))) {
+                n += 1;
+            }
+            let text: String = chars[i..i + n].iter().map(|c| *c).collect();
+            match text.parse::<f64>() {
+                Ok(v) => token = Some(Token::Number(v)),
+                Err(_) => return Err(error(path, line, column, format!("invalid number '{}'", text))),
+            }
+            len = n;
+        } else if c.is_alphabetic() || c == '_' {
+            let mut n = 1;
+            while i + n < chars.len() && (chars[i + n].is_alphanumeric() || chars[i + n] == '_') {
+                n += 1;
+            }
+            len = n;
+            token = Some(Token::Word(chars[i..i + n].iter().map(|c| *c).collect()));
+        } else {
+            return Err(error(path, line, column, format!("unexpected character '{}'", c)));
+        }
+
+        if let Some(token) = token {
+            tokens.push(Spanned { token: token, line: start_line, column: start_column });
+        }
+        i += len;
+        column += len;
+    }
+
+    tokens.push(Spanned { token: Token::End, line: line, column: column });
+    Ok(tokens)
+}
+
+fn describe(token: &Token) -> String {
+    match *token {
+        Token::Word(ref w) => format!("'{}'", w),
+        Token::Number(v) => format!("number {}", v),
+        Token::Str(ref s) => format!("string \"{}\"", s),
+        Token::Open => "'{'".to_string(),
+        Token::Close => "'}'".to_string(),
+        Token::End => "end of file".to_string(),
+    }
+}
+
+struct Parser<'a> {
+    path: &'a Path,
+    tokens: Vec<Spanned>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> &Spanned {
+        &self.tokens[self.pos]
+    }
+
+    fn next(&mut self) -> Spanned {
+        let t = self.tokens[self.pos].clone();
+        if t.token != Token::End {
+            self.pos += 1;
+        }
+        t
+    }
+
+    fn error_at(&self, at: &Spanned, message: String) -> Error {
+        error(self.path, at.line, at.column, message)
+    }
+
+    fn unexpected(&self, at: &Spanned, expected: &str) -> Error {
+        self.error_at(at, format!("expected {}, found {}", expected, describe(&at.token)))
+    }
+
+    fn number(&mut self) -> Result<f64, Error> {
+        let t = self.next();
+        match t.token {
+            Token::Number(v) => Ok(v),
+            _ => Err(self.unexpected(&t, "a number")),
+        }
+    }
+
+    fn positive(&mut self) -> Result<f64, Error> {
+        let at = self.peek().clone();
+        let v = try!(self.number());
+        if !(v > 0.0) || v.is_infinite() {
+            return Err(self.error_at(&at, format!("expected a positive number, found {}", v)));
+        }
+        Ok(v)
+    }
+
+    fn count(&mut self) -> Result<usize, Error> {
+        let at = self.peek().clone();
+        let v = try!(self.number());
+        if !(v >= 1.0) || v.fract() != 0.0 || v > 1e9 {
+            return Err(self.error_at(&at, format!("expected a positive whole number, found {}", v)));
+        }
+        Ok(v as usize)
+    }
+
+    fn vector(&mut self) -> Result<Vector, Error> {
+        let x = try!(self.number());
+        let y = try!(self.number());
+        let z = try!(self.number());
+        Ok(Vector::new(x, y, z))
+    }
+
+    /// Adds the keyframe of a `keyframe <time> <x y z>` property to `keys`.
+    fn keyframe(&mut self, keys: &mut Vec<Keyframe>) -> Result<(), Error> {
+        let at = self.peek().clone();
+        let time = try!(self.number());
+        if keys.last().map_or(false, |k| time <= k.time) {
+            return Err(self.error_at(&at, format!("keyframe at time {} is not after the one before", time)));
+        }
+        let offset = try!(self.vector());
+        keys.push(Keyframe { time: time, offset: offset });
+        Ok(())
+    }
+
+    /// The motion of a block with the given velocity property or keyframes.
+    fn motion(&self, velocity: Option<(Spanned, Vector)>, keys: Vec<Keyframe>) -> Result<Motion, Error> {
+        match velocity {
+            Some((ref at, _)) if keys.len() > 0 =>
+                Err(self.error_at(at, "a velocity and keyframes do not go together".to_string())),
+            Some((_, v)) => Ok(Motion::Linear(v)),
+            None if keys.len() > 0 => Ok(Motion::Keyframed(Arc::new(keys))),
+            None => Ok(Motion::Static),
+        }
+    }
+
+    fn direction(&mut self) -> Result<Vector, Error> {
+        let at = self.peek().clone();
+        let v = try!(self.vector());
+        if v.dot(&v) == 0.0 {
+            return Err(self.error_at(&at, "direction must not be zero".to_string()));
+        }
+        Ok(v)
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        let t = self.next();
+        match t.token {
+            Token::Str(s) => Ok(s),
+            _ => Err(self.unexpected(&t, "a string")),
+        }
+    }
+
+    fn open(&mut self) -> Result<(), Error> {
+        let t = self.next();
+        match t.token {
+            Token::Open => Ok(()),
+            _ => Err(self.unexpected(&t, "'{'")),
+        }
+    }
+
+    /// Next property name of a block, None at its closing brace.
+    fn property(&mut self) -> Result<Option<Spanned>, Error> {
+        let t = self.next();
+        match t.token {
+            Token::Close => Ok(None),
+            Token::Word(_) => Ok(Some(t)),
+            _ => Err(self.unexpected(&t, "a property or '}'")),
+        }
+    }
+}
+
+fn word(t: &Spanned) -> &str {
+    match t.token {
+        Token::Word(ref w) => &w[..],
+        _ => "",
+    }
+}
+
+struct Loader {
+    scene: Scene,
+    textures: HashMap<String, Texture>,
+    materials: HashMap<String, MaterialDef>,
+    files: Vec<PathBuf>, // include stack, to report cycles
+}
+
+/// Reads the scene file at `path` and the files it includes.
+pub fn load(path: &Path) -> Result<Scene, Error> {
+    let mut camera: Camera = Default::default();
+    camera.eye.d = Vector::new(0.0, 0.0, -1.0);
+    camera.up = Vector::new(0.0, 1.0, 0.0);
+    let mut loader = Loader {
+        scene: Scene {
+            camera: camera,
+            spheres: Vec::new(),
+            triangles: Vec::new(),
+            width: 512,
+            height: 512,
+            samples: 1,
+            integrator: Integrator::Path,
+            max_depth: MAX_DEPTH,
+            background: Vector::new(0.0, 0.0, 0.0),
+            light: Vector::new(0.0, 0.0, 1.0),
+        },
+        textures: HashMap::new(),
+        materials: HashMap::new(),
+        files: Vec::new(),
+    };
+    try!(loader.file(path));
+    Ok(loader.scene)
+}
+
+impl Loader {
+    fn file(&mut self, path: &Path) -> Result<(), Error> {
+        let mut src = String::new();
+        if let Err(e) = File::open(path).and_then(|mut f| f.read_to_string(&mut src)) {
+            return Err(error(path, 0, 0, format!("{}", e)));
+        }
+        let tokens = try!(tokenize(path, &src[..]));
+        let mut p = Parser { path: path, tokens: tokens, pos: 0 };
+
+        self.files.push(path.to_path_buf());
+        loop {
+            let t = p.next();
+            match t.token {
+                Token::End => break,
+                Token::Word(_) => try!(self.statement(&mut p, &t)),
+                _ => return Err(p.unexpected(&t, "a statement")),
+            }
+        }
+        self.files.pop();
+        Ok(())
+    }
+
+    fn statement(&mut self, p: &mut Parser, t: &Spanned) -> Result<(), Error> {
+        match word(t) {
+            "include" => {
+                let name = try!(p.string());
+                let included = p.path.parent().unwrap_or(Path::new("")).join(&name[..]);
+                if self.files.iter().any(|f| *f == included) {
+                    return Err(p.error_at(t, format!("{} includes itself", included.display())));
+                }
+                self.file(&included)
+            }
+            "film" => self.film(p),
+            "integrator" => {
+                let at = p.next();
+                match at.token {
+                    Token::Word(ref w) => match w.parse() {
+                        Ok(integrator) => { self.scene.integrator = integrator; Ok(()) }
+                        Err(e) => Err(p.error_at(&at, e)),
+                    },
+                    _ => Err(p.unexpected(&at, "flat, diffuse or path")),
+                }
+            }
+            "background" => {
+                self.scene.background = try!(p.vector());
+                Ok(())
+            }
+            "camera" => self.camera(p),
+            "texture" => self.texture(p),
+            "material" => self.material(p),
+            "sphere" => {
+                let sphere = try!(self.sphere(p, t));
+                self.scene.spheres.push(sphere);
+                Ok(())
+            }
+            "light" => self.light(p, t),
+            w => Err(p.error_at(t, format!("unknown statement '{}'", w))),
+        }
+    }
+
+    fn film(&mut self, p: &mut Parser) -> Result<(), Error> {
+        try!(p.open());
+        while let Some(prop) = try!(p.property()) {
+            match word(&prop) {
+                "width" => self.scene.width = try!(p.count()),
+                "height" => self.scene.height = try!(p.count()),
+                "samples" => self.scene.samples = try!(p.count()),
+                w => return Err(p.error_at(&prop, format!("unknown film property '{}'", w))),
+            }
+        }
+        Ok(())
+    }
+
+    fn camera(&mut self, p: &mut Parser) -> Result<(), Error> {
+        let camera = &mut self.scene.camera;
+        let mut look_at = None;
+        let (mut velocity, mut keys) = (None, Vec::new());
+        let (mut pan, mut pan_keys) = (None, Vec::new());
+        try!(p.open());
+        while let Some(prop) = try!(p.property()) {
+            match word(&prop) {
+                "eye" => camera.eye.o = try!(p.vector()),
+                "direction" => camera.eye.d = try!(p.direction()),
+                "look_at" => look_at = Some((prop.clone(), try!(p.vector()))),
+                "up" => camera.up = try!(p.direction()),
+                "perspective" => camera.projection = Projection::Perspective(try!(p.positive())),
+                "orthographic" => camera.projection = Projection::Orthographic(try!(p.positive())),
+                "fov" => {
+                    let at = p.peek().clone();
+                    let fov = try!(p.positive());
+                    if fov >= 180.0 {
+                        return Err(p.error_at(&at, format!("field of view {} is not below 180 degrees", fov)));
+                    }
+                    camera.projection = Projection::Fov(fov);
+                }
+                "shutter" => {
+                    camera.shutter_open = try!(p.number());
+                    camera.shutter_close = try!(p.number());
+                }
+                "velocity" => velocity = Some((prop.clone(), try!(p.vector()))),
+                "keyframe" => try!(p.keyframe(&mut keys)),
+                "pan" => pan = Some((prop.clone(), try!(p.vector()))),
+                "pan_keyframe" => try!(p.keyframe(&mut pan_keys)),
+                w => return Err(p.error_at(&prop, format!("unknown camera property '{}'", w))),
+            }
+        }
+        if let Some((at, target)) = look_at {
+            let d = &target - &camera.eye.o;
+            if d.dot(&d) == 0.0 {
+                return Err(p.error_at(&at, "look_at point lies at the eye".to_string()));
+            }
+            camera.eye.d = d.norm();
+        }
+        camera.motion = try!(p.motion(velocity, keys));
+        camera.pan = try!(p.motion(pan, pan_keys));
+        Ok(())
+    }
+
+    fn texture(&mut self, p: &mut Parser) -> Result<(), Error> {
+        let name = try!(p.string());
+        let mut texture = None;
+        let mut size = 1.0;
+        try!(p.open());
+        while let Some(prop) = try!(p.property()) {
+            match word(&prop) {
+                "constant" => texture = Some(Texture::Constant(try!(p.vector()))),
+                "checker" => {
+                    let a = try!(p.vector());
+                    let b = try!(p.vector());
+                    texture = Some(Texture::Checker(a, b, 1.0));
+                }
+                "size" => size = try!(p.positive()),
+                "image" => {
+                    let at = p.peek().clone();
+                    let file = try!(p.string());
+                    let image_path = p.path.parent().unwrap_or(Path::new("")).join(&file[..]);
+                    match image::load(&image_path) {
+                        Ok(img) => texture = Some(Texture::Image(Arc::new(img))),
+                        Err(e) => return Err(p.error_at(&at, format!("cannot read {}: {}", image_path.display(), e))),
+                    }
+                }
+                w => return Err(p.error_at(&prop, format!("unknown texture property '{}'", w))),
+            }
+        }
+        let texture = match texture {
+            Some(Texture::Checker(a, b, _)) => Texture::Checker(a, b, size),
+            Some(texture) => texture,
+            None => return Err(p.unexpected(&p.tokens[p.pos - 1], "constant, checker or image")),
+        };
+        self.textures.insert(name, texture);
+        Ok(())
+    }
+
+    fn material(&mut self, p: &mut Parser) -> Result<(), Error> {
+        let name = try!(p.string());
+        let mut def = MaterialDef {
+            material: Material::Diffuse,
+            color: Vector::new(0.75, 0.75, 0.75),
+            texture: None,
+            emission: Vector::new(0.0, 0.0, 0.0),
+        };
+        try!(p.open());
+        while let Some(prop) = try!(p.property()) {
+            match word(&prop) {
+                "diffuse" => def.material = Material::Diffuse,
+                "glossy" => def.material = Material::Glossy(try!(p.positive())),
+                "mirror" => def.material = Material::Mirror,
+                "dielectric" => def.material = Material::Dielectric(try!(p.positive())),
+                "color" => def.color = try!(p.vector()),
+                "texture" => {
+                    let at = p.peek().clone();
+                    let texture = try!(p.string());
+                    match self.textures.get(&texture) {
+                        Some(t) => def.texture = Some(t.clone()),
+                        None => return Err(p.error_at(&at, format!("unknown texture \"{}\"", texture))),
+                    }
+                }
+                "emission" => def.emission = try!(p.vector()),
+                w => return Err(p.error_at(&prop, format!("unknown material property '{}'", w))),
+            }
+        }
+        self.materials.insert(name, def);
+        Ok(())
+    }
+
+    fn sphere(&mut self, p: &mut Parser, t: &Spanned) -> Result<Sphere, Error> {
+        let (mut radius, mut center, mut def) = (None, None, None);
+        let (mut velocity, mut keys) = (None, Vec::new());
+        try!(p.open());
+        while let Some(prop) = try!(p.property()) {
+            match word(&prop) {
+                "radius" => radius = Some(try!(p.positive())),
+                "center" => center = Some(try!(p.vector())),
+                "material" => {
+                    let at = p.peek().clone();
+                    let name = try!(p.string());
+                    match self.materials.get(&name) {
+                        Some(m) => def = Some(m.clone()),
+                        None => return Err(p.error_at(&at, format!("unknown material \"{}\"", name))),
+                    }
+                }
+                "velocity" => velocity = Some((prop.clone(), try!(p.vector()))),
+                "keyframe" => try!(p.keyframe(&mut keys)),
+                w => return Err(p.error_at(&prop, format!("unknown sphere property '{}'", w))),
+            }
+        }
+        let motion = try!(p.motion(velocity, keys));
+        match (radius, center, def) {
+            (Some(radius), Some(center), Some(def)) => Ok(Sphere {
+                radius: radius,
+                position: center,
+                emission: def.emission,
+                color: def.color,
+                texture: def.texture,
+                material: def.material,
+                motion: motion,
+            }),
+            (None, _, _) => Err(p.error_at(t, "sphere without radius".to_string())),
+            (_, None, _) => Err(p.error_at(t, "sphere without center".to_string())),
+            (_, _, None) => Err(p.error_at(t, "sphere without material".to_string())),
+        }
+    }
+
+    fn light(&mut self, p: &mut Parser, t: &Spanned) -> Result<(), Error> {
+        let (mut direction, mut radius, mut center) = (None, None, None);
+        let mut emission = None;
+        let mut color = Vector::new(0.0, 0.0, 0.0);
+        try!(p.open());
+        while let Some(prop) = try!(p.property()) {
+            match word(&prop) {
+                "direction" => direction = Some(try!(p.direction()).norm()),
+                "radius" => radius = Some(try!(p.positive())),
+                "center" => center = Some(try!(p.vector())),
+                "emission" => emission = Some(try!(p.vector())),
+                "color" => color = try!(p.vector()),
+                w => return Err(p.error_at(&prop, format!("unknown light property '{}'", w))),
+            }
+        }
+        match (direction, radius, center, emission) {
+            (Some(d), None, None, None) => {
+                self.scene.light = d;
+                Ok(())
+            }
+            (None, Some(radius), Some(center), Some(emission)) => {
+                self.scene.spheres.push(Sphere::new(radius, center, emission, color));
+                Ok(())
+            }
+            _ => Err(p.error_at(t, "light needs either a direction or a radius, center and emission".to_string())),
+        }
+    }
+}
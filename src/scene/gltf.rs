@@ -0,0 +1,664 @@
+//! Importer for glTF 2.0 scenes: `.gltf` files (JSON with external or
+//! embedded buffers) and binary `.glb` files.
+//!
+//! The default scene, or the first, is read with its node hierarchy. Nodes
+//! place meshes, cameras and lights with a matrix or with translation,
+//! rotation and scale. The renderer knows fewer features than glTF, so
+//! scenes are mapped onto it:
+//!
+//! * Meshes: triangle, triangle strip and triangle fan primitives, indexed
+//!   or not, with `TEXCOORD_0` for textures. Points and lines are left out,
+//!   as are normals, tangents, morph targets and skins.
+//! * Materials: metallic-roughness. A metallic factor of at least 0.5 makes
+//!   a mirror, or a glossy lobe when the roughness is above 0.05; anything
+//!   else is diffuse. The base color factor scales the base color texture.
+//!   PNG textures are supported, other image types leave the factor alone.
+//!   The metallic-roughness, normal, occlusion and emissive textures are
+//!   ignored. The emissive factor, times `KHR_materials_emissive_strength`,
+//!   makes triangles emit.
+//! * Cameras: the first perspective camera in the node hierarchy. The film
+//!   is 640 pixels wide and as tall as the aspect ratio says, 480 pixels if
+//!   it is not given. Without a camera the scene is viewed along -z, from
+//!   far enough to fit its bounds.
+//! * `KHR_lights_punctual`: point and spot lights become small emissive
+//!   spheres, as with `pbrt`, spot cones are ignored. Directional lights set
+//!   the light of the diffuse integrator.
+
+use std::num::Float;
+use std::io::prelude::*;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+use std::collections::HashMap;
+use std::default::Default;
+
+use vector::{Vector, VectorOps};
+use shape::{Sphere, Triangle};
+use material::Material;
+use texture::Texture;
+use camera::{Camera, Projection};
+use integrator::{Integrator, MAX_DEPTH};
+use transform::Transform;
+use image::{Image, png};
+use super::{Scene, Error, point_light};
+use super::json::{self, Json};
+
+fn error(path: &Path, message: String) -> Error {
+    Error { path: path.to_path_buf(), line: 0, column: 0, message: message }
+}
+
+fn u32_le(b: &[u8], at: usize) -> u32 {
+    (b[at] as u32) | (b[at + 1] as u32) << 8 | (b[at + 2] as u32) << 16 | (b[at + 3] as u32) << 24
+}
+
+/// Splits a `.glb` file into its JSON and binary chunks.
+fn glb(path: &Path, data: &[u8]) -> Result<(Vec<u8>, Option<Vec<u8>>), Error> {
+    if data.len() < 12 || u32_le(data, 4) != 2 {
+        return Err(error(path, "only version 2 binary glTF files are supported".to_string()));
+    }
+    let length = (u32_le(data, 8) as usize).min(data.len());
+    let (mut pos, mut text, mut bin) = (12, None, None);
+    while pos + 8 <= length {
+        let (size, kind) = (u32_le(data, pos) as usize, u32_le(data, pos + 4));
+        if pos + 8 + size > length {
+            return Err(error(path, "truncated binary glTF chunk".to_string()));
+        }
+        let chunk = data[pos + 8..pos + 8 + size].to_vec();
+        match kind {
+            0x4e4f534a if text.is_none() => text = Some(chunk), // "JSON"
+            0x004e4942 if bin.is_none() => bin = Some(chunk), // "BIN\0"
+            _ => {}
+        }
+        pos += 8 + size;
+    }
+    match text {
+        Some(text) => Ok((text, bin)),
+        None => Err(error(path, "binary glTF file without JSON chunk".to_string())),
+    }
+}
+
+fn base64(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let (mut bits, mut n) = (0u32, 0);
+    for c in s.bytes() {
+        let v = match c {
+            b'A'...b'Z' => c - b'A',
+            b'a'...b'z' => c - b'a' + 26,
+            b'0'...b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => break,
+            b' ' | b'\n' | b'\r' | b'\t' => continue,
+            _ => return None,
+        };
+        bits = bits << 6 | v as u32;
+        n += 6;
+        if n >= 8 {
+            n -= 8;
+            out.push((bits >> n) as u8);
+        }
+    }
+    Some(out)
+}
+
+/// Undoes the percent encoding of file names in URIs.
+fn unescape(uri: &str) -> String {
+    let b = uri.as_bytes();
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < b.len() {
+        let digit = |c: u8| (c as char).to_digit(16);
+        let hex = if b[i] == b'%' && i + 2 < b.len() {
+            match (digit(b[i + 1]), digit(b[i + 2])) {
+                (Some(h), Some(l)) => Some((h * 16 + l) as u8),
+                _ => None,
+            }
+        } else {
+            None
+        };
+        match hex {
+            Some(v) => {
+                out.push(v);
+                i += 3;
+            }
+            None => {
+                out.push(b[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out[..]).into_owned()
+}
+
+/// Material properties of a primitive.
+#[derive(Clone)]
+struct Look {
+    material: Material,
+    color: Vector,
+    texture: Option<Texture>,
+    emission: Vector,
+}
+
+struct Importer<'a> {
+    path: &'a Path,
+    root: Json,
+    buffers: Vec<Vec<u8>>,
+    looks: HashMap<usize, Look>,
+    camera: Option<(Transform, usize)>,
+    triangles: Vec<Triangle>,
+    point_lights: Vec<(Vector, Vector)>, // position and intensity
+    light: Vector,
+}
+
+/// Reads the glTF or binary glTF file at `path`, with the buffers and
+/// images it refers to.
+pub fn load(path: &Path) -> Result<Scene, Error> {
+    let mut data = Vec::new();
+    if let Err(e) = File::open(path).and_then(|mut f| f.read_to_end(&mut data)) {
+        return Err(error(path, format!("{}", e)));
+    }
+    let (text, bin) = if data.starts_with(b"glTF") { try!(glb(path, &data[..])) } else { (data, None) };
+    let text = match String::from_utf8(text) {
+        Ok(text) => text,
+        Err(_) => return Err(error(path, "glTF JSON is not UTF-8".to_string())),
+    };
+    let root = try!(json::parse(path, &text[..]));
+    match root.get("asset").and_then(|a| a.get("version")).and_then(|v| v.as_str()) {
+        Some(v) if v.starts_with("2.") => {}
+        _ => return Err(error(path, "only glTF 2.0 files are supported".to_string())),
+    }
+
+    let mut importer = Importer {
+        path: path,
+        root: root,
+        buffers: Vec::new(),
+        looks: HashMap::new(),
+        camera: None,
+        triangles: Vec::new(),
+        point_lights: Vec::new(),
+        light: Vector::new(0.0, 0.0, 1.0),
+    };
+    try!(importer.load_buffers(bin));
+
+    let scene = importer.root.get("scene").and_then(|s| s.as_usize()).unwrap_or(0);
+    let nodes: Vec<usize> = match importer.root.get("scenes").map(|s| s.members()) {
+        Some(scenes) if scenes.len() > 0 => match scenes.get(scene) {
+            Some(s) => try!(importer.indices(s.get("nodes"), "scene nodes")),
+            None => return Err(error(path, format!("scene {} does not exist", scene))),
+        },
+        _ => {
+            // Without scenes, all nodes that are not children of others
+            let count = importer.list("nodes").len();
+            let mut is_child = vec![false; count];
+            for node in importer.list("nodes").iter() {
+                for child in try!(importer.indices(node.get("children"), "node children")).iter() {
+                    if *child < count {
+                        is_child[*child] = true;
+                    }
+                }
+            }
+            (0..count).filter(|i| !is_child[*i]).collect()
+        }
+    };
+    for node in nodes.iter() {
+        try!(importer.node(*node, Transform::identity(), 0));
+    }
+    importer.finish()
+}
+
+impl<'a> Importer<'a> {
+    fn list(&self, kind: &str) -> &[Json] {
+        self.root.get(kind).map(|l| l.members()).unwrap_or(&[])
+    }
+
+    /// Element `index` of the top level array `kind`.
+    fn item(&self, kind: &str, index: usize) -> Result<&Json, Error> {
+        match self.list(kind).get(index) {
+            Some(item) => Ok(item),
+            None => Err(error(self.path, format!("{}[{}] does not exist", kind, index))),
+        }
+    }
+
+    /// An optional index, `what` describes it in errors.
+    fn index(&self, value: Option<&Json>, what: &str) -> Result<Option<usize>, Error> {
+        match value {
+            None => Ok(None),
+            Some(v) => match v.as_usize() {
+                Some(i) => Ok(Some(i)),
+                None => Err(error(self.path, format!("{} is not an index", what))),
+            },
+        }
+    }
+
+    fn indices(&self, value: Option<&Json>, what: &str) -> Result<Vec<usize>, Error> {
+        match value {
+            None => Ok(Vec::new()),
+            Some(v) => v.members().iter().map(|i| match i.as_usize() {
+                Some(i) => Ok(i),
+                None => Err(error(self.path, format!("{} are not indices", what))),
+            }).collect(),
+        }
+    }
+
+    /// Reads the data of a URI, embedded or relative to the glTF file.
+    fn uri(&self, uri: &str) -> Result<Vec<u8>, Error> {
+        if uri.starts_with("data:") {
+            return match uri.find(";base64,").and_then(|i| base64(&uri[i + 8..])) {
+                Some(data) => Ok(data),
+                None => Err(error(self.path, "data URIs have to be base64 encoded".to_string())),
+            };
+        }
+        let file = self.path.parent().unwrap_or(Path::new("")).join(&unescape(uri)[..]);
+        let mut data = Vec::new();
+        match File::open(&file).and_then(|mut f| f.read_to_end(&mut data)) {
+            Ok(_) => Ok(data),
+            Err(e) => Err(error(self.path, format!("cannot read {}: {}", file.display(), e))),
+        }
+    }
+
+    fn load_buffers(&mut self, mut bin: Option<Vec<u8>>) -> Result<(), Error> {
+        let mut buffers = Vec::new();
+        for (i, buffer) in self.list("buffers").iter().enumerate() {
+            let data = match buffer.get("uri").and_then(|u| u.as_str()) {
+                Some(uri) => try!(self.uri(uri)),
+                // The first buffer of a .glb file may be its binary chunk
+                None if i == 0 && bin.is_some() => bin.take().unwrap(),
+                None => return Err(error(self.path, format!("buffers[{}] has no data", i))),
+            };
+            let length = buffer.get("byteLength").and_then(|l| l.as_usize()).unwrap_or(0);
+            if data.len() < length {
+                return Err(error(self.path, format!("buffers[{}] is shorter than its byteLength", i)));
+            }
+            buffers.push(data);
+        }
+        self.buffers = buffers;
+        Ok(())
+    }
+
+    /// The bytes of buffer view `index`, with its stride if it has one.
+    fn buffer_view(&self, index: usize) -> Result<(&[u8], Option<usize>), Error> {
+        let view = try!(self.item("bufferViews", index));
+        let buffer = try!(self.index(view.get("buffer"), "bufferView buffer")).unwrap_or(0);
+        let data = match self.buffers.get(buffer) {
+            Some(data) => data,
+            None => return Err(error(self.path, format!("buffers[{}] does not exist", buffer))),
+        };
+        let offset = view.get("byteOffset").and_then(|o| o.as_usize()).unwrap_or(0);
+        let length = view.get("byteLength").and_then(|l| l.as_usize()).unwrap_or(0);
+        if offset + length > data.len() {
+            return Err(error(self.path, format!("bufferViews[{}] lies outside its buffer", index)));
+        }
+        let stride = view.get("byteStride").and_then(|s| s.as_usize());
+        Ok((&data[offset..offset + length], stride))
+    }
+
+    /// Reads accessor `index` as numbers, with the number of components per
+    /// element. Normalized integers are mapped to [0, 1] or [-1, 1].
+    fn accessor(&self, index: usize) -> Result<(Vec<f64>, usize), Error> {
+        let accessor = try!(self.item("accessors", index));
+        let what = format!("accessors[{}]", index);
+        if accessor.get("sparse").is_some() {
+            return Err(error(self.path, format!("{}: sparse accessors are not supported", what)));
+        }
+        let count = accessor.get("count").and_then(|c| c.as_usize()).unwrap_or(0);
+        let components = match accessor.get("type").and_then(|t| t.as_str()) {
+            Some("SCALAR") => 1,
+            Some("VEC2") => 2,
+            Some("VEC3") => 3,
+            Some("VEC4") | Some("MAT2") => 4,
+            Some("MAT3") => 9,
+            Some("MAT4") => 16,
+            _ => return Err(error(self.path, format!("{} has an unknown type", what))),
+        };
+        let (size, max) = match accessor.get("componentType").and_then(|c| c.as_usize()) {
+            Some(5120) => (1, 127.0),
+            Some(5121) => (1, 255.0),
+            Some(5122) => (2, 32767.0),
+            Some(5123) => (2, 65535.0),
+            Some(5125) => (4, 4294967295.0),
+            Some(5126) => (4, 1.0),
+            _ => return Err(error(self.path, format!("{} has an unknown componentType", what))),
+        };
+        let ty = accessor.get("componentType").and_then(|c| c.as_usize()).unwrap();
+        let normalized = accessor.get("normalized").and_then(|n| n.as_bool()).unwrap_or(false) && ty != 5126;
+
+        let view = match try!(self.index(accessor.get("bufferView"), "accessor bufferView")) {
+            Some(view) => view,
+            // Without buffer view the elements are zero
+            None => return Ok((vec![0.0; count * components], components)),
+        };
+        let (data, stride) = try!(self.buffer_view(view));
+        let offset = accessor.get("byteOffset").and_then(|o| o.as_usize()).unwrap_or(0);
+        let element = size * components;
+        let stride = stride.unwrap_or(element);
+        if count > 0 && offset + stride * (count - 1) + element > data.len() {
+            return Err(error(self.path, format!("{} reads past the end of its buffer view", what)));
+        }
+
+        let mut values = Vec::with_capacity(count * components);
+        for i in 0..count {
+            for c in 0..components {
+                let at = offset + i * stride + c * size;
+                let v = match ty {
+                    5120 => data[at] as i8 as f64,
+                    5121 => data[at] as f64,
+                    5122 => ((data[at] as u16) | (data[at + 1] as u16) << 8) as i16 as f64,
+                    5123 => ((data[at] as u16) | (data[at + 1] as u16) << 8) as f64,
+                    5125 => u32_le(data, at) as f64,
+                    _ => {
+                        let v: f32 = unsafe { ::std::mem::transmute(u32_le(data, at)) };
+                        v as f64
+                    }
+                };
+                values.push(if normalized { (v / max).max(-1.0) } else { v });
+            }
+        }
+        Ok((values, components))
+    }
+
+    /// Decodes image `index`, None for formats other than PNG.
+    fn image(&self, index: usize) -> Result<Option<Image>, Error> {
+        let image = try!(self.item("images", index));
+        let data = match image.get("uri").and_then(|u| u.as_str()) {
+            Some(uri) => try!(self.uri(uri)),
+            None => match try!(self.index(image.get("bufferView"), "image bufferView")) {
+                Some(view) => try!(self.buffer_view(view)).0.to_vec(),
+                None => return Err(error(self.path, format!("images[{}] has no data", index))),
+            },
+        };
+        if !data.starts_with(b"\x89PNG") {
+            return Ok(None);
+        }
+        match png::read(&mut &data[..]) {
+            Ok(img) => Ok(Some(img)),
+            Err(e) => Err(error(self.path, format!("images[{}]: {}", index, e))),
+        }
+    }
+
+    fn look(&mut self, index: Option<usize>) -> Result<Look, Error> {
+        if let Some(look) = index.and_then(|i| self.looks.get(&i)) {
+            return Ok(look.clone());
+        }
+        let material = match index {
+            Some(i) => try!(self.item("materials", i)).clone(),
+            // The default material
+            None => Json::Null,
+        };
+        let numbers = |v: Option<&Json>| -> Vec<f64> {
+            v.map(|v| v.members().iter().filter_map(|x| x.as_f64()).collect()).unwrap_or(Vec::new())
+        };
+        let pbr = material.get("pbrMetallicRoughness");
+        let base = numbers(pbr.and_then(|p| p.get("baseColorFactor")));
+        let color = if base.len() >= 3 { Vector::new(base[0], base[1], base[2]) } else { Vector::new(1.0, 1.0, 1.0) };
+        let metallic = pbr.and_then(|p| p.get("metallicFactor")).and_then(|m| m.as_f64()).unwrap_or(1.0);
+        let roughness = pbr.and_then(|p| p.get("roughnessFactor")).and_then(|r| r.as_f64()).unwrap_or(1.0);
+
+        let mut texture = None;
+        if let Some(info) = pbr.and_then(|p| p.get("baseColorTexture")) {
+            let tex_coord = info.get("texCoord").and_then(|t| t.as_usize()).unwrap_or(0);
+            let index = try!(self.index(info.get("index"), "baseColorTexture index"));
+            let source = match index {
+                Some(i) => try!(self.index(try!(self.item("textures", i)).get("source"), "texture source")),
+                None => None,
+            };
+            if let (0, Some(source)) = (tex_coord, source) {
+                if let Some(img) = try!(self.image(source)) {
+                    let mut scaled = Image::new(img.width, img.height, 3);
+                    for y in 0..img.height {
+                        for x in 0..img.width {
+                            let (r, g, b) = img.rgb(x, y);
+                            scaled.set_rgb(x, y, r * color.x, g * color.y, b * color.z);
+                        }
+                    }
+                    texture = Some(Texture::Mapped(Arc::new(scaled)));
+                }
+            }
+        }
+
+        let emissive = numbers(material.get("emissiveFactor"));
+        let strength = material.get("extensions").and_then(|e| e.get("KHR_materials_emissive_strength"))
+                               .and_then(|e| e.get("emissiveStrength")).and_then(|s| s.as_f64()).unwrap_or(1.0);
+        let emission = if emissive.len() >= 3 {
+            Vector::new(emissive[0], emissive[1], emissive[2]).smul(strength)
+        } else {
+            Vector::new(0.0, 0.0, 0.0)
+        };
+
+        let alpha = roughness * roughness;
+        let look = Look {
+            material: if metallic < 0.5 {
+                Material::Diffuse
+            } else if roughness <= 0.05 {
+                Material::Mirror
+            } else {
+                Material::Glossy((2.0 / (alpha * alpha) - 2.0).max(1.0))
+            },
+            color: color,
+            texture: texture,
+            emission: emission,
+        };
+        if let Some(i) = index {
+            self.looks.insert(i, look.clone());
+        }
+        Ok(look)
+    }
+
+    fn node(&mut self, index: usize, parent: Transform, depth: usize) -> Result<(), Error> {
+        if depth > self.list("nodes").len() {
+            return Err(error(self.path, "the node hierarchy has a cycle".to_string()));
+        }
+        let node = try!(self.item("nodes", index)).clone();
+        let numbers = |key: &str| -> Option<Vec<f64>> {
+            node.get(key).map(|v| v.members().iter().filter_map(|x| x.as_f64()).collect())
+        };
+        let local = match numbers("matrix") {
+            Some(ref m) if m.len() == 16 => Transform::from_columns(&m[..]),
+            Some(_) => return Err(error(self.path, format!("nodes[{}] has an invalid matrix", index))),
+            None => {
+                let t = numbers("translation").unwrap_or(vec![0.0, 0.0, 0.0]);
+                let r = numbers("rotation").unwrap_or(vec![0.0, 0.0, 0.0, 1.0]);
+                let s = numbers("scale").unwrap_or(vec![1.0, 1.0, 1.0]);
+                if t.len() != 3 || r.len() != 4 || s.len() != 3 {
+                    return Err(error(self.path, format!("nodes[{}] has an invalid transformation", index)));
+                }
+                Transform::translate(Vector::new(t[0], t[1], t[2]))
+                    .mul(&Transform::quaternion(r[0], r[1], r[2], r[3]))
+                    .mul(&Transform::scale(Vector::new(s[0], s[1], s[2])))
+            }
+        };
+        let world = parent.mul(&local);
+
+        if let Some(mesh) = try!(self.index(node.get("mesh"), "node mesh")) {
+            try!(self.mesh(mesh, &world));
+        }
+        if let Some(camera) = try!(self.index(node.get("camera"), "node camera")) {
+            let perspective = try!(self.item("cameras", camera)).get("type").and_then(|t| t.as_str()) == Some("perspective");
+            if self.camera.is_none() && perspective {
+                self.camera = Some((world, camera));
+            }
+        }
+        let light = node.get("extensions").and_then(|e| e.get("KHR_lights_punctual")).and_then(|l| l.get("light"));
+        if let Some(light) = try!(self.index(light, "node light")) {
+            try!(self.punctual_light(light, &world));
+        }
+        for child in try!(self.indices(node.get("children"), "node children")).iter() {
+            try!(self.node(*child, world, depth + 1));
+        }
+        Ok(())
+    }
+
+    fn mesh(&mut self, index: usize, world: &Transform) -> Result<(), Error> {
+        let primitives = try!(self.item("meshes", index)).get("primitives").map(|p| p.members().to_vec())
+                                                          .unwrap_or(Vec::new());
+        for (k, primitive) in primitives.iter().enumerate() {
+            let what = format!("meshes[{}].primitives[{}]", index, k);
+            let mode = primitive.get("mode").and_then(|m| m.as_usize()).unwrap_or(4);
+            if mode < 4 {
+                // Points and lines have no area
+                continue;
+            }
+            let attributes = primitive.get("attributes");
+            let position = match try!(self.index(attributes.and_then(|a| a.get("POSITION")), "POSITION")) {
+                Some(p) => p,
+                None => return Err(error(self.path, format!("{} has no POSITION", what))),
+            };
+            let (positions, n) = try!(self.accessor(position));
+            if n != 3 {
+                return Err(error(self.path, format!("{}: POSITION is not a VEC3", what)));
+            }
+            let vertices = positions.len() / 3;
+            let uvs = match try!(self.index(attributes.and_then(|a| a.get("TEXCOORD_0")), "TEXCOORD_0")) {
+                Some(t) => {
+                    let (uvs, n) = try!(self.accessor(t));
+                    if n != 2 || uvs.len() / 2 != vertices {
+                        return Err(error(self.path, format!("{}: TEXCOORD_0 does not match POSITION", what)));
+                    }
+                    Some(uvs)
+                }
+                None => None,
+            };
+            let indices: Vec<usize> = match try!(self.index(primitive.get("indices"), "indices")) {
+                Some(i) => try!(self.accessor(i)).0.iter().map(|i| *i as usize).collect(),
+                None => (0..vertices).collect(),
+            };
+            if indices.iter().any(|i| *i >= vertices) {
+                return Err(error(self.path, format!("{} refers to a missing vertex", what)));
+            }
+
+            let mut corners = Vec::new();
+            match mode {
+                4 => for i in 0..indices.len() / 3 {
+                    corners.push([indices[3 * i], indices[3 * i + 1], indices[3 * i + 2]]);
+                },
+                // Strips alternate their winding
+                5 => for i in 2..indices.len() {
+                    corners.push(if i % 2 == 0 {
+                        [indices[i - 2], indices[i - 1], indices[i]]
+                    } else {
+                        [indices[i - 1], indices[i - 2], indices[i]]
+                    });
+                },
+                6 => for i in 2..indices.len() {
+                    corners.push([indices[0], indices[i - 1], indices[i]]);
+                },
+                _ => return Err(error(self.path, format!("{} has an unknown mode {}", what, mode))),
+            }
+
+            let look = try!(self.look(try!(self.index(primitive.get("material"), "material"))));
+            // Mirroring transformations turn the winding around
+            let flip = world.determinant() < 0.0;
+            for c in corners.iter() {
+                let c = if flip { [c[0], c[2], c[1]] } else { *c };
+                let p = |i: usize| world.point(Vector::new(positions[3 * i], positions[3 * i + 1], positions[3 * i + 2]));
+                let mut triangle = Triangle::new([p(c[0]), p(c[1]), p(c[2])], look.emission, look.color);
+                triangle.material = look.material;
+                if let Some(ref uvs) = uvs {
+                    triangle.uv = [(uvs[2 * c[0]], uvs[2 * c[0] + 1]), (uvs[2 * c[1]], uvs[2 * c[1] + 1]),
+                                   (uvs[2 * c[2]], uvs[2 * c[2] + 1])];
+                    triangle.texture = look.texture.clone();
+                }
+                self.triangles.push(triangle);
+            }
+        }
+        Ok(())
+    }
+
+    fn punctual_light(&mut self, index: usize, world: &Transform) -> Result<(), Error> {
+        let light = match self.root.get("extensions").and_then(|e| e.get("KHR_lights_punctual"))
+                                  .and_then(|l| l.get("lights")).and_then(|l| l.members().get(index)) {
+            Some(light) => light.clone(),
+            None => return Err(error(self.path, format!("KHR_lights_punctual light {} does not exist", index))),
+        };
+        let color: Vec<f64> = light.get("color").map(|c| c.members().iter().filter_map(|x| x.as_f64()).collect())
+                                   .unwrap_or(vec![1.0, 1.0, 1.0]);
+        if color.len() != 3 {
+            return Err(error(self.path, format!("KHR_lights_punctual light {} has an invalid color", index)));
+        }
+        let intensity = light.get("intensity").and_then(|i| i.as_f64()).unwrap_or(1.0);
+        let color = Vector::new(color[0], color[1], color[2]).smul(intensity);
+        match light.get("type").and_then(|t| t.as_str()) {
+            Some("point") | Some("spot") => {
+                let position = world.point(Vector::new(0.0, 0.0, 0.0));
+                self.point_lights.push((position, color));
+            }
+            Some("directional") => {
+                // Lights shine along their -z axis
+                let d = world.vector(Vector::new(0.0, 0.0, 1.0));
+                if d.dot(&d) > 0.0 {
+                    self.light = d.norm();
+                }
+            }
+            _ => return Err(error(self.path, format!("KHR_lights_punctual light {} has an unknown type", index))),
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Scene, Error> {
+        let mut camera: Camera = Default::default();
+        let (mut width, mut height) = (640, 480);
+        match self.camera {
+            Some((world, index)) => {
+                let perspective = try!(self.item("cameras", index)).get("perspective");
+                let yfov = match perspective.and_then(|p| p.get("yfov")).and_then(|f| f.as_f64()) {
+                    Some(f) if f > 0.0 && f < ::std::f64::consts::PI => f,
+                    _ => return Err(error(self.path, format!("cameras[{}] has no valid yfov", index))),
+                };
+                let aspect = perspective.and_then(|p| p.get("aspectRatio")).and_then(|a| a.as_f64());
+                if let Some(a) = aspect {
+                    if a > 0.0 {
+                        height = ((width as f64 / a).round() as usize).max(1);
+                    }
+                }
+                // The field of view across the shorter side
+                let fov = if width >= height {
+                    yfov
+                } else {
+                    2.0 * ((yfov / 2.0).tan() * width as f64 / height as f64).atan()
+                };
+                let forward = world.vector(Vector::new(0.0, 0.0, -1.0)).norm();
+                let up = world.vector(Vector::new(0.0, 1.0, 0.0)).norm();
+                camera.eye.o = world.point(Vector::new(0.0, 0.0, 0.0));
+                camera.eye.d = forward;
+                // The renderer's camera takes the direction of increasing columns
+                camera.up = forward.cross(up);
+                camera.projection = Projection::Fov(fov.to_degrees());
+            }
+            None => {
+                let inf = 1e300;
+                let (mut lo, mut hi) = (Vector::new(inf, inf, inf), Vector::new(-inf, -inf, -inf));
+                for t in self.triangles.iter() {
+                    for v in t.vertices.iter() {
+                        lo = Vector::new(lo.x.min(v.x), lo.y.min(v.y), lo.z.min(v.z));
+                        hi = Vector::new(hi.x.max(v.x), hi.y.max(v.y), hi.z.max(v.z));
+                    }
+                }
+                let (center, radius) = if self.triangles.len() > 0 {
+                    let d = &hi - &lo;
+                    ((&lo + &hi).smul(0.5), 0.5 * d.dot(&d).sqrt())
+                } else {
+                    (Vector::new(0.0, 0.0, 0.0), 1.0)
+                };
+                let fov: f64 = 45.0;
+                camera.eye.o = &center + &Vector::new(0.0, 0.0, 1.1 * radius / (fov.to_radians() / 2.0).sin());
+                camera.eye.d = Vector::new(0.0, 0.0, -1.0);
+                camera.up = Vector::new(1.0, 0.0, 0.0);
+                camera.projection = Projection::Fov(fov);
+            }
+        }
+
+        let eye = camera.eye.o;
+        let spheres: Vec<Sphere> = self.point_lights.iter().map(|l| point_light(l.0, l.1, eye)).collect();
+        Ok(Scene {
+            camera: camera,
+            spheres: spheres,
+            triangles: self.triangles,
+            width: width,
+            height: height,
+            samples: 16,
+            integrator: Integrator::Path,
+            max_depth: MAX_DEPTH,
+            background: Vector::new(0.0, 0.0, 0.0),
+            light: self.light,
+        })
+    }
+}
@@ -0,0 +1,273 @@
+//! A small JSON reader for the scene importers.
+//!
+//! Numbers are read as f64, objects keep their last value for repeated keys.
+
+use std::num::Float;
+use std::collections::BTreeMap;
+use std::char;
+use std::path::Path;
+
+use super::Error;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(BTreeMap<String, Json>),
+}
+
+impl Json {
+    /// The member `key` of an object.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match *self {
+            Json::Object(ref members) => members.get(key),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Json::Number(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// The value as an index or count, None unless it is a whole number that
+    /// is not negative.
+    pub fn as_usize(&self) -> Option<usize> {
+        match *self {
+            Json::Number(v) if v >= 0.0 && v.fract() == 0.0 && v < 4294967296.0 => Some(v as usize),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Json::Bool(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Json::String(ref s) => Some(&s[..]),
+            _ => None,
+        }
+    }
+
+    /// The elements of an array, no elements for anything else.
+    pub fn members(&self) -> &[Json] {
+        match *self {
+            Json::Array(ref v) => &v[..],
+            _ => &[],
+        }
+    }
+}
+
+struct Parser<'a> {
+    path: &'a Path,
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: String) -> Error {
+        let before = &self.chars[..self.pos.min(self.chars.len())];
+        let line = before.iter().filter(|c| **c == '\n').count() + 1;
+        let column = before.iter().rev().take_while(|c| **c != '\n').count() + 1;
+        Error { path: self.path.to_path_buf(), line: line, column: column, message: message }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.chars.len() && self.chars[self.pos].is_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).map(|c| *c)
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), Error> {
+        self.skip_whitespace();
+        if self.peek() != Some(c) {
+            return Err(self.unexpected(&format!("'{}'", c)[..]));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn unexpected(&self, expected: &str) -> Error {
+        match self.peek() {
+            Some(c) => self.error(format!("expected {}, found '{}'", expected, c)),
+            None => self.error(format!("expected {}, found end of file", expected)),
+        }
+    }
+
+    fn keyword(&mut self, word: &str, value: Json) -> Result<Json, Error> {
+        let end = self.pos + word.len();
+        if end <= self.chars.len() && self.chars[self.pos..end].iter().map(|c| *c).collect::<String>() == word {
+            self.pos = end;
+            Ok(value)
+        } else {
+            Err(self.unexpected("a value"))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, Error> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{') => {
+                self.pos += 1;
+                let mut members = BTreeMap::new();
+                self.skip_whitespace();
+                if self.peek() == Some('}') {
+                    self.pos += 1;
+                    return Ok(Json::Object(members));
+                }
+                loop {
+                    self.skip_whitespace();
+                    if self.peek() != Some('"') {
+                        return Err(self.unexpected("a member name"));
+                    }
+                    let key = try!(self.string());
+                    try!(self.expect(':'));
+                    let value = try!(self.value());
+                    members.insert(key, value);
+                    self.skip_whitespace();
+                    match self.peek() {
+                        Some(',') => self.pos += 1,
+                        Some('}') => {
+                            self.pos += 1;
+                            return Ok(Json::Object(members));
+                        }
+                        _ => return Err(self.unexpected("',' or '}'")),
+                    }
+                }
+            }
+            Some('[') => {
+                self.pos += 1;
+                let mut elements = Vec::new();
+                self.skip_whitespace();
+                if self.peek() == Some(']') {
+                    self.pos += 1;
+                    return Ok(Json::Array(elements));
+                }
+                loop {
+                    elements.push(try!(self.value()));
+                    self.skip_whitespace();
+                    match self.peek() {
+                        Some(',') => self.pos += 1,
+                        Some(']') => {
+                            self.pos += 1;
+                            return Ok(Json::Array(elements));
+                        }
+                        _ => return Err(self.unexpected("',' or ']'")),
+                    }
+                }
+            }
+            Some('"') => Ok(Json::String(try!(self.string()))),
+            Some('t') => self.keyword("true", Json::Bool(true)),
+            Some('f') => self.keyword("false", Json::Bool(false)),
+            Some('n') => self.keyword("null", Json::Null),
+            Some(c) if c == '-' || c.is_digit(10) => {
+                let start = self.pos;
+                while self.pos < self.chars.len() {
+                    match self.chars[self.pos] {
+                        '0'...'9' | '+' | '-' | '.' | 'e' | 'E' => self.pos += 1,
+                        _ => break,
+                    }
+                }
+                let text: String = self.chars[start..self.pos].iter().map(|c| *c).collect();
+                match text.parse() {
+                    Ok(v) => Ok(Json::Number(v)),
+                    Err(_) => {
+                        self.pos = start;
+                        Err(self.error(format!("invalid number '{}'", text)))
+                    }
+                }
+            }
+            _ => Err(self.unexpected("a value")),
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, Error> {
+        let mut v = 0;
+        for _ in 0..4 {
+            match self.peek().and_then(|c| c.to_digit(16)) {
+                Some(d) => v = v * 16 + d,
+                None => return Err(self.unexpected("a hex digit")),
+            }
+            self.pos += 1;
+        }
+        Ok(v)
+    }
+
+    /// A string starting at the opening quote.
+    fn string(&mut self) -> Result<String, Error> {
+        self.pos += 1;
+        let mut s = String::new();
+        loop {
+            let c = match self.peek() {
+                Some(c) => c,
+                None => return Err(self.error("unterminated string".to_string())),
+            };
+            self.pos += 1;
+            match c {
+                '"' => return Ok(s),
+                '\\' => {
+                    let e = match self.peek() {
+                        Some(e) => e,
+                        None => return Err(self.error("unterminated string".to_string())),
+                    };
+                    self.pos += 1;
+                    match e {
+                        '"' | '\\' | '/' => s.push(e),
+                        'b' => s.push('\x08'),
+                        'f' => s.push('\x0c'),
+                        'n' => s.push('\n'),
+                        'r' => s.push('\r'),
+                        't' => s.push('\t'),
+                        'u' => {
+                            let mut code = try!(self.hex4());
+                            // Characters outside the basic plane come as surrogate pairs
+                            if code >= 0xd800 && code < 0xdc00 && self.peek() == Some('\\') {
+                                self.pos += 1;
+                                try!(self.expect('u'));
+                                let low = try!(self.hex4());
+                                if low < 0xdc00 || low >= 0xe000 {
+                                    return Err(self.error(format!("invalid low surrogate {:x}", low)));
+                                }
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                            }
+                            match char::from_u32(code) {
+                                Some(c) => s.push(c),
+                                None => return Err(self.error(format!("invalid character code {:x}", code))),
+                            }
+                        }
+                        _ => {
+                            self.pos -= 1;
+                            return Err(self.error(format!("invalid escape '\\{}'", e)));
+                        }
+                    }
+                }
+                _ => s.push(c),
+            }
+        }
+    }
+}
+
+/// Parses the JSON text `src` read from `path`, errors carry the line and
+/// column they were found at.
+pub fn parse(path: &Path, src: &str) -> Result<Json, Error> {
+    let mut p = Parser { path: path, chars: src.chars().collect(), pos: 0 };
+    let value = try!(p.value());
+    p.skip_whitespace();
+    if p.pos < p.chars.len() {
+        return Err(p.unexpected("end of file"));
+    }
+    Ok(value)
+}
@@ -0,0 +1,263 @@
+//! What gets rendered: spheres, a camera, the image size and how to render
+//! it.
+//!
+//! Scenes are read from files with `load`, see `format` for the syntax.
+//! Files ending in `.pbrt` are imported with `pbrt` instead, `.gltf` and
+//! `.glb` files with `gltf`. `builder` puts scenes together in code. The
+//! files in scenes/ describe the scenes of the example programs, which are
+//! also available as constructors so tests can render them without files.
+
+use std::fmt;
+use std::mem;
+use std::num::Float;
+use std::collections::HashMap;
+use std::default::Default;
+use std::path::{Path, PathBuf};
+use std::f64::consts::PI;
+
+use vector::{Vector, VectorOps};
+use ray::Ray;
+use shape::{Shape, Surface, Sphere, Triangle};
+use material::Material;
+use texture::Texture;
+use image::Image;
+use camera::{Camera, Projection};
+use integrator::{Integrator, MAX_DEPTH};
+use stats;
+use self::builder::SceneBuilder;
+
+pub mod builder;
+pub mod format;
+pub mod pbrt;
+pub mod ply;
+pub mod gltf;
+pub mod json;
+
+#[derive(Debug, Clone)]
+pub struct Scene {
+    pub camera: Camera,
+    pub spheres: Vec<Sphere>,
+    pub triangles: Vec<Triangle>,
+    pub width: usize,
+    pub height: usize,
+    pub samples: usize, // per pixel
+    pub integrator: Integrator,
+    pub max_depth: usize, // bounces after which paths only add the emission they reach
+    pub background: Vector, // radiance of rays that leave the scene
+    pub light: Vector, // direction towards the light for simple shading
+}
+
+/// Why a scene could not be loaded, with the position in the file it
+/// concerns.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    pub path: PathBuf, // the included file for errors in included files
+    pub line: usize, // starting at 1, 0 if the file could not be read
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: {}", self.path.display(), self.message)
+        } else {
+            write!(f, "{}:{}:{}: {}", self.path.display(), self.line, self.column, self.message)
+        }
+    }
+}
+
+/// A small emissive sphere standing in for a point light of the given
+/// intensity, for importers of formats with point lights. Its radius is 1%
+/// of the distance to `eye`, so it stays small in the image.
+fn point_light(position: Vector, intensity: Vector, eye: Vector) -> Sphere {
+    let to_eye = &position - &eye;
+    let radius = 0.01 * to_eye.dot(&to_eye).sqrt().max(1e-3);
+    // A sphere of radius r and radiance L has intensity pi r^2 L
+    let emission = intensity.smul(1.0 / (PI * radius * radius));
+    Sphere::new(radius, position, emission, Vector::new(0.0, 0.0, 0.0))
+}
+
+/// What sets the look of a surface apart, as bits to compare. Image
+/// textures compare by identity.
+fn appearance(material: Material, color: Vector, emission: Vector, texture: &Option<Texture>) -> Vec<u64> {
+    let bits = |v: f64| -> u64 { unsafe { mem::transmute(v) } };
+    let mut key = match material {
+        Material::Diffuse => vec![0, 0],
+        Material::Glossy(exponent) => vec![1, bits(exponent)],
+        Material::Mirror => vec![2, 0],
+        Material::Dielectric(ior) => vec![3, bits(ior)],
+    };
+    key.push_all(&[bits(color.x), bits(color.y), bits(color.z), bits(emission.x), bits(emission.y),
+                   bits(emission.z)]);
+    match *texture {
+        None => key.push(0),
+        Some(Texture::Constant(c)) => key.push_all(&[1, bits(c.x), bits(c.y), bits(c.z)]),
+        Some(Texture::Checker(a, b, size)) => key.push_all(&[2, bits(a.x), bits(a.y), bits(a.z), bits(b.x),
+                                                             bits(b.y), bits(b.z), bits(size)]),
+        Some(Texture::Image(ref img)) => key.push_all(&[3, &**img as *const Image as usize as u64]),
+        Some(Texture::Mapped(ref img)) => key.push_all(&[4, &**img as *const Image as usize as u64]),
+    }
+    key
+}
+
+impl Scene {
+    /// Starts a scene to be put together in code, see `builder`.
+    pub fn builder() -> SceneBuilder {
+        SceneBuilder::new()
+    }
+
+    /// Reads a scene description file, or imports a pbrt or glTF scene
+    /// depending on the file extension.
+    pub fn load(path: &Path) -> Result<Scene, Error> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("pbrt") => pbrt::load(path),
+            Some("gltf") | Some("glb") => gltf::load(path),
+            _ => format::load(path),
+        }
+    }
+
+    /// Finds the closest hit of `r`. Objects are numbered spheres first,
+    /// then triangles; `object` looks them up.
+    pub fn intersect(&self, r: &Ray, t: &mut f64, id: &mut usize) -> bool
+    {
+        let inf = 10e20f64;
+        *t = inf;
+        stats::ray(self.spheres.len() + self.triangles.len());
+        for (i, sphere) in self.spheres.iter().enumerate() {
+            let d: f64 = sphere.intersect(r);
+            if d != 0.0 && d < *t {
+                *t = d;
+                *id = i;
+            }
+
+        }
+        for (i, triangle) in self.triangles.iter().enumerate() {
+            let d: f64 = triangle.intersect(r);
+            if d != 0.0 && d < *t {
+                *t = d;
+                *id = self.spheres.len() + i;
+            }
+        }
+        return *t < inf;
+
+    }
+
+    pub fn object(&self, id: usize) -> &Surface {
+        if id < self.spheres.len() {
+            &self.spheres[id]
+        } else {
+            &self.triangles[id - self.spheres.len()]
+        }
+    }
+
+    /// Numbers the distinct materials of the scene by object: objects that
+    /// scatter, color and emit light the same way share a number, counted in
+    /// the order objects are numbered.
+    pub fn material_ids(&self) -> Vec<usize> {
+        let mut numbers: HashMap<Vec<u64>, usize> = HashMap::new();
+        let mut ids = Vec::with_capacity(self.spheres.len() + self.triangles.len());
+        let looks = self.spheres.iter().map(|s| appearance(s.material, s.color, s.emission, &s.texture))
+            .chain(self.triangles.iter().map(|t| appearance(t.material, t.color, t.emission, &t.texture)));
+        for look in looks {
+            let known = numbers.get(&look).map(|&n| n);
+            ids.push(match known {
+                Some(n) => n,
+                None => {
+                    let n = numbers.len();
+                    numbers.insert(look, n);
+                    n
+                }
+            });
+        }
+        ids
+    }
+
+    /// Two overlapping spheres seen head on by an orthographic camera, as
+    /// drawn by raytracer_2d. The view is 500 units wide, so a 500x500 image
+    /// has one unit per pixel.
+    pub fn two_spheres(width: usize, height: usize) -> Scene {
+        let mut camera: Camera = Default::default();
+        camera.eye.o = Vector::new(250.0, 250.0, 0.0);
+        camera.eye.d = Vector::new(0.0, 0.0, -1.0);
+        camera.up = Vector::new(0.0, 1.0, 0.0);
+        camera.projection = Projection::Orthographic(250.0);
+        let black = Vector::new(0.0, 0.0, 0.0);
+        Scene {
+            camera: camera,
+            spheres: vec![
+                Sphere::new(150.0, Vector::new(212.0, 284.0, -1000.0), black, Vector::new(0.25, 0.25, 0.75)),
+                Sphere::new(150.0, Vector::new(300.0, 200.0, -1000.0), black, Vector::new(0.25, 0.50, 0.75)),
+            ],
+            triangles: Vec::new(),
+            width: width,
+            height: height,
+            samples: 1,
+            integrator: Integrator::Flat,
+            max_depth: MAX_DEPTH,
+            background: Vector::new(0.5, 0.5, 0.5),
+            light: Vector::new(0.0, 0.0, 1.0),
+        }
+    }
+
+    /// A single sphere in front of a pinhole camera, lit head on, as drawn by
+    /// raytracer_pinhole.
+    pub fn pinhole_sphere(width: usize, height: usize) -> Scene {
+        let mut camera: Camera = Default::default();
+        camera.eye.o = Vector::new(0.0, 0.0, 1.0);
+        camera.eye.d = Vector::new(0.0, 0.0, -1.0);
+        camera.up = Vector::new(0.0, 1.0, 0.0);
+        camera.projection = Projection::Perspective(1.0);
+        Scene {
+            camera: camera,
+            spheres: vec![
+                Sphere::new(1.41, Vector::new(0.0, 0.0, -1.0), Vector::new(0.0, 0.0, 0.0), Vector::new(0.75, 0.75, 0.75)),
+            ],
+            triangles: Vec::new(),
+            width: width,
+            height: height,
+            samples: 1,
+            integrator: Integrator::Diffuse,
+            max_depth: MAX_DEPTH,
+            background: Vector::new(0.25, 0.25, 0.25),
+            light: Vector::new(0.0, 0.0, 1.0).norm(),
+        }
+    }
+
+    /// The smallpt Cornell box, as path traced by raytracer_pinhole_path.
+    pub fn cornell_box(width: usize, height: usize) -> Scene {
+        let mut camera: Camera = Default::default();
+        camera.eye.o = Vector::new(50.0, 52.0, 295.6);
+        camera.eye.d = Vector::new(0.0, -0.042612, -1.0);
+        camera.up = Vector::new(1.0, 0.0, 0.0);
+        camera.projection = Projection::Perspective(2.0);
+        // Widen the shutter interval (and give spheres or the camera a Motion)
+        // to get motion blur:
+        camera.shutter_open = 0.0;
+        camera.shutter_close = 0.0;
+
+        let black = Vector::new(0.0, 0.0, 0.0);
+        Scene {
+            camera: camera,
+            spheres: vec![
+                Sphere::new(1e5, Vector::new(1e5 + 1.0, 40.8, 81.6), black, Vector::new(0.75, 0.25, 0.25)), // Left
+                Sphere::new(1e5, Vector::new(-1e5 + 99.0, 40.8, 81.6), black, Vector::new(0.25, 0.25, 0.75)), // Rght
+                Sphere::new(1e5, Vector::new(50.0, 40.8, 1e5), black, Vector::new(0.75, 0.75, 0.75)), // Back
+                Sphere::new(1e5, Vector::new(50.0, 40.8, -1e5 + 600.0), black, Vector::new(1.0, 1.0, 1.0)), // Frnt
+                Sphere::new(1e5, Vector::new(50.0, 1e5, 81.6), black, Vector::new(0.75, 0.75, 0.75)), // Botm
+                Sphere::new(1e5, Vector::new(50.0, -1e5 + 81.6, 81.6), black, Vector::new(0.75, 0.75, 0.75)), // Top
+                Sphere::new(16.5, Vector::new(27.0, 16.5, 47.0), black, Vector::new(0.999, 0.999, 0.999)), // Mirr
+                Sphere::new(16.5, Vector::new(73.0, 16.5, 78.0), black, Vector::new(0.999, 0.999, 0.999)), // Glas
+                Sphere::new(600.0, Vector::new(50.0, 681.6 - 0.27, 81.6), Vector::new(12.0, 12.0, 12.0), Vector::new(1.0, 1.0, 1.0)), // Lite
+            ],
+            triangles: Vec::new(),
+            width: width,
+            height: height,
+            samples: 5000,
+            integrator: Integrator::Path,
+            max_depth: MAX_DEPTH,
+            background: black,
+            light: Vector::new(0.0, 1.0, 0.0),
+        }
+    }
+}
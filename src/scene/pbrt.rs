@@ -0,0 +1,923 @@
+//! Importer for the common subset of pbrt-v3 and pbrt-v4 scene files.
+//!
+//! Supported directives: `LookAt`, `Translate`, `Scale`, `Rotate`,
+//! `Transform`, `ConcatTransform`, `Identity`, `CoordinateSystem`,
+//! `CoordSysTransform`, `Camera "perspective"`, `Film`, `Sampler`,
+//! `Integrator`, `WorldBegin`/`WorldEnd`, `AttributeBegin`/`AttributeEnd`,
+//! `TransformBegin`/`TransformEnd`, `Material`, `MakeNamedMaterial`,
+//! `NamedMaterial`, `LightSource`, `AreaLightSource "diffuse"`, `Shape` and
+//! `Include`/`Import`. Directives that do not change the image much
+//! (`PixelFilter`, `Accelerator`, `ColorSpace`, `Option`, `Texture`,
+//! `ReverseOrientation`, `Attribute`) are skipped, anything else is an
+//! error.
+//!
+//! The renderer knows fewer features than pbrt, so scenes are mapped onto it:
+//!
+//! * Shapes: full spheres, `trianglemesh`, `plymesh` and `bilinearmesh`.
+//!   Shading normals, uv coordinates and alpha textures are ignored.
+//! * Materials: `diffuse` (`matte`), and `coateddiffuse`, `plastic`,
+//!   `substrate` and `uber` as diffuse; `conductor` (`metal`) as a mirror or,
+//!   when rough, as a glossy lobe colored by the normal incidence Fresnel
+//!   reflectance; `dielectric` (`glass`) as smooth glass. Textures in place
+//!   of colors fall back to the defaults.
+//! * Lights: area lights make their shapes emit from both sides. Point
+//!   lights become small spheres of the same intensity, with a radius of 1%
+//!   of their distance to the camera. Infinite lights set the background to
+//!   their radiance, or the average of their environment map. Distant lights
+//!   set the light of the diffuse integrator.
+//! * Every integrator maps to the path tracer, and its maximum depth is the
+//!   renderer's own.
+//! * Spectra are RGB; blackbody emitters are approximated at three
+//!   wavelengths, sampled spectra by their mean.
+//!
+//! pbrt's camera space is left-handed. Scenes are mirrored across the plane
+//! of the camera's view and up directions so that the renderer's right-handed
+//! camera shows the same image.
+
+use std::num::Float;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use std::default::Default;
+
+use vector::{Vector, VectorOps};
+use shape::{Sphere, Triangle};
+use material::Material;
+use camera::{Camera, Projection};
+use integrator::{Integrator, MAX_DEPTH};
+use transform::Transform;
+use image;
+use super::{Scene, Error, point_light};
+use super::ply;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Number(f64),
+    Str(String),
+    Open,
+    Close,
+    End,
+}
+
+#[derive(Debug, Clone)]
+struct Spanned {
+    token: Token,
+    line: usize,
+    column: usize,
+}
+
+fn error(path: &Path, line: usize, column: usize, message: String) -> Error {
+    Error { path: path.to_path_buf(), line: line, column: column, message: message }
+}
+
+fn tokenize(path: &Path, src: &str) -> Result<Vec<Spanned>, Error> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut tokens = Vec::new();
+    let (mut i, mut line, mut column) = (0, 1, 1);
+
+    while i < chars.len() {
+        let c = chars[i];
+        let (start_line, start_column) = (line, column);
+        let mut len = 1;
+        let mut token = None;
+        if c == '\n' {
+            i += 1;
+            line += 1;
+            column = 1;
+            continue;
+        } else if c.is_whitespace() {
+        } else if c == '#' {
+            while i + len < chars.len() && chars[i + len] != '\n' {
+                len += 1;
+            }
+        } else if c == '[' {
+            token = Some(Token::Open);
+        } else if c == ']' {
+            token = Some(Token::Close);
+        } else if c == '"' {
+            while i + len < chars.len() && chars[i + len] != '"' && chars[i + len] != '\n' {
+                len += 1;
+            }
+            if i + len == chars.len() || chars[i + len] == '\n' {
+                return Err(error(path, line, column, "unterminated string".to_string()));
+            }
+            token = Some(Token::Str(chars[i + 1..i + len].iter().map(|c| *c).collect()));
+            len += 1;
+        } else if c.is_numeric() || c == '-' || c == '+' || c == '.' {
+            while i + len < chars.len() && (chars[i + len].is_alphanumeric() || chars[i + len] == '.' ||
+                                            ((chars[i + len] == '-' || chars[i + len] == '+') &&
+                                             (chars[i + len - 1] == 'e' || chars[i + len - 1] == 'E'))) {
+                len += 1;
+            }
+            let text: String = chars[i..i + len].iter().map(|c| *c).collect();
+            match text.parse::<f64>() {
+                Ok(v) => token = Some(Token::Number(v)),
+                Err(_) => return Err(error(path, line, column, format!("invalid number '{}'", text))),
+            }
+        } else if c.is_alphabetic() {
+            while i + len < chars.len() && chars[i + len].is_alphanumeric() {
+                len += 1;
+            }
+            token = Some(Token::Word(chars[i..i + len].iter().map(|c| *c).collect()));
+        } else {
+            return Err(error(path, line, column, format!("unexpected character '{}'", c)));
+        }
+
+        if let Some(token) = token {
+            tokens.push(Spanned { token: token, line: start_line, column: start_column });
+        }
+        i += len;
+        column += len;
+    }
+
+    tokens.push(Spanned { token: Token::End, line: line, column: column });
+    Ok(tokens)
+}
+
+fn describe(token: &Token) -> String {
+    match *token {
+        Token::Word(ref w) => format!("'{}'", w),
+        Token::Number(v) => format!("number {}", v),
+        Token::Str(ref s) => format!("string \"{}\"", s),
+        Token::Open => "'['".to_string(),
+        Token::Close => "']'".to_string(),
+        Token::End => "end of file".to_string(),
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    Number(f64),
+    Str(String),
+    Bool(bool),
+}
+
+/// A `"type name" [values]` parameter of a directive.
+#[derive(Debug, Clone)]
+struct Param {
+    ty: String,
+    name: String,
+    values: Vec<Value>,
+    at: Spanned,
+}
+
+struct Parser<'a> {
+    path: &'a Path,
+    tokens: Vec<Spanned>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> &Spanned {
+        &self.tokens[self.pos]
+    }
+
+    fn next(&mut self) -> Spanned {
+        let t = self.tokens[self.pos].clone();
+        if t.token != Token::End {
+            self.pos += 1;
+        }
+        t
+    }
+
+    fn error_at(&self, at: &Spanned, message: String) -> Error {
+        error(self.path, at.line, at.column, message)
+    }
+
+    fn unexpected(&self, at: &Spanned, expected: &str) -> Error {
+        self.error_at(at, format!("expected {}, found {}", expected, describe(&at.token)))
+    }
+
+    fn number(&mut self) -> Result<f64, Error> {
+        let t = self.next();
+        match t.token {
+            Token::Number(v) => Ok(v),
+            _ => Err(self.unexpected(&t, "a number")),
+        }
+    }
+
+    /// `n` numbers, optionally in brackets.
+    fn numbers(&mut self, n: usize) -> Result<Vec<f64>, Error> {
+        let bracketed = self.peek().token == Token::Open;
+        if bracketed {
+            self.next();
+        }
+        let mut v = Vec::new();
+        for _ in 0..n {
+            v.push(try!(self.number()));
+        }
+        if bracketed {
+            let t = self.next();
+            if t.token != Token::Close {
+                return Err(self.unexpected(&t, "']'"));
+            }
+        }
+        Ok(v)
+    }
+
+    fn vector(&mut self) -> Result<Vector, Error> {
+        let v = try!(self.numbers(3));
+        Ok(Vector::new(v[0], v[1], v[2]))
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        let t = self.next();
+        match t.token {
+            Token::Str(s) => Ok(s),
+            _ => Err(self.unexpected(&t, "a string")),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, Error> {
+        let t = self.next();
+        match t.token {
+            Token::Number(v) => Ok(Value::Number(v)),
+            Token::Str(s) => Ok(Value::Str(s)),
+            Token::Word(ref w) if &w[..] == "true" => Ok(Value::Bool(true)),
+            Token::Word(ref w) if &w[..] == "false" => Ok(Value::Bool(false)),
+            _ => Err(self.unexpected(&t, "a parameter value")),
+        }
+    }
+
+    /// The parameter list following the positional arguments of a directive.
+    fn params(&mut self) -> Result<Vec<Param>, Error> {
+        let mut params = Vec::new();
+        loop {
+            let at = self.peek().clone();
+            let decl = match at.token {
+                Token::Str(ref s) => s.clone(),
+                _ => return Ok(params),
+            };
+            self.next();
+            let words: Vec<&str> = decl.split(' ').filter(|w| !w.is_empty()).collect();
+            if words.len() != 2 {
+                return Err(self.error_at(&at, format!("expected a \"type name\" parameter, found \"{}\"", decl)));
+            }
+            let mut values = Vec::new();
+            if self.peek().token == Token::Open {
+                self.next();
+                while self.peek().token != Token::Close {
+                    values.push(try!(self.value()));
+                }
+                self.next();
+            } else {
+                values.push(try!(self.value()));
+            }
+            params.push(Param { ty: words[0].to_string(), name: words[1].to_string(), values: values, at: at });
+        }
+    }
+}
+
+/// Looks up and converts parameters, reporting errors at their position.
+struct Params<'a> {
+    path: &'a Path,
+    list: Vec<Param>,
+}
+
+impl<'a> Params<'a> {
+    fn find(&self, name: &str) -> Option<&Param> {
+        self.list.iter().rev().find(|p| &p.name[..] == name)
+    }
+
+    fn error(&self, p: &Param, message: String) -> Error {
+        error(self.path, p.at.line, p.at.column, message)
+    }
+
+    fn numbers(&self, p: &Param) -> Result<Vec<f64>, Error> {
+        p.values.iter().map(|v| match *v {
+            Value::Number(x) => Ok(x),
+            _ => Err(self.error(p, format!("parameter \"{}\" takes numbers", p.name))),
+        }).collect()
+    }
+
+    fn float(&self, name: &str, default: f64) -> Result<f64, Error> {
+        match self.find(name) {
+            Some(p) => {
+                let v = try!(self.numbers(p));
+                if v.len() != 1 {
+                    return Err(self.error(p, format!("parameter \"{}\" takes one number", name)));
+                }
+                Ok(v[0])
+            }
+            None => Ok(default),
+        }
+    }
+
+    fn string(&self, name: &str) -> Result<Option<String>, Error> {
+        match self.find(name) {
+            Some(p) => match (p.values.len(), p.values.first()) {
+                (1, Some(&Value::Str(ref s))) => Ok(Some(s.clone())),
+                _ => Err(self.error(p, format!("parameter \"{}\" takes one string", name))),
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn bool(&self, name: &str, default: bool) -> Result<bool, Error> {
+        match self.find(name) {
+            Some(p) => match (p.values.len(), p.values.first()) {
+                (1, Some(&Value::Bool(b))) => Ok(b),
+                // pbrt-v3 writes booleans as strings
+                (1, Some(&Value::Str(ref s))) if &s[..] == "true" || &s[..] == "false" => Ok(&s[..] == "true"),
+                _ => Err(self.error(p, format!("parameter \"{}\" takes true or false", name))),
+            },
+            None => Ok(default),
+        }
+    }
+
+    /// A list of 3D points, flattened.
+    fn points(&self, name: &str) -> Result<Option<Vec<Vector>>, Error> {
+        match self.find(name) {
+            Some(p) => {
+                let v = try!(self.numbers(p));
+                if v.len() % 3 != 0 {
+                    return Err(self.error(p, format!("parameter \"{}\" takes a multiple of 3 numbers", name)));
+                }
+                Ok(Some((0..v.len() / 3).map(|i| Vector::new(v[3 * i], v[3 * i + 1], v[3 * i + 2])).collect()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// An RGB color from `rgb`, `color`, `blackbody` or `spectrum` values,
+    /// None if the parameter is missing or a texture.
+    fn color(&self, name: &str) -> Result<Option<Vector>, Error> {
+        let p = match self.find(name) {
+            Some(p) => p,
+            None => return Ok(None),
+        };
+        match &p.ty[..] {
+            "rgb" | "color" => {
+                let v = try!(self.numbers(p));
+                if v.len() != 3 {
+                    return Err(self.error(p, format!("parameter \"{}\" takes 3 numbers", name)));
+                }
+                Ok(Some(Vector::new(v[0], v[1], v[2])))
+            }
+            "blackbody" => {
+                let v = try!(self.numbers(p));
+                if v.len() == 0 || v[0] <= 0.0 {
+                    return Err(self.error(p, format!("parameter \"{}\" takes a temperature", name)));
+                }
+                // pbrt-v3 appends a scale
+                Ok(Some(blackbody(v[0]).smul(if v.len() > 1 { v[1] } else { 1.0 })))
+            }
+            "spectrum" => match p.values.first() {
+                Some(&Value::Str(ref s)) => match named_spectrum(&s[..]) {
+                    Some(c) => Ok(Some(c)),
+                    None => Err(self.error(p, format!("unknown named spectrum \"{}\"", s))),
+                },
+                _ => {
+                    // Wavelength and value pairs
+                    let v = try!(self.numbers(p));
+                    if v.len() < 2 || v.len() % 2 != 0 {
+                        return Err(self.error(p, format!("parameter \"{}\" takes wavelength and value pairs", name)));
+                    }
+                    let mean = (0..v.len() / 2).map(|i| v[2 * i + 1]).fold(0.0, |a, b| a + b) / (v.len() / 2) as f64;
+                    Ok(Some(Vector::new(mean, mean, mean)))
+                }
+            },
+            "texture" => Ok(None),
+            _ => Err(self.error(p, format!("parameter \"{}\" is not a color", name))),
+        }
+    }
+}
+
+/// Color of a black body at `kelvin`, from Planck's law at three
+/// wavelengths and scaled to unit luminance.
+fn blackbody(kelvin: f64) -> Vector {
+    let planck = |nm: f64| {
+        let l = nm * 1e-9;
+        let (h, c, k) = (6.62606957e-34, 299792458.0, 1.3806488e-23);
+        2.0 * h * c * c / (l.powi(5) * ((h * c / (l * k * kelvin)).exp() - 1.0))
+    };
+    let rgb = Vector::new(planck(610.0), planck(550.0), planck(465.0));
+    rgb.smul(1.0 / (0.2126 * rgb.x + 0.7152 * rgb.y + 0.0722 * rgb.z))
+}
+
+/// Normal incidence reflectance of pbrt's named metal spectra, either name
+/// of the eta and k pair gives the same color.
+fn named_spectrum(name: &str) -> Option<Vector> {
+    let metal = name.trim_right_matches("-eta").trim_right_matches("-k");
+    match metal {
+        "metal-Au" => Some(Vector::new(1.0, 0.766, 0.336)),
+        "metal-Ag" => Some(Vector::new(0.972, 0.960, 0.915)),
+        "metal-Cu" => Some(Vector::new(0.955, 0.638, 0.538)),
+        "metal-CuZn" => Some(Vector::new(0.910, 0.778, 0.423)),
+        "metal-Al" => Some(Vector::new(0.913, 0.922, 0.924)),
+        "metal-MgO" => Some(Vector::new(0.07, 0.07, 0.07)),
+        "metal-TiO2" => Some(Vector::new(0.18, 0.19, 0.21)),
+        _ if name.starts_with("glass-") => Some(Vector::new(1.5, 1.5, 1.5)),
+        _ => None,
+    }
+}
+
+/// Surface of shapes, None for pbrt's "interface" material, which has none.
+type Look = Option<(Material, Vector)>;
+
+fn material(params: &Params, ty: &str, at: &Spanned, path: &Path) -> Result<Look, Error> {
+    let white = Vector::new(1.0, 1.0, 1.0);
+    match ty {
+        "diffuse" | "matte" | "coateddiffuse" | "plastic" | "substrate" | "uber" => {
+            let kd = try!(params.color("reflectance")).or(try!(params.color("Kd")));
+            Ok(Some((Material::Diffuse, kd.unwrap_or(Vector::new(0.5, 0.5, 0.5)))))
+        }
+        "conductor" | "metal" => {
+            let roughness = match params.find("roughness") {
+                Some(_) => try!(params.float("roughness", 0.0)),
+                None => 0.5 * (try!(params.float("uroughness", 0.0)) + try!(params.float("vroughness", 0.0))),
+            };
+            let alpha = if try!(params.bool("remaproughness", true)) { roughness.sqrt() } else { roughness };
+            let color = match try!(params.color("reflectance")) {
+                Some(c) => c,
+                None => {
+                    let eta = try!(params.color("eta")).unwrap_or(named_spectrum("metal-Cu").unwrap());
+                    match (params.find("eta").map(|p| &p.ty[..]), try!(params.color("k"))) {
+                        // An RGB index of refraction and absorption
+                        (Some("rgb"), Some(k)) | (Some("color"), Some(k)) => {
+                            let f = |n: f64, k: f64| ((n - 1.0) * (n - 1.0) + k * k) / ((n + 1.0) * (n + 1.0) + k * k);
+                            Vector::new(f(eta.x, k.x), f(eta.y, k.y), f(eta.z, k.z))
+                        }
+                        // A named spectrum, which stands for the reflectance
+                        _ => eta,
+                    }
+                }
+            };
+            if alpha <= 1e-3 {
+                Ok(Some((Material::Mirror, color)))
+            } else {
+                Ok(Some((Material::Glossy((2.0 / (alpha * alpha) - 2.0).max(1.0)), color)))
+            }
+        }
+        "dielectric" | "glass" => {
+            let eta = match params.find("eta").or(params.find("index")) {
+                Some(p) => match p.values.first() {
+                    Some(&Value::Str(_)) => 1.5,
+                    _ => try!(params.float(&p.name[..], 1.5)),
+                },
+                None => 1.5,
+            };
+            Ok(Some((Material::Dielectric(eta), white)))
+        }
+        "interface" => Ok(None),
+        _ => Err(error(path, at.line, at.column, format!("unsupported material type \"{}\"", ty))),
+    }
+}
+
+#[derive(Clone)]
+struct Attributes {
+    ctm: Transform,
+    look: Look,
+    emission: Vector, // of area lights
+}
+
+struct PointLight {
+    position: Vector,
+    intensity: Vector,
+}
+
+struct Importer {
+    attributes: Attributes,
+    attribute_stack: Vec<Attributes>,
+    transform_stack: Vec<Transform>,
+    named_materials: HashMap<String, Look>,
+    coordinate_systems: HashMap<String, Transform>,
+    camera_to_world: Transform,
+    fov: f64,
+    width: usize,
+    height: usize,
+    samples: usize,
+    spheres: Vec<Sphere>,
+    triangles: Vec<Triangle>,
+    point_lights: Vec<PointLight>,
+    background: Vector,
+    light: Vector,
+    files: Vec<PathBuf>,
+}
+
+/// Reads the pbrt scene at `path` and the files it includes.
+pub fn load(path: &Path) -> Result<Scene, Error> {
+    let mut importer = Importer {
+        attributes: Attributes {
+            ctm: Transform::identity(),
+            look: Some((Material::Diffuse, Vector::new(0.5, 0.5, 0.5))),
+            emission: Vector::new(0.0, 0.0, 0.0),
+        },
+        attribute_stack: Vec::new(),
+        transform_stack: Vec::new(),
+        named_materials: HashMap::new(),
+        coordinate_systems: HashMap::new(),
+        camera_to_world: Transform::identity(),
+        fov: 90.0,
+        width: 1280,
+        height: 720,
+        samples: 16,
+        spheres: Vec::new(),
+        triangles: Vec::new(),
+        point_lights: Vec::new(),
+        background: Vector::new(0.0, 0.0, 0.0),
+        light: Vector::new(0.0, 0.0, 1.0),
+        files: Vec::new(),
+    };
+    try!(importer.file(path));
+    Ok(importer.finish())
+}
+
+impl Importer {
+    fn file(&mut self, path: &Path) -> Result<(), Error> {
+        let mut src = String::new();
+        if let Err(e) = File::open(path).and_then(|mut f| f.read_to_string(&mut src)) {
+            return Err(error(path, 0, 0, format!("{}", e)));
+        }
+        let tokens = try!(tokenize(path, &src[..]));
+        let mut p = Parser { path: path, tokens: tokens, pos: 0 };
+
+        self.files.push(path.to_path_buf());
+        loop {
+            let t = p.next();
+            match t.token {
+                Token::End => break,
+                Token::Word(_) => try!(self.directive(&mut p, &t)),
+                _ => return Err(p.unexpected(&t, "a directive")),
+            }
+        }
+        self.files.pop();
+        Ok(())
+    }
+
+    fn concat(&mut self, t: Transform) {
+        self.attributes.ctm = self.attributes.ctm.mul(&t);
+    }
+
+    fn directive(&mut self, p: &mut Parser, t: &Spanned) -> Result<(), Error> {
+        let name = match t.token {
+            Token::Word(ref w) => w.clone(),
+            _ => unreachable!(),
+        };
+        match &name[..] {
+            "Include" | "Import" => {
+                let file = try!(p.string());
+                let included = p.path.parent().unwrap_or(Path::new("")).join(&file[..]);
+                if self.files.iter().any(|f| *f == included) {
+                    return Err(p.error_at(t, format!("{} includes itself", included.display())));
+                }
+                return self.file(&included);
+            }
+            "Identity" => self.attributes.ctm = Transform::identity(),
+            "Translate" => {
+                let d = try!(p.vector());
+                self.concat(Transform::translate(d));
+            }
+            "Scale" => {
+                let s = try!(p.vector());
+                self.concat(Transform::scale(s));
+            }
+            "Rotate" => {
+                let v = try!(p.numbers(4));
+                let axis = Vector::new(v[1], v[2], v[3]);
+                if axis.dot(&axis) == 0.0 {
+                    return Err(p.error_at(t, "rotation axis must not be zero".to_string()));
+                }
+                self.concat(Transform::rotate(v[0], axis));
+            }
+            "LookAt" => {
+                let v = try!(p.numbers(9));
+                let eye = Vector::new(v[0], v[1], v[2]);
+                let dir = &Vector::new(v[3], v[4], v[5]) - &eye;
+                let up = Vector::new(v[6], v[7], v[8]);
+                if dir.dot(&dir) == 0.0 || up.cross(dir).dot(&up.cross(dir)) == 0.0 {
+                    return Err(p.error_at(t, "LookAt needs distinct points and an up vector off the view direction".to_string()));
+                }
+                // pbrt's left-handed camera frame
+                let dir = dir.norm();
+                let right = up.norm().cross(dir).norm();
+                let new_up = dir.cross(right);
+                let camera_to_world = Transform::new([[right.x, new_up.x, dir.x, eye.x],
+                                                      [right.y, new_up.y, dir.y, eye.y],
+                                                      [right.z, new_up.z, dir.z, eye.z],
+                                                      [0.0, 0.0, 0.0, 1.0]]);
+                self.concat(camera_to_world.inverse().unwrap());
+            }
+            "Transform" => {
+                let v = try!(p.numbers(16));
+                self.attributes.ctm = Transform::from_columns(&v[..]);
+            }
+            "ConcatTransform" => {
+                let v = try!(p.numbers(16));
+                self.concat(Transform::from_columns(&v[..]));
+            }
+            "CoordinateSystem" => {
+                let name = try!(p.string());
+                self.coordinate_systems.insert(name, self.attributes.ctm);
+            }
+            "CoordSysTransform" => {
+                let at = p.peek().clone();
+                let name = try!(p.string());
+                match self.coordinate_systems.get(&name) {
+                    Some(ctm) => self.attributes.ctm = *ctm,
+                    None => return Err(p.error_at(&at, format!("unknown coordinate system \"{}\"", name))),
+                }
+            }
+            "Camera" => {
+                let at = p.peek().clone();
+                let ty = try!(p.string());
+                let params = Params { path: p.path, list: try!(p.params()) };
+                if &ty[..] != "perspective" {
+                    return Err(p.error_at(&at, format!("unsupported camera type \"{}\"", ty)));
+                }
+                self.camera_to_world = match self.attributes.ctm.inverse() {
+                    Some(m) => m,
+                    None => return Err(p.error_at(t, "camera transformation is singular".to_string())),
+                };
+                self.coordinate_systems.insert("camera".to_string(), self.camera_to_world);
+                self.fov = try!(params.float("fov", 90.0));
+                if !(self.fov > 0.0 && self.fov < 180.0) {
+                    let fov = params.find("fov").unwrap();
+                    return Err(params.error(fov, format!("field of view {} is not between 0 and 180 degrees", self.fov)));
+                }
+            }
+            "Film" => {
+                try!(p.string());
+                let params = Params { path: p.path, list: try!(p.params()) };
+                self.width = try!(count(&params, "xresolution", self.width));
+                self.height = try!(count(&params, "yresolution", self.height));
+            }
+            "Sampler" => {
+                try!(p.string());
+                let params = Params { path: p.path, list: try!(p.params()) };
+                self.samples = try!(count(&params, "pixelsamples", self.samples));
+            }
+            "Integrator" | "PixelFilter" | "Accelerator" | "Texture" | "Attribute" => {
+                // Positional arguments, then parameters
+                while let Token::Str(ref s) = p.peek().token.clone() {
+                    if s.trim().contains(" ") {
+                        break;
+                    }
+                    p.next();
+                }
+                try!(p.params());
+            }
+            "ColorSpace" => {
+                try!(p.string());
+            }
+            "Option" => {
+                try!(p.params());
+            }
+            "ReverseOrientation" | "WorldEnd" => {}
+            "WorldBegin" => {
+                self.attributes.ctm = Transform::identity();
+                self.coordinate_systems.insert("world".to_string(), Transform::identity());
+            }
+            "AttributeBegin" => self.attribute_stack.push(self.attributes.clone()),
+            "AttributeEnd" => match self.attribute_stack.pop() {
+                Some(a) => self.attributes = a,
+                None => return Err(p.error_at(t, "AttributeEnd without AttributeBegin".to_string())),
+            },
+            "TransformBegin" => self.transform_stack.push(self.attributes.ctm),
+            "TransformEnd" => match self.transform_stack.pop() {
+                Some(ctm) => self.attributes.ctm = ctm,
+                None => return Err(p.error_at(t, "TransformEnd without TransformBegin".to_string())),
+            },
+            "Material" => {
+                let at = p.peek().clone();
+                let ty = try!(p.string());
+                let params = Params { path: p.path, list: try!(p.params()) };
+                self.attributes.look = try!(material(&params, &ty[..], &at, p.path));
+            }
+            "MakeNamedMaterial" => {
+                let name = try!(p.string());
+                let params = Params { path: p.path, list: try!(p.params()) };
+                let ty = match try!(params.string("type")) {
+                    Some(ty) => ty,
+                    None => return Err(p.error_at(t, format!("named material \"{}\" has no type", name))),
+                };
+                let at = params.find("type").unwrap().at.clone();
+                let look = try!(material(&params, &ty[..], &at, p.path));
+                self.named_materials.insert(name, look);
+            }
+            "NamedMaterial" => {
+                let at = p.peek().clone();
+                let name = try!(p.string());
+                match self.named_materials.get(&name) {
+                    Some(look) => self.attributes.look = *look,
+                    None => return Err(p.error_at(&at, format!("unknown material \"{}\"", name))),
+                }
+            }
+            "LightSource" => {
+                let at = p.peek().clone();
+                let ty = try!(p.string());
+                let params = Params { path: p.path, list: try!(p.params()) };
+                try!(self.light_source(&params, &ty[..], &at, p.path));
+            }
+            "AreaLightSource" => {
+                let at = p.peek().clone();
+                let ty = try!(p.string());
+                let params = Params { path: p.path, list: try!(p.params()) };
+                if &ty[..] != "diffuse" {
+                    return Err(p.error_at(&at, format!("unsupported area light type \"{}\"", ty)));
+                }
+                let l = try!(params.color("L")).unwrap_or(Vector::new(1.0, 1.0, 1.0));
+                self.attributes.emission = l.smul(try!(params.float("scale", 1.0)));
+            }
+            "Shape" => {
+                let at = p.peek().clone();
+                let ty = try!(p.string());
+                let params = Params { path: p.path, list: try!(p.params()) };
+                try!(self.shape(&params, &ty[..], &at, p.path));
+            }
+            _ => return Err(p.error_at(t, format!("unsupported directive '{}'", name))),
+        }
+        Ok(())
+    }
+
+    fn light_source(&mut self, params: &Params, ty: &str, at: &Spanned, path: &Path) -> Result<(), Error> {
+        let scale = try!(params.float("scale", 1.0));
+        match ty {
+            "point" => {
+                let from = match try!(params.points("from")) {
+                    Some(ref v) if v.len() == 1 => v[0],
+                    Some(_) => return Err(params.error(params.find("from").unwrap(), "\"from\" takes one point".to_string())),
+                    None => Vector::new(0.0, 0.0, 0.0),
+                };
+                let intensity = try!(params.color("I")).unwrap_or(Vector::new(1.0, 1.0, 1.0)).smul(scale);
+                self.point_lights.push(PointLight { position: self.attributes.ctm.point(from), intensity: intensity });
+            }
+            "infinite" => {
+                let l = match try!(params.string("filename")) {
+                    Some(file) => {
+                        let map_path = path.parent().unwrap_or(Path::new("")).join(&file[..]);
+                        match image::load(&map_path) {
+                            Ok(img) => {
+                                let n = (img.width * img.height) as f64;
+                                let mut sum = Vector::new(0.0, 0.0, 0.0);
+                                for y in 0..img.height {
+                                    for x in 0..img.width {
+                                        let (r, g, b) = img.rgb(x, y);
+                                        sum = &sum + &Vector::new(r, g, b);
+                                    }
+                                }
+                                sum.smul(1.0 / n)
+                            }
+                            Err(e) => return Err(params.error(params.find("filename").unwrap(),
+                                                              format!("cannot read {}: {}", map_path.display(), e))),
+                        }
+                    }
+                    None => try!(params.color("L")).unwrap_or(Vector::new(1.0, 1.0, 1.0)),
+                };
+                self.background = &self.background + &l.smul(scale);
+            }
+            "distant" => {
+                let from = try!(params.points("from")).and_then(|v| v.first().map(|p| *p)).unwrap_or(Vector::new(0.0, 0.0, 0.0));
+                let to = try!(params.points("to")).and_then(|v| v.first().map(|p| *p)).unwrap_or(Vector::new(0.0, 0.0, 1.0));
+                let d = self.attributes.ctm.vector(&from - &to);
+                if d.dot(&d) > 0.0 {
+                    self.light = d.norm();
+                }
+            }
+            _ => return Err(error(path, at.line, at.column, format!("unsupported light type \"{}\"", ty))),
+        }
+        Ok(())
+    }
+
+    fn shape(&mut self, params: &Params, ty: &str, at: &Spanned, path: &Path) -> Result<(), Error> {
+        let (material, color) = match self.attributes.look {
+            Some(look) => look,
+            // Only bounds media, which are not supported
+            None => return Ok(()),
+        };
+        let emission = self.attributes.emission;
+        let ctm = self.attributes.ctm;
+        let mut triangles: Vec<[Vector; 3]> = Vec::new();
+        match ty {
+            "sphere" => {
+                let radius = try!(params.float("radius", 1.0));
+                for &(name, default) in [("zmin", -radius), ("zmax", radius), ("phimax", 360.0)].iter() {
+                    if try!(params.float(name, default)) != default {
+                        return Err(params.error(params.find(name).unwrap(), "partial spheres are not supported".to_string()));
+                    }
+                }
+                let scale = match ctm.uniform_scale() {
+                    Some(s) => s,
+                    None => return Err(error(path, at.line, at.column, "spheres have to be scaled uniformly".to_string())),
+                };
+                if !(radius * scale > 0.0) {
+                    return Err(error(path, at.line, at.column, format!("sphere radius {} is not positive", radius * scale)));
+                }
+                let mut sphere = Sphere::new(radius * scale, ctm.point(Vector::new(0.0, 0.0, 0.0)), emission, color);
+                sphere.material = material;
+                self.spheres.push(sphere);
+                return Ok(());
+            }
+            "trianglemesh" | "bilinearmesh" => {
+                let positions = match try!(params.points("P")) {
+                    Some(p) => p,
+                    None => return Err(error(path, at.line, at.column, "mesh without \"P\"".to_string())),
+                };
+                let corners = if ty == "trianglemesh" { 3 } else { 4 };
+                let indices: Vec<f64> = match params.find("indices") {
+                    Some(p) => try!(params.numbers(p)),
+                    None if positions.len() == corners => (0..corners).map(|i| i as f64).collect(),
+                    None => return Err(error(path, at.line, at.column, "mesh without \"indices\"".to_string())),
+                };
+                if indices.len() % corners != 0 || indices.iter().any(|i| *i < 0.0 || *i >= positions.len() as f64) {
+                    return Err(params.error(params.find("indices").unwrap(), "invalid mesh indices".to_string()));
+                }
+                for face in indices.chunks(corners) {
+                    let v: Vec<Vector> = face.iter().map(|i| positions[*i as usize]).collect();
+                    triangles.push([v[0], v[1], v[2]]);
+                    if corners == 4 {
+                        // Bilinear patches have their corners in the order p00, p10, p01, p11
+                        triangles.push([v[2], v[1], v[3]]);
+                    }
+                }
+            }
+            "plymesh" => {
+                let file = match try!(params.string("filename")) {
+                    Some(file) => file,
+                    None => return Err(error(path, at.line, at.column, "plymesh without \"filename\"".to_string())),
+                };
+                let ply_path = path.parent().unwrap_or(Path::new("")).join(&file[..]);
+                let mesh = match File::open(&ply_path).and_then(|f| ply::read(&mut BufReader::new(f))) {
+                    Ok(mesh) => mesh,
+                    Err(e) => return Err(params.error(params.find("filename").unwrap(),
+                                                      format!("cannot read {}: {}", ply_path.display(), e))),
+                };
+                for t in mesh.triangles.iter() {
+                    triangles.push([mesh.positions[t[0]], mesh.positions[t[1]], mesh.positions[t[2]]]);
+                }
+            }
+            _ => return Err(error(path, at.line, at.column, format!("unsupported shape type \"{}\"", ty))),
+        }
+
+        for v in triangles.iter() {
+            let mut triangle = Triangle::new([ctm.point(v[0]), ctm.point(v[1]), ctm.point(v[2])], emission, color);
+            triangle.material = material;
+            self.triangles.push(triangle);
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Scene {
+        let c2w = self.camera_to_world;
+        let eye = c2w.point(Vector::new(0.0, 0.0, 0.0));
+        let forward = c2w.vector(Vector::new(0.0, 0.0, 1.0)).norm();
+        let up = c2w.vector(Vector::new(0.0, 1.0, 0.0)).norm();
+
+        // Mirror left-handed views across the plane of forward and up
+        let left_handed = c2w.determinant() > 0.0;
+        let mirror = if left_handed {
+            c2w.mul(&Transform::scale(Vector::new(-1.0, 1.0, 1.0))).mul(&c2w.inverse().unwrap())
+        } else {
+            Transform::identity()
+        };
+
+        let mut camera: Camera = Default::default();
+        camera.eye.o = eye;
+        camera.eye.d = forward;
+        // The renderer's camera takes the direction of increasing columns
+        camera.up = forward.cross(up);
+        camera.projection = Projection::Fov(self.fov);
+
+        let mut spheres = self.spheres;
+        for light in self.point_lights.iter() {
+            spheres.push(point_light(light.position, light.intensity, eye));
+        }
+        for sphere in spheres.iter_mut() {
+            sphere.position = mirror.point(sphere.position);
+        }
+        let mut triangles = self.triangles;
+        for triangle in triangles.iter_mut() {
+            let v = triangle.vertices;
+            // Mirroring flips the winding, swap two vertices to keep the normal
+            triangle.vertices = if left_handed {
+                [mirror.point(v[0]), mirror.point(v[2]), mirror.point(v[1])]
+            } else {
+                v
+            };
+        }
+
+        Scene {
+            camera: camera,
+            spheres: spheres,
+            triangles: triangles,
+            width: self.width,
+            height: self.height,
+            samples: self.samples,
+            integrator: Integrator::Path,
+            max_depth: MAX_DEPTH,
+            background: self.background,
+            light: mirror.vector(self.light).norm(),
+        }
+    }
+}
+
+fn count(params: &Params, name: &str, default: usize) -> Result<usize, Error> {
+    let v = try!(params.float(name, default as f64));
+    if !(v >= 1.0) || v.fract() != 0.0 || v > 1e9 {
+        return Err(params.error(params.find(name).unwrap(), format!("\"{}\" takes a positive whole number", name)));
+    }
+    Ok(v as usize)
+}
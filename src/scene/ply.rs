@@ -0,0 +1,246 @@
+//! Triangle meshes from PLY files, in ASCII or binary encoding.
+//!
+//! Only the vertex positions (`x`, `y`, `z`) and the face vertex lists
+//! (`vertex_indices` or `vertex_index`) are read, polygons are split into
+//! triangle fans. Other elements and properties are skipped.
+
+use std::io;
+use std::io::prelude::*;
+use std::str;
+use std::mem;
+
+use vector::Vector;
+
+pub struct Mesh {
+    pub positions: Vec<Vector>,
+    pub triangles: Vec<[usize; 3]>, // indices into positions
+}
+
+fn invalid(desc: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, desc, None)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Type {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+fn parse_type(s: &str) -> io::Result<Type> {
+    match s {
+        "char" | "int8" => Ok(Type::I8),
+        "uchar" | "uint8" => Ok(Type::U8),
+        "short" | "int16" => Ok(Type::I16),
+        "ushort" | "uint16" => Ok(Type::U16),
+        "int" | "int32" => Ok(Type::I32),
+        "uint" | "uint32" => Ok(Type::U32),
+        "float" | "float32" => Ok(Type::F32),
+        "double" | "float64" => Ok(Type::F64),
+        _ => Err(invalid("unknown PLY property type")),
+    }
+}
+
+fn size(ty: Type) -> usize {
+    match ty {
+        Type::I8 | Type::U8 => 1,
+        Type::I16 | Type::U16 => 2,
+        Type::I32 | Type::U32 | Type::F32 => 4,
+        Type::F64 => 8,
+    }
+}
+
+enum Property {
+    Scalar(String, Type),
+    List(String, Type, Type), // name, type of the count, type of the items
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Encoding {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+struct Body<'a> {
+    data: &'a [u8],
+    pos: usize,
+    encoding: Encoding,
+}
+
+impl<'a> Body<'a> {
+    fn value(&mut self, ty: Type) -> io::Result<f64> {
+        if self.encoding == Encoding::Ascii {
+            while self.pos < self.data.len() && (self.data[self.pos] as char).is_whitespace() {
+                self.pos += 1;
+            }
+            let start = self.pos;
+            while self.pos < self.data.len() && !(self.data[self.pos] as char).is_whitespace() {
+                self.pos += 1;
+            }
+            if start == self.pos {
+                return Err(invalid("truncated PLY file"));
+            }
+            return match str::from_utf8(&self.data[start..self.pos]).ok().and_then(|s| s.parse::<f64>().ok()) {
+                Some(v) => Ok(v),
+                None => Err(invalid("invalid number in PLY file")),
+            };
+        }
+
+        let n = size(ty);
+        if self.pos + n > self.data.len() {
+            return Err(invalid("truncated PLY file"));
+        }
+        let mut bits: u64 = 0;
+        for i in 0..n {
+            let k = if self.encoding == Encoding::LittleEndian { n - 1 - i } else { i };
+            bits = bits << 8 | self.data[self.pos + k] as u64;
+        }
+        self.pos += n;
+        Ok(match ty {
+            Type::I8 => bits as u8 as i8 as f64,
+            Type::U8 => bits as u8 as f64,
+            Type::I16 => bits as u16 as i16 as f64,
+            Type::U16 => bits as u16 as f64,
+            Type::I32 => bits as u32 as i32 as f64,
+            Type::U32 => bits as u32 as f64,
+            Type::F32 => { let v: f32 = unsafe { mem::transmute(bits as u32) }; v as f64 }
+            Type::F64 => { let v: f64 = unsafe { mem::transmute(bits) }; v }
+        })
+    }
+}
+
+fn index(v: f64, vertices: usize) -> io::Result<usize> {
+    if v < 0.0 || v >= vertices as f64 {
+        return Err(invalid("PLY face refers to a missing vertex"));
+    }
+    Ok(v as usize)
+}
+
+pub fn read<R: Read>(r: &mut R) -> io::Result<Mesh> {
+    let mut data = Vec::new();
+    try!(r.read_to_end(&mut data));
+
+    // The header is text, ending with the end_header line
+    let mut pos = 0;
+    let mut encoding = None;
+    let mut elements: Vec<Element> = Vec::new();
+    let mut first = true;
+    loop {
+        let start = pos;
+        while pos < data.len() && data[pos] != b'\n' {
+            pos += 1;
+        }
+        if pos == data.len() {
+            return Err(invalid("PLY header has no end_header line"));
+        }
+        pos += 1;
+        let line = match str::from_utf8(&data[start..pos]) {
+            Ok(line) => line.trim(),
+            Err(_) => return Err(invalid("PLY header is not text")),
+        };
+        let words: Vec<&str> = line.split(' ').filter(|w| !w.is_empty()).collect();
+        if first {
+            if line != "ply" {
+                return Err(invalid("not a PLY file"));
+            }
+            first = false;
+            continue;
+        }
+        if words.len() == 0 {
+            continue;
+        }
+        match words[0] {
+            "format" if words.len() >= 2 => encoding = Some(match words[1] {
+                "ascii" => Encoding::Ascii,
+                "binary_little_endian" => Encoding::LittleEndian,
+                "binary_big_endian" => Encoding::BigEndian,
+                _ => return Err(invalid("unknown PLY format")),
+            }),
+            "element" if words.len() == 3 => {
+                let count = try!(words[2].parse().map_err(|_| invalid("invalid PLY element count")));
+                elements.push(Element { name: words[1].to_string(), count: count, properties: Vec::new() });
+            }
+            "property" => {
+                let property = if words.len() == 5 && words[1] == "list" {
+                    Property::List(words[4].to_string(), try!(parse_type(words[2])), try!(parse_type(words[3])))
+                } else if words.len() == 3 {
+                    Property::Scalar(words[2].to_string(), try!(parse_type(words[1])))
+                } else {
+                    return Err(invalid("invalid PLY property"));
+                };
+                match elements.last_mut() {
+                    Some(e) => e.properties.push(property),
+                    None => return Err(invalid("PLY property outside of an element")),
+                }
+            }
+            "comment" | "obj_info" => {}
+            "end_header" => break,
+            _ => return Err(invalid("invalid PLY header line")),
+        }
+    }
+
+    let mut body = Body {
+        data: &data[pos..],
+        pos: 0,
+        encoding: match encoding {
+            Some(e) => e,
+            None => return Err(invalid("PLY header has no format line")),
+        },
+    };
+    let mut mesh = Mesh { positions: Vec::new(), triangles: Vec::new() };
+    let mut faces = Vec::new();
+    for element in elements.iter() {
+        for _ in 0..element.count {
+            let mut p = Vector::new(0.0, 0.0, 0.0);
+            for property in element.properties.iter() {
+                match *property {
+                    Property::Scalar(ref name, ty) => {
+                        let v = try!(body.value(ty));
+                        if element.name == "vertex" {
+                            match &name[..] {
+                                "x" => p.x = v,
+                                "y" => p.y = v,
+                                "z" => p.z = v,
+                                _ => {}
+                            }
+                        }
+                    }
+                    Property::List(ref name, count_type, item_type) => {
+                        let count = try!(body.value(count_type));
+                        let mut items = Vec::new();
+                        for _ in 0..count as usize {
+                            items.push(try!(body.value(item_type)));
+                        }
+                        if element.name == "face" && (name == "vertex_indices" || name == "vertex_index") {
+                            faces.push(items);
+                        }
+                    }
+                }
+            }
+            if element.name == "vertex" {
+                mesh.positions.push(p);
+            }
+        }
+    }
+
+    let vertices = mesh.positions.len();
+    for face in faces.iter() {
+        for k in 2..face.len() {
+            mesh.triangles.push([try!(index(face[0], vertices)), try!(index(face[k - 1], vertices)),
+                                 try!(index(face[k], vertices))]);
+        }
+    }
+    Ok(mesh)
+}
@@ -0,0 +1,248 @@
+use std::num::Float;
+
+use vector::{Vector, VectorOps};
+use ray::Ray;
+use motion::Motion;
+use float::gamma;
+use material::Material;
+use texture::Texture;
+
+pub trait Shape {
+    /// Distance along `r` to the closest hit in front of its origin, 0 for a
+    /// miss. Roots that cannot be told apart from zero given the floating
+    /// point error of their computation do not count, which skips the
+    /// surface a ray spawned with `Ray::spawn` starts on.
+    fn intersect(&self, r: &Ray) -> f64;
+
+    /// Outward unit normal at the surface point `x` at `time`.
+    fn normal(&self, x: Vector, time: f64) -> Vector;
+
+    /// Moves the hit point `x` computed as `o + t * d` onto the surface and
+    /// returns it with a bound on the absolute error of each coordinate.
+    fn surface_point(&self, x: Vector, time: f64) -> (Vector, Vector);
+}
+
+/// A shape together with how its surface looks.
+pub trait Surface: Shape {
+    fn emission(&self) -> Vector;
+
+    fn material(&self) -> Material;
+
+    /// Surface color at the surface point `x` at `time`.
+    fn albedo(&self, x: Vector, time: f64) -> Vector;
+}
+
+fn abs(v: Vector) -> Vector {
+    Vector { x: v.x.abs(), y: v.y.abs(), z: v.z.abs() }
+}
+
+fn length(v: Vector) -> f64 {
+    v.dot(&v).sqrt()
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Sphere {
+    pub radius: f64,
+    pub position: Vector,
+    pub emission: Vector,
+    pub color: Vector,
+    pub texture: Option<Texture>, // varies the color over the surface
+    pub material: Material,
+    pub motion: Motion,
+}
+
+impl Sphere {
+    pub fn new(radius: f64, position: Vector, emission: Vector, color: Vector) -> Sphere {
+        Sphere { radius: radius, position: position, emission: emission, color: color, texture: None,
+                 material: Material::Diffuse, motion: Motion::Static }
+    }
+
+    pub fn center(&self, time: f64) -> Vector {
+        &self.position + &self.motion.offset(time)
+    }
+}
+
+impl Shape for Sphere {
+    fn intersect(&self, r: &Ray) -> f64 {
+        // Solve t^2 - 2*t*(p-o).d + (p-o).(p-o)-R^2 = 0 for unit d. The roots
+        // are b -+ sqrt(det), both computed without cancellation: det from
+        // the distance of the line to the center, the root of smaller
+        // magnitude from the product of the roots.
+        let center = self.center(r.time);
+        let op = &center - &r.o;
+        let b = op.dot(&r.d);
+        let closest = &op - &r.d.smul(b);
+        let r2 = self.radius * self.radius;
+        let det = r2 - closest.dot(&closest);
+
+        if det < 0.0 {
+            return 0.0;
+        }
+        let det = det.sqrt();
+        let q = if b < 0.0 { b - det } else { b + det };
+        if q == 0.0 {
+            return 0.0;
+        }
+        let c = op.dot(&op) - r2;
+        let (near, far) = if c / q < q { (c / q, q) } else { (q, c / q) };
+
+        // Error bounds of the roots, the subtraction of the origin counts as
+        // it is large for shapes far from the origin
+        let op_len = length(op);
+        let op_error = gamma(1) * (length(abs(center)) + length(abs(r.o)));
+        let det2_error = gamma(8) * (op.dot(&op) + r2) + 2.0 * op_len * op_error;
+        let det_error = (det2_error / (2.0 * det)).min(det2_error.sqrt());
+        let q_error = gamma(5) * op_len + op_error + det_error;
+        let c_error = gamma(5) * (op.dot(&op) + r2) + 2.0 * op_len * op_error;
+        let error = |t: f64| if t == q { q_error } else { (c_error + t.abs() * q_error) / q.abs() };
+
+        if near > error(near) {
+            return near;
+        }
+
+        if far > error(far) {
+            return far;
+        }
+
+        return 0.0;
+    }
+
+    fn normal(&self, x: Vector, time: f64) -> Vector {
+        (&x - &self.center(time)).norm()
+    }
+
+    fn surface_point(&self, x: Vector, time: f64) -> (Vector, Vector) {
+        let center = self.center(time);
+        let rel = &x - &center;
+        let rel = rel.smul(self.radius / length(rel));
+        let p = &center + &rel;
+        (p, &abs(rel).smul(gamma(5)) + &abs(p).smul(gamma(1)))
+    }
+}
+
+impl Surface for Sphere {
+    fn emission(&self) -> Vector {
+        self.emission
+    }
+
+    fn material(&self) -> Material {
+        self.material
+    }
+
+    fn albedo(&self, x: Vector, time: f64) -> Vector {
+        match self.texture {
+            Some(ref texture) => texture.eval(&x - &self.center(time)),
+            None => self.color,
+        }
+    }
+}
+
+/// A single sided triangle, its normal follows the counterclockwise order
+/// of the vertices.
+#[derive(Debug, Clone)]
+pub struct Triangle {
+    pub vertices: [Vector; 3],
+    pub emission: Vector,
+    pub color: Vector,
+    pub material: Material,
+    pub texture: Option<Texture>, // replaces color, looked up with the texture coordinates
+    pub uv: [(f64, f64); 3], // texture coordinates of the vertices
+}
+
+impl Triangle {
+    pub fn new(vertices: [Vector; 3], emission: Vector, color: Vector) -> Triangle {
+        Triangle {
+            vertices: vertices,
+            emission: emission,
+            color: color,
+            material: Material::Diffuse,
+            texture: None,
+            uv: [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)],
+        }
+    }
+
+    /// Weights of the second and third vertex for the point `x` on the
+    /// triangle.
+    pub fn barycentric(&self, x: Vector) -> (f64, f64) {
+        let e1 = &self.vertices[1] - &self.vertices[0];
+        let e2 = &self.vertices[2] - &self.vertices[0];
+        let d = &x - &self.vertices[0];
+        let (d11, d12, d22) = (e1.dot(&e1), e1.dot(&e2), e2.dot(&e2));
+        let (d1, d2) = (d.dot(&e1), d.dot(&e2));
+        let det = d11 * d22 - d12 * d12;
+        ((d22 * d1 - d12 * d2) / det, (d11 * d2 - d12 * d1) / det)
+    }
+}
+
+impl Shape for Triangle {
+    fn intersect(&self, r: &Ray) -> f64 {
+        // Moeller-Trumbore: solve o + t*d = p0 + u*e1 + v*e2 with Cramer's rule
+        let (p0, p1, p2) = (self.vertices[0], self.vertices[1], self.vertices[2]);
+        let e1 = &p1 - &p0;
+        let e2 = &p2 - &p0;
+        let pvec = r.d.cross(e2);
+        let det = e1.dot(&pvec);
+        if det == 0.0 {
+            return 0.0;
+        }
+        let s = &r.o - &p0;
+        let u = s.dot(&pvec) / det;
+        if u < 0.0 || u > 1.0 {
+            return 0.0;
+        }
+        let qvec = s.cross(e1);
+        let v = r.d.dot(&qvec) / det;
+        if v < 0.0 || u + v > 1.0 {
+            return 0.0;
+        }
+        let t = e2.dot(&qvec) / det;
+
+        // Error bound of t, from the rounding of the edges, of the offset of
+        // the origin and of the two triple products
+        let (e1_len, e2_len, s_len) = (length(e1), length(e2), length(s));
+        let edge_error = gamma(1) * (length(abs(p0)) + length(abs(p1)) + length(abs(p2)));
+        let s_error = gamma(1) * (length(abs(r.o)) + length(abs(p0)));
+        let num_error = gamma(6) * e1_len * e2_len * s_len + (e1_len + e2_len) * edge_error * s_len +
+                        e1_len * e2_len * s_error;
+        let det_error = gamma(6) * e1_len * e2_len + (e1_len + e2_len) * edge_error;
+        if t > (num_error + t.abs() * det_error) / det.abs() { t } else { 0.0 }
+    }
+
+    fn normal(&self, _: Vector, _: f64) -> Vector {
+        let (p0, p1, p2) = (self.vertices[0], self.vertices[1], self.vertices[2]);
+        (&p1 - &p0).cross(&p2 - &p0).norm()
+    }
+
+    fn surface_point(&self, x: Vector, time: f64) -> (Vector, Vector) {
+        // Project onto the plane of the triangle
+        let p0 = self.vertices[0];
+        let n = self.normal(x, time);
+        let rel = &x - &p0;
+        let p = &x - &n.smul(rel.dot(&n));
+        (p, (&(&abs(p) + &abs(p0)) + &Vector::new(1.0, 1.0, 1.0).smul(length(rel))).smul(gamma(8)))
+    }
+}
+
+impl Surface for Triangle {
+    fn emission(&self) -> Vector {
+        self.emission
+    }
+
+    fn material(&self) -> Material {
+        self.material
+    }
+
+    fn albedo(&self, x: Vector, _: f64) -> Vector {
+        match self.texture {
+            Some(ref texture) => {
+                let (b1, b2) = self.barycentric(x);
+                let b0 = 1.0 - b1 - b2;
+                let uv = &self.uv;
+                let u = b0 * uv[0].0 + b1 * uv[1].0 + b2 * uv[2].0;
+                let v = b0 * uv[0].1 + b1 * uv[1].1 + b2 * uv[2].1;
+                texture.eval(Vector::new(u, v, 0.0))
+            }
+            None => self.color,
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! Counting the work of a render and reporting its progress.
+//!
+//! Rays and intersection tests are counted per thread, without locking, and
+//! collected with `take` after each tile. A path is the camera ray and every
+//! ray traced after it for the same sample, so the rays of a sample give its
+//! length. Shadow rays, which only the wavefront integrator traces, are
+//! counted apart from paths. The scene intersects each ray with every object,
+//! there is no BVH yet, so the count of BVH nodes stays at zero for now.
+
+use std::num::Float;
+use std::cell::RefCell;
+use std::default::Default;
+use std::io::prelude::*;
+use std::io;
+use std::fmt;
+use std::sync::Mutex;
+
+use time;
+
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+    pub camera_rays: u64,
+    pub secondary_rays: u64, // rays of a path after the camera ray
+    pub shadow_rays: u64,
+    pub intersection_tests: u64, // of a ray with a single object
+    pub bvh_nodes: u64, // visited while tracing rays
+    pub path_lengths: Vec<u64>, // paths by number of rays
+    pub seconds: f64, // spent rendering
+}
+
+struct Counters {
+    rays: u64,
+    stats: Stats,
+}
+
+thread_local!(static COUNTERS: RefCell<Counters> = RefCell::new(Counters {
+    rays: 0,
+    stats: Default::default(),
+}));
+
+/// Counts a ray tested against `objects` objects.
+pub fn ray(objects: usize) {
+    COUNTERS.with(|c| {
+        let mut c = c.borrow_mut();
+        c.rays += 1;
+        c.stats.intersection_tests += objects as u64;
+    });
+}
+
+/// The rays this thread has traced so far.
+pub fn rays() -> u64 {
+    COUNTERS.with(|c| c.borrow().rays)
+}
+
+/// Counts a path of `length` rays.
+pub fn path(length: u64) {
+    if length == 0 {
+        return;
+    }
+    COUNTERS.with(|c| {
+        let stats = &mut c.borrow_mut().stats;
+        stats.camera_rays += 1;
+        stats.secondary_rays += length - 1;
+        let length = length as usize;
+        while stats.path_lengths.len() <= length {
+            stats.path_lengths.push(0);
+        }
+        stats.path_lengths[length] += 1;
+    });
+}
+
+/// Counts a shadow ray, which does not belong to the length of a path.
+pub fn shadow_ray() {
+    COUNTERS.with(|c| c.borrow_mut().stats.shadow_rays += 1);
+}
+
+/// The statistics this thread gathered since the last call.
+pub fn take() -> Stats {
+    COUNTERS.with(|c| {
+        let mut c = c.borrow_mut();
+        let stats = c.stats.clone();
+        c.stats = Default::default();
+        stats
+    })
+}
+
+impl Stats {
+    pub fn add(&mut self, other: &Stats) {
+        self.camera_rays += other.camera_rays;
+        self.secondary_rays += other.secondary_rays;
+        self.shadow_rays += other.shadow_rays;
+        self.intersection_tests += other.intersection_tests;
+        self.bvh_nodes += other.bvh_nodes;
+        while self.path_lengths.len() < other.path_lengths.len() {
+            self.path_lengths.push(0);
+        }
+        for (n, count) in other.path_lengths.iter().enumerate() {
+            self.path_lengths[n] += *count;
+        }
+        self.seconds += other.seconds;
+    }
+
+    pub fn rays(&self) -> u64 {
+        self.camera_rays + self.secondary_rays + self.shadow_rays
+    }
+
+    /// Camera rays, that is samples, per second.
+    pub fn samples_per_second(&self) -> f64 {
+        if self.seconds > 0.0 { self.camera_rays as f64 / self.seconds } else { 0.0 }
+    }
+
+    /// Millions of rays per second.
+    pub fn mrays_per_second(&self) -> f64 {
+        if self.seconds > 0.0 { self.rays() as f64 / self.seconds / 1e6 } else { 0.0 }
+    }
+
+    pub fn mean_path_length(&self) -> f64 {
+        if self.camera_rays > 0 { self.rays() as f64 / self.camera_rays as f64 } else { 0.0 }
+    }
+
+    pub fn write_json(&self, w: &mut Write) -> io::Result<()> {
+        let mut lengths = String::new();
+        for (n, count) in self.path_lengths.iter().enumerate() {
+            if n > 0 {
+                lengths.push_str(", ");
+            }
+            lengths.push_str(&count.to_string()[..]);
+        }
+        write!(w, "{{\n  \"seconds\": {},\n  \"camera_rays\": {},\n  \"secondary_rays\": {},\n  \
+                   \"shadow_rays\": {},\n  \"rays\": {},\n  \"intersection_tests\": {},\n  \
+                   \"bvh_nodes\": {},\n  \"samples_per_second\": {},\n  \"mrays_per_second\": {},\n  \
+                   \"mean_path_length\": {},\n  \"path_lengths\": [{}]\n}}\n",
+               self.seconds, self.camera_rays, self.secondary_rays, self.shadow_rays, self.rays(),
+               self.intersection_tests, self.bvh_nodes, self.samples_per_second(),
+               self.mrays_per_second(), self.mean_path_length(), lengths)
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} samples in {}: {:.0} samples/s, {:.2} Mrays/s, {:.2} rays per path", self.camera_rays,
+               format_seconds(self.seconds), self.samples_per_second(), self.mrays_per_second(),
+               self.mean_path_length())
+    }
+}
+
+/// A progress bar on stdout with the time left, redrawn at most every
+/// quarter of a second.
+pub struct Progress {
+    start: f64,
+    last: Mutex<f64>,
+}
+
+impl Progress {
+    pub fn new() -> Progress {
+        Progress { start: time::precise_time_s(), last: Mutex::new(0.0) }
+    }
+
+    /// Shows `fraction` of the work as done, with `note` behind the bar.
+    pub fn update(&self, fraction: f64, note: &str) {
+        let now = time::precise_time_s();
+        {
+            let mut last = self.last.lock().unwrap();
+            if now - *last < 0.25 && fraction < 1.0 {
+                return;
+            }
+            *last = now;
+        }
+        let fraction = fraction.max(0.0).min(1.0);
+        let elapsed = now - self.start;
+        let width = 30;
+        let filled = (fraction * width as f64) as usize;
+        let bar: String = (0..width).map(|n| if n < filled { '=' } else if n == filled { '>' } else { ' ' }).collect();
+        let eta = if fraction > 0.0 {
+            format_seconds(elapsed * (1.0 - fraction) / fraction)
+        } else {
+            "?".to_string()
+        };
+        print!("\r[{}] {:3.0}% {} elapsed, {} left {}  ", bar, fraction * 100.0, format_seconds(elapsed),
+               eta, note);
+        io::stdout().flush().ok();
+    }
+}
+
+/// Seconds as 1h02m03s, 2m03s or 3.4s.
+pub fn format_seconds(s: f64) -> String {
+    let whole = s as u64;
+    if whole >= 3600 {
+        format!("{}h{:02}m{:02}s", whole / 3600, whole / 60 % 60, whole % 60)
+    } else if whole >= 60 {
+        format!("{}m{:02}s", whole / 60, whole % 60)
+    } else {
+        format!("{:.1}s", s)
+    }
+}
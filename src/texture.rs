@@ -0,0 +1,48 @@
+//! Surface colors that vary over a shape.
+//!
+//! Spheres look textures up with the hit point relative to their center, so
+//! they move along with it. Triangles look them up with the texture
+//! coordinates (u, v, 0) of the hit point.
+
+use std::num::Float;
+use std::sync::Arc;
+use std::f64::consts::PI;
+
+use vector::{Vector, VectorOps};
+use image::Image;
+
+#[derive(Debug, Clone)]
+pub enum Texture {
+    Constant(Vector),
+    Checker(Vector, Vector, f64), // 3D checkerboard of two colors with cubes of the given size
+    Image(Arc<Image>), // latitude-longitude map, +y is up and the seam lies at -x
+    Mapped(Arc<Image>), // repeating in x and y, (0, 0) is the top left corner and (1, 1) the bottom right
+}
+
+impl Texture {
+    /// Color at the point `p`, relative to the center of the shape.
+    pub fn eval(&self, p: Vector) -> Vector {
+        match *self {
+            Texture::Constant(c) => c,
+            Texture::Checker(a, b, size) => {
+                let cell = (p.x / size).floor() + (p.y / size).floor() + (p.z / size).floor();
+                if cell % 2.0 == 0.0 { a } else { b }
+            }
+            Texture::Image(ref img) => {
+                let d = p.norm();
+                let u = 0.5 + d.z.atan2(-d.x) / (2.0 * PI);
+                let v = d.y.max(-1.0).min(1.0).acos() / PI;
+                let x = ((u * img.width as f64) as usize).min(img.width - 1);
+                let y = ((v * img.height as f64) as usize).min(img.height - 1);
+                let (r, g, b) = img.rgb(x, y);
+                Vector::new(r, g, b)
+            }
+            Texture::Mapped(ref img) => {
+                let x = ((p.x - p.x.floor()) * img.width as f64) as usize;
+                let y = ((p.y - p.y.floor()) * img.height as f64) as usize;
+                let (r, g, b) = img.rgb(x.min(img.width - 1), y.min(img.height - 1));
+                Vector::new(r, g, b)
+            }
+        }
+    }
+}
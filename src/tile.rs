@@ -0,0 +1,285 @@
+//! Rendering the film in tiles on several threads.
+//!
+//! The film is cut into square tiles on a grid anchored at the top left
+//! corner of the image, so a cropped film gets the tiles of the full image
+//! clipped to the crop window. The tiles are ordered along a Hilbert curve,
+//! a spiral from the center or row by row, and dealt out to the threads in
+//! consecutive runs. A thread takes tiles from the front of its own queue
+//! and, once that is empty, steals from the back of the others, so threads
+//! that got cheap tiles help out with the expensive ones. Finished tiles are
+//! written into the film right away.
+//!
+//! Every sample of a pixel draws from its own random number stream, seeded
+//! from the render seed, the pixel and the sample number, so the image
+//! depends neither on the number of threads, the tile size or which thread
+//! rendered which tile, nor on how the samples were split into passes, and a
+//! crop window shows exactly that part of the full image.
+
+use std::num::Float;
+use std::cmp;
+use std::str::FromStr;
+use std::default::Default;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use scene::Scene;
+use film::{Film, Pixel};
+use integrator;
+use wavefront;
+use stats::{self, Stats};
+use rng::Rng;
+use time;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Order {
+    Hilbert, // along a Hilbert curve, neighboring tiles are rendered close in time
+    Spiral, // outwards from the center of the image
+    Scanline, // row by row
+}
+
+impl FromStr for Order {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Order, String> {
+        match s {
+            "hilbert" => Ok(Order::Hilbert),
+            "spiral" => Ok(Order::Spiral),
+            "scanline" => Ok(Order::Scanline),
+            _ => Err(format!("unknown tile order '{}'", s)),
+        }
+    }
+}
+
+/// How the samples of a tile are traced.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Kernel {
+    Megakernel, // one path after the other with `integrator::get_sample`
+    Wavefront, // batches of paths in stages with `wavefront::render_tile`
+}
+
+impl FromStr for Kernel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Kernel, String> {
+        match s {
+            "megakernel" => Ok(Kernel::Megakernel),
+            "wavefront" => Ok(Kernel::Wavefront),
+            _ => Err(format!("unknown kernel '{}'", s)),
+        }
+    }
+}
+
+impl Kernel {
+    /// Adds samples `first..first + count` to the pixels of `tile`.
+    pub fn render_tile(&self, scene: &Scene, tile: &Tile, seed: u64, first: usize, count: usize,
+                       pixels: &mut [Pixel]) {
+        match *self {
+            Kernel::Megakernel => render_tile(scene, tile, seed, first, count, pixels),
+            Kernel::Wavefront => wavefront::render_tile(scene, tile, seed, first, count, pixels),
+        }
+    }
+}
+
+/// Columns x0..x1 and rows y0..y1 of the image.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Tile {
+    pub x0: usize,
+    pub y0: usize,
+    pub x1: usize,
+    pub y1: usize,
+}
+
+impl Tile {
+    pub fn pixels(&self) -> usize {
+        (self.x1 - self.x0) * (self.y1 - self.y0)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Settings {
+    pub tile_size: usize, // in pixels along each side
+    pub order: Order,
+    pub threads: usize,
+    pub seed: u64,
+    pub kernel: Kernel,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings { tile_size: 32, order: Order::Hilbert, threads: ::std::os::num_cpus(), seed: 0,
+                   kernel: Kernel::Megakernel }
+    }
+}
+
+/// Position of cell (x, y) along the Hilbert curve through an n x n grid,
+/// n a power of two.
+fn hilbert_index(n: usize, x: usize, y: usize) -> usize {
+    let (mut x, mut y) = (x, y);
+    let mut d = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = if x & s > 0 { 1 } else { 0 };
+        let ry = if y & s > 0 { 1 } else { 0 };
+        d += s * s * ((3 * rx) ^ ry);
+        // Rotate the quadrant so the curve continues where the last one ended
+        if ry == 0 {
+            if rx == 1 {
+                x = n - 1 - x;
+                y = n - 1 - y;
+            }
+            let t = x;
+            x = y;
+            y = t;
+        }
+        s /= 2;
+    }
+    d
+}
+
+/// The tiles covering columns x0..x1 and rows y0..y1 in rendering order.
+pub fn tiles(x0: usize, y0: usize, x1: usize, y1: usize, size: usize, order: Order) -> Vec<Tile> {
+    assert!(size > 0);
+    if x0 >= x1 || y0 >= y1 {
+        return Vec::new();
+    }
+    // Grid cells touched by the window
+    let (cx0, cy0) = (x0 / size, y0 / size);
+    let (columns, rows) = ((x1 - 1) / size + 1 - cx0, (y1 - 1) / size + 1 - cy0);
+
+    let mut cells: Vec<(usize, usize)> = Vec::with_capacity(columns * rows);
+    for r in 0..rows {
+        for c in 0..columns {
+            cells.push((c, r));
+        }
+    }
+    match order {
+        Order::Scanline => {}
+        Order::Hilbert => {
+            let n = cmp::max(columns, rows).next_power_of_two();
+            cells.sort_by(|a, b| hilbert_index(n, a.0, a.1).cmp(&hilbert_index(n, b.0, b.1)));
+        }
+        Order::Spiral => {
+            // By square ring around the center, then by angle within a ring
+            let (mx, my) = ((columns - 1) as f64 / 2.0, (rows - 1) as f64 / 2.0);
+            let key = |&(c, r): &(usize, usize)| {
+                let (dx, dy) = (c as f64 - mx, r as f64 - my);
+                (dx.abs().max(dy.abs()).round(), dy.atan2(dx))
+            };
+            cells.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap_or(cmp::Ordering::Equal));
+        }
+    }
+
+    cells.iter().map(|&(c, r)| {
+        let (tx, ty) = ((cx0 + c) * size, (cy0 + r) * size);
+        Tile {
+            x0: cmp::max(tx, x0),
+            y0: cmp::max(ty, y0),
+            x1: cmp::min(tx + size, x1),
+            y1: cmp::min(ty + size, y1),
+        }
+    }).collect()
+}
+
+/// Adds samples `first..first + count` to the pixels of `tile`, sample number
+/// by sample number, each pixel and sample drawing from its own random
+/// number stream.
+pub fn render_tile(scene: &Scene, tile: &Tile, seed: u64, first: usize, count: usize, pixels: &mut [Pixel]) {
+    for k in first..first + count {
+        let mut index = 0;
+        for i in tile.y0..tile.y1 {
+            for j in tile.x0..tile.x1 {
+                let mut rng = Rng::with_sample(seed, i, j, k);
+                let time = scene.camera.sample_time(&mut rng);
+                let ray = scene.camera.get_ray(i, j, scene.width, scene.height, time);
+                pixels[index].add(&integrator::get_sample(scene, scene.integrator, ray, &mut rng), 1.0);
+                index += 1;
+            }
+        }
+    }
+}
+
+/// The next tile for thread `k`: the front of its own queue, or the back of
+/// another one.
+fn next_tile(queues: &[Mutex<VecDeque<Tile>>], k: usize) -> Option<Tile> {
+    for n in 0..queues.len() {
+        let mut queue = queues[(k + n) % queues.len()].lock().unwrap();
+        let tile = if n == 0 { queue.pop_front() } else { queue.pop_back() };
+        if tile.is_some() {
+            return tile;
+        }
+    }
+    None
+}
+
+/// Adds samples until every pixel of `film` has `scene.samples`. `progress`
+/// is called after each tile with the number of pixels done and the number
+/// of pixels in the film.
+pub fn render<F>(scene: &Scene, film: &mut Film, settings: &Settings, progress: F) -> Stats
+    where F: Fn(usize, usize) + Sync
+{
+    let (first, count) = (film.samples, scene.samples - cmp::min(film.samples, scene.samples));
+    render_pass(scene, film, settings, first, count, progress)
+}
+
+/// Adds samples `first..first + count` to every pixel of `film`, which must
+/// hold the samples before `first`, and returns what it took. Rendering all
+/// samples in one pass gives the same film as rendering them in several.
+pub fn render_pass<F>(scene: &Scene, film: &mut Film, settings: &Settings, first: usize, count: usize,
+                      progress: F) -> Stats
+    where F: Fn(usize, usize) + Sync
+{
+    let start = time::precise_time_s();
+    assert!(film.samples == first);
+    let tiles = tiles(film.x0, film.y0, film.x0 + film.width, film.y0 + film.height,
+                      settings.tile_size, settings.order);
+    let threads = cmp::max(1, cmp::min(settings.threads, tiles.len()));
+    let queues: Vec<Mutex<VecDeque<Tile>>> = (0..threads).map(|k| {
+        let run = &tiles[k * tiles.len() / threads..(k + 1) * tiles.len() / threads];
+        Mutex::new(run.iter().map(|t| *t).collect())
+    }).collect();
+
+    let total = film.width * film.height;
+    let totals: Mutex<Stats> = Mutex::new(Default::default());
+    {
+        let done = AtomicUsize::new(0);
+        let shared = Mutex::new(&mut *film);
+        let (seed, kernel) = (settings.seed, settings.kernel);
+        let workers: Vec<_> = (0..threads).map(|k| {
+            let (queues, done, film, totals, progress) = (&queues, &done, &shared, &totals, &progress);
+            thread::scoped(move || {
+                while let Some(tile) = next_tile(&queues[..], k) {
+                    // Tiles do not overlap, so the pixels can be taken out of the
+                    // film while the samples are added
+                    let mut pixels = Vec::with_capacity(tile.pixels());
+                    {
+                        let film = film.lock().unwrap();
+                        for i in tile.y0..tile.y1 {
+                            for j in tile.x0..tile.x1 {
+                                pixels.push(*film.pixel(j, i));
+                            }
+                        }
+                    }
+                    kernel.render_tile(scene, &tile, seed, first, count, &mut pixels[..]);
+                    totals.lock().unwrap().add(&stats::take());
+                    {
+                        let mut film = film.lock().unwrap();
+                        let width = tile.x1 - tile.x0;
+                        for (index, p) in pixels.iter().enumerate() {
+                            *film.pixel_mut(tile.x0 + index % width, tile.y0 + index / width) = *p;
+                        }
+                    }
+                    let finished = done.fetch_add(tile.pixels(), Ordering::SeqCst) + tile.pixels();
+                    progress(finished, total);
+                }
+            })
+        }).collect();
+        // Joins the threads
+        drop(workers);
+    }
+    film.samples += count;
+    let mut stats = totals.lock().unwrap().clone();
+    stats.seconds = time::precise_time_s() - start;
+    stats
+}
@@ -0,0 +1,268 @@
+//! Mapping linear radiance to 8 or 16 bit display values.
+//!
+//! A value goes through exposure compensation, a tone mapping operator that
+//! compresses it into [0, 1], the sRGB transfer function and finally
+//! quantization, optionally with a dither offset to break up banding.
+
+use std::num::Float;
+use std::str::FromStr;
+
+/// Tone mapping curve, applied to each color channel separately.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Operator {
+    Clamp, // hard clip at 1
+    Reinhard, // x / (1 + x)
+    ReinhardExtended(f64), // Reinhard with the given white point mapping to 1
+    Aces, // Narkowicz' fit of the ACES filmic curve
+    Hable, // John Hable's Uncharted 2 filmic curve
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Dither {
+    None,
+    Ordered, // 8x8 Bayer matrix
+    BlueNoise, // 64x64 void and cluster threshold map
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ToneMap {
+    pub exposure: f64, // in stops, radiance is scaled by 2^exposure
+    pub operator: Operator,
+    pub dither: Dither,
+}
+
+impl Default for ToneMap {
+    fn default() -> ToneMap {
+        ToneMap { exposure: 0.0, operator: Operator::Clamp, dither: Dither::None }
+    }
+}
+
+impl FromStr for Operator {
+    type Err = String;
+
+    /// Parses `clamp`, `reinhard`, `reinhard:<white>`, `aces` or `hable`.
+    fn from_str(s: &str) -> Result<Operator, String> {
+        match s {
+            "clamp" => Ok(Operator::Clamp),
+            "reinhard" => Ok(Operator::Reinhard),
+            "aces" => Ok(Operator::Aces),
+            "hable" => Ok(Operator::Hable),
+            _ if s.starts_with("reinhard:") => {
+                match s["reinhard:".len()..].parse::<f64>() {
+                    Ok(white) if white > 0.0 => Ok(Operator::ReinhardExtended(white)),
+                    _ => Err(format!("invalid white point in '{}'", s)),
+                }
+            }
+            _ => Err(format!("unknown tone mapping operator '{}'", s)),
+        }
+    }
+}
+
+impl FromStr for Dither {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Dither, String> {
+        match s {
+            "none" => Ok(Dither::None),
+            "ordered" => Ok(Dither::Ordered),
+            "blue-noise" => Ok(Dither::BlueNoise),
+            _ => Err(format!("unknown dither mode '{}'", s)),
+        }
+    }
+}
+
+fn clamp(x: f64) -> f64 {
+    if x < 0.0 {
+        return 0.0;
+    }
+    if x > 1.0 {
+        return 1.0;
+    }
+
+    x
+}
+
+fn hable_curve(x: f64) -> f64 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+impl Operator {
+    /// Maps a non-negative linear value into [0, 1].
+    pub fn apply(&self, x: f64) -> f64 {
+        let x = x.max(0.0);
+        clamp(match *self {
+            Operator::Clamp => x,
+            Operator::Reinhard => x / (1.0 + x),
+            Operator::ReinhardExtended(white) => x * (1.0 + x / (white * white)) / (1.0 + x),
+            Operator::Aces => (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14),
+            // The usual exposure bias of 2 and linear white point of 11.2
+            Operator::Hable => hable_curve(2.0 * x) / hable_curve(11.2),
+        })
+    }
+}
+
+/// The sRGB transfer function (IEC 61966-2-1), for values in [0, 1].
+pub fn srgb_encode(x: f64) -> f64 {
+    if x <= 0.0031308 { 12.92 * x } else { 1.055 * x.powf(1.0 / 2.4) - 0.055 }
+}
+
+/// Inverse of `srgb_encode`.
+pub fn srgb_decode(x: f64) -> f64 {
+    if x <= 0.04045 { x / 12.92 } else { ((x + 0.055) / 1.055).powf(2.4) }
+}
+
+impl ToneMap {
+    /// Display referred value in [0, 1] for linear `x`, before sRGB encoding.
+    pub fn map(&self, x: f64) -> f64 {
+        self.operator.apply(x * 2.0f64.powf(self.exposure))
+    }
+
+    /// Prepares quantization with this tone map, setting up the dither
+    /// threshold map if needed.
+    pub fn quantizer(&self) -> Quantizer {
+        let (size, thresholds) = match self.dither {
+            Dither::None => (1, vec![0.0]),
+            Dither::Ordered => (8, bayer(8)),
+            Dither::BlueNoise => (64, blue_noise(64)),
+        };
+        Quantizer { tonemap: *self, size: size, thresholds: thresholds }
+    }
+}
+
+pub struct Quantizer {
+    tonemap: ToneMap,
+    size: usize,
+    thresholds: Vec<f64>, // dither offsets in (-0.5, 0.5), size * size of them
+}
+
+impl Quantizer {
+    /// Code value in [0, max] for linear `v` at pixel (x, y).
+    pub fn encode(&self, v: f64, x: usize, y: usize, max: u32) -> u32 {
+        let offset = self.thresholds[(y % self.size) * self.size + x % self.size];
+        let code = (srgb_encode(self.tonemap.map(v)) * max as f64 + 0.5 + offset).floor();
+        if code < 0.0 { 0 } else if code > max as f64 { max } else { code as u32 }
+    }
+}
+
+/// Turns ranks 0..n into thresholds centered in (-0.5, 0.5).
+fn thresholds(ranks: &[usize]) -> Vec<f64> {
+    let n = ranks.len() as f64;
+    ranks.iter().map(|&r| (r as f64 + 0.5) / n - 0.5).collect()
+}
+
+/// Recursive Bayer matrix, `size` must be a power of two.
+fn bayer(size: usize) -> Vec<f64> {
+    let mut ranks = vec![0];
+    let mut n = 1;
+    while n < size {
+        let mut next = vec![0; 4 * n * n];
+        for y in 0..n {
+            for x in 0..n {
+                let r = 4 * ranks[y * n + x];
+                next[y * 2 * n + x] = r;
+                next[y * 2 * n + x + n] = r + 2;
+                next[(y + n) * 2 * n + x] = r + 3;
+                next[(y + n) * 2 * n + x + n] = r + 1;
+            }
+        }
+        ranks = next;
+        n *= 2;
+    }
+    thresholds(&ranks[..])
+}
+
+/// Threshold map generated with Ulichney's void and cluster method on a
+/// torus, using a Gaussian energy filter.
+fn blue_noise(size: usize) -> Vec<f64> {
+    let n = size * size;
+    let sigma = 1.5;
+    let radius = 6;
+
+    // Toroidal Gaussian kernel, kernel[dy * size + dx]
+    let mut kernel = vec![0.0; n];
+    for dy in 0..size {
+        for dx in 0..size {
+            let wx = (if dx > size / 2 { size - dx } else { dx }) as isize;
+            let wy = (if dy > size / 2 { size - dy } else { dy }) as isize;
+            if wx <= radius && wy <= radius {
+                kernel[dy * size + dx] = (-((wx * wx + wy * wy) as f64) / (2.0 * sigma * sigma)).exp();
+            }
+        }
+    }
+
+    let mut energy = vec![0.0; n];
+    let update = |energy: &mut Vec<f64>, p: usize, sign: f64| {
+        let (px, py) = (p % size, p / size);
+        for y in 0..size {
+            for x in 0..size {
+                let dx = (x + size - px) % size;
+                let dy = (y + size - py) % size;
+                energy[y * size + x] += sign * kernel[dy * size + dx];
+            }
+        }
+    };
+    // Tightest cluster: the set pixel with the most energy, largest void:
+    // the empty pixel with the least
+    let extreme = |energy: &Vec<f64>, pattern: &Vec<bool>, set: bool| -> usize {
+        let mut best = n;
+        for i in 0..n {
+            if pattern[i] == set && (best == n || (set && energy[i] > energy[best]) ||
+                                     (!set && energy[i] < energy[best])) {
+                best = i;
+            }
+        }
+        best
+    };
+
+    // Initial pattern: about a tenth of the pixels, placed by a fixed
+    // xorshift sequence so the map is the same on every run
+    let mut pattern = vec![false; n];
+    let mut state = 0x9e3779b9u32;
+    let mut ones = 0;
+    while ones < n / 10 {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        let p = state as usize % n;
+        if !pattern[p] {
+            pattern[p] = true;
+            update(&mut energy, p, 1.0);
+            ones += 1;
+        }
+    }
+
+    // Spread the initial points evenly
+    loop {
+        let cluster = extreme(&energy, &pattern, true);
+        pattern[cluster] = false;
+        update(&mut energy, cluster, -1.0);
+        let void = extreme(&energy, &pattern, false);
+        if void == cluster {
+            pattern[cluster] = true;
+            update(&mut energy, cluster, 1.0);
+            break;
+        }
+        pattern[void] = true;
+        update(&mut energy, void, 1.0);
+    }
+
+    let mut ranks = vec![0; n];
+    // Rank the initial points by removing tightest clusters from a copy
+    let mut removed = pattern.clone();
+    let mut removed_energy = energy.clone();
+    for rank in (0..ones).rev() {
+        let cluster = extreme(&removed_energy, &removed, true);
+        removed[cluster] = false;
+        update(&mut removed_energy, cluster, -1.0);
+        ranks[cluster] = rank;
+    }
+    // Rank the remaining pixels by filling the largest voids
+    for rank in ones..n {
+        let void = extreme(&energy, &pattern, false);
+        pattern[void] = true;
+        update(&mut energy, void, 1.0);
+        ranks[void] = rank;
+    }
+    thresholds(&ranks[..])
+}
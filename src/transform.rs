@@ -0,0 +1,149 @@
+//! Affine transformations of points and directions, for scene importers
+//! that place objects with transformation hierarchies.
+
+use std::num::Float;
+use std::f64::consts::PI;
+
+use vector::{Vector, VectorOps};
+
+/// A 4x4 matrix in row major order, applied to column vectors.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Transform {
+    pub m: [[f64; 4]; 4],
+}
+
+impl Transform {
+    pub fn identity() -> Transform {
+        Transform::new([[1.0, 0.0, 0.0, 0.0],
+                        [0.0, 1.0, 0.0, 0.0],
+                        [0.0, 0.0, 1.0, 0.0],
+                        [0.0, 0.0, 0.0, 1.0]])
+    }
+
+    pub fn new(m: [[f64; 4]; 4]) -> Transform {
+        Transform { m: m }
+    }
+
+    /// From 16 values in column major order, as stored by OpenGL, glTF and
+    /// pbrt.
+    pub fn from_columns(v: &[f64]) -> Transform {
+        assert!(v.len() == 16);
+        let mut m = [[0.0; 4]; 4];
+        for c in 0..4 {
+            for r in 0..4 {
+                m[r][c] = v[c * 4 + r];
+            }
+        }
+        Transform::new(m)
+    }
+
+    pub fn translate(d: Vector) -> Transform {
+        Transform::new([[1.0, 0.0, 0.0, d.x],
+                        [0.0, 1.0, 0.0, d.y],
+                        [0.0, 0.0, 1.0, d.z],
+                        [0.0, 0.0, 0.0, 1.0]])
+    }
+
+    pub fn scale(s: Vector) -> Transform {
+        Transform::new([[s.x, 0.0, 0.0, 0.0],
+                        [0.0, s.y, 0.0, 0.0],
+                        [0.0, 0.0, s.z, 0.0],
+                        [0.0, 0.0, 0.0, 1.0]])
+    }
+
+    /// Rotation by `degrees` counterclockwise around `axis`, looking against
+    /// the axis.
+    pub fn rotate(degrees: f64, axis: Vector) -> Transform {
+        let a = axis.norm();
+        let (s, c) = (degrees * PI / 180.0).sin_cos();
+        Transform::new([[a.x * a.x + (1.0 - a.x * a.x) * c, a.x * a.y * (1.0 - c) - a.z * s, a.x * a.z * (1.0 - c) + a.y * s, 0.0],
+                        [a.x * a.y * (1.0 - c) + a.z * s, a.y * a.y + (1.0 - a.y * a.y) * c, a.y * a.z * (1.0 - c) - a.x * s, 0.0],
+                        [a.x * a.z * (1.0 - c) - a.y * s, a.y * a.z * (1.0 - c) + a.x * s, a.z * a.z + (1.0 - a.z * a.z) * c, 0.0],
+                        [0.0, 0.0, 0.0, 1.0]])
+    }
+
+    /// Rotation by the unit quaternion (x, y, z, w).
+    pub fn quaternion(x: f64, y: f64, z: f64, w: f64) -> Transform {
+        Transform::new([[1.0 - 2.0 * (y * y + z * z), 2.0 * (x * y - z * w), 2.0 * (x * z + y * w), 0.0],
+                        [2.0 * (x * y + z * w), 1.0 - 2.0 * (x * x + z * z), 2.0 * (y * z - x * w), 0.0],
+                        [2.0 * (x * z - y * w), 2.0 * (y * z + x * w), 1.0 - 2.0 * (x * x + y * y), 0.0],
+                        [0.0, 0.0, 0.0, 1.0]])
+    }
+
+    pub fn mul(&self, other: &Transform) -> Transform {
+        let mut m = [[0.0; 4]; 4];
+        for r in 0..4 {
+            for c in 0..4 {
+                m[r][c] = (0..4).map(|k| self.m[r][k] * other.m[k][c]).fold(0.0, |a, b| a + b);
+            }
+        }
+        Transform::new(m)
+    }
+
+    /// The inverse by Gauss-Jordan elimination, None if the matrix is
+    /// singular.
+    pub fn inverse(&self) -> Option<Transform> {
+        let mut a = self.m;
+        let mut inv = Transform::identity().m;
+        for c in 0..4 {
+            let pivot = (c..4).fold(c, |best, r| if a[r][c].abs() > a[best][c].abs() { r } else { best });
+            if a[pivot][c] == 0.0 {
+                return None;
+            }
+            a.swap(c, pivot);
+            inv.swap(c, pivot);
+            let p = a[c][c];
+            for k in 0..4 {
+                a[c][k] /= p;
+                inv[c][k] /= p;
+            }
+            for r in 0..4 {
+                if r != c {
+                    let f = a[r][c];
+                    for k in 0..4 {
+                        a[r][k] -= f * a[c][k];
+                        inv[r][k] -= f * inv[c][k];
+                    }
+                }
+            }
+        }
+        Some(Transform::new(inv))
+    }
+
+    pub fn point(&self, p: Vector) -> Vector {
+        let m = &self.m;
+        let x = m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3];
+        let y = m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3];
+        let z = m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3];
+        let w = m[3][0] * p.x + m[3][1] * p.y + m[3][2] * p.z + m[3][3];
+        if w == 1.0 { Vector::new(x, y, z) } else { Vector::new(x / w, y / w, z / w) }
+    }
+
+    pub fn vector(&self, v: Vector) -> Vector {
+        let m = &self.m;
+        Vector::new(m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+                    m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+                    m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z)
+    }
+
+    /// Determinant of the linear part, negative for transformations that
+    /// mirror.
+    pub fn determinant(&self) -> f64 {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1]) -
+        m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0]) +
+        m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    /// The factor lengths are scaled by, None unless the transformation
+    /// scales all directions alike.
+    pub fn uniform_scale(&self) -> Option<f64> {
+        let lengths: Vec<f64> = [Vector::new(1.0, 0.0, 0.0), Vector::new(0.0, 1.0, 0.0), Vector::new(0.0, 0.0, 1.0)]
+            .iter().map(|v| { let t = self.vector(*v); t.dot(&t).sqrt() }).collect();
+        let s = lengths[0];
+        let (x, y, z) = (self.vector(Vector::new(1.0, 0.0, 0.0)), self.vector(Vector::new(0.0, 1.0, 0.0)),
+                         self.vector(Vector::new(0.0, 0.0, 1.0)));
+        let orthogonal = x.dot(&y).abs() + y.dot(&z).abs() + z.dot(&x).abs() <= 1e-6 * s * s;
+        if orthogonal && lengths.iter().all(|l| (l - s).abs() <= 1e-6 * s) { Some(s) } else { None }
+    }
+}
@@ -0,0 +1,84 @@
+use std::ops::{Add, Sub, Mul};
+use std::num::Float;
+
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Vector {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64
+}
+
+impl Vector {
+    pub fn new(x: f64, y: f64, z: f64) -> Vector {
+        Vector { x: x, y: y, z: z }
+    }
+}
+
+impl<'a> Add for &'a Vector {
+    type Output = Vector;
+
+    fn add(self, other: &'a Vector) -> Vector {
+        Vector {x: self.x + other.x, y: self.y + other.y, z: self.z + other.z}
+    }
+}
+
+impl<'a> Sub for &'a Vector {
+    type Output = Vector;
+
+    fn sub(self, other: &'a Vector) -> Vector {
+        Vector {x: self.x - other.x, y: self.y - other.y, z: self.z - other.z}
+    }
+}
+
+impl<'a> Mul for &'a Vector {
+    type Output = Vector;
+
+    fn mul(self, other: &'a Vector) -> Vector {
+        Vector {x: self.x * other.x, y: self.y * other.y, z: self.z * other.z}
+    }
+}
+
+pub trait VectorOps {
+    fn smul(self, rhs: f64) -> Vector;
+    fn norm(self) -> Vector;
+    fn cross(self, rhs: Vector) -> Vector;
+    fn dot(&self, rhs: &Vector) -> f64;
+}
+
+impl VectorOps for Vector {
+
+    fn smul(self, other: f64) -> Vector {
+        Vector {x: self.x * other, y: self.y * other, z: self.z * other}
+    }
+
+    fn norm(self) -> Vector {
+        let normalize = 1.0 / (self.x * self.x + self.y * self.y + self.z * self.z).sqrt() ;
+        self.smul( normalize )
+    }
+
+    fn cross(self, b: Vector) -> Vector {
+        Vector{x: self.y * b.z - self.z * b.y, y: self.z * b.x - self.x * b.z, z: self.x * b.y - self.y * b.x}
+    }
+
+    fn dot(&self, other: &Vector) -> f64 {
+        (*self).x * (*other).x + (*self).y * (*other).y + (*self).z * (*other).z
+    }
+}
+
+/// Conversion for APIs that take points, directions or colors, so callers can
+/// pass `(x, y, z)` tuples.
+pub trait IntoVector {
+    fn into_vector(self) -> Vector;
+}
+
+impl IntoVector for Vector {
+    fn into_vector(self) -> Vector {
+        self
+    }
+}
+
+impl IntoVector for (f64, f64, f64) {
+    fn into_vector(self) -> Vector {
+        Vector::new(self.0, self.1, self.2)
+    }
+}
@@ -0,0 +1,298 @@
+//! Path tracing a tile in stages over batches of paths.
+//!
+//! The megakernel in `integrator` follows one path after the other, which
+//! jumps between intersection code, materials and lights at every bounce.
+//! This integrator keeps a batch of paths in flight instead, their state in
+//! one array per field, and runs each stage over the whole queue of paths it
+//! applies to:
+//!
+//! * generate: the camera rays of every pixel and sample number of a batch,
+//! * intersect: the closest hit of every live ray,
+//! * shade: adds the emission that was hit, sends a shadow ray towards a
+//!   light and samples the BSDF for the next ray. The queue is sorted by
+//!   material first, so paths on the same material are shaded together,
+//! * shadow: traces the shadow rays and adds the light of those that get
+//!   through,
+//! * accumulate: adds the finished samples to the pixels.
+//!
+//! The stages are the places a SIMD or GPU backend would replace.
+//!
+//! Unlike the megakernel, the shade stage samples emitting spheres directly.
+//! It combines light and BSDF samples with the power heuristic, so the
+//! estimate has the same expected value with less noise. It also splits the
+//! light into direct and indirect the same way. Every path draws from its own
+//! random number stream, seeded by pixel and sample number, so the image
+//! depends neither on the batch size nor on the order paths are shaded in.
+//! It does not match the megakernel's image sample by sample.
+
+use std::cmp;
+use std::default::Default;
+
+use vector::{Vector, VectorOps};
+use ray::Ray;
+use scene::Scene;
+use material::Material;
+use light::Light;
+use integrator::{self, Integrator, Sample};
+use film::Pixel;
+use tile::Tile;
+use rng::Rng;
+use stats;
+
+/// Paths in flight at most, more samples than fit are rendered in
+/// several batches.
+pub const BATCH: usize = 1 << 14;
+
+/// The state of a batch of paths, one array per field.
+struct Paths {
+    pixel: Vec<usize>, // index into the pixels of the tile
+    origin: Vec<Vector>,
+    direction: Vec<Vector>,
+    time: Vec<f64>,
+    depth: Vec<usize>, // vertices before the current ray
+    throughput: Vec<Vector>,
+    specular: Vec<bool>, // whether the last bounce was
+    pdf: Vec<f64>, // of the last bounce, to weigh the emitter it hits
+    from: Vec<Vector>, // the vertex the current ray left
+    from_object: Vec<usize>,
+    rng: Vec<Rng>,
+    // Closest hit of the current ray
+    hit: Vec<bool>,
+    t: Vec<f64>,
+    object: Vec<usize>,
+    sample: Vec<Sample>,
+}
+
+/// Shadow rays of a shade stage. A ray that reaches `light` adds
+/// `radiance` to the direct or indirect light of `path`.
+struct Shadows {
+    path: Vec<usize>,
+    ray: Vec<Ray>,
+    light: Vec<usize>,
+    radiance: Vec<Vector>,
+    direct: Vec<bool>,
+}
+
+/// Emitting spheres, which the shade stage samples.
+fn lights(scene: &Scene) -> Vec<usize> {
+    (0..scene.spheres.len()).filter(|&i| {
+        let e = scene.spheres[i].emission;
+        e.x > 0.0 || e.y > 0.0 || e.z > 0.0
+    }).collect()
+}
+
+fn material_order(m: Material) -> usize {
+    match m {
+        Material::Diffuse => 0,
+        Material::Glossy(_) => 1,
+        Material::Mirror => 2,
+        Material::Dielectric(_) => 3,
+    }
+}
+
+/// Power heuristic weight of a sample drawn with density `a` when the other
+/// strategy had density `b`.
+fn power_heuristic(a: f64, b: f64) -> f64 {
+    if a == 0.0 { 0.0 } else { a * a / (a * a + b * b) }
+}
+
+/// Camera rays for samples `first..first + count` of every pixel of `tile`,
+/// sample number by sample number.
+fn generate(scene: &Scene, tile: &Tile, seed: u64, first: usize, count: usize) -> Paths {
+    let n = tile.pixels() * count;
+    let mut paths = Paths {
+        pixel: Vec::with_capacity(n),
+        origin: Vec::with_capacity(n),
+        direction: Vec::with_capacity(n),
+        time: Vec::with_capacity(n),
+        depth: vec![0; n],
+        throughput: vec![Vector::new(1.0, 1.0, 1.0); n],
+        specular: vec![false; n],
+        pdf: vec![0.0; n],
+        from: vec![Default::default(); n],
+        from_object: vec![0; n],
+        rng: Vec::with_capacity(n),
+        hit: vec![false; n],
+        t: vec![0.0; n],
+        object: vec![0; n],
+        sample: vec![Default::default(); n],
+    };
+    for k in first..first + count {
+        let mut index = 0;
+        for i in tile.y0..tile.y1 {
+            for j in tile.x0..tile.x1 {
+                let mut rng = Rng::with_sample(seed, i, j, k);
+                let time = scene.camera.sample_time(&mut rng);
+                let ray = scene.camera.get_ray(i, j, scene.width, scene.height, time);
+                paths.pixel.push(index);
+                paths.origin.push(ray.o);
+                paths.direction.push(ray.d);
+                paths.time.push(time);
+                paths.rng.push(rng);
+                index += 1;
+            }
+        }
+    }
+    paths
+}
+
+fn intersect(scene: &Scene, paths: &mut Paths, queue: &[usize]) {
+    for &p in queue.iter() {
+        let ray = Ray { o: paths.origin[p], d: paths.direction[p], time: paths.time[p] };
+        paths.hit[p] = scene.intersect(&ray, &mut paths.t[p], &mut paths.object[p]);
+    }
+}
+
+/// Adds `radiance` reached after `depth` vertices to the direct or indirect
+/// light of `sample`, split as `integrator::get_sample` does.
+fn add_light(sample: &mut Sample, depth: usize, radiance: &Vector) {
+    if depth <= 1 {
+        sample.direct = &sample.direct + radiance;
+    } else {
+        sample.indirect = &sample.indirect + radiance;
+    }
+}
+
+/// Shades the hits of the paths in `queue` and returns the paths that go on
+/// together with the shadow rays to trace. Paths that end are counted.
+fn shade(scene: &Scene, lights: &[usize], paths: &mut Paths, queue: &[usize]) -> (Vec<usize>, Shadows) {
+    // Misses first, then hits by material and object
+    let mut order: Vec<(usize, usize, usize)> = queue.iter().map(|&p| if paths.hit[p] {
+        (1 + material_order(scene.object(paths.object[p]).material()), paths.object[p], p)
+    } else {
+        (0, 0, p)
+    }).collect();
+    order.sort();
+
+    let mut next = Vec::with_capacity(queue.len());
+    let mut shadows = Shadows { path: Vec::new(), ray: Vec::new(), light: Vec::new(), radiance: Vec::new(),
+                                direct: Vec::new() };
+    for &(_, _, p) in order.iter() {
+        let depth = paths.depth[p];
+        let ray = Ray { o: paths.origin[p], d: paths.direction[p], time: paths.time[p] };
+        let throughput = paths.throughput[p];
+        if !paths.hit[p] {
+            add_light(&mut paths.sample[p], depth, &(&throughput * &scene.background));
+            stats::path((depth + 1) as u64);
+            continue;
+        }
+
+        let id = paths.object[p];
+        let t = paths.t[p];
+        let (x, nl, error) = integrator::hit_frame(scene, &ray, t, id);
+        let object = scene.object(id);
+        if depth == 0 {
+            let s = &mut paths.sample[p];
+            s.albedo = object.albedo(x, ray.time);
+            s.normal = nl;
+            s.position = x;
+            s.depth = t * ray.d.dot(&scene.camera.forward(ray.time));
+            s.id = (id + 1) as f64;
+            match scene.integrator {
+                Integrator::Flat => s.direct = s.albedo,
+                Integrator::Diffuse => s.direct = s.albedo.smul(nl.dot(&scene.light)),
+                Integrator::Path => {}
+            }
+            if scene.integrator != Integrator::Path {
+                stats::path(1);
+                continue;
+            }
+        }
+
+        // Emission, weighed against having sampled the light from the last
+        // vertex
+        let emission = object.emission();
+        let mut weight = 1.0;
+        if depth > 0 && !paths.specular[p] && id != paths.from_object[p] && lights.contains(&id) {
+            let light_pdf = scene.spheres[id].pdf(paths.from[p], ray.time, ray.d) / lights.len() as f64;
+            weight = power_heuristic(paths.pdf[p], light_pdf);
+        }
+        add_light(&mut paths.sample[p], depth, &(&throughput * &emission).smul(weight));
+        if depth > scene.max_depth {
+            stats::path((depth + 1) as u64);
+            continue;
+        }
+
+        let outside = object.normal(x, ray.time).dot(&nl) > 0.0;
+        let material = object.material().seen_from(outside);
+        let albedo = object.albedo(x, ray.time);
+        let wo = ray.d.smul(-1.0);
+        if !material.is_specular() && lights.len() > 0 {
+            let rng = &mut paths.rng[p];
+            let light = lights[cmp::min((rng.next_f64() * lights.len() as f64) as usize, lights.len() - 1)];
+            let (u1, u2) = (rng.next_f64(), rng.next_f64());
+            if light != id {
+                if let Some(ls) = scene.spheres[light].sample(x, ray.time, u1, u2) {
+                    let light_pdf = ls.pdf / lights.len() as f64;
+                    let f = material.eval(albedo, nl, wo, ls.wi);
+                    let weight = power_heuristic(light_pdf, material.pdf(nl, wo, ls.wi));
+                    let radiance = (&(&throughput * &f) * &ls.radiance).smul(weight / light_pdf);
+                    if radiance.x > 0.0 || radiance.y > 0.0 || radiance.z > 0.0 {
+                        shadows.path.push(p);
+                        shadows.ray.push(Ray::spawn(x, error, nl, ls.wi, ray.time));
+                        shadows.light.push(light);
+                        shadows.radiance.push(radiance);
+                        // Light reaching the next vertex
+                        shadows.direct.push(depth == 0);
+                    }
+                }
+            }
+        }
+
+        match integrator::sample_bsdf(scene, &ray, id, x, nl, &mut paths.rng[p]) {
+            Some(s) => {
+                let next_ray = Ray::spawn(x, error, nl, s.wi, ray.time);
+                paths.origin[p] = next_ray.o;
+                paths.direction[p] = next_ray.d;
+                paths.throughput[p] = &throughput * &s.weight;
+                paths.specular[p] = s.specular;
+                paths.pdf[p] = s.pdf;
+                paths.from[p] = x;
+                paths.from_object[p] = id;
+                paths.depth[p] = depth + 1;
+                next.push(p);
+            }
+            None => stats::path((depth + 1) as u64),
+        }
+    }
+    (next, shadows)
+}
+
+/// Adds the light of the shadow rays that reach their light.
+fn shadow(scene: &Scene, shadows: &Shadows, paths: &mut Paths) {
+    let (mut t, mut id) = (0.0, 0);
+    for n in 0..shadows.path.len() {
+        stats::shadow_ray();
+        if scene.intersect(&shadows.ray[n], &mut t, &mut id) && id == shadows.light[n] {
+            let depth = if shadows.direct[n] { 1 } else { 2 };
+            add_light(&mut paths.sample[shadows.path[n]], depth, &shadows.radiance[n]);
+        }
+    }
+}
+
+fn accumulate(paths: &Paths, pixels: &mut [Pixel]) {
+    for (p, s) in paths.sample.iter().enumerate() {
+        pixels[paths.pixel[p]].add(s, 1.0);
+    }
+}
+
+/// Adds samples `first..first + count` to the pixels of `tile`, like
+/// `tile::render_tile` does with the megakernel.
+pub fn render_tile(scene: &Scene, tile: &Tile, seed: u64, first: usize, count: usize, pixels: &mut [Pixel]) {
+    let lights = lights(scene);
+    let per_batch = cmp::max(1, BATCH / tile.pixels());
+    let mut k = first;
+    while k < first + count {
+        let n = cmp::min(per_batch, first + count - k);
+        let mut paths = generate(scene, tile, seed, k, n);
+        let mut queue: Vec<usize> = (0..paths.pixel.len()).collect();
+        while queue.len() > 0 {
+            intersect(scene, &mut paths, &queue[..]);
+            let (next, shadows) = shade(scene, &lights[..], &mut paths, &queue[..]);
+            shadow(scene, &shadows, &mut paths);
+            queue = next;
+        }
+        accumulate(&paths, pixels);
+        k += n;
+    }
+}
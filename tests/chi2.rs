@@ -0,0 +1,267 @@
+//! Chi-square tests of the sampling routines of materials and lights.
+//!
+//! Sampled directions are histogrammed over a grid on the sphere of
+//! directions and compared against the counts expected from integrating
+//! `pdf` numerically over each cell (Pearson's test, as in Mitsuba's test
+//! suite). Cells with small expectations are pooled, samples rejected by
+//! `sample` form a cell of their own. The same runs check that `pdf`
+//! integrates to one and that each sample's weight and pdf agree with
+//! `eval` and `pdf`.
+
+#![allow(unstable)]
+
+extern crate raytracer;
+
+use std::num::Float;
+use std::f64::consts::PI;
+use raytracer::vector::{Vector, VectorOps};
+use raytracer::material::Material;
+use raytracer::light::Light;
+use raytracer::shape::Sphere;
+use raytracer::rng::Rng;
+
+const COS_BINS: usize = 20;
+const PHI_BINS: usize = 40;
+const SAMPLES: usize = 200000;
+// Significance level of a single test, Sidak corrected for the number of
+// tests in this file
+const TESTS: usize = 6;
+const ALPHA: f64 = 0.01;
+
+/// Index of the grid cell holding direction `d`. Cells are uniform in
+/// cos(theta) and phi, so they all cover the same solid angle.
+fn cell(d: Vector) -> usize {
+    let c = ((d.z + 1.0) / 2.0 * COS_BINS as f64).floor() as isize;
+    let phi = d.y.atan2(d.x);
+    let phi = if phi < 0.0 { phi + 2.0 * PI } else { phi };
+    let p = (phi / (2.0 * PI) * PHI_BINS as f64).floor() as isize;
+    let c = if c < 0 { 0 } else if c >= COS_BINS as isize { COS_BINS - 1 } else { c as usize };
+    let p = if p < 0 { 0 } else if p >= PHI_BINS as isize { PHI_BINS - 1 } else { p as usize };
+    c * PHI_BINS + p
+}
+
+/// Integrates `pdf` over each cell with a 32x32 midpoint rule, fine enough
+/// for the edges of the light cones.
+fn integrate<F: Fn(Vector) -> f64>(pdf: F) -> Vec<f64> {
+    let steps = 32;
+    let cell_area = 2.0 / COS_BINS as f64 * 2.0 * PI / PHI_BINS as f64;
+    let mut out = Vec::with_capacity(COS_BINS * PHI_BINS);
+    for c in 0..COS_BINS {
+        for p in 0..PHI_BINS {
+            let mut sum = 0.0;
+            for a in 0..steps {
+                for b in 0..steps {
+                    let z = -1.0 + (c as f64 + (a as f64 + 0.5) / steps as f64) * 2.0 / COS_BINS as f64;
+                    let phi = (p as f64 + (b as f64 + 0.5) / steps as f64) * 2.0 * PI / PHI_BINS as f64;
+                    let s = (1.0 - z * z).max(0.0).sqrt();
+                    sum += pdf(Vector::new(phi.cos() * s, phi.sin() * s, z));
+                }
+            }
+            out.push(sum / (steps * steps) as f64 * cell_area);
+        }
+    }
+    out
+}
+
+fn ln_gamma(x: f64) -> f64 {
+    // Lanczos approximation, g = 7
+    let coefficients = [0.99999999999980993, 676.5203681218851, -1259.1392167224028,
+                        771.32342877765313, -176.61502916214059, 12.507343278686905,
+                        -0.13857109526572012, 9.9843695780195716e-6, 1.5056327351493116e-7];
+    let x = x - 1.0;
+    let mut a = coefficients[0];
+    let t = x + 7.5;
+    for i in 1..9 {
+        a += coefficients[i] / (x + i as f64);
+    }
+    0.5 * (2.0 * PI).ln() + (x + 0.5) * t.ln() - t + a.ln()
+}
+
+/// Regularized upper incomplete gamma function Q(a, x).
+fn gamma_q(a: f64, x: f64) -> f64 {
+    if x < a + 1.0 {
+        // Series for P(a, x)
+        let (mut term, mut sum, mut n) = (1.0 / a, 1.0 / a, a);
+        for _ in 0..1000 {
+            n += 1.0;
+            term *= x / n;
+            sum += term;
+            if term.abs() < sum.abs() * 1e-15 {
+                break;
+            }
+        }
+        1.0 - sum * (-x + a * x.ln() - ln_gamma(a)).exp()
+    } else {
+        // Continued fraction for Q(a, x), modified Lentz
+        let tiny = 1e-300;
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / tiny;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        for i in 1..1000 {
+            let an = -(i as f64) * (i as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            if d.abs() < tiny { d = tiny; }
+            c = b + an / c;
+            if c.abs() < tiny { c = tiny; }
+            d = 1.0 / d;
+            let delta = d * c;
+            h *= delta;
+            if (delta - 1.0).abs() < 1e-15 {
+                break;
+            }
+        }
+        (-x + a * x.ln() - ln_gamma(a)).exp() * h
+    }
+}
+
+/// Pearson's test of `observed` against `expected` counts, pooling cells
+/// expected to hold fewer than 5 samples (including those the numerical
+/// integration found empty). Returns the p-value.
+fn chi2_test(observed: &[f64], expected: &[f64]) -> f64 {
+    let mut cells: Vec<(f64, f64)> = observed.iter().zip(expected.iter()).map(|(o, e)| (*o, *e)).collect();
+    cells.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+
+    let (mut chi2, mut dof) = (0.0, 0);
+    let (mut pooled_o, mut pooled_e) = (0.0, 0.0);
+    for &(o, e) in cells.iter() {
+        if e < 5.0 {
+            pooled_o += o;
+            pooled_e += e;
+        } else {
+            chi2 += (o - e) * (o - e) / e;
+            dof += 1;
+        }
+    }
+    if pooled_e > 0.0 {
+        chi2 += (pooled_o - pooled_e) * (pooled_o - pooled_e) / pooled_e;
+        dof += 1;
+    }
+    // One degree of freedom is lost to the fixed total
+    gamma_q((dof - 1) as f64 / 2.0, chi2 / 2.0)
+}
+
+struct Drawn {
+    wi: Vector,
+    pdf: f64, // as reported by the sampler
+}
+
+/// Histograms `SAMPLES` draws of `sample` (None counts as rejected) against
+/// `pdf`, checks that the pdf integrates to at most one and that the
+/// rejected fraction accounts for the rest, and runs the chi-square test.
+fn check_distribution<S, P>(name: &str, mut sample: S, pdf: P)
+    where S: FnMut(f64, f64) -> Option<Drawn>, P: Fn(Vector) -> f64 {
+    let mut rng = Rng::new(1);
+    let mut observed = vec![0.0; COS_BINS * PHI_BINS + 1];
+    for _ in 0..SAMPLES {
+        let (u1, u2) = (rng.next_f64(), rng.next_f64());
+        match sample(u1, u2) {
+            Some(d) => {
+                let density = pdf(d.wi);
+                assert!(density > 0.0, "{}: sampled a direction of zero pdf", name);
+                assert!((d.pdf - density).abs() <= 1e-6 * density,
+                        "{}: sample pdf {} but pdf() gives {}", name, d.pdf, density);
+                observed[cell(d.wi)] += 1.0;
+            }
+            None => observed[COS_BINS * PHI_BINS] += 1.0,
+        }
+    }
+
+    let mut expected = integrate(pdf);
+    let total = expected.iter().fold(0.0, |s, e| s + *e);
+    assert!(total <= 1.0 + 1e-3, "{}: pdf integrates to {}", name, total);
+    expected.push((1.0 - total).max(0.0));
+    for e in expected.iter_mut() {
+        *e *= SAMPLES as f64;
+    }
+
+    let p = chi2_test(&observed[..], &expected[..]);
+    let significance = 1.0 - (1.0 - ALPHA).powf(1.0 / TESTS as f64);
+    assert!(p > significance, "{}: chi-square test rejected the distribution, p = {}", name, p);
+}
+
+fn normal() -> Vector {
+    Vector::new(0.0, 0.0, 1.0)
+}
+
+fn outgoing() -> Vector {
+    Vector::new(0.3, 0.2, 0.8).norm()
+}
+
+fn check_material(name: &str, material: Material) {
+    let color = Vector::new(0.8, 0.6, 0.4);
+    let (n, wo) = (normal(), outgoing());
+    check_distribution(name, |u1, u2| {
+        material.sample(color, n, wo, u1, u2).map(|s| {
+            // The weight has to be eval / pdf
+            let expected = material.eval(color, n, wo, s.wi).smul(1.0 / s.pdf);
+            let error = &s.weight - &expected;
+            assert!(error.dot(&error).sqrt() <= 1e-6 * (1.0 + expected.dot(&expected).sqrt()),
+                    "{}: sample weight {:?} but eval / pdf gives {:?}", name, s.weight, expected);
+            Drawn { wi: s.wi, pdf: s.pdf }
+        })
+    }, |wi| material.pdf(n, wo, wi));
+}
+
+#[test]
+fn diffuse_sampling() {
+    check_material("diffuse", Material::Diffuse);
+}
+
+#[test]
+fn glossy_sampling() {
+    check_material("glossy 10", Material::Glossy(10.0));
+}
+
+#[test]
+fn sharp_glossy_sampling() {
+    check_material("glossy 50", Material::Glossy(50.0));
+}
+
+#[test]
+fn mirror_sampling() {
+    // Specular directions have no density, only check the reflection
+    let (n, wo) = (normal(), outgoing());
+    let s = Material::Mirror.sample(Vector::new(1.0, 1.0, 1.0), n, wo, 0.5, 0.5).unwrap();
+    assert!(s.specular);
+    assert!((s.wi.dot(&n) - wo.dot(&n)).abs() < 1e-12);
+    assert!((s.wi.x + wo.x).abs() < 1e-12 && (s.wi.y + wo.y).abs() < 1e-12);
+    assert_eq!(Material::Mirror.pdf(n, wo, s.wi), 0.0);
+}
+
+fn check_light(name: &str, light: Sphere) {
+    let x = Vector::new(0.5, -0.3, 0.2);
+    check_distribution(name, |u1, u2| {
+        light.sample(x, 0.0, u1, u2).map(|s| {
+            // The sampled point has to lie on the sphere
+            let p = &x + &s.wi.smul(s.distance);
+            let r = (&p - &light.position).dot(&(&p - &light.position)).sqrt();
+            assert!((r - light.radius).abs() < 1e-6 * light.radius, "{}: sampled point off the light", name);
+            Drawn { wi: s.wi, pdf: s.pdf }
+        })
+    }, |wi| light.pdf(x, 0.0, wi));
+}
+
+// The light cones stay clear of the poles of the grid, where the cells are
+// too thin for the integration to find a cone's edge.
+
+#[test]
+fn near_sphere_light_sampling() {
+    let e = Vector::new(1.0, 1.0, 1.0);
+    check_light("near sphere light", Sphere::new(1.0, Vector::new(1.7, 0.5, 0.6), e, Vector::new(0.0, 0.0, 0.0)));
+}
+
+#[test]
+fn far_sphere_light_sampling() {
+    let e = Vector::new(1.0, 1.0, 1.0);
+    check_light("far sphere light", Sphere::new(2.0, Vector::new(-6.0, 4.0, -5.0), e, Vector::new(0.0, 0.0, 0.0)));
+}
+
+#[test]
+fn gamma_q_matches_known_values() {
+    // Chi-square survival function, e.g. P(chi2 with 10 dof > 18.307) = 0.05
+    assert!((gamma_q(5.0, 18.307 / 2.0) - 0.05).abs() < 1e-4);
+    assert!((gamma_q(1.0, 2.0) - (-2.0f64).exp()).abs() < 1e-12);
+    assert!((gamma_q(50.0, 40.0) - 0.929665).abs() < 1e-5);
+}
@@ -0,0 +1,153 @@
+//! Furnace tests: scenes whose radiance is known in closed form.
+//!
+//! A diffuse surface inside a uniform emitter reflects exactly its albedo
+//! times the emitted radiance, whatever the shape, so any energy gained or
+//! lost in sampling bounces shows up as a deviation. The sphere light above
+//! a plane checks the cosine weighting, where the estimate is noisy and is
+//! compared within confidence bounds.
+//!
+//! Only `Integrator::Path` transports light; `Flat` and `Diffuse` are
+//! shading previews without a radiometric meaning and are not tested here.
+
+#![allow(unstable)]
+
+extern crate raytracer;
+
+use std::num::Float;
+use std::default::Default;
+use raytracer::vector::{Vector, VectorOps};
+use raytracer::shape::Sphere;
+use raytracer::camera::{Camera, Projection};
+use raytracer::scene::Scene;
+use raytracer::integrator::{self, Integrator, Sample, MAX_DEPTH};
+use raytracer::rng::Rng;
+
+const SEED: u64 = 7;
+
+fn gray(v: f64) -> Vector {
+    Vector::new(v, v, v)
+}
+
+/// Perspective camera at `eye` looking at `target`.
+fn camera(eye: Vector, target: Vector) -> Camera {
+    let mut cam: Camera = Default::default();
+    cam.eye.o = eye;
+    cam.eye.d = (&target - &eye).norm();
+    cam.up = if cam.eye.d.y.abs() > 0.9 { Vector::new(0.0, 0.0, 1.0) } else { Vector::new(0.0, 1.0, 0.0) };
+    cam.projection = Projection::Perspective(1.0);
+    cam
+}
+
+fn scene(camera: Camera, spheres: Vec<Sphere>, background: Vector) -> Scene {
+    Scene {
+        camera: camera,
+        spheres: spheres,
+        triangles: Vec::new(),
+        width: 16,
+        height: 16,
+        samples: 1,
+        integrator: Integrator::Path,
+        max_depth: MAX_DEPTH,
+        background: background,
+        light: Vector::new(0.0, 1.0, 0.0),
+    }
+}
+
+/// Renders every pixel and checks its luminance against `expected`, which
+/// gets the pixel's first hit. Each pixel has to lie within 5 standard
+/// deviations of its estimate, and the errors have to average out over the
+/// image.
+fn assert_radiance<F: Fn(&Sample) -> f64>(scene: &Scene, samples: usize, expected: F) {
+    let (mut error_sum, mut variance_sum) = (0.0, 0.0);
+    for i in 0..scene.height {
+        for j in 0..scene.width {
+            let mut rng = Rng::with_stream(SEED, i, j);
+            let (s, variance) = integrator::render_pixel(scene, Integrator::Path, i, j, samples, &mut rng);
+            let want = expected(&s);
+            let got = integrator::luminance(&s.radiance());
+            let error = got - want;
+            assert!(error.abs() <= 5.0 * variance.sqrt() + 1e-9 * (1.0 + want.abs()),
+                    "pixel ({}, {}): expected {}, got {} +- {}", j, i, want, got, variance.sqrt());
+            error_sum += error;
+            variance_sum += variance;
+        }
+    }
+    if variance_sum > 0.0 {
+        let z = error_sum / variance_sum.sqrt();
+        assert!(z.abs() < 4.0, "total error is {} standard deviations off", z);
+    }
+}
+
+/// A closed sphere that emits `emission` and absorbs everything.
+fn furnace(emission: f64) -> Sphere {
+    Sphere::new(100.0, Vector::new(0.0, 0.0, 0.0), gray(emission), gray(0.0))
+}
+
+#[test]
+fn white_sphere_in_uniform_emitter() {
+    let s = scene(camera(Vector::new(0.0, 0.0, 50.0), Vector::new(0.0, 0.0, 0.0)),
+                  vec![furnace(1.5), Sphere::new(10.0, Vector::new(0.0, 0.0, 0.0), gray(0.0), gray(1.0))],
+                  gray(0.0));
+    // The sphere is indistinguishable from the emitter
+    assert_radiance(&s, 16, |_| 1.5);
+}
+
+#[test]
+fn gray_sphere_in_uniform_emitter() {
+    let s = scene(camera(Vector::new(0.0, 0.0, 50.0), Vector::new(0.0, 0.0, 0.0)),
+                  vec![furnace(1.5), Sphere::new(10.0, Vector::new(0.0, 0.0, 0.0), gray(0.0), gray(0.4))],
+                  gray(0.0));
+    // Ids count from 1, the sphere is the second
+    assert_radiance(&s, 16, |h| if h.id == 2.0 { 0.4 * 1.5 } else { 1.5 });
+}
+
+#[test]
+fn sphere_under_constant_sky() {
+    let s = scene(camera(Vector::new(0.0, 0.0, 50.0), Vector::new(0.0, 0.0, 0.0)),
+                  vec![Sphere::new(10.0, Vector::new(0.0, 0.0, 0.0), gray(0.0), gray(0.7))],
+                  gray(2.0));
+    assert_radiance(&s, 16, |h| if h.id == 1.0 { 0.7 * 2.0 } else { 2.0 });
+}
+
+#[test]
+fn diffuse_plane_under_constant_sky() {
+    // The plane y = 0, as a huge sphere
+    let plane = Sphere::new(1e5, Vector::new(0.0, -1e5, 0.0), gray(0.0), gray(0.6));
+    let s = scene(camera(Vector::new(0.0, 10.0, 0.0), Vector::new(0.0, 0.0, 0.0)), vec![plane], gray(1.0));
+    assert_radiance(&s, 16, |_| 0.6);
+}
+
+#[test]
+fn emitting_gray_furnace_sums_all_bounces() {
+    // Everything emits e and reflects rho, so every path collects
+    // e * (1 + rho + rho^2 + ...) up to the depth limit, independent of the
+    // directions it takes
+    let (e, rho) = (0.25, 0.8);
+    let s = scene(camera(Vector::new(0.0, 0.0, 50.0), Vector::new(0.0, 0.0, 0.0)),
+                  vec![Sphere::new(100.0, Vector::new(0.0, 0.0, 0.0), gray(e), gray(rho)),
+                       Sphere::new(10.0, Vector::new(0.0, 0.0, 0.0), gray(e), gray(rho))],
+                  gray(0.0));
+    let expected = (0..MAX_DEPTH + 2).fold(0.0, |sum, k| sum + e * rho.powi(k as i32));
+    assert_radiance(&s, 16, |_| expected);
+}
+
+#[test]
+fn diffuse_plane_under_sphere_light() {
+    // A sphere light fully above the horizon gives the irradiance of a point
+    // light, E = pi * L * (R / d)^2 * cos(theta), and the plane reflects
+    // rho / pi of it
+    let (radiance, radius, height, rho) = (10.0, 5.0, 30.0, 0.5);
+    let center = Vector::new(0.0, height, 0.0);
+    let light = Sphere::new(radius, center, gray(radiance), gray(0.0));
+    let plane = Sphere::new(1e5, Vector::new(0.0, -1e5, 0.0), gray(0.0), gray(rho));
+    let mut cam = camera(Vector::new(0.0, 10.0, 0.0), Vector::new(0.0, 0.0, 0.0));
+    cam.projection = Projection::Orthographic(20.0);
+    let s = scene(cam, vec![light, plane], gray(0.0));
+
+    assert_radiance(&s, 4096, |h| {
+        let to_light = &center - &h.position;
+        let d2 = to_light.dot(&to_light);
+        let cos_theta = to_light.y / d2.sqrt();
+        rho * radiance * radius * radius / d2 * cos_theta
+    });
+}
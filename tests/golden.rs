@@ -0,0 +1,183 @@
+//! Golden image regression tests.
+//!
+//! Low resolution renders of the example scenes are compared against the
+//! references in tests/golden. The 2D spheres and the pinhole sphere are
+//! deterministic and have to match the stored 8 bit images. The Cornell box
+//! is path traced with a fixed seed and compared against a converged
+//! reference, which also stores the variance of a single sample's luminance
+//! per pixel. The squared luminance error of every pixel divided by the
+//! variance of its estimate has to average out near 1, as it does for an
+//! unbiased renderer. The wavefront kernel is held to the same reference.
+//!
+//! After an intended change to the images run
+//! `cargo test --test golden -- --ignored` to write new references.
+
+#![allow(unstable)]
+
+extern crate raytracer;
+
+use std::num::Float;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::io::prelude::*;
+use std::path::Path;
+use std::default::Default;
+use raytracer::image::{self, exr, Image};
+use raytracer::scene::Scene;
+use raytracer::integrator::{self, Integrator};
+use raytracer::film::Film;
+use raytracer::tile::{self, Settings, Kernel};
+use raytracer::tonemap::{ToneMap, srgb_encode};
+use raytracer::rng::Rng;
+
+const SEED: u64 = 1;
+const CORNELL_SAMPLES: usize = 256;
+const REFERENCE_SAMPLES: usize = 32768;
+
+fn reference_path(name: &str) -> String {
+    format!("tests/golden/{}", name)
+}
+
+/// Asserts that `img` shows the same 8 bit values as the stored reference,
+/// allowing one code value for rounding.
+fn assert_matches_display(img: &Image, name: &str) {
+    let reference = image::load(Path::new(&reference_path(name))).unwrap();
+    assert_eq!((img.width, img.height), (reference.width, reference.height));
+    let tonemap: ToneMap = Default::default();
+    let mut mismatches = 0;
+    for y in 0..img.height {
+        for x in 0..img.width {
+            for c in 0..3 {
+                let shown = srgb_encode(tonemap.map(img.get(x, y, c)));
+                let expected = srgb_encode(reference.get(x, y, c));
+                if (shown - expected).abs() > 1.5 / 255.0 {
+                    mismatches += 1;
+                }
+            }
+        }
+    }
+    assert!(mismatches == 0, "{} values differ from {}", mismatches, name);
+}
+
+fn two_spheres() -> Scene {
+    Scene::two_spheres(50, 50)
+}
+
+fn pinhole_sphere() -> Scene {
+    Scene::pinhole_sphere(50, 50)
+}
+
+fn cornell_box() -> Scene {
+    Scene::cornell_box(40, 30)
+}
+
+#[test]
+fn golden_two_spheres() {
+    let img = integrator::render(&two_spheres(), Integrator::Flat, 1, SEED);
+    assert_matches_display(&img, "two_spheres.png");
+}
+
+#[test]
+fn golden_pinhole_sphere() {
+    let img = integrator::render(&pinhole_sphere(), Integrator::Diffuse, 1, SEED);
+    assert_matches_display(&img, "pinhole_sphere.png");
+}
+
+/// Reads the Cornell box reference and its per sample variance.
+fn load_cornell_reference() -> (Image, Image) {
+    let mut r = BufReader::new(File::open(&reference_path("cornell_box.exr")).unwrap());
+    let (width, height, channels) = exr::read(&mut r).unwrap();
+    (exr::layer(width, height, &channels[..], "").unwrap(),
+     exr::layer(width, height, &channels[..], "variance").unwrap())
+}
+
+/// Asserts that `estimate`, the mean luminance of `CORNELL_SAMPLES` samples
+/// of row `i` and column `j`, converges to the Cornell box reference.
+fn assert_matches_reference<F>(scene: &Scene, estimate: F) where F: Fn(usize, usize) -> f64 {
+    let (reference, sample_variance) = load_cornell_reference();
+    assert_eq!((scene.width, scene.height), (reference.width, reference.height));
+
+    let (mut chi2, mut count) = (0.0, 0);
+    let (mut error_sum, mut variance_sum) = (0.0, 0.0);
+    for i in 0..scene.height {
+        for j in 0..scene.width {
+            let error = estimate(i, j) - reference.luminance(j, i);
+            let sigma2 = sample_variance.get(j, i, 0);
+            let variance = sigma2 / CORNELL_SAMPLES as f64 + sigma2 / REFERENCE_SAMPLES as f64;
+            error_sum += error;
+            variance_sum += variance;
+            if variance > 0.0 {
+                chi2 += error * error / variance;
+                count += 1;
+            } else {
+                // Every sample sees the same value
+                assert!(error.abs() < 1e-3 * (1.0 + reference.luminance(j, i)),
+                        "pixel ({}, {}) is off by {}", j, i, error);
+            }
+        }
+    }
+
+    // Heavy tailed pixels (paths that rarely find the light) make this
+    // noisy, so it leaves some room above 1. A shifted camera ray or a lost
+    // bounce ends up far above.
+    let mean_chi2 = chi2 / count as f64;
+    assert!(mean_chi2 < 1.5, "mean normalized squared error {} is too large", mean_chi2);
+    // The errors also have to cancel over the image, to catch a small bias
+    // that hides in the per pixel noise
+    let z = error_sum / variance_sum.sqrt();
+    assert!(z.abs() < 4.0, "total error is {} standard deviations off", z);
+}
+
+#[test]
+fn golden_cornell_box() {
+    let scene = cornell_box();
+    assert_matches_reference(&scene, |i, j| {
+        let mut rng = Rng::with_stream(SEED, i, j);
+        let (s, _) = integrator::render_pixel(&scene, Integrator::Path, i, j, CORNELL_SAMPLES, &mut rng);
+        integrator::luminance(&s.radiance())
+    });
+}
+
+/// The wavefront kernel estimates the same image with less noise, so the
+/// variance of the megakernel's samples is a bound on its error.
+#[test]
+fn golden_cornell_box_wavefront() {
+    let scene = cornell_box();
+    let mut film = Film::new(0, 0, scene.width, scene.height);
+    let settings = Settings { kernel: Kernel::Wavefront, seed: SEED, ..Default::default() };
+    tile::render_pass(&scene, &mut film, &settings, 0, CORNELL_SAMPLES, |_, _| {});
+    assert_matches_reference(&scene, |i, j| {
+        let (s, _) = film.pixel(j, i).mean();
+        integrator::luminance(&s.radiance())
+    });
+}
+
+#[test]
+#[ignore]
+fn regenerate_references() {
+    let img = integrator::render(&two_spheres(), Integrator::Flat, 1, SEED);
+    image::save(Path::new(&reference_path("two_spheres.png")), &img).unwrap();
+    let img = integrator::render(&pinhole_sphere(), Integrator::Diffuse, 1, SEED);
+    image::save(Path::new(&reference_path("pinhole_sphere.png")), &img).unwrap();
+
+    let scene = cornell_box();
+    let mut img = Image::new(scene.width, scene.height, 3);
+    let mut sample_variance = Image::new(scene.width, scene.height, 1);
+    for i in 0..scene.height {
+        for j in 0..scene.width {
+            // A different seed than the tests so the noise is independent
+            let mut rng = Rng::with_stream(SEED + 1, i, j);
+            let (s, variance) = integrator::render_pixel(&scene, Integrator::Path, i, j,
+                                                         REFERENCE_SAMPLES, &mut rng);
+            let color = s.radiance();
+            img.set_rgb(j, i, color.x, color.y, color.z);
+            sample_variance.set(j, i, 0, variance * REFERENCE_SAMPLES as f64);
+        }
+    }
+    let mut channels = exr::channels(&img, "");
+    channels.push_all(&exr::channels(&sample_variance, "variance")[..]);
+    let options = exr::Options { pixel_type: exr::PixelType::Float, ..Default::default() };
+    let mut w = BufWriter::new(File::create(&reference_path("cornell_box.exr")).unwrap());
+    exr::write_channels(&mut w, scene.width, scene.height, &channels[..], options).unwrap();
+    w.flush().unwrap();
+}
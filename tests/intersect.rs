@@ -0,0 +1,420 @@
+//! Property based tests of ray-primitive intersection.
+//!
+//! Random shapes and rays check that hits lie on the surface, that normals
+//! are unit length and point outwards, that the reported distance is the
+//! nearest root in front of the ray, that misses are reported as such and
+//! that rays spawned from hit points do not hit the surface they leave. Each
+//! shape provides an independent reference solution through `Reference`;
+//! every `Shape` implementation should have one and be listed in the tests
+//! at the bottom.
+//!
+//! Failures print the case number and seed, `CASES` runs are deterministic.
+
+#![allow(unstable)]
+
+extern crate raytracer;
+
+use std::num::Float;
+use std::f64::consts::PI;
+use raytracer::vector::{Vector, VectorOps};
+use raytracer::ray::Ray;
+use raytracer::shape::{Shape, Sphere, Triangle};
+use raytracer::rng::Rng;
+
+const CASES: usize = 20000;
+const SEED: u64 = 36;
+// Roots closer to the origin than this, relative to the size of the case,
+// may come out on either side of it
+const AMBIGUOUS: f64 = 1e-9;
+
+trait Reference: Shape {
+    fn random(rng: &mut Rng) -> Self;
+
+    /// A length of the size of the shape, tolerances are relative to it.
+    fn scale(&self) -> f64;
+
+    /// A point in the middle of the shape.
+    fn centroid(&self) -> Vector;
+
+    /// All distances along `r` (which may be negative) where the line meets
+    /// the surface, sorted.
+    fn roots(&self, r: &Ray) -> Vec<f64>;
+
+    /// Distance of `x` from the surface.
+    fn surface_distance(&self, x: Vector) -> f64;
+
+    /// True if `n` points out of the shape at the surface point `x`.
+    fn outward(&self, x: Vector, n: Vector) -> bool;
+
+    /// A random ray that starts inside the shape, None for open surfaces.
+    fn ray_from_inside(&self, rng: &mut Rng) -> Option<Ray>;
+
+    /// A random ray whose line passes the surface at distance `offset` from
+    /// a tangent, outside for positive offsets.
+    fn tangent_ray(&self, offset: f64, rng: &mut Rng) -> Ray;
+}
+
+fn uniform(rng: &mut Rng, a: f64, b: f64) -> f64 {
+    a + (b - a) * rng.next_f64()
+}
+
+fn random_direction(rng: &mut Rng) -> Vector {
+    let z = uniform(rng, -1.0, 1.0);
+    let phi = uniform(rng, 0.0, 2.0 * PI);
+    let s = (1.0 - z * z).max(0.0).sqrt();
+    Vector::new(phi.cos() * s, phi.sin() * s, z)
+}
+
+/// Two unit vectors perpendicular to each other and to `d`.
+fn perpendicular(d: Vector) -> (Vector, Vector) {
+    let a = if d.x.abs() > 0.5 { Vector::new(0.0, 1.0, 0.0) } else { Vector::new(1.0, 0.0, 0.0) };
+    let u = a.cross(d).norm();
+    (u, d.cross(u))
+}
+
+impl Reference for Sphere {
+    fn random(rng: &mut Rng) -> Sphere {
+        // Radii from 1e-2 up to the 1e5 walls of the Cornell box
+        let radius = 10.0f64.powf(uniform(rng, -2.0, 5.0));
+        let center = random_direction(rng).smul(uniform(rng, 0.0, 100.0));
+        Sphere::new(radius, center, Vector::new(0.0, 0.0, 0.0), Vector::new(0.5, 0.5, 0.5))
+    }
+
+    fn scale(&self) -> f64 {
+        self.radius
+    }
+
+    fn centroid(&self) -> Vector {
+        self.position
+    }
+
+    fn roots(&self, r: &Ray) -> Vec<f64> {
+        // Numerically stable form: the discriminant from the distance of the
+        // line to the center, the smaller root through Vieta's formula
+        let oc = &r.o - &self.position;
+        let b = oc.dot(&r.d);
+        let closest = &oc - &r.d.smul(b);
+        let disc = self.radius * self.radius - closest.dot(&closest);
+        if disc < 0.0 {
+            return vec![];
+        }
+        let c = oc.dot(&oc) - self.radius * self.radius;
+        let q = -b - b.signum() * disc.sqrt();
+        if q == 0.0 {
+            return vec![0.0];
+        }
+        let (t0, t1) = (c / q, q);
+        if t0 < t1 { vec![t0, t1] } else { vec![t1, t0] }
+    }
+
+    fn surface_distance(&self, x: Vector) -> f64 {
+        let d = &x - &self.position;
+        (d.dot(&d).sqrt() - self.radius).abs()
+    }
+
+    fn outward(&self, x: Vector, n: Vector) -> bool {
+        n.dot(&(&x - &self.position)) > 0.0
+    }
+
+    fn ray_from_inside(&self, rng: &mut Rng) -> Option<Ray> {
+        let o = &self.position + &random_direction(rng).smul(self.radius * uniform(rng, 0.0, 0.9));
+        Some(Ray { o: o, d: random_direction(rng), time: 0.0 })
+    }
+
+    fn tangent_ray(&self, offset: f64, rng: &mut Rng) -> Ray {
+        // The line runs along d at distance radius + offset from the center
+        let d = random_direction(rng);
+        let (u, _) = perpendicular(d);
+        let closest = &self.position + &u.smul(self.radius + offset);
+        let back = uniform(rng, 2.0, 10.0) * self.radius;
+        Ray { o: &closest - &d.smul(back), d: d, time: 0.0 }
+    }
+}
+
+impl Reference for Triangle {
+    fn random(rng: &mut Rng) -> Triangle {
+        let size = 10.0f64.powf(uniform(rng, -2.0, 3.0));
+        let center = random_direction(rng).smul(uniform(rng, 0.0, 100.0));
+        loop {
+            let v: Vec<Vector> = (0..3).map(|_| &center + &random_direction(rng).smul(size * uniform(rng, 0.5, 1.0)))
+                                       .collect();
+            let t = Triangle::new([v[0], v[1], v[2]], Vector::new(0.0, 0.0, 0.0), Vector::new(0.5, 0.5, 0.5));
+            // No slivers, so points just inside an edge are inside the triangle
+            let area = plane_normal(&t).dot(&plane_normal(&t)).sqrt() / 2.0;
+            let longest = t.scale();
+            if 2.0 * area / longest >= 0.1 * longest {
+                return t;
+            }
+        }
+    }
+
+    fn scale(&self) -> f64 {
+        let v = &self.vertices;
+        (0..3).map(|i| { let e = &v[(i + 1) % 3] - &v[i]; e.dot(&e).sqrt() }).fold(0.0, |a: f64, b| a.max(b))
+    }
+
+    fn centroid(&self) -> Vector {
+        (&(&self.vertices[0] + &self.vertices[1]) + &self.vertices[2]).smul(1.0 / 3.0)
+    }
+
+    fn roots(&self, r: &Ray) -> Vec<f64> {
+        // Where the line meets the plane, if that point is on the inner side
+        // of all three edges
+        let n = plane_normal(self);
+        let dn = r.d.dot(&n);
+        if dn == 0.0 {
+            return vec![];
+        }
+        let t = (&self.vertices[0] - &r.o).dot(&n) / dn;
+        let x = &r.o + &r.d.smul(t);
+        let v = &self.vertices;
+        let inside = (0..3).all(|i| (&v[i] - &x).cross(&v[(i + 1) % 3] - &x).dot(&n) >= 0.0);
+        if inside { vec![t] } else { vec![] }
+    }
+
+    fn surface_distance(&self, x: Vector) -> f64 {
+        (&x - &self.vertices[0]).dot(&plane_normal(self).norm()).abs()
+    }
+
+    fn outward(&self, _: Vector, n: Vector) -> bool {
+        n.dot(&plane_normal(self)) > 0.0
+    }
+
+    fn ray_from_inside(&self, _: &mut Rng) -> Option<Ray> {
+        None
+    }
+
+    fn tangent_ray(&self, offset: f64, rng: &mut Rng) -> Ray {
+        // Crosses the plane at distance offset outside a random edge
+        let n = plane_normal(self).norm();
+        let i = (rng.next_f64() * 3.0) as usize % 3;
+        let (a, b) = (self.vertices[i], self.vertices[(i + 1) % 3]);
+        let on_edge = &a + &(&b - &a).smul(uniform(rng, 0.25, 0.75));
+        let out = (&b - &a).cross(n).norm();
+        let crossing = &on_edge + &out.smul(offset);
+        let mut d = random_direction(rng);
+        while d.dot(&n).abs() < 0.1 {
+            d = random_direction(rng);
+        }
+        let back = uniform(rng, 2.0, 10.0) * self.scale();
+        Ray { o: &crossing - &d.smul(back), d: d, time: 0.0 }
+    }
+}
+
+/// Normal of the triangle's plane from the vertex order, not normalized.
+fn plane_normal(t: &Triangle) -> Vector {
+    let v = &t.vertices;
+    (&v[1] - &v[0]).cross(&v[2] - &v[0])
+}
+
+/// A random ray starting near `shape`, within a few times its size.
+fn random_ray<S: Reference>(shape: &S, rng: &mut Rng) -> Ray {
+    let o = &shape.centroid() + &random_direction(rng).smul(uniform(rng, 0.0, 4.0) * shape.scale());
+    Ray { o: o, d: random_direction(rng), time: 0.0 }
+}
+
+/// Runs `property` on `CASES` random cases.
+fn check<F: Fn(&mut Rng) -> Result<(), String>>(name: &str, property: F) {
+    let mut rng = Rng::new(SEED);
+    for case in 0..CASES {
+        if let Err(msg) = property(&mut rng) {
+            panic!("{}: case {} (seed {}) failed: {}", name, case, SEED, msg);
+        }
+    }
+}
+
+/// Nearest root in front of the origin, if any. Err if a root is too close to
+/// the origin to tell whether it counts.
+fn nearest_root(roots: &[f64], scale: f64) -> Result<Option<f64>, ()> {
+    if roots.iter().any(|t| t.abs() <= AMBIGUOUS * scale) {
+        return Err(());
+    }
+    Ok(roots.iter().map(|t| *t).find(|t| *t > 0.0))
+}
+
+/// Checks a reported hit at distance `t` along `r`.
+fn check_hit<S: Reference>(shape: &S, r: &Ray, t: f64) -> Result<(), String> {
+    let x = &r.o + &r.d.smul(t);
+    let scale = shape.scale() + (&r.o - &x).dot(&(&r.o - &x)).sqrt();
+    let error = shape.surface_distance(x);
+    if error > 1e-9 * scale {
+        return Err(format!("hit point {:?} is {} off the surface", x, error));
+    }
+    let n = shape.normal(x, r.time);
+    if (n.dot(&n) - 1.0).abs() > 1e-12 {
+        return Err(format!("normal {:?} is not unit length", n));
+    }
+    if !shape.outward(x, n) {
+        return Err(format!("normal {:?} points inwards at {:?}", n, x));
+    }
+    Ok(())
+}
+
+fn hits_match_reference<S: Reference>(shape: &S, r: &Ray) -> Result<(), String> {
+    let t = shape.intersect(r);
+    let roots = shape.roots(r);
+    let scale = shape.scale() + (&r.o - &shape.centroid()).dot(&(&r.o - &shape.centroid())).sqrt();
+    match nearest_root(&roots[..], scale) {
+        Err(()) => Ok(()),
+        Ok(Some(expected)) => {
+            if t <= 0.0 {
+                return Err(format!("missed, expected a hit at {} (roots {:?})", expected, roots));
+            }
+            let scale = shape.scale() + expected;
+            if (t - expected).abs() > 1e-9 * scale {
+                return Err(format!("hit at {}, expected the nearest root {} (roots {:?})", t, expected, roots));
+            }
+            check_hit(shape, r, t)
+        }
+        Ok(None) => {
+            if t != 0.0 {
+                return Err(format!("hit at {} but there is no root in front of the ray (roots {:?})", t, roots));
+            }
+            Ok(())
+        }
+    }
+}
+
+fn random_rays<S: Reference>() {
+    check("random rays", |rng| {
+        let shape: S = Reference::random(rng);
+        let r = random_ray(&shape, rng);
+        hits_match_reference(&shape, &r)
+    });
+}
+
+fn rays_from_inside<S: Reference>() {
+    check("rays from inside", |rng| {
+        let shape: S = Reference::random(rng);
+        let r = match shape.ray_from_inside(rng) {
+            Some(r) => r,
+            None => return Ok(()),
+        };
+        if shape.intersect(&r) <= 0.0 {
+            return Err("a ray from inside a closed shape has to hit it".to_string());
+        }
+        hits_match_reference(&shape, &r)
+    });
+}
+
+fn tangent_rays<S: Reference>() {
+    check("tangent rays", |rng| {
+        let shape: S = Reference::random(rng);
+        let offset = shape.scale() * 10.0f64.powf(uniform(rng, -6.0, -2.0));
+        // Just outside: a clean miss
+        let outside = shape.tangent_ray(offset, rng);
+        if shape.intersect(&outside) != 0.0 {
+            return Err(format!("ray passing {} outside the surface hit it", offset));
+        }
+        // Just inside: a hit close to the tangent point
+        let inside = shape.tangent_ray(-offset, rng);
+        hits_match_reference(&shape, &inside)
+    });
+}
+
+fn rays_pointing_away<S: Reference>() {
+    check("rays pointing away", |rng| {
+        let shape: S = Reference::random(rng);
+        let r = random_ray(&shape, rng);
+        let roots = shape.roots(&r);
+        if roots.len() == 0 || roots[roots.len() - 1] >= 0.0 {
+            return Ok(());
+        }
+        // Everything lies behind the origin
+        let t = shape.intersect(&r);
+        if t != 0.0 {
+            return Err(format!("hit at {} behind the ray (roots {:?})", t, roots));
+        }
+        Ok(())
+    });
+}
+
+fn spawned_rays<S: Reference>() {
+    check("spawned rays", |rng| {
+        let shape: S = Reference::random(rng);
+        let r = random_ray(&shape, rng);
+        let t = shape.intersect(&r);
+        if t == 0.0 {
+            return Ok(());
+        }
+        let (x, error) = shape.surface_point(&r.o + &r.d.smul(t), r.time);
+        let n = shape.normal(x, r.time);
+        // Leave to either side of the surface, at any angle
+        let mut d = random_direction(rng);
+        if d.dot(&n) == 0.0 {
+            return Ok(());
+        }
+        if rng.next_f64() < 0.5 {
+            d = d.smul(-1.0);
+        }
+        let spawned = Ray::spawn(x, error, n, d, r.time);
+        let t = shape.intersect(&spawned);
+        // The surface the ray leaves lies just behind the new origin, any
+        // other root has to be a proper distance away
+        let roots = shape.roots(&spawned);
+        if let Some(behind) = roots.iter().find(|t| t.abs() <= AMBIGUOUS * shape.scale()) {
+            if *behind > 0.0 {
+                return Err(format!("spawned origin lies {} in front of the surface", behind));
+            }
+        }
+        let expected = roots.iter().map(|t| *t).find(|t| *t > AMBIGUOUS * shape.scale());
+        match expected {
+            Some(expected) => {
+                if (t - expected).abs() > 1e-9 * (shape.scale() + expected) {
+                    return Err(format!("spawned ray hit at {}, expected {} (roots {:?})", t, expected, roots));
+                }
+            }
+            None => {
+                if t != 0.0 {
+                    return Err(format!("spawned ray hit its own surface at {} (roots {:?})", t, roots));
+                }
+            }
+        }
+        Ok(())
+    });
+}
+
+#[test]
+fn sphere_random_rays() {
+    random_rays::<Sphere>();
+}
+
+#[test]
+fn sphere_rays_from_inside() {
+    rays_from_inside::<Sphere>();
+}
+
+#[test]
+fn sphere_tangent_rays() {
+    tangent_rays::<Sphere>();
+}
+
+#[test]
+fn sphere_rays_pointing_away() {
+    rays_pointing_away::<Sphere>();
+}
+
+#[test]
+fn sphere_spawned_rays() {
+    spawned_rays::<Sphere>();
+}
+
+#[test]
+fn triangle_random_rays() {
+    random_rays::<Triangle>();
+}
+
+#[test]
+fn triangle_tangent_rays() {
+    tangent_rays::<Triangle>();
+}
+
+#[test]
+fn triangle_rays_pointing_away() {
+    rays_pointing_away::<Triangle>();
+}
+
+#[test]
+fn triangle_spawned_rays() {
+    spawned_rays::<Triangle>();
+}